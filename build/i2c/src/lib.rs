@@ -104,9 +104,23 @@ struct I2cDevice {
     /// device is removable
     #[serde(default)]
     removable: bool,
+
+    /// device uses SMBus Packet Error Checking (PEC)
+    #[serde(default)]
+    pec: bool,
+
+    /// device can safely be probed by an I2C scan; this should be false for
+    /// any device that can lock up the bus if addressed while not powered,
+    /// which prevents its bus from being scanned at all
+    #[serde(default = "I2cDevice::default_probe")]
+    probe: bool,
 }
 
 impl I2cDevice {
+    fn default_probe() -> bool {
+        true
+    }
+
     /// Checks whether the given sensor kind is associated with an `I2cPower`
     /// struct stored in this device, returning it if that's the case.
     ///
@@ -136,6 +150,7 @@ struct I2cPort {
     pins: Vec<I2cPinSet>,
     #[serde(default)]
     muxes: Vec<I2cMux>,
+    recovery: Option<I2cRecovery>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -153,6 +168,38 @@ struct I2cGpio {
     pin: u8,
 }
 
+///
+/// Bus recovery for a port, which uses the port's own pins: what to do if
+/// clocking SCL doesn't free SDA, e.g.:
+///
+/// ```toml
+/// [config.i2c.controllers.ports.B]
+/// pins = [ { pins = [ 10, 11 ], af = 4 } ]
+///
+/// [config.i2c.controllers.ports.B.recovery]
+/// escalate = [ "mux-reset", "power-cycle" ]
+/// power = { port = "G", pin = 3 }
+/// ```
+///
+/// The power enable is driven by the I2C server while power-cycling, and so
+/// must not be one that another task (e.g., a sequencer) also drives.
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct I2cRecovery {
+    #[serde(default)]
+    escalate: Vec<I2cEscalation>,
+    /// active-high enable for power to the port's devices
+    power: Option<I2cGpio>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum I2cEscalation {
+    MuxReset,
+    PowerCycle,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct I2cMux {
@@ -432,6 +479,16 @@ pub enum Sensor {
     Speed,
 }
 
+impl std::str::FromStr for Sensor {
+    type Err = serde::de::value::Error;
+
+    /// Parses a sensor kind as spelled in app.toml (e.g., `input-voltage`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use serde::de::IntoDeserializer;
+        Self::deserialize(s.into_deserializer())
+    }
+}
+
 impl std::fmt::Display for Sensor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -842,6 +899,254 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    pub fn generate_recovery(&mut self) -> Result<()> {
+        if self.disposition != Disposition::Initiator {
+            panic!("illegal disposition for recovery generation");
+        }
+
+        let mut recovery = vec![];
+
+        for c in &self.controllers {
+            for (index, (p, port)) in c.ports.iter().enumerate() {
+                let r = match &port.recovery {
+                    Some(r) => r,
+                    None => continue,
+                };
+
+                //
+                // We tell SCL from SDA by which is held low, so the port
+                // must have exactly the two.
+                //
+                let npins =
+                    port.pins.iter().map(|set| set.pins.len()).sum::<usize>();
+
+                if npins != 2 {
+                    panic!(
+                        "I2C{} port {p}: recovery requires exactly two pins",
+                        c.controller
+                    );
+                }
+
+                if r.escalate.contains(&I2cEscalation::PowerCycle)
+                    && r.power.is_none()
+                {
+                    panic!(
+                        "I2C{} port {p}: power-cycle escalation requires \
+                        a power enable",
+                        c.controller
+                    );
+                }
+
+                if r.escalate.contains(&I2cEscalation::MuxReset)
+                    && !port.muxes.iter().any(|m| m.nreset.is_some())
+                {
+                    panic!(
+                        "I2C{} port {p}: mux-reset escalation requires a \
+                        mux with a reset line",
+                        c.controller
+                    );
+                }
+
+                let escalation = r
+                    .escalate
+                    .iter()
+                    .map(|e| format!("I2cEscalation::{e:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                //
+                // Lest we power-cycle something other than the port's
+                // devices, the power enable can't be one of the port's pins
+                // or a mux reset.
+                //
+                if let Some(power) = &r.power {
+                    let conflict = c.ports.iter().any(|(p, port)| {
+                        port.pins.iter().any(|set| {
+                            set.gpio_port.as_ref().unwrap_or(p) == &power.port
+                                && set.pins.contains(&power.pin)
+                        }) || port.muxes.iter().any(|m| {
+                            m.nreset.as_ref().map_or(false, |n| {
+                                n.port == power.port && n.pin == power.pin
+                            })
+                        })
+                    });
+
+                    if conflict {
+                        panic!(
+                            "I2C{} port {p}: power enable is in use",
+                            c.controller
+                        );
+                    }
+                }
+
+                let power = match &r.power {
+                    Some(power) => format!(
+                        r##"Some(I2cGpio {{
+                    gpio_pins: gpio_api::Port::{}.pin({}),
+                }})"##,
+                        power.port, power.pin
+                    ),
+                    None => "None".to_string(),
+                };
+
+                recovery.push(format!(
+                    r##"
+            I2cRecovery {{
+                controller: Controller::I2C{controller},
+                port: PortIndex({index}),
+                escalation: &[{escalation}],
+                power: {power},
+            }},"##,
+                    controller = c.controller,
+                ));
+            }
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+    #[allow(unused_imports)]
+    use drv_stm32xx_i2c::{{I2cEscalation, I2cRecovery}};
+
+    pub fn recovery() -> [I2cRecovery; {}] {{
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{Controller, PortIndex}};
+
+        #[allow(unused_imports)]
+        use drv_stm32xx_sys_api as gpio_api;
+
+        ["##,
+            recovery.len()
+        )?;
+
+        for r in recovery {
+            write!(&mut self.output, "{}", r)?;
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+        ]
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
+    pub fn generate_pec(&mut self) -> Result<()> {
+        if self.disposition != Disposition::Initiator {
+            panic!("illegal disposition for PEC generation");
+        }
+
+        //
+        // We only emit devices on our own controllers; the server looks up
+        // each transaction in this list to determine if it should send and
+        // check a PEC byte.
+        //
+        let mut pec = vec![];
+
+        for d in self.devices.iter().filter(|d| d.pec) {
+            let (controller, port) = self.lookup_controller_port(d);
+
+            if !self.controllers.iter().any(|c| c.controller == controller) {
+                continue;
+            }
+
+            pec.push(format!(
+                r##"
+            // {description}
+            (
+                Controller::I2C{controller},
+                PortIndex({port}),
+                {segment},
+                {address:#x},
+            ),"##,
+                description = d.description,
+                segment = Self::generate_segment(d),
+                address = d.address,
+            ));
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+    #[allow(dead_code)]
+    pub fn pec_devices() -> [(
+        drv_i2c_api::Controller,
+        drv_i2c_api::PortIndex,
+        Option<(drv_i2c_api::Mux, drv_i2c_api::Segment)>,
+        u8,
+    ); {}] {{
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{Controller, PortIndex}};
+
+        ["##,
+            pec.len()
+        )?;
+
+        for p in pec {
+            write!(&mut self.output, "{}", p)?;
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+        ]
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
+    pub fn generate_stats(&mut self) -> Result<()> {
+        if self.disposition != Disposition::Initiator {
+            panic!("illegal disposition for stats generation");
+        }
+
+        //
+        // The server keeps statistics for each segment and for each device
+        // on each segment.  We size its table to accommodate every port, the
+        // segments, devices and muxes on our controllers, and a handful of
+        // unlisted addresses (e.g., those probed from the debugger); beyond
+        // that, new entries are dropped.
+        //
+        let mut segments = std::collections::BTreeSet::new();
+        let mut ndevices = 0;
+
+        for d in &self.devices {
+            let (controller, port) = self.lookup_controller_port(d);
+
+            if !self.controllers.iter().any(|c| c.controller == controller) {
+                continue;
+            }
+
+            if let (Some(mux), Some(segment)) = (d.mux, d.segment) {
+                segments.insert((controller, port, mux, segment));
+            }
+
+            ndevices += 1;
+        }
+
+        let nports: usize =
+            self.controllers.iter().map(|c| c.ports.len()).sum();
+        let nmuxes: usize = self
+            .controllers
+            .iter()
+            .flat_map(|c| c.ports.values())
+            .map(|p| p.muxes.len())
+            .sum();
+
+        writeln!(
+            &mut self.output,
+            r##"
+    #[allow(dead_code)]
+    pub const NSTATS: usize = {};"##,
+            nports + segments.len() + ndevices + nmuxes + 8
+        )?;
+
+        Ok(())
+    }
+
     fn lookup_controller_port(&self, d: &I2cDevice) -> (u8, usize) {
         let controller = match &d.bus {
             Some(bus) => self.buses.get(bus).unwrap().0,
@@ -884,10 +1189,8 @@ impl ConfigGenerator {
         (controller, *port)
     }
 
-    fn generate_device(&self, d: &I2cDevice, indent: usize) -> String {
-        let (controller, port) = self.lookup_controller_port(d);
-
-        let segment = match (d.mux, d.segment) {
+    fn generate_segment(d: &I2cDevice) -> String {
+        match (d.mux, d.segment) {
             (Some(mux), Some(segment)) => {
                 format!(
                     "Some((drv_i2c_api::Mux::M{}, drv_i2c_api::Segment::S{}))",
@@ -901,7 +1204,12 @@ impl ConfigGenerator {
             (None, Some(_)) => {
                 panic!("device {} specifies a segment but no mux", d.device)
             }
-        };
+        }
+    }
+
+    fn generate_device(&self, d: &I2cDevice, indent: usize) -> String {
+        let (controller, port) = self.lookup_controller_port(d);
+        let segment = Self::generate_segment(d);
 
         let indent = format!("{:indent$}", "", indent = indent);
 
@@ -1141,6 +1449,20 @@ impl ConfigGenerator {
 
         drivers.remove("lib");
 
+        //
+        // PMBus drivers are generated from descriptions (see `build-pmbus`)
+        // rather than living in their own source files.
+        //
+        println!("cargo:rerun-if-changed={}", dir.join("pmbus").display());
+
+        for entry in std::fs::read_dir(dir.join("pmbus"))? {
+            if let Some(f) = entry?.path().file_name() {
+                if let Some(name) = f.to_str().unwrap().strip_suffix(".toml") {
+                    drivers.insert(name.to_string());
+                }
+            }
+        }
+
         write!(
             &mut self.output,
             r##"
@@ -1211,6 +1533,154 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    pub fn generate_scan(&mut self) -> Result<()> {
+        let mut buses = vec![];
+
+        for c in &self.controllers {
+            for (index, port) in c.ports.values().enumerate() {
+                let mut segments = vec![None];
+
+                for (mindex, mux) in port.muxes.iter().enumerate() {
+                    let m = mindex as u8 + 1;
+
+                    //
+                    // For a mux whose segments we don't know, we scan only
+                    // those that have devices on them.
+                    //
+                    let nsegments = match mux.driver.as_str() {
+                        "ltc4306" => Some(4),
+                        "max7358" | "pca9548" => Some(8),
+                        _ => None,
+                    };
+
+                    let on_mux = |segment| {
+                        self.devices.iter().any(|d| {
+                            self.lookup_controller_port(d)
+                                == (c.controller, index)
+                                && d.mux == Some(m)
+                                && d.segment == Some(segment)
+                        })
+                    };
+
+                    for segment in 1..=nsegments.unwrap_or(u8::MAX) {
+                        if nsegments.is_some() || on_mux(segment) {
+                            segments.push(Some((m, segment)));
+                        }
+                    }
+                }
+
+                //
+                // Devices on the port itself answer on every segment, as do
+                // the muxes; we don't probe their addresses on segments.
+                //
+                let on = |d: &I2cDevice, segment: Option<(u8, u8)>| {
+                    self.lookup_controller_port(d) == (c.controller, index)
+                        && match segment {
+                            Some((mux, segment)) => {
+                                d.mux == Some(mux) && d.segment == Some(segment)
+                            }
+                            None => d.mux.is_none(),
+                        }
+                };
+
+                let muxes =
+                    port.muxes.iter().map(|m| m.address).collect::<Vec<_>>();
+
+                for segment in segments {
+                    let mut expected = vec![];
+                    let mut skip = false;
+
+                    for (i, d) in self.devices.iter().enumerate() {
+                        if on(d, segment) {
+                            expected.push(format!(
+                                "({:#x}, {i}, {})",
+                                d.address, d.removable
+                            ));
+                            skip |= !d.probe;
+                        }
+                    }
+
+                    let mut shadowed = muxes.clone();
+
+                    if segment.is_some() {
+                        shadowed.extend(
+                            self.devices
+                                .iter()
+                                .filter(|d| on(d, None))
+                                .map(|d| d.address),
+                        );
+                    }
+
+                    let shadowed = shadowed
+                        .iter()
+                        .map(|a| format!("{a:#x}"))
+                        .collect::<Vec<_>>();
+
+                    buses.push(format!(
+                        r##"
+            Bus {{
+                controller: Controller::I2C{controller},
+                port: PortIndex({index}),
+                segment: {segment},
+                expected: &[{expected}],
+                shadowed: &[{shadowed}],
+                skip: {skip},
+            }},"##,
+                        controller = c.controller,
+                        segment = match segment {
+                            Some((mux, segment)) => format!(
+                                "Some((Mux::M{mux}, Segment::S{segment}))"
+                            ),
+                            None => "None".to_string(),
+                        },
+                        expected = expected.join(", "),
+                        shadowed = shadowed.join(", "),
+                    ));
+                }
+            }
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+    pub mod scan {{
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{Controller, Mux, PortIndex, Segment}};
+
+        ///
+        /// A bus to be scanned: a port, or a segment of a mux on a port.
+        ///
+        pub struct Bus {{
+            pub controller: Controller,
+            pub port: PortIndex,
+            pub segment: Option<(Mux, Segment)>,
+            /// Address, validation index and removability of each device
+            /// expected on this bus
+            pub expected: &'static [(u8, usize, bool)],
+            /// Addresses that answer from elsewhere, and aren't probed
+            pub shadowed: &'static [u8],
+            /// Whether the bus isn't safe to probe
+            pub skip: bool,
+        }}
+
+        pub const BUSES: [Bus; {}] = ["##,
+            buses.len()
+        )?;
+
+        for b in buses {
+            write!(&mut self.output, "{}", b)?;
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+        ];
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
     fn generate_power(&mut self, which: PowerDevices) -> Result<()> {
         let mut byrail = HashMap::new();
 
@@ -1421,6 +1891,9 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
             g.generate_pins()?;
             g.generate_ports()?;
             g.generate_muxes()?;
+            g.generate_recovery()?;
+            g.generate_pec()?;
+            g.generate_stats()?;
         }
 
         Disposition::Devices => {
@@ -1435,6 +1908,7 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
         Disposition::Validation => {
             g.generate_devices()?;
             g.generate_validation()?;
+            g.generate_scan()?;
         }
     }
 
@@ -1451,6 +1925,52 @@ pub struct I2cDeviceDescription {
     pub sensors: Vec<DeviceSensor>,
 }
 
+/// Where a sensor lives and what it measures, as exposed by the sensor task.
+pub struct I2cSensorMetadata {
+    pub id: usize,
+    pub kind: Sensor,
+    pub device: String,
+    pub name: Option<String>,
+    pub refdes: Option<String>,
+    pub controller: u8,
+    pub port: usize,
+    pub mux: Option<u8>,
+    pub segment: Option<u8>,
+    pub address: u8,
+}
+
+///
+/// Returns metadata for each I2C sensor, ordered by sensor ID.
+///
+pub fn sensor_metadata() -> Vec<I2cSensorMetadata> {
+    let g = ConfigGenerator::new(Disposition::Sensors);
+    let sensors = g.sensors_description();
+
+    let mut rval = vec![];
+
+    for (d, sensors) in g.devices.iter().zip(sensors.device_sensors) {
+        let (controller, port) = g.lookup_controller_port(d);
+
+        for s in sensors {
+            rval.push(I2cSensorMetadata {
+                id: s.id,
+                kind: s.kind,
+                device: d.device.clone(),
+                name: s.name,
+                refdes: d.refdes.clone(),
+                controller,
+                port,
+                mux: d.mux,
+                segment: d.segment,
+                address: d.address,
+            });
+        }
+    }
+
+    rval.sort_by_key(|s| s.id);
+    rval
+}
+
 ///
 /// Returns a list of I2C device descriptions.
 ///
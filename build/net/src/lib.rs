@@ -31,11 +31,12 @@ pub struct NetConfig {
 }
 
 /// TODO: this type really wants to be an enum, but the toml crate's enum
-/// handling is really, really fragile, and currently it would be an enum with a
-/// single variant anyway.
+/// handling is really, really fragile.
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SocketConfig {
+    /// Either `"udp"` or `"tcp"`; TCP sockets need the net task's `tcp`
+    /// feature.
     pub kind: String,
     pub owner: TaskNote,
     pub port: u16,
@@ -43,19 +44,43 @@ pub struct SocketConfig {
     pub rx: BufSize,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct VLanConfig {
     /// Address of the 0-index VLAN
     pub start: usize,
     /// Number of VLANs
     pub count: usize,
+    /// Address configuration for each VLAN, in order from `start`. VLANs past
+    /// the end of this list have only an IPv6 link-local address.
+    #[serde(default)]
+    pub addresses: Vec<AddressConfig>,
+}
+
+/// Address configuration for a VLAN, beyond the IPv6 link-local address that
+/// every VLAN has. Any IPv4 address needs the net task's `ipv4` feature, and
+/// any global IPv6 address its `ipv6-autoconf` feature.
+///
+/// TODO: like `SocketConfig`, this wants to be made of enums.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AddressConfig {
+    /// Either `"static"` or `"dhcp"`; if omitted, there's no IPv4 address.
+    pub ipv4: Option<String>,
+    /// Static IPv4 address, with its prefix length (e.g. `"10.0.0.2/24"`)
+    pub ipv4_address: Option<String>,
+    /// Static IPv4 default gateway, if any
+    pub ipv4_gateway: Option<String>,
+    /// Either `"slaac"` or `"dhcpv6"`, for a global IPv6 address
+    pub ipv6: Option<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct BufSize {
-    pub packets: usize,
+    /// Number of packets that can be queued; required for UDP sockets, and
+    /// must be omitted for TCP sockets, which queue a stream of bytes.
+    pub packets: Option<usize>,
     pub bytes: usize,
 }
 
@@ -78,6 +103,11 @@ pub fn load_net_config() -> Result<NetConfig> {
         }
         _ => (),
     }
+    if let Some(vlan) = &cfg.vlan {
+        if vlan.addresses.len() > vlan.count {
+            panic!("more VLAN address configs than VLANs");
+        }
+    }
 
     Ok(cfg)
 }
@@ -86,7 +116,7 @@ pub fn generate_vlan_consts(
     config: &NetConfig,
     mut out: impl std::io::Write,
 ) -> Result<(), std::io::Error> {
-    let vlan = config.vlan.unwrap();
+    let vlan = config.vlan.as_ref().unwrap();
     let end = vlan.start + vlan.count;
     if end > 0xFFF {
         panic!("Invalid VLAN range (must be < 4096)");
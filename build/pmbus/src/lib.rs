@@ -0,0 +1,793 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PMBus driver generation
+//!
+//! Most PMBus parts differ only in which commands they support, whether they
+//! have pages (rails), how their readings are encoded, and how they identify
+//! themselves.  Rather than hand-writing a driver for each, a part is
+//! described in a TOML file and its driver is generated at build time:
+//!
+//! ```toml
+//! description = "RAA229618 power controller"
+//! name = "Raa229618"
+//! commands = "raa229618"
+//! paged = true
+//! operation = true
+//! operation-mut = true
+//! vout-max = 3.050
+//!
+//! [validate]
+//! command = "IC_DEVICE_ID"
+//! expected = [0x00, 0x99, 0xd2, 0x49]
+//!
+//! [sensors]
+//! temperature = "READ_TEMPERATURE_1"
+//! current = "READ_IOUT"
+//! voltage = "READ_VOUT"
+//!
+//! [status]
+//! VOUT = 15
+//! IOUT = 14
+//! ```
+//!
+//! The module is named after the file (e.g., `raa229618.toml` generates
+//! `raa229618`), and `commands` names the module in `pmbus::commands` that
+//! defines the part's commands.  The sensors that may be given are
+//! `temperature`, `current`, `voltage`, `input-current` and `input-voltage`,
+//! each of which implements the corresponding trait.  Sensors are read in
+//! the encoding that the command has in that module (LINEAR11, or LINEAR16
+//! relative to `VOUT_MODE`), unless coefficients are given for a command in
+//! DIRECT format:
+//!
+//! ```toml
+//! [sensors.current]
+//! command = "READ_IOUT"
+//! coefficients = { m = 347, b = 0, r = -1 }
+//! ```
+//!
+//! The coefficients may instead name a method of the driver that returns
+//! them, for parts whose coefficients depend on their configuration, and
+//! `enable` may name a method to call before each reading.  `power` may also
+//! be given, implementing `PowerSensor`.
+//!
+//! Each bit in `status` generates a constant for its mask in `STATUS_WORD`,
+//! and an entry in a table of all such bits for the use of fault logging.
+//! A part with a `status` table (even an empty one) also gets
+//! `read_status_word`, and `read_status` to read the `STATUS_*` registers
+//! that the word summarizes.
+//!
+//! `operation-mut` makes `turn_on` and `turn_off` take `&mut self`, and
+//! `error-eq` derives `Eq` for the driver's `Error`, to keep the API of the
+//! hand-written driver that a description replaced.
+//!
+//! Parts that need more than this can name a file of hand-written items in
+//! `extra`, which is spliced into the generated module; it may use anything
+//! that the module imports.  A part with `custom` set has its struct, `new`
+//! and (if needed) `read_mode` written there too, rather than generated.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Description {
+    /// Part and what it is, for documentation
+    description: String,
+    /// Name of the driver type
+    name: String,
+    /// Module within `pmbus::commands` that defines the part's commands
+    commands: String,
+    /// Whether the part has pages, each of which is a rail
+    #[serde(default)]
+    paged: bool,
+    /// Whether `VOUT_MODE` differs between pages; defaults to `paged`
+    paged_mode: Option<bool>,
+    /// Whether `PAGE` must be written in a transaction of its own, rather
+    /// than along with each command
+    #[serde(default)]
+    page_write: bool,
+    /// Whether the part can be turned on and off via `OPERATION`
+    #[serde(default)]
+    operation: bool,
+    /// Whether `turn_on` and `turn_off` take `&mut self` rather than `&self`
+    #[serde(default)]
+    operation_mut: bool,
+    /// Maximum output voltage that can be set via `VOUT_COMMAND`; if absent,
+    /// the output voltage cannot be set.
+    vout_max: Option<f32>,
+    validate: Option<Validation>,
+    #[serde(default)]
+    sensors: Sensors,
+    /// Bits of `STATUS_WORD`, by name
+    status: Option<BTreeMap<String, u8>>,
+    /// Additional (unit) variants of the driver's `Error`
+    #[serde(default)]
+    errors: Vec<String>,
+    /// Whether the driver's `Error` derives `Eq`
+    #[serde(default)]
+    error_eq: bool,
+    /// Whether the struct, `new` and `read_mode` are hand-written in `extra`
+    #[serde(default)]
+    custom: bool,
+    /// File of hand-written items to splice into the module, relative to
+    /// the description
+    extra: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Validation {
+    /// Block command that identifies the part
+    command: String,
+    expected: Expected,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Expected {
+    Bytes(Vec<u8>),
+    String(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Sensors {
+    temperature: Option<Reading>,
+    current: Option<Reading>,
+    voltage: Option<Reading>,
+    input_current: Option<Reading>,
+    input_voltage: Option<Reading>,
+    power: Option<Reading>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Reading {
+    Command(String),
+    Detailed(Detailed),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Detailed {
+    command: String,
+    /// DIRECT format coefficients; if absent, the command's own encoding
+    coefficients: Option<Source>,
+    /// Method to call before reading, e.g. to enable sampling
+    enable: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Source {
+    Fixed(Coefficients),
+    /// Method of the driver that returns the coefficients
+    Method(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Coefficients {
+    m: i32,
+    b: i32,
+    r: i8,
+}
+
+fn is_command(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+///
+/// Strips the license header from hand-written items, lest it be repeated in
+/// the middle of the generated file.
+///
+fn without_license(extra: &str) -> &str {
+    match extra.split_once("\n\n") {
+        Some((header, rest)) if header.contains("Mozilla Public") => rest,
+        _ => extra,
+    }
+}
+
+fn is_method(name: &str) -> bool {
+    is_module(name) && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn is_module(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+///
+/// Generates a driver for each description in `dir` into `filename` in
+/// `OUT_DIR`.  The output is intended to be included into the root of
+/// `drv-i2c-devices`, where the PMBus macros and sensor traits are defined.
+///
+pub fn codegen(dir: impl AsRef<Path>, filename: &str) -> Result<()> {
+    let dir = dir.as_ref();
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut paths = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read {}", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+
+    paths.sort();
+
+    let mut out = String::new();
+
+    for path in &paths {
+        //
+        // Files other than descriptions may be spliced into a driver, so we
+        // depend on them too.
+        //
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        if path.extension().map_or(false, |e| e == "toml") {
+            out.push_str(&generate_part(path)?);
+        }
+    }
+
+    let dest = PathBuf::from(
+        std::env::var("OUT_DIR").context("could not get OUT_DIR")?,
+    )
+    .join(filename);
+
+    std::fs::write(&dest, out)?;
+    call_rustfmt::rustfmt(&dest)?;
+
+    Ok(())
+}
+
+///
+/// Generates the driver for the description at `path`, returning its source
+/// (which has yet to be formatted).
+///
+pub fn generate_part(path: &Path) -> Result<String> {
+    let module = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("bad description filename")?;
+
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let part: Description = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let extra = match &part.extra {
+        Some(extra) => {
+            let extra = path.with_file_name(extra);
+            Some(std::fs::read_to_string(&extra).with_context(|| {
+                format!("failed to read {}", extra.display())
+            })?)
+        }
+        None => None,
+    };
+
+    generate(module, &part, extra.as_deref())
+        .with_context(|| format!("bad description {}", path.display()))
+}
+
+fn generate(
+    module: &str,
+    part: &Description,
+    extra: Option<&str>,
+) -> Result<String> {
+    if !is_module(module) || !is_module(&part.commands) {
+        bail!("module names must be lowercase identifiers");
+    }
+
+    let paged_mode = part.paged_mode.unwrap_or(part.paged);
+
+    if (part.page_write || paged_mode) && !part.paged {
+        bail!("page-write and paged-mode require paged");
+    }
+
+    if part.custom && extra.is_none() {
+        bail!("a custom part must have its struct written in extra");
+    }
+
+    let name = &part.name;
+    let commands = &part.commands;
+
+    //
+    // Reads of the part's own commands are paged if it has pages: either
+    // PAGE is written along with each command, or (for parts that require
+    // it) the page is selected beforehand, in which case `select` must
+    // precede the read.
+    //
+    let rail_paged = part.paged && !part.page_write;
+    let select = if part.page_write {
+        "self.set_rail()?;\n"
+    } else {
+        ""
+    };
+
+    let read = |cmd: &str| {
+        if rail_paged {
+            format!("pmbus_rail_read!(self.device, self.rail, {cmd})")
+        } else {
+            format!("pmbus_read!(self.device, {cmd})")
+        }
+    };
+
+    let write = |cmd: &str, data: &str| {
+        if rail_paged {
+            format!("pmbus_rail_write!(self.device, self.rail, {cmd}, {data})")
+        } else {
+            format!("pmbus_write!(self.device, {cmd}, {data})")
+        }
+    };
+
+    let mut imports = vec!["BadValidation"];
+    let mut methods = String::new();
+    let mut impls = String::new();
+
+    if part.page_write {
+        write!(
+            &mut methods,
+            r##"
+        fn set_rail(&self) -> Result<(), Error> {{
+            let page = {commands}::PAGE::CommandData(self.rail);
+            pmbus_write!(self.device, {commands}::PAGE, page)
+        }}
+"##,
+        )?;
+    }
+
+    if part.operation {
+        let receiver = if part.operation_mut {
+            "&mut self"
+        } else {
+            "&self"
+        };
+
+        for (method, state) in [("turn_off", "Off"), ("turn_on", "On")] {
+            write!(
+                &mut methods,
+                r##"
+        pub fn {method}({receiver}) -> Result<(), Error> {{
+            {select}let mut op = {}?;
+            op.set_on_off_state({commands}::OPERATION::OnOffState::{state});
+            {}
+        }}
+"##,
+                read(&format!("{commands}::OPERATION")),
+                write(&format!("{commands}::OPERATION"), "op"),
+            )?;
+        }
+    }
+
+    if let Some(max) = part.vout_max {
+        if !max.is_finite() || max <= 0.0 {
+            bail!("vout-max must be positive");
+        }
+
+        write!(
+            &mut methods,
+            r##"
+        pub fn set_vout(&mut self, value: Volts) -> Result<(), Error> {{
+            if value > Volts({max:?}) {{
+                Err(Error::InvalidData {{
+                    err: pmbus::Error::ValueOutOfRange,
+                }})
+            }} else {{
+                {select}let mut vout = {commands}::VOUT_COMMAND::CommandData(0);
+                vout.set(self.read_mode()?, pmbus::units::Volts(value.0))?;
+                {}
+            }}
+        }}
+"##,
+            write(&format!("{commands}::VOUT_COMMAND"), "vout"),
+        )?;
+    }
+
+    let mut status = String::new();
+
+    if let Some(bits) = &part.status {
+        let mut table = String::new();
+
+        for (bit, value) in bits {
+            if !is_command(bit) || *value > 15 {
+                bail!("bad status bit {bit} = {value}");
+            }
+
+            writeln!(
+                &mut status,
+                "    pub const STATUS_{bit}: u16 = 1 << {value};"
+            )?;
+            writeln!(&mut table, "        (STATUS_{bit}, \"{bit}\"),")?;
+        }
+
+        let payload = |width: &str| {
+            if rail_paged {
+                format!(
+                    "write_read_reg::<u8, {width}>(\n\
+                    cmd,\n\
+                    &[CommandCode::PAGE as u8, self.rail],\n\
+                    )"
+                )
+            } else {
+                format!("read_reg::<u8, {width}>(cmd)")
+            }
+        };
+        let (word, byte) = (payload("u16"), payload("u8"));
+        let named = if bits.is_empty() {
+            ""
+        } else {
+            ", whose bits are named in [`STATUS_BITS`]"
+        };
+
+        //
+        // If the page is selected beforehand, the `STATUS_*` registers are
+        // read with it still selected by `read_status_word`.
+        //
+        imports.push("pmbus_status");
+
+        write!(
+            &mut methods,
+            r##"
+        ///
+        /// Reads `STATUS_WORD`{named}.
+        ///
+        pub fn read_status_word(&self) -> Result<u16, Error> {{
+            {select}let cmd = CommandCode::STATUS_WORD as u8;
+
+            self.device
+                .{word}
+                .map_err(|code| Error::BadRead {{ cmd, code }})
+        }}
+
+        ///
+        /// Reads `STATUS_WORD`, along with each `STATUS_*` register that has
+        /// its summary bit set.
+        ///
+        pub fn read_status(
+            &self,
+        ) -> Result<task_power_api::PmbusStatus, Error> {{
+            pmbus_status(self.read_status_word()?, |cmd| {{
+                let cmd = cmd as u8;
+
+                self.device
+                    .{byte}
+                    .map_err(|code| Error::BadRead {{ cmd, code }})
+            }})
+        }}
+"##,
+        )?;
+
+        if !bits.is_empty() {
+            write!(
+                &mut status,
+                r##"
+    /// Bits of `STATUS_WORD` supported by this part, and their names
+    pub const STATUS_BITS: &[(u16, &str)] = &[
+{table}    ];
+"##
+            )?;
+        }
+    }
+
+    match &part.validate {
+        Some(v) => {
+            if !is_command(&v.command) {
+                bail!("bad validation command {}", v.command);
+            }
+
+            let expected = match &v.expected {
+                Expected::Bytes(bytes) => {
+                    let bytes = bytes
+                        .iter()
+                        .map(|b| format!("{b:#04x}"))
+                        .collect::<Vec<_>>();
+                    format!("&[{}]", bytes.join(", "))
+                }
+                Expected::String(s)
+                    if s.bytes().all(|b| b.is_ascii_graphic()) =>
+                {
+                    format!("b{s:?}")
+                }
+                Expected::String(s) => {
+                    bail!("expected string {s:?} must be printable ASCII")
+                }
+            };
+
+            imports.push("pmbus_validate");
+            write!(
+                &mut impls,
+                r##"
+    impl Validate<Error> for {name} {{
+        fn validate(device: &I2cDevice) -> Result<bool, Error> {{
+            let expected = {expected};
+            pmbus_validate(device, CommandCode::{}, expected)
+                .map_err(Into::into)
+        }}
+    }}
+"##,
+                v.command
+            )?;
+        }
+        None => {
+            writeln!(&mut impls, "\n    impl Validate<Error> for {name} {{}}")?;
+        }
+    }
+    imports.push("Validate");
+
+    //
+    // Each sensor is its trait, method, unit, whether its reading is
+    // relative to `VOUT_MODE`, and the receiver of its method.
+    //
+    let sensors = [
+        (
+            &part.sensors.temperature,
+            "TempSensor",
+            "read_temperature",
+            "Celsius",
+            false,
+            "&self",
+        ),
+        (
+            &part.sensors.current,
+            "CurrentSensor",
+            "read_iout",
+            "Amperes",
+            false,
+            "&self",
+        ),
+        (
+            &part.sensors.voltage,
+            "VoltageSensor",
+            "read_vout",
+            "Volts",
+            true,
+            "&self",
+        ),
+        (
+            &part.sensors.input_current,
+            "InputCurrentSensor",
+            "read_iin",
+            "Amperes",
+            false,
+            "&self",
+        ),
+        (
+            &part.sensors.input_voltage,
+            "InputVoltageSensor",
+            "read_vin",
+            "Volts",
+            false,
+            "&self",
+        ),
+        (
+            &part.sensors.power,
+            "PowerSensor",
+            "read_power",
+            "Watts",
+            false,
+            "&mut self",
+        ),
+    ];
+
+    let mut sensed = false;
+
+    for (reading, tr, method, unit, vout, receiver) in sensors {
+        let reading = match reading {
+            Some(reading) => reading,
+            None => continue,
+        };
+
+        let (cmd, coefficients, enable) = match reading {
+            Reading::Command(cmd) => (cmd, None, None),
+            Reading::Detailed(d) => {
+                (&d.command, d.coefficients.as_ref(), d.enable.as_ref())
+            }
+        };
+
+        if !is_command(cmd) {
+            bail!("bad command {cmd} for {tr}");
+        }
+
+        let get = match coefficients {
+            None if vout => "self.read_mode()?".into(),
+            None => String::new(),
+            Some(Source::Fixed(c)) => format!(
+                "&pmbus::Coefficients {{ m: {}, b: {}, R: {} }}",
+                c.m, c.b, c.r
+            ),
+            Some(Source::Method(m)) if is_method(m) => format!("&self.{m}()?"),
+            Some(Source::Method(m)) => {
+                bail!("bad coefficients method {m} for {tr}")
+            }
+        };
+
+        let enable = match enable {
+            None => String::new(),
+            Some(m) if is_method(m) => format!("self.{m}()?;\n"),
+            Some(m) => bail!("bad enable method {m} for {tr}"),
+        };
+
+        imports.push(tr);
+        sensed = true;
+        write!(
+            &mut impls,
+            r##"
+    impl {tr}<Error> for {name} {{
+        fn {method}({receiver}) -> Result<{unit}, Error> {{
+            {enable}{select}let value = {}?;
+            Ok({unit}(value.get({get})?.0))
+        }}
+    }}
+"##,
+            read(&format!("{commands}::{cmd}")),
+        )?;
+    }
+
+    imports.sort();
+
+    //
+    // Only import what the driver uses, lest it be warned about.
+    //
+    let mut uses = String::new();
+    if !part.custom {
+        writeln!(&mut uses, "    use core::cell::Cell;\n")?;
+    }
+    writeln!(&mut uses, "    use crate::{{{}}};", imports.join(", "))?;
+    writeln!(&mut uses, "    use drv_i2c_api::*;")?;
+    if !part.custom {
+        writeln!(&mut uses, "    use pmbus::commands;")?;
+    }
+    if sensed || part.operation || part.vout_max.is_some() || part.page_write {
+        writeln!(&mut uses, "    use pmbus::commands::{commands};")?;
+    }
+    if part.validate.is_some() || part.status.is_some() {
+        writeln!(&mut uses, "    use pmbus::commands::CommandCode;")?;
+    }
+    if sensed || part.vout_max.is_some() {
+        writeln!(&mut uses, "    use userlib::units::*;")?;
+    }
+
+    let mut errors = String::new();
+    for e in &part.errors {
+        if !e.starts_with(|c: char| c.is_ascii_uppercase())
+            || !e.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            bail!("bad error variant {e}");
+        }
+        writeln!(&mut errors, "        {e},")?;
+    }
+    let eq = if part.error_eq { "Eq, " } else { "" };
+
+    //
+    // A custom part has its struct, `new` and `read_mode` written by hand.
+    //
+    let (def, constructor) = if part.custom {
+        (String::new(), String::new())
+    } else {
+        let (rail, rail_arg, rail_init) = if part.paged {
+            ("\n        rail: u8,", "rail", "\n                rail,")
+        } else {
+            ("", "_rail", "")
+        };
+
+        let mode = if paged_mode && rail_paged {
+            "pmbus_rail_read!(self.device, self.rail, commands::VOUT_MODE)"
+        } else {
+            "pmbus_read!(self.device, commands::VOUT_MODE)"
+        };
+        let mode_select = if paged_mode { select } else { "" };
+
+        (
+            format!(
+                r##"
+    pub struct {name} {{
+        device: I2cDevice,{rail}
+        mode: Cell<Option<pmbus::VOutModeCommandData>>,
+    }}
+"##
+            ),
+            format!(
+                r##"
+        pub fn new(device: &I2cDevice, {rail_arg}: u8) -> Self {{
+            {name} {{
+                device: *device,{rail_init}
+                mode: Cell::new(None),
+            }}
+        }}
+
+        pub fn read_mode(&self) -> Result<pmbus::VOutModeCommandData, Error> {{
+            Ok(match self.mode.get() {{
+                None => {{
+                    {mode_select}let mode = {mode}?;
+                    self.mode.set(Some(mode));
+                    mode
+                }}
+                Some(mode) => mode,
+            }})
+        }}
+"##
+            ),
+        )
+    };
+
+    let methods = format!("{constructor}{methods}");
+    let inherent = if methods.is_empty() {
+        String::new()
+    } else {
+        format!("\n    impl {name} {{{methods}    }}\n")
+    };
+
+    let description = &part.description;
+    let (source, extra) = match (&part.extra, extra) {
+        (Some(file), Some(extra)) => (
+            format!(
+                "`pmbus/{module}.toml`, along\n    //! with the \
+                hand-written items in `pmbus/{file}`"
+            ),
+            without_license(extra),
+        ),
+        _ => (format!("`pmbus/{module}.toml`"), ""),
+    };
+
+    Ok(format!(
+        r##"
+pub mod {module} {{
+    //! Driver for the {description}
+    //!
+    //! This driver is generated from {source}.
+
+{uses}
+{status}{def}
+    impl core::fmt::Display for {name} {{
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {{
+            write!(f, "{module}: {{}}", &self.device)
+        }}
+    }}
+
+    #[derive(Copy, Clone, Debug, {eq}PartialEq)]
+    pub enum Error {{
+        BadRead {{ cmd: u8, code: ResponseCode }},
+        BadWrite {{ cmd: u8, code: ResponseCode }},
+        BadData {{ cmd: u8 }},
+        BadValidation {{ cmd: u8, code: ResponseCode }},
+        InvalidData {{ err: pmbus::Error }},
+{errors}    }}
+
+    impl From<BadValidation> for Error {{
+        fn from(value: BadValidation) -> Self {{
+            Self::BadValidation {{
+                cmd: value.cmd,
+                code: value.code,
+            }}
+        }}
+    }}
+
+    impl From<pmbus::Error> for Error {{
+        fn from(err: pmbus::Error) -> Self {{
+            Error::InvalidData {{ err }}
+        }}
+    }}
+
+    impl From<Error> for ResponseCode {{
+        fn from(err: Error) -> Self {{
+            match err {{
+                Error::BadRead {{ code, .. }} => code,
+                Error::BadWrite {{ code, .. }} => code,
+                Error::BadValidation {{ code, .. }} => code,
+                _ => ResponseCode::BadDeviceState,
+            }}
+        }}
+    }}
+{inherent}{impls}
+{extra}}}
+"##
+    ))
+}
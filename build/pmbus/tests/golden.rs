@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checks the drivers generated from the descriptions in `drv-i2c-devices`
+//! against the expected output in `tests/golden`.  If a change to the
+//! generator or to a description is intended, rerun with `UPDATE_GOLDEN=1`
+//! set to rewrite the expected output, and review the difference.
+
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "\
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Generated by build-pmbus; see tests/golden.rs.
+";
+
+fn descriptions() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../drv/i2c-devices/pmbus");
+
+    let mut paths = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|p| p.extension().map_or(false, |e| e == "toml"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn golden() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    let paths = descriptions();
+    assert!(!paths.is_empty());
+
+    let mut mismatched = vec![];
+
+    for path in &paths {
+        let module = path.file_stem().unwrap().to_str().unwrap();
+        let out = tmp.join(format!("{module}.rs"));
+
+        let driver = build_pmbus::generate_part(path).unwrap();
+        std::fs::write(&out, driver).unwrap();
+        call_rustfmt::rustfmt(&out).unwrap();
+
+        let actual =
+            format!("{HEADER}{}", std::fs::read_to_string(&out).unwrap());
+        let expected = golden.join(format!("{module}.rs"));
+
+        if update {
+            std::fs::write(&expected, actual).unwrap();
+        } else if std::fs::read_to_string(&expected).ok() != Some(actual) {
+            mismatched.push(module.to_string());
+        }
+    }
+
+    assert!(
+        mismatched.is_empty(),
+        "generated drivers differ from tests/golden: {mismatched:?}"
+    );
+}
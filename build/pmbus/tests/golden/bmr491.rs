@@ -0,0 +1,173 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Generated by build-pmbus; see tests/golden.rs.
+pub mod bmr491 {
+    //! Driver for the BMR491 IBC
+    //!
+    //! This driver is generated from `pmbus/bmr491.toml`.
+
+    use core::cell::Cell;
+
+    use crate::{
+        pmbus_status, pmbus_validate, BadValidation, CurrentSensor, TempSensor,
+        Validate, VoltageSensor,
+    };
+    use drv_i2c_api::*;
+    use pmbus::commands;
+    use pmbus::commands::bmr491;
+    use pmbus::commands::CommandCode;
+    use userlib::units::*;
+
+    pub const STATUS_CML: u16 = 1 << 1;
+    pub const STATUS_INPUT: u16 = 1 << 13;
+    pub const STATUS_IOUT: u16 = 1 << 14;
+    pub const STATUS_IOUT_OC: u16 = 1 << 4;
+    pub const STATUS_MFR: u16 = 1 << 12;
+    pub const STATUS_OFF: u16 = 1 << 6;
+    pub const STATUS_OTHER: u16 = 1 << 9;
+    pub const STATUS_POWER_GOOD_N: u16 = 1 << 11;
+    pub const STATUS_TEMPERATURE: u16 = 1 << 2;
+    pub const STATUS_VIN_UV: u16 = 1 << 3;
+    pub const STATUS_VOUT: u16 = 1 << 15;
+    pub const STATUS_VOUT_OV: u16 = 1 << 5;
+
+    /// Bits of `STATUS_WORD` supported by this part, and their names
+    pub const STATUS_BITS: &[(u16, &str)] = &[
+        (STATUS_CML, "CML"),
+        (STATUS_INPUT, "INPUT"),
+        (STATUS_IOUT, "IOUT"),
+        (STATUS_IOUT_OC, "IOUT_OC"),
+        (STATUS_MFR, "MFR"),
+        (STATUS_OFF, "OFF"),
+        (STATUS_OTHER, "OTHER"),
+        (STATUS_POWER_GOOD_N, "POWER_GOOD_N"),
+        (STATUS_TEMPERATURE, "TEMPERATURE"),
+        (STATUS_VIN_UV, "VIN_UV"),
+        (STATUS_VOUT, "VOUT"),
+        (STATUS_VOUT_OV, "VOUT_OV"),
+    ];
+
+    pub struct Bmr491 {
+        device: I2cDevice,
+        mode: Cell<Option<pmbus::VOutModeCommandData>>,
+    }
+
+    impl core::fmt::Display for Bmr491 {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "bmr491: {}", &self.device)
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Error {
+        BadRead { cmd: u8, code: ResponseCode },
+        BadWrite { cmd: u8, code: ResponseCode },
+        BadData { cmd: u8 },
+        BadValidation { cmd: u8, code: ResponseCode },
+        InvalidData { err: pmbus::Error },
+    }
+
+    impl From<BadValidation> for Error {
+        fn from(value: BadValidation) -> Self {
+            Self::BadValidation {
+                cmd: value.cmd,
+                code: value.code,
+            }
+        }
+    }
+
+    impl From<pmbus::Error> for Error {
+        fn from(err: pmbus::Error) -> Self {
+            Error::InvalidData { err }
+        }
+    }
+
+    impl From<Error> for ResponseCode {
+        fn from(err: Error) -> Self {
+            match err {
+                Error::BadRead { code, .. } => code,
+                Error::BadWrite { code, .. } => code,
+                Error::BadValidation { code, .. } => code,
+                _ => ResponseCode::BadDeviceState,
+            }
+        }
+    }
+
+    impl Bmr491 {
+        pub fn new(device: &I2cDevice, _rail: u8) -> Self {
+            Bmr491 {
+                device: *device,
+                mode: Cell::new(None),
+            }
+        }
+
+        pub fn read_mode(&self) -> Result<pmbus::VOutModeCommandData, Error> {
+            Ok(match self.mode.get() {
+                None => {
+                    let mode = pmbus_read!(self.device, commands::VOUT_MODE)?;
+                    self.mode.set(Some(mode));
+                    mode
+                }
+                Some(mode) => mode,
+            })
+        }
+
+        ///
+        /// Reads `STATUS_WORD`, whose bits are named in [`STATUS_BITS`].
+        ///
+        pub fn read_status_word(&self) -> Result<u16, Error> {
+            let cmd = CommandCode::STATUS_WORD as u8;
+
+            self.device
+                .read_reg::<u8, u16>(cmd)
+                .map_err(|code| Error::BadRead { cmd, code })
+        }
+
+        ///
+        /// Reads `STATUS_WORD`, along with each `STATUS_*` register that has
+        /// its summary bit set.
+        ///
+        pub fn read_status(
+            &self,
+        ) -> Result<task_power_api::PmbusStatus, Error> {
+            pmbus_status(self.read_status_word()?, |cmd| {
+                let cmd = cmd as u8;
+
+                self.device
+                    .read_reg::<u8, u8>(cmd)
+                    .map_err(|code| Error::BadRead { cmd, code })
+            })
+        }
+    }
+
+    impl Validate<Error> for Bmr491 {
+        fn validate(device: &I2cDevice) -> Result<bool, Error> {
+            let expected = b"Flex";
+            pmbus_validate(device, CommandCode::MFR_ID, expected)
+                .map_err(Into::into)
+        }
+    }
+
+    impl TempSensor<Error> for Bmr491 {
+        fn read_temperature(&self) -> Result<Celsius, Error> {
+            let value = pmbus_read!(self.device, bmr491::READ_TEMPERATURE_1)?;
+            Ok(Celsius(value.get()?.0))
+        }
+    }
+
+    impl CurrentSensor<Error> for Bmr491 {
+        fn read_iout(&self) -> Result<Amperes, Error> {
+            let value = pmbus_read!(self.device, bmr491::READ_IOUT)?;
+            Ok(Amperes(value.get()?.0))
+        }
+    }
+
+    impl VoltageSensor<Error> for Bmr491 {
+        fn read_vout(&self) -> Result<Volts, Error> {
+            let value = pmbus_read!(self.device, bmr491::READ_VOUT)?;
+            Ok(Volts(value.get(self.read_mode()?)?.0))
+        }
+    }
+}
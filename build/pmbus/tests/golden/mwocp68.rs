@@ -0,0 +1,476 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Generated by build-pmbus; see tests/golden.rs.
+pub mod mwocp68 {
+    //! Driver for the MWOCP68-3600 Murata power shelf
+    //!
+    //! This driver is generated from `pmbus/mwocp68.toml`, along
+    //! with the hand-written items in `pmbus/mwocp68.rs`.
+
+    use core::cell::Cell;
+
+    use crate::{
+        pmbus_status, pmbus_validate, BadValidation, CurrentSensor,
+        InputCurrentSensor, InputVoltageSensor, PowerSensor, Validate,
+        VoltageSensor,
+    };
+    use drv_i2c_api::*;
+    use pmbus::commands;
+    use pmbus::commands::mwocp68;
+    use pmbus::commands::CommandCode;
+    use userlib::units::*;
+
+    pub struct Mwocp68 {
+        device: I2cDevice,
+        rail: u8,
+        mode: Cell<Option<pmbus::VOutModeCommandData>>,
+    }
+
+    impl core::fmt::Display for Mwocp68 {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "mwocp68: {}", &self.device)
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Error {
+        BadRead { cmd: u8, code: ResponseCode },
+        BadWrite { cmd: u8, code: ResponseCode },
+        BadData { cmd: u8 },
+        BadValidation { cmd: u8, code: ResponseCode },
+        InvalidData { err: pmbus::Error },
+    }
+
+    impl From<BadValidation> for Error {
+        fn from(value: BadValidation) -> Self {
+            Self::BadValidation {
+                cmd: value.cmd,
+                code: value.code,
+            }
+        }
+    }
+
+    impl From<pmbus::Error> for Error {
+        fn from(err: pmbus::Error) -> Self {
+            Error::InvalidData { err }
+        }
+    }
+
+    impl From<Error> for ResponseCode {
+        fn from(err: Error) -> Self {
+            match err {
+                Error::BadRead { code, .. } => code,
+                Error::BadWrite { code, .. } => code,
+                Error::BadValidation { code, .. } => code,
+                _ => ResponseCode::BadDeviceState,
+            }
+        }
+    }
+
+    impl Mwocp68 {
+        pub fn new(device: &I2cDevice, rail: u8) -> Self {
+            Mwocp68 {
+                device: *device,
+                rail,
+                mode: Cell::new(None),
+            }
+        }
+
+        pub fn read_mode(&self) -> Result<pmbus::VOutModeCommandData, Error> {
+            Ok(match self.mode.get() {
+                None => {
+                    let mode = pmbus_read!(self.device, commands::VOUT_MODE)?;
+                    self.mode.set(Some(mode));
+                    mode
+                }
+                Some(mode) => mode,
+            })
+        }
+
+        fn set_rail(&self) -> Result<(), Error> {
+            let page = mwocp68::PAGE::CommandData(self.rail);
+            pmbus_write!(self.device, mwocp68::PAGE, page)
+        }
+
+        ///
+        /// Reads `STATUS_WORD`.
+        ///
+        pub fn read_status_word(&self) -> Result<u16, Error> {
+            self.set_rail()?;
+            let cmd = CommandCode::STATUS_WORD as u8;
+
+            self.device
+                .read_reg::<u8, u16>(cmd)
+                .map_err(|code| Error::BadRead { cmd, code })
+        }
+
+        ///
+        /// Reads `STATUS_WORD`, along with each `STATUS_*` register that has
+        /// its summary bit set.
+        ///
+        pub fn read_status(
+            &self,
+        ) -> Result<task_power_api::PmbusStatus, Error> {
+            pmbus_status(self.read_status_word()?, |cmd| {
+                let cmd = cmd as u8;
+
+                self.device
+                    .read_reg::<u8, u8>(cmd)
+                    .map_err(|code| Error::BadRead { cmd, code })
+            })
+        }
+    }
+
+    impl Validate<Error> for Mwocp68 {
+        fn validate(device: &I2cDevice) -> Result<bool, Error> {
+            let expected = b"MWOCP68-3600-D-RM";
+            pmbus_validate(device, CommandCode::MFR_MODEL, expected)
+                .map_err(Into::into)
+        }
+    }
+
+    impl CurrentSensor<Error> for Mwocp68 {
+        fn read_iout(&self) -> Result<Amperes, Error> {
+            self.set_rail()?;
+            let value = pmbus_read!(self.device, mwocp68::READ_IOUT)?;
+            Ok(Amperes(value.get()?.0))
+        }
+    }
+
+    impl VoltageSensor<Error> for Mwocp68 {
+        fn read_vout(&self) -> Result<Volts, Error> {
+            self.set_rail()?;
+            let value = pmbus_read!(self.device, mwocp68::READ_VOUT)?;
+            Ok(Volts(value.get(self.read_mode()?)?.0))
+        }
+    }
+
+    impl InputCurrentSensor<Error> for Mwocp68 {
+        fn read_iin(&self) -> Result<Amperes, Error> {
+            self.set_rail()?;
+            let value = pmbus_read!(self.device, mwocp68::READ_IIN)?;
+            Ok(Amperes(value.get()?.0))
+        }
+    }
+
+    impl InputVoltageSensor<Error> for Mwocp68 {
+        fn read_vin(&self) -> Result<Volts, Error> {
+            self.set_rail()?;
+            let value = pmbus_read!(self.device, mwocp68::READ_VIN)?;
+            Ok(Volts(value.get()?.0))
+        }
+    }
+
+    impl PowerSensor<Error> for Mwocp68 {
+        fn read_power(&mut self) -> Result<Watts, Error> {
+            self.set_rail()?;
+            let value = pmbus_read!(self.device, mwocp68::READ_PIN)?;
+            Ok(Watts(value.get()?.0))
+        }
+    }
+
+    //
+    // Beyond the rail's voltage and current, the shelf has temperature sensors
+    // and fans that aren't associated with a rail.  These are read by the same
+    // index as the rail: the sensor index when reading temperature (0-2) or fan
+    // speed (0-1).
+    //
+
+    use task_power_api::PmbusValue;
+
+    impl Mwocp68 {
+        pub fn read_temperature(&self) -> Result<pmbus::units::Celsius, Error> {
+            // Temperatures are accessible on all pages
+            let r = match self.rail {
+                0 => pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_1)?
+                    .get()?,
+                1 => pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_2)?
+                    .get()?,
+                2 => pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_3)?
+                    .get()?,
+                _ => {
+                    return Err(Error::InvalidData {
+                        err: pmbus::Error::InvalidCode,
+                    })
+                }
+            };
+            Ok(r)
+        }
+
+        pub fn read_speed(&self) -> Result<pmbus::units::Rpm, Error> {
+            let r = match self.rail {
+                0 => pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_1)?
+                    .get()?,
+                1 => pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_2)?
+                    .get()?,
+                _ => {
+                    return Err(Error::InvalidData {
+                        err: pmbus::Error::InvalidCode,
+                    })
+                }
+            };
+            Ok(r)
+        }
+
+        #[inline(always)]
+        fn read_block<const N: usize>(
+            &self,
+            cmd: CommandCode,
+        ) -> Result<PmbusValue, Error> {
+            // We can't use static_assertions with const generics (yet), so use
+            // a regular assert and hope that the compiler removes it since both
+            // of these are known constants.
+            assert!(N <= task_power_api::MAX_BLOCK_LEN);
+
+            // Pass through to the non-generic implementation.
+            self.read_block_impl(cmd, N)
+        }
+
+        #[inline(never)]
+        fn read_block_impl(
+            &self,
+            cmd: CommandCode,
+            len: usize,
+        ) -> Result<PmbusValue, Error> {
+            let cmd = cmd as u8;
+            let mut data = [0; task_power_api::MAX_BLOCK_LEN];
+            let len = self
+                .device
+                .read_block(cmd, &mut data[..len])
+                .map_err(|code| Error::BadRead { cmd, code })?;
+            Ok(PmbusValue::Block {
+                data,
+                len: len as u8,
+            })
+        }
+
+        pub fn pmbus_read(
+            &self,
+            op: task_power_api::Operation,
+        ) -> Result<PmbusValue, Error> {
+            use task_power_api::Operation;
+
+            self.set_rail()?;
+
+            let val = match op {
+                Operation::FanConfig1_2 => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::FAN_CONFIG_1_2)?
+                            .raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::FanCommand1 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::FAN_COMMAND_1)?.get()?,
+                ),
+                Operation::FanCommand2 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::FAN_COMMAND_1)?.get()?,
+                ),
+                Operation::IoutOcFaultLimit => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::IOUT_OC_FAULT_LIMIT)?
+                        .get()?,
+                ),
+                Operation::IoutOcWarnLimit => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::IOUT_OC_WARN_LIMIT)?
+                        .get()?,
+                ),
+                Operation::OtWarnLimit => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::OT_WARN_LIMIT)?.get()?,
+                ),
+                Operation::IinOcWarnLimit => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::IIN_OC_WARN_LIMIT)?
+                        .get()?,
+                ),
+                Operation::PoutOpWarnLimit => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::POUT_OP_WARN_LIMIT)?
+                        .get()?,
+                ),
+                Operation::PinOpWarnLimit => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::PIN_OP_WARN_LIMIT)?
+                        .get()?,
+                ),
+                Operation::StatusByte => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_BYTE)?.raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusWord => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_WORD)?.raw();
+                    assert_eq!(width.0, 16);
+                    PmbusValue::Raw16(val as u16)
+                }
+                Operation::StatusVout => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_VOUT)?.raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusIout => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_IOUT)?.raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusInput => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_INPUT)?.raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusTemperature => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_TEMPERATURE)?
+                            .raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusCml => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_CML)?.raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusMfrSpecific => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_MFR_SPECIFIC)?
+                            .raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::StatusFans1_2 => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::STATUS_FANS_1_2)?
+                            .raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::ReadEin => {
+                    self.read_block::<6>(CommandCode::READ_EIN)?
+                }
+                Operation::ReadEout => {
+                    self.read_block::<6>(CommandCode::READ_EOUT)?
+                }
+                Operation::ReadVin => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_VIN)?.get()?,
+                ),
+                Operation::ReadIin => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_IIN)?.get()?,
+                ),
+                Operation::ReadVcap => {
+                    let vcap = pmbus_read!(self.device, mwocp68::READ_VCAP)?;
+                    PmbusValue::from(vcap.get(self.read_mode()?)?)
+                }
+                Operation::ReadVout => {
+                    let vout = pmbus_read!(self.device, mwocp68::READ_VOUT)?;
+                    PmbusValue::from(vout.get(self.read_mode()?)?)
+                }
+                Operation::ReadIout => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_IOUT)?.get()?,
+                ),
+                Operation::ReadTemperature1 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_1)?
+                        .get()?,
+                ),
+                Operation::ReadTemperature2 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_2)?
+                        .get()?,
+                ),
+                Operation::ReadTemperature3 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_3)?
+                        .get()?,
+                ),
+                Operation::ReadFanSpeed1 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_1)?
+                        .get()?,
+                ),
+                Operation::ReadFanSpeed2 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_2)?
+                        .get()?,
+                ),
+                Operation::ReadPout => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_POUT)?.get()?,
+                ),
+                Operation::ReadPin => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::READ_PIN)?.get()?,
+                ),
+                Operation::PmbusRevision => {
+                    let (val, width) =
+                        pmbus_read!(self.device, mwocp68::PMBUS_REVISION)?
+                            .raw();
+                    assert_eq!(width.0, 8);
+                    PmbusValue::Raw8(val as u8)
+                }
+                Operation::MfrId => {
+                    self.read_block::<9>(CommandCode::MFR_ID)?
+                }
+                Operation::MfrModel => {
+                    self.read_block::<17>(CommandCode::MFR_MODEL)?
+                }
+                Operation::MfrRevision => {
+                    self.read_block::<14>(CommandCode::MFR_REVISION)?
+                }
+                Operation::MfrLocation => {
+                    self.read_block::<5>(CommandCode::MFR_LOCATION)?
+                }
+                Operation::MfrDate => {
+                    self.read_block::<4>(CommandCode::MFR_DATE)?
+                }
+                Operation::MfrSerial => {
+                    self.read_block::<12>(CommandCode::MFR_SERIAL)?
+                }
+                Operation::MfrVinMin => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_VIN_MIN)?.get()?,
+                ),
+                Operation::MfrVinMax => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_VIN_MAX)?.get()?,
+                ),
+                Operation::MfrIinMax => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_IIN_MAX)?.get()?,
+                ),
+                Operation::MfrPinMax => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_PIN_MAX)?.get()?,
+                ),
+                Operation::MfrVoutMin => {
+                    let vout = pmbus_read!(self.device, mwocp68::MFR_VOUT_MIN)?;
+                    PmbusValue::from(vout.get(self.read_mode()?)?)
+                }
+                Operation::MfrVoutMax => {
+                    let vout = pmbus_read!(self.device, mwocp68::MFR_VOUT_MAX)?;
+                    PmbusValue::from(vout.get(self.read_mode()?)?)
+                }
+                Operation::MfrIoutMax => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_IOUT_MAX)?.get()?,
+                ),
+                Operation::MfrPoutMax => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_POUT_MAX)?.get()?,
+                ),
+                Operation::MfrTambientMax => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_TAMBIENT_MAX)?
+                        .get()?,
+                ),
+                Operation::MfrTambientMin => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_TAMBIENT_MIN)?
+                        .get()?,
+                ),
+                Operation::MfrEfficiencyHl => {
+                    self.read_block::<14>(CommandCode::MFR_EFFICIENCY_HL)?
+                }
+                Operation::MfrMaxTemp1 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_MAX_TEMP_1)?.get()?,
+                ),
+                Operation::MfrMaxTemp2 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_MAX_TEMP_2)?.get()?,
+                ),
+                Operation::MfrMaxTemp3 => PmbusValue::from(
+                    pmbus_read!(self.device, mwocp68::MFR_MAX_TEMP_3)?.get()?,
+                ),
+            };
+
+            Ok(val)
+        }
+    }
+}
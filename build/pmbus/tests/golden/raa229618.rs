@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Generated by build-pmbus; see tests/golden.rs.
+pub mod raa229618 {
+    //! Driver for the RAA229618 power controller
+    //!
+    //! This driver is generated from `pmbus/raa229618.toml`.
+
+    use core::cell::Cell;
+
+    use crate::{
+        pmbus_status, pmbus_validate, BadValidation, CurrentSensor, TempSensor,
+        Validate, VoltageSensor,
+    };
+    use drv_i2c_api::*;
+    use pmbus::commands;
+    use pmbus::commands::raa229618;
+    use pmbus::commands::CommandCode;
+    use userlib::units::*;
+
+    pub const STATUS_CML: u16 = 1 << 1;
+    pub const STATUS_INPUT: u16 = 1 << 13;
+    pub const STATUS_IOUT: u16 = 1 << 14;
+    pub const STATUS_IOUT_OC: u16 = 1 << 4;
+    pub const STATUS_MFR: u16 = 1 << 12;
+    pub const STATUS_OFF: u16 = 1 << 6;
+    pub const STATUS_OTHER: u16 = 1 << 9;
+    pub const STATUS_POWER_GOOD_N: u16 = 1 << 11;
+    pub const STATUS_TEMPERATURE: u16 = 1 << 2;
+    pub const STATUS_VIN_UV: u16 = 1 << 3;
+    pub const STATUS_VOUT: u16 = 1 << 15;
+    pub const STATUS_VOUT_OV: u16 = 1 << 5;
+
+    /// Bits of `STATUS_WORD` supported by this part, and their names
+    pub const STATUS_BITS: &[(u16, &str)] = &[
+        (STATUS_CML, "CML"),
+        (STATUS_INPUT, "INPUT"),
+        (STATUS_IOUT, "IOUT"),
+        (STATUS_IOUT_OC, "IOUT_OC"),
+        (STATUS_MFR, "MFR"),
+        (STATUS_OFF, "OFF"),
+        (STATUS_OTHER, "OTHER"),
+        (STATUS_POWER_GOOD_N, "POWER_GOOD_N"),
+        (STATUS_TEMPERATURE, "TEMPERATURE"),
+        (STATUS_VIN_UV, "VIN_UV"),
+        (STATUS_VOUT, "VOUT"),
+        (STATUS_VOUT_OV, "VOUT_OV"),
+    ];
+
+    pub struct Raa229618 {
+        device: I2cDevice,
+        rail: u8,
+        mode: Cell<Option<pmbus::VOutModeCommandData>>,
+    }
+
+    impl core::fmt::Display for Raa229618 {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "raa229618: {}", &self.device)
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Error {
+        BadRead { cmd: u8, code: ResponseCode },
+        BadWrite { cmd: u8, code: ResponseCode },
+        BadData { cmd: u8 },
+        BadValidation { cmd: u8, code: ResponseCode },
+        InvalidData { err: pmbus::Error },
+    }
+
+    impl From<BadValidation> for Error {
+        fn from(value: BadValidation) -> Self {
+            Self::BadValidation {
+                cmd: value.cmd,
+                code: value.code,
+            }
+        }
+    }
+
+    impl From<pmbus::Error> for Error {
+        fn from(err: pmbus::Error) -> Self {
+            Error::InvalidData { err }
+        }
+    }
+
+    impl From<Error> for ResponseCode {
+        fn from(err: Error) -> Self {
+            match err {
+                Error::BadRead { code, .. } => code,
+                Error::BadWrite { code, .. } => code,
+                Error::BadValidation { code, .. } => code,
+                _ => ResponseCode::BadDeviceState,
+            }
+        }
+    }
+
+    impl Raa229618 {
+        pub fn new(device: &I2cDevice, rail: u8) -> Self {
+            Raa229618 {
+                device: *device,
+                rail,
+                mode: Cell::new(None),
+            }
+        }
+
+        pub fn read_mode(&self) -> Result<pmbus::VOutModeCommandData, Error> {
+            Ok(match self.mode.get() {
+                None => {
+                    let mode = pmbus_rail_read!(
+                        self.device,
+                        self.rail,
+                        commands::VOUT_MODE
+                    )?;
+                    self.mode.set(Some(mode));
+                    mode
+                }
+                Some(mode) => mode,
+            })
+        }
+
+        pub fn turn_off(&mut self) -> Result<(), Error> {
+            let mut op =
+                pmbus_rail_read!(self.device, self.rail, raa229618::OPERATION)?;
+            op.set_on_off_state(raa229618::OPERATION::OnOffState::Off);
+            pmbus_rail_write!(self.device, self.rail, raa229618::OPERATION, op)
+        }
+
+        pub fn turn_on(&mut self) -> Result<(), Error> {
+            let mut op =
+                pmbus_rail_read!(self.device, self.rail, raa229618::OPERATION)?;
+            op.set_on_off_state(raa229618::OPERATION::OnOffState::On);
+            pmbus_rail_write!(self.device, self.rail, raa229618::OPERATION, op)
+        }
+
+        pub fn set_vout(&mut self, value: Volts) -> Result<(), Error> {
+            if value > Volts(3.05) {
+                Err(Error::InvalidData {
+                    err: pmbus::Error::ValueOutOfRange,
+                })
+            } else {
+                let mut vout = raa229618::VOUT_COMMAND::CommandData(0);
+                vout.set(self.read_mode()?, pmbus::units::Volts(value.0))?;
+                pmbus_rail_write!(
+                    self.device,
+                    self.rail,
+                    raa229618::VOUT_COMMAND,
+                    vout
+                )
+            }
+        }
+
+        ///
+        /// Reads `STATUS_WORD`, whose bits are named in [`STATUS_BITS`].
+        ///
+        pub fn read_status_word(&self) -> Result<u16, Error> {
+            let cmd = CommandCode::STATUS_WORD as u8;
+
+            self.device
+                .write_read_reg::<u8, u16>(
+                    cmd,
+                    &[CommandCode::PAGE as u8, self.rail],
+                )
+                .map_err(|code| Error::BadRead { cmd, code })
+        }
+
+        ///
+        /// Reads `STATUS_WORD`, along with each `STATUS_*` register that has
+        /// its summary bit set.
+        ///
+        pub fn read_status(
+            &self,
+        ) -> Result<task_power_api::PmbusStatus, Error> {
+            pmbus_status(self.read_status_word()?, |cmd| {
+                let cmd = cmd as u8;
+
+                self.device
+                    .write_read_reg::<u8, u8>(
+                        cmd,
+                        &[CommandCode::PAGE as u8, self.rail],
+                    )
+                    .map_err(|code| Error::BadRead { cmd, code })
+            })
+        }
+    }
+
+    impl Validate<Error> for Raa229618 {
+        fn validate(device: &I2cDevice) -> Result<bool, Error> {
+            let expected = &[0x00, 0x99, 0xd2, 0x49];
+            pmbus_validate(device, CommandCode::IC_DEVICE_ID, expected)
+                .map_err(Into::into)
+        }
+    }
+
+    impl TempSensor<Error> for Raa229618 {
+        fn read_temperature(&self) -> Result<Celsius, Error> {
+            let value = pmbus_rail_read!(
+                self.device,
+                self.rail,
+                raa229618::READ_TEMPERATURE_1
+            )?;
+            Ok(Celsius(value.get()?.0))
+        }
+    }
+
+    impl CurrentSensor<Error> for Raa229618 {
+        fn read_iout(&self) -> Result<Amperes, Error> {
+            let value =
+                pmbus_rail_read!(self.device, self.rail, raa229618::READ_IOUT)?;
+            Ok(Amperes(value.get()?.0))
+        }
+    }
+
+    impl VoltageSensor<Error> for Raa229618 {
+        fn read_vout(&self) -> Result<Volts, Error> {
+            let value =
+                pmbus_rail_read!(self.device, self.rail, raa229618::READ_VOUT)?;
+            Ok(Volts(value.get(self.read_mode()?)?.0))
+        }
+    }
+}
@@ -71,6 +71,14 @@ SECTIONS
     KEEP(*(.idolatry));
   }
 
+  /* ## .stack_sizes */
+  /* Per-function stack frame sizes from `-Z emit-stack-sizes`, used for
+     static stack analysis during packaging. */
+  .stack_sizes (INFO) : {
+    . = .;
+    KEEP(*(.stack_sizes));
+  }
+
   /* ## Discarded sections */
   /DISCARD/ :
   {
@@ -14,31 +14,67 @@ use serde::{Deserialize, Serialize};
 use crate::auxflash::{build_auxflash, AuxFlash, AuxFlashData};
 use lpc55_areas::{DebugSettings, DefaultIsp, ROTKeyStatus};
 
-/// A `PatchedConfig` allows a minimal form of inheritance between TOML files
-/// Specifically, it allows you to **add features** to specific tasks; nothing
-/// else.
+/// `ConfigPatches` are the directives applied by an `app.toml` file which
+/// inherits from one or more other TOML files.
 ///
-/// Here's an example:
+/// An inheriting file names its parents in a top-level `inherit` key, which
+/// may be either a single path or a list of paths (relative to the inheriting
+/// file).  Parents are loaded recursively and merged in order, so a file can
+/// pull in a complete base configuration followed by smaller fragments (e.g.
+/// an I2C topology, a network config, or a set of tasks).  Fragments need not
+/// be complete configurations; only the fully-merged result must be.
+///
+/// Merging is done table-by-table: tables are merged recursively, while any
+/// other value (including arrays and arrays of tables) in a later file
+/// replaces the value from an earlier file.  Keys in the inheriting file
+/// itself are merged last.
+///
+/// The `[patches]` table then allows for changes that can't be expressed by
+/// merging alone:
 /// ```toml
-/// name = "sidecar-c-lab"
+/// inherit = ["rev-c.toml", "lab-net.toml"]
 ///
 /// [patches]
-/// inherit = "rev-c.toml"
+/// name = "sidecar-c-lab"
 /// features.sequencer = ["stay-in-a2"]
+/// remove-features.net = ["vlan"]
+/// remove = ["tasks.udpecho", "config.net.sockets.echo"]
 /// ```
+///
+/// `remove` deletes (dotted) keys from the inherited configuration before the
+/// inheriting file is merged, which allows for a table to be replaced
+/// wholesale instead of merged.  `features` and `remove-features` add and
+/// remove individual features from tasks after merging.
+///
+/// Paths within the merged configuration (e.g. `chip`) are relative to the
+/// top-level `app.toml` file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigPatches {
+    name: Option<String>,
+    #[serde(default)]
+    features: IndexMap<String, Vec<String>>,
+    #[serde(default)]
+    remove_features: IndexMap<String, Vec<String>>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+/// Values for the `inherit` key, which may be a single file or a list
 #[derive(Clone, Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct PatchedConfig {
-    inherit: String,
-    patches: ConfigPatches,
+#[serde(untagged)]
+enum Inherit {
+    One(String),
+    Many(Vec<String>),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct ConfigPatches {
-    name: String,
-    #[serde(default)]
-    features: IndexMap<String, Vec<String>>,
+impl Inherit {
+    fn files(&self) -> &[String] {
+        match self {
+            Inherit::One(f) => std::slice::from_ref(f),
+            Inherit::Many(fs) => fs,
+        }
+    }
 }
 
 /// A `RawConfig` represents an `app.toml` file that has been deserialized,
@@ -62,8 +98,10 @@ struct RawConfig {
     external_images: Vec<String>,
     #[serde(default)]
     signing: Option<RoTMfgSettings>,
+    image_signing: Option<ImageSigning>,
     secure_separation: Option<bool>,
     stacksize: Option<u32>,
+    stack_margin: Option<u32>,
     kernel: Kernel,
     tasks: IndexMap<String, Task>,
     #[serde(default)]
@@ -85,8 +123,13 @@ pub struct Config {
     pub image_names: Vec<String>,
     pub external_images: Vec<String>,
     pub signing: Option<RoTMfgSettings>,
+    pub image_signing: Option<ImageSigning>,
     pub secure_separation: Option<bool>,
     pub stacksize: Option<u32>,
+    /// If present, tasks are built with frame sizes recorded, and the build
+    /// fails unless every task's worst-case stack usage can be bounded and
+    /// its stack size exceeds that by at least this many bytes
+    pub stack_margin: Option<u32>,
     pub kernel: Kernel,
     pub outputs: IndexMap<String, Vec<Output>>,
     pub tasks: IndexMap<String, Task>,
@@ -95,7 +138,6 @@ pub struct Config {
     pub config: Option<ordered_toml::Value>,
     pub buildhash: u64,
     pub app_toml_path: PathBuf,
-    pub patches: Option<ConfigPatches>,
     pub secure_task: Option<String>,
     pub auxflash: Option<AuxFlashData>,
     pub dice_mfg: Option<Output>,
@@ -126,46 +168,12 @@ pub struct CabooseConfig {
 
 impl Config {
     pub fn from_file(cfg: &Path) -> Result<Self> {
-        Self::from_file_with_hasher(cfg, DefaultHasher::new())
-    }
-
-    fn from_file_with_hasher(
-        cfg: &Path,
-        mut hasher: DefaultHasher,
-    ) -> Result<Self> {
-        let cfg_contents = std::fs::read(&cfg)
-            .with_context(|| format!("could not read {}", cfg.display()))?;
-
-        // Accumulate the contents into the buildhash here, so that we hash both
-        // the inheritance file and the target if this is an `PatchedConfig`
-        hasher.write(&cfg_contents);
-
-        // Minimal TOML file inheritance, to enable features on a per-task basis
-        if let Ok(inherited) =
-            toml::from_str::<PatchedConfig>(std::str::from_utf8(&cfg_contents)?)
-        {
-            let file = cfg.parent().unwrap().join(&inherited.inherit);
-            let mut original = Config::from_file_with_hasher(&file, hasher)
-                .context(format!("Could not load template from {file:?}"))?;
-            original.name = inherited.patches.name.to_owned();
-            for (task, features) in &inherited.patches.features {
-                let t = original
-                    .tasks
-                    .get_mut(task)
-                    .ok_or_else(|| anyhow!("No such task {task}"))?;
-                for f in features {
-                    if t.features.contains(f) {
-                        bail!("Task {task} already contains feature {f}");
-                    }
-                    t.features.push(f.to_owned());
-                }
-            }
-            original.patches = Some(inherited.patches);
-            return Ok(original);
-        }
+        // Accumulate the contents of every file into the buildhash, so that
+        // we hash both the target and any files that it inherits from.
+        let mut hasher = DefaultHasher::new();
+        let cfg_contents = expand_toml(cfg, &mut hasher)?;
 
-        let toml: RawConfig =
-            toml::from_str(std::str::from_utf8(&cfg_contents)?)?;
+        let toml: RawConfig = toml::from_str(&cfg_contents)?;
         if toml.tasks.contains_key("kernel") {
             bail!("'kernel' is reserved and cannot be used as a task name");
         }
@@ -226,8 +234,10 @@ impl Config {
             epoch: toml.epoch,
             version: toml.version,
             signing: toml.signing,
+            image_signing: toml.image_signing,
             secure_separation: toml.secure_separation,
             stacksize: toml.stacksize,
+            stack_margin: toml.stack_margin,
             kernel: toml.kernel,
             outputs,
             tasks: toml.tasks,
@@ -237,7 +247,6 @@ impl Config {
             auxflash,
             buildhash,
             app_toml_path: cfg.to_owned(),
-            patches: None,
             secure_task: toml.secure_task,
             dice_mfg,
             caboose: toml.caboose,
@@ -562,6 +571,194 @@ impl Config {
     }
 }
 
+/// Returns the contents of the given `app.toml` file, with inheritance
+/// resolved and patches applied
+///
+/// If the file does not inherit from anything, its contents are returned
+/// verbatim.
+pub fn expanded_toml(cfg: &Path) -> Result<String> {
+    expand_toml(cfg, &mut DefaultHasher::new())
+}
+
+fn expand_toml(cfg: &Path, hasher: &mut DefaultHasher) -> Result<String> {
+    let (table, inherited) = load_toml(cfg, hasher, &mut vec![])?;
+    if inherited {
+        ordered_toml::to_string(&ordered_toml::Value::Table(table))
+            .with_context(|| format!("could not expand {}", cfg.display()))
+    } else {
+        std::fs::read_to_string(cfg)
+            .with_context(|| format!("could not read {}", cfg.display()))
+    }
+}
+
+/// Loads a TOML file, recursively loading and merging any files that it
+/// inherits from (see [`ConfigPatches`] for details).
+///
+/// `stack` is the chain of files currently being loaded, which is used to
+/// detect circular inheritance.  Returns the merged table and a flag
+/// indicating whether any inheritance or patching took place.
+fn load_toml(
+    cfg: &Path,
+    hasher: &mut DefaultHasher,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(ordered_toml::value::Table, bool)> {
+    let cfg_contents = std::fs::read(cfg)
+        .with_context(|| format!("could not read {}", cfg.display()))?;
+    hasher.write(&cfg_contents);
+
+    let canonical = cfg.canonicalize()?;
+    if stack.contains(&canonical) {
+        bail!("circular inheritance: {} inherits itself", cfg.display());
+    }
+
+    let mut table: ordered_toml::value::Table =
+        ordered_toml::from_str(std::str::from_utf8(&cfg_contents)?)
+            .with_context(|| format!("could not parse {}", cfg.display()))?;
+
+    let inherit: Option<Inherit> = remove_key(&mut table, "inherit")
+        .map(|v| v.try_into())
+        .transpose()
+        .with_context(|| format!("invalid `inherit` in {}", cfg.display()))?;
+    let patches: Option<ConfigPatches> = remove_key(&mut table, "patches")
+        .map(|v| v.try_into())
+        .transpose()
+        .with_context(|| format!("invalid `patches` in {}", cfg.display()))?;
+    if inherit.is_none() && patches.is_none() {
+        return Ok((table, false));
+    }
+
+    let mut merged = ordered_toml::value::Table::new();
+    if let Some(inherit) = inherit {
+        stack.push(canonical);
+        for f in inherit.files() {
+            let file = cfg.parent().unwrap().join(f);
+            let (parent, _) =
+                load_toml(&file, hasher, stack).with_context(|| {
+                    format!("could not load template from {file:?}")
+                })?;
+            merge_toml(&mut merged, parent);
+        }
+        stack.pop();
+    }
+
+    let patches = patches.unwrap_or_default();
+    for path in &patches.remove {
+        remove_path(&mut merged, path).with_context(|| {
+            format!("could not apply patches in {}", cfg.display())
+        })?;
+    }
+    merge_toml(&mut merged, table);
+
+    if let Some(name) = &patches.name {
+        merged.insert(
+            "name".to_string(),
+            ordered_toml::Value::String(name.to_owned()),
+        );
+    }
+    for (task, features) in &patches.features {
+        let t = task_features(&mut merged, task)?;
+        for f in features {
+            if t.iter().any(|v| v.as_str() == Some(f)) {
+                bail!("Task {task} already contains feature {f}");
+            }
+            t.push(ordered_toml::Value::String(f.to_owned()));
+        }
+    }
+    for (task, features) in &patches.remove_features {
+        let t = task_features(&mut merged, task)?;
+        for f in features {
+            let prev_len = t.len();
+            t.retain(|v| v.as_str() != Some(f));
+            if t.len() == prev_len {
+                bail!("Task {task} does not contain feature {f}");
+            }
+        }
+    }
+
+    Ok((merged, true))
+}
+
+/// Merges `overlay` into `base`
+///
+/// Tables are merged recursively; any other value in `overlay` replaces the
+/// corresponding value in `base`.
+fn merge_toml(
+    base: &mut ordered_toml::value::Table,
+    overlay: ordered_toml::value::Table,
+) {
+    for (k, v) in overlay {
+        match v {
+            ordered_toml::Value::Table(o) => {
+                if let Some(ordered_toml::Value::Table(b)) = base.get_mut(&k) {
+                    merge_toml(b, o);
+                } else {
+                    base.insert(k, ordered_toml::Value::Table(o));
+                }
+            }
+            v => {
+                base.insert(k, v);
+            }
+        }
+    }
+}
+
+/// Removes a key from a table, preserving the order of the remaining keys
+fn remove_key(
+    table: &mut ordered_toml::value::Table,
+    key: &str,
+) -> Option<ordered_toml::Value> {
+    let mut out = None;
+    *table = std::mem::replace(table, ordered_toml::value::Table::new())
+        .into_iter()
+        .filter_map(|(k, v)| {
+            if k == key {
+                out = Some(v);
+                None
+            } else {
+                Some((k, v))
+            }
+        })
+        .collect();
+    out
+}
+
+/// Removes a dotted key (e.g. `config.net.sockets.echo`) from a table
+fn remove_path(
+    table: &mut ordered_toml::value::Table,
+    path: &str,
+) -> Result<()> {
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let last = keys.pop().unwrap();
+    let mut t = table;
+    for k in keys {
+        t = t
+            .get_mut(k)
+            .and_then(ordered_toml::Value::as_table_mut)
+            .ok_or_else(|| anyhow!("cannot remove {path}: no table {k}"))?;
+    }
+    remove_key(t, last).ok_or_else(|| anyhow!("cannot remove {path}"))?;
+    Ok(())
+}
+
+/// Returns the `features` array for the given task, creating it if needed
+fn task_features<'a>(
+    table: &'a mut ordered_toml::value::Table,
+    task: &str,
+) -> Result<&'a mut Vec<ordered_toml::Value>> {
+    let t = table
+        .get_mut("tasks")
+        .and_then(ordered_toml::Value::as_table_mut)
+        .and_then(|t| t.get_mut(task))
+        .and_then(ordered_toml::Value::as_table_mut)
+        .ok_or_else(|| anyhow!("No such task {task}"))?;
+    if !t.contains_key("features") {
+        t.insert("features".to_string(), ordered_toml::Value::Array(vec![]));
+    }
+    t.get_mut("features")
+        .and_then(ordered_toml::Value::as_array_mut)
+        .ok_or_else(|| anyhow!("features for task {task} must be an array"))
+}
+
 /// Represents an MPU's desired alignment strategy
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum MpuAlignment {
@@ -616,6 +813,16 @@ pub struct RoTMfgSettings {
     pub boot_error_gpio: RoTBootErrorPin,
 }
 
+/// Settings for a detached signature over the final image (see
+/// `signing::sign_archive`), which may be used for any image
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ImageSigning {
+    /// Path to a file containing a hex-encoded Ed25519 seed, relative to the
+    /// `app.toml` file
+    pub private_key: PathBuf,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct RoTBootErrorPin {
@@ -19,10 +19,10 @@ use path_slash::{PathBufExt, PathExt};
 use zerocopy::AsBytes;
 
 use crate::{
-    config::{BuildConfig, CabooseConfig, Config, ConfigPatches},
+    config::{BuildConfig, CabooseConfig, Config},
     elf,
     sizes::load_task_size,
-    task_slot,
+    stack, task_slot,
 };
 
 /// In practice, applications with active interrupt activity tend to use about
@@ -40,12 +40,9 @@ pub struct PackageConfig {
     /// Path to the `app.toml` file being built
     ///
     /// If this app is built using inheritance, `app_toml_file` refers to the
-    /// **root** TOML file (and patches are in `self.patches`)
+    /// top-level TOML file, which may be incomplete on its own.
     app_toml_file: PathBuf,
 
-    /// Patches from TOML inheritance mechanism
-    patches: Option<ConfigPatches>,
-
     /// Directory containing the `app.toml` file being built
     app_src_dir: PathBuf,
 
@@ -120,7 +117,6 @@ impl PackageConfig {
 
         Ok(Self {
             app_toml_file: toml.app_toml_path.to_path_buf(),
-            patches: toml.patches.clone(),
             app_src_dir: app_src_dir.to_path_buf(),
             toml,
             verbose,
@@ -535,6 +531,17 @@ pub fn package(
             archive.overwrite()?;
         }
 
+        // Post-build modifications: add a detached signature over the final
+        // image.  This must be last, since it covers the caboose and any
+        // changes made by RoT signing.
+        if let Some(signing) = &cfg.toml.image_signing {
+            crate::signing::sign_archive(
+                &archive_name,
+                &cfg.app_src_dir.join(&signing.private_key),
+            )
+            .context("could not sign archive")?;
+        }
+
         // Unzip the signed + caboose'd images into our build directory
         let archive = hubtools::RawHubrisArchive::load(&archive_name)?;
         for ext in ["elf", "bin"] {
@@ -715,16 +722,12 @@ fn build_archive(
         "git-rev",
         format!("{}{}", git_rev, if git_dirty { "-dirty" } else { "" }),
     )?;
-    archive.copy(&cfg.app_toml_file, "app.toml")?;
-    if let Some(patches) = cfg.patches.as_ref() {
-        archive
-            .text(
-                "patches.toml",
-                toml::to_string(patches)
-                    .context("Could not serialize patches")?,
-            )
-            .context("Could not write patches.toml")?;
-    }
+    // Write the fully expanded configuration, so that the archive does not
+    // depend on any files that this app.toml inherits from.
+    archive.text(
+        "app.toml",
+        crate::config::expanded_toml(&cfg.app_toml_file)?,
+    )?;
     let chip_dir = cfg.app_src_dir.join(cfg.toml.chip.clone());
     let chip_file = chip_dir.join("chip.toml");
     let chip_filename = chip_file.file_name().unwrap();
@@ -1079,6 +1082,14 @@ fn task_entry_point(
             );
         }
     }
+    if let Some(margin) = cfg.toml.stack_margin {
+        stack::check_task(
+            &cfg.toml,
+            name,
+            &cfg.img_file(name, image_name),
+            margin,
+        )?;
+    }
     Ok(ep)
 }
 
@@ -1568,17 +1579,25 @@ fn build(
         .iter()
         .map(|r| format!(" --remap-path-prefix={}={}", r.0.display(), r.1))
         .collect();
+    // Frame sizes are only needed for stack analysis, which the app asks for
+    // by setting `stack-margin`.
+    let stack_sizes = if cfg.toml.stack_margin.is_some() {
+        "-Z emit-stack-sizes"
+    } else {
+        ""
+    };
     cmd.env(
         "RUSTFLAGS",
         &format!(
             "-C link-arg=-z -C link-arg=common-page-size=0x20 \
              -C link-arg=-z -C link-arg=max-page-size=0x20 \
              -C llvm-args=--enable-machine-outliner=never \
+             {} \
              -C overflow-checks=y \
              -C metadata={} \
              {}
              ",
-            cfg.link_script_hash, remap_path_prefix,
+            stack_sizes, cfg.link_script_hash, remap_path_prefix,
         ),
     );
     cmd.arg("--");
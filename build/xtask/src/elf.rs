@@ -32,3 +32,29 @@ pub fn get_section_by_vma<'a>(
         addr >= section.sh_addr && addr < (section.sh_addr + section.sh_size)
     })
 }
+
+/// Splits a legacy-mangled Rust symbol (`_ZN...E`) into its path components,
+/// dropping the trailing hash (e.g. `h0123456789abcdef`) if present.
+///
+/// Characters which are escaped in the mangled name (e.g. `$LT$`) are left
+/// as-is, since this is only used to match paths and print them.
+pub fn demangle_path(sym: &str) -> Option<Vec<&str>> {
+    let mut s = sym.strip_prefix("_ZN")?;
+    let mut out = vec![];
+    while !s.starts_with('E') {
+        let digits = s.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = s[..digits].parse().ok()?;
+        let rest = &s[digits..];
+        out.push(rest.get(..len)?);
+        s = &rest[len..];
+    }
+    let is_hash = |p: &str| {
+        p.len() == 17
+            && p.starts_with('h')
+            && p[1..].chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if out.last().map_or(false, |p| is_hash(p)) {
+        out.pop();
+    }
+    Some(out)
+}
@@ -0,0 +1,572 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Host-side driver for the peripheral emulators
+//!
+//! The emulator tasks are thin wrappers around the models in the
+//! `peripheral-emulator` crate; this runs those same models on the host,
+//! driven by a script describing the devices on the bus and a sequence of
+//! steps (transactions, out-of-band register changes, device removal, and
+//! sequencer transitions) along with their expected results.
+//!
+//! A script can also run the `thermal` task's control loop, which lives in
+//! the `thermal-control` crate, against the emulated devices: each iteration
+//! reads temperatures from emulated TMP117s, runs the controller, and writes
+//! the resulting duty cycle to an emulated MAX31790 (or powers down through
+//! the mock sequencer), as the task does on a board.
+//!
+//! The I2C drivers and `task-power` can't be built for the host, so they are
+//! *not* exercised here; that's done on a board with
+//! `app-sidecar-emulator.toml`, which points the real `task-power` at the
+//! emulator task.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use drv_gimlet_state::PowerState;
+use num_traits::FromPrimitive;
+use peripheral_emulator::{
+    i2c::{self, Address, Bus, Device, Null, RegisterFile, Target},
+    models,
+    seq::{MockSequencer, GIMLET_TRANSITIONS},
+};
+use serde::Deserialize;
+use thermal_control::{
+    ControlResult, PidConfig, ThermalController, ThermalProperties,
+};
+use units::{Celsius, PWMDuty};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Script {
+    sequencer: Option<Sequencer>,
+    thermal: Option<Thermal>,
+    #[serde(default)]
+    devices: BTreeMap<String, EmulatedDevice>,
+    #[serde(default)]
+    step: Vec<Step>,
+}
+
+/// A mock sequencer, with the same transitions as the corresponding task
+#[derive(Debug, Deserialize)]
+#[serde(tag = "model", rename_all = "kebab-case", deny_unknown_fields)]
+enum Sequencer {
+    /// `drv-mock-gimlet-seq-server`
+    Gimlet { initial: String },
+}
+
+/// The thermal control loop, as run by the `thermal` task
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Thermal {
+    /// Time between iterations of the loop, in milliseconds
+    #[serde(default = "default_period_ms")]
+    period_ms: u64,
+    #[serde(default)]
+    target_margin: f32,
+    pid: Pid,
+    /// Temperature inputs, each of which must be a `tmp117`
+    inputs: Vec<ThermalInput>,
+    /// Fan controller, which must be a `max31790`
+    fans: String,
+    /// Number of fans on the controller, all of which are driven by the loop
+    #[serde(default = "default_fan_count")]
+    fan_count: u8,
+}
+
+fn default_period_ms() -> u64 {
+    1000
+}
+
+fn default_fan_count() -> u8 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Pid {
+    zero: f32,
+    gain_p: f32,
+    gain_i: f32,
+    gain_d: f32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ThermalInput {
+    device: String,
+    target: f32,
+    critical: f32,
+    power_down: f32,
+    #[serde(default)]
+    slew: f32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct EmulatedDevice {
+    controller: u8,
+    #[serde(default)]
+    port: u8,
+    mux: Option<u8>,
+    segment: Option<u8>,
+    address: u8,
+    /// A model from `peripheral_emulator::models`, as used by the emulator
+    /// tasks; if absent, the device is a plain register file
+    model: Option<Model>,
+    /// Number of registers; a device without registers (or a model)
+    /// acknowledges every transaction but reads nothing
+    #[serde(default)]
+    registers: usize,
+    /// Width of each register, in bytes
+    #[serde(default = "default_width")]
+    width: usize,
+    #[serde(default)]
+    big_endian: bool,
+    /// Initial register values, keyed by register number
+    #[serde(default)]
+    values: BTreeMap<String, u32>,
+}
+
+fn default_width() -> usize {
+    1
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Model {
+    Tmp117,
+    Ltc4282,
+    Max31790,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum Step {
+    #[serde(rename_all = "kebab-case")]
+    WriteRead {
+        device: String,
+        #[serde(default)]
+        write: Vec<u8>,
+        #[serde(default)]
+        read: usize,
+        expect: Option<Vec<u8>>,
+        expect_error: Option<ExpectedError>,
+    },
+    SetRegister {
+        device: String,
+        register: u8,
+        value: u32,
+    },
+    Remove {
+        device: String,
+    },
+    Insert {
+        device: String,
+    },
+    #[serde(rename_all = "kebab-case")]
+    SetState {
+        state: String,
+        expect_error: Option<ExpectedError>,
+    },
+    /// Runs iterations of the thermal control loop
+    #[serde(rename_all = "kebab-case")]
+    Thermal {
+        #[serde(default = "default_iterations")]
+        iterations: usize,
+        /// Expected state of the controller afterwards, e.g. "Running"
+        expect_state: Option<String>,
+        /// Expected range of fan duty cycles afterwards, in percent, as read
+        /// back from the fan controller
+        expect_pwm: Option<[u8; 2]>,
+    },
+}
+
+fn default_iterations() -> usize {
+    1
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum ExpectedError {
+    NoDevice,
+    NoRegister,
+    BadArg,
+    IllegalTransition,
+}
+
+impl From<i2c::Error> for ExpectedError {
+    fn from(e: i2c::Error) -> Self {
+        match e {
+            i2c::Error::NoDevice => ExpectedError::NoDevice,
+            i2c::Error::NoRegister => ExpectedError::NoRegister,
+            i2c::Error::BadArg => ExpectedError::BadArg,
+        }
+    }
+}
+
+pub fn run(script_path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(script_path)
+        .with_context(|| format!("could not read {}", script_path.display()))?;
+    let script: Script = toml::from_str(&text).with_context(|| {
+        format!("could not parse {}", script_path.display())
+    })?;
+
+    let mut addresses = BTreeMap::new();
+    let mut targets: Vec<Box<dyn Target>> = vec![];
+    for (name, dev) in &script.devices {
+        let segment = match (dev.mux, dev.segment) {
+            (Some(m), Some(s)) => Some((m, s)),
+            (None, None) => None,
+            _ => bail!("device {name} must specify both mux and segment"),
+        };
+        let address = Address {
+            controller: dev.controller,
+            port: dev.port,
+            segment,
+            address: dev.address,
+        };
+        if addresses.values().any(|a| *a == address) {
+            bail!("device {name} has the same address as another device");
+        }
+        addresses.insert(name.as_str(), address);
+
+        if let Some(model) = dev.model {
+            if dev.registers != 0 || !dev.values.is_empty() {
+                bail!("device {name} has both a model and registers");
+            }
+            targets.push(match model {
+                Model::Tmp117 => Box::new(models::tmp117()),
+                Model::Ltc4282 => Box::new(models::ltc4282()),
+                Model::Max31790 => Box::new(models::max31790()),
+            });
+            continue;
+        }
+
+        if dev.registers == 0 {
+            if !dev.values.is_empty() {
+                bail!("device {name} has values but no registers");
+            }
+            targets.push(Box::new(Null));
+            continue;
+        }
+        let mut regs = RegisterFile::new(
+            vec![0u8; dev.registers * dev.width],
+            dev.width,
+            dev.big_endian,
+        );
+        for (reg, value) in &dev.values {
+            let reg = parse_u8(reg).with_context(|| {
+                format!("invalid register {reg:?} for device {name}")
+            })?;
+            regs.set_register(reg, *value).map_err(|e| {
+                anyhow!("could not set register {reg} of {name}: {e:?}")
+            })?;
+        }
+        targets.push(Box::new(regs));
+    }
+
+    let mut devices: Vec<Device> = addresses
+        .values()
+        .zip(targets.iter_mut())
+        .map(|(address, target)| Device {
+            address: *address,
+            present: true,
+            target: target.as_mut(),
+        })
+        .collect();
+    let mut bus = Bus::new(&mut devices, None);
+
+    let mut seq = match &script.sequencer {
+        Some(Sequencer::Gimlet { initial }) => Some(MockSequencer::new(
+            parse_state(initial)?,
+            GIMLET_TRANSITIONS,
+        )),
+        None => None,
+    };
+
+    let lookup = |name: &str| {
+        addresses
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("no such device {name}"))
+    };
+
+    let mut thermal = match &script.thermal {
+        Some(t) => {
+            let model =
+                |name: &str| script.devices.get(name).and_then(|d| d.model);
+            let mut inputs = vec![];
+            for input in &t.inputs {
+                if !matches!(model(&input.device), Some(Model::Tmp117)) {
+                    bail!("thermal input {} is not a tmp117", input.device);
+                }
+                let properties = ThermalProperties {
+                    target_temperature: Celsius(input.target),
+                    critical_temperature: Celsius(input.critical),
+                    power_down_temperature: Celsius(input.power_down),
+                    temperature_slew_deg_per_sec: input.slew,
+                };
+                inputs.push((lookup(&input.device)?, properties));
+            }
+            if inputs.len() > MAX_THERMAL_INPUTS {
+                bail!("at most {MAX_THERMAL_INPUTS} thermal inputs");
+            }
+            if !matches!(model(&t.fans), Some(Model::Max31790)) {
+                bail!("thermal fans {} is not a max31790", t.fans);
+            }
+            if t.fan_count > 6 {
+                bail!("a max31790 has at most 6 fans");
+            }
+
+            let mut controller = ThermalController::new(PidConfig {
+                zero: t.pid.zero,
+                gain_p: t.pid.gain_p,
+                gain_i: t.pid.gain_i,
+                gain_d: t.pid.gain_d,
+            });
+            controller.set_target_margin(Celsius(t.target_margin));
+            Some(ThermalLoop {
+                controller,
+                inputs,
+                fans: lookup(&t.fans)?,
+                fan_count: t.fan_count,
+                period_ms: t.period_ms,
+                now_ms: 0,
+            })
+        }
+        None => None,
+    };
+
+    for (i, step) in script.step.iter().enumerate() {
+        let result = match step {
+            Step::WriteRead {
+                device,
+                write,
+                read,
+                expect,
+                expect_error,
+            } => {
+                let mut buf = vec![0u8; *read];
+                match bus.write_read(lookup(device)?, write, &mut buf) {
+                    Ok(n) => {
+                        buf.truncate(n);
+                        println!(
+                            "{i}: {device}: write {write:x?}, read {buf:x?}"
+                        );
+                        match (expect, expect_error) {
+                            (_, Some(e)) => Err(format!("expected {e:?}")),
+                            (Some(e), _) if *e != buf => {
+                                Err(format!("expected {e:x?}"))
+                            }
+                            _ => Ok(()),
+                        }
+                    }
+                    Err(e) => {
+                        println!("{i}: {device}: write {write:x?}, {e:?}");
+                        check_error(e.into(), *expect_error)
+                    }
+                }
+            }
+            Step::SetRegister {
+                device,
+                register,
+                value,
+            } => {
+                let dev = bus.device_mut(lookup(device)?).unwrap();
+                dev.target.set_register(*register, *value).map_err(|e| {
+                    anyhow!(
+                        "could not set register {register} of {device}: {e:?}"
+                    )
+                })?;
+                println!("{i}: {device}: register {register} = {value:#x}");
+                Ok(())
+            }
+            Step::Remove { device } | Step::Insert { device } => {
+                let present = matches!(step, Step::Insert { .. });
+                bus.device_mut(lookup(device)?).unwrap().present = present;
+                println!("{i}: {device}: present = {present}");
+                Ok(())
+            }
+            Step::SetState {
+                state,
+                expect_error,
+            } => {
+                let seq = seq
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("step {i} requires a sequencer"))?;
+                match seq.set_state(parse_state(state)?) {
+                    Ok(()) => {
+                        println!("{i}: sequencer: {state}");
+                        match expect_error {
+                            Some(e) => Err(format!("expected {e:?}")),
+                            None => Ok(()),
+                        }
+                    }
+                    Err(_) => {
+                        println!(
+                            "{i}: sequencer: {:?} -> {state} is illegal",
+                            seq.state()
+                        );
+                        check_error(
+                            ExpectedError::IllegalTransition,
+                            *expect_error,
+                        )
+                    }
+                }
+            }
+            Step::Thermal {
+                iterations,
+                expect_state,
+                expect_pwm,
+            } => {
+                let thermal = thermal.as_mut().ok_or_else(|| {
+                    anyhow!("step {i} requires a thermal loop")
+                })?;
+                let mut result = Ok(());
+                let mut last = None;
+                for _ in 0..*iterations {
+                    match thermal.iterate(&mut bus, seq.as_mut()) {
+                        Ok(r) => last = Some(r),
+                        Err(e) => {
+                            result = Err(format!("could not set fans: {e:?}"));
+                            break;
+                        }
+                    }
+                }
+                let state = format!("{:?}", thermal.controller.state());
+                let pwm = thermal.fan_pwm(&mut bus);
+                let fans = match pwm {
+                    Ok(p) => format!("{p}%"),
+                    Err(e) => format!("{e:?}"),
+                };
+                println!(
+                    "{i}: thermal: {iterations} iterations, {last:?}, \
+                     {state}, fans at {fans}"
+                );
+                match (expect_state, expect_pwm, pwm) {
+                    _ if result.is_err() => result,
+                    (Some(s), _, _) if *s != state => {
+                        Err(format!("expected {s}"))
+                    }
+                    (_, Some(_), Err(e)) => {
+                        Err(format!("could not read fans: {e:?}"))
+                    }
+                    (_, Some([lo, hi]), Ok(p)) if p < *lo || p > *hi => {
+                        Err(format!("expected fans at {lo}-{hi}%"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        };
+
+        if let Err(msg) = result {
+            bail!("step {i} failed: {msg}");
+        }
+    }
+
+    println!("{} steps passed", script.step.len());
+    Ok(())
+}
+
+/// Most inputs that the thermal loop can have; inputs beyond those in the
+/// script have no thermal model, and so are ignored by the controller
+const MAX_THERMAL_INPUTS: usize = 16;
+
+/// The thermal control loop, reading temperatures from emulated TMP117s and
+/// driving the fans on an emulated MAX31790
+struct ThermalLoop {
+    controller: ThermalController<MAX_THERMAL_INPUTS>,
+    inputs: Vec<(Address, ThermalProperties)>,
+    fans: Address,
+    fan_count: u8,
+    period_ms: u64,
+    now_ms: u64,
+}
+
+impl ThermalLoop {
+    /// Runs one iteration of the loop, as the `thermal` task does once per
+    /// period: read every input, run the controller, and apply the result
+    fn iterate(
+        &mut self,
+        bus: &mut Bus,
+        seq: Option<&mut MockSequencer<PowerState>>,
+    ) -> Result<ControlResult, i2c::Error> {
+        self.now_ms += self.period_ms;
+
+        for (i, (address, _)) in self.inputs.iter().enumerate() {
+            // As in the `tmp117` driver.  If the read fails, the previous
+            // reading is left to age (becoming more pessimistic as it does),
+            // as it is in the sensor task.
+            let mut buf = [0u8; 2];
+            if let Ok(2) = bus.write_read(*address, &[0x00], &mut buf) {
+                let t = f32::from(i16::from_be_bytes(buf)) / 128.0;
+                self.controller
+                    .write_temperature(i, self.now_ms, Celsius(t));
+            }
+        }
+
+        let inputs = &self.inputs;
+        let result = self
+            .controller
+            .run(self.now_ms, |i| inputs.get(i).map(|(_, p)| *p));
+
+        // On power down, the task turns the fans off and asks the sequencer
+        // for A2.
+        let pwm = match result {
+            ControlResult::Pwm(pwm) => pwm,
+            ControlResult::PowerDown => PWMDuty(0),
+        };
+        for fan in 0..self.fan_count {
+            // As in the `max31790` driver: a 9-bit duty cycle, left-justified
+            // in the PWMOUT target duty cycle register.
+            let duty = f32::from(pwm.0.min(100)) / 100.0 * 511.0;
+            let [msb, lsb] = ((duty as u16) << 7).to_be_bytes();
+            bus.write_read(self.fans, &[0x40 + 2 * fan, msb, lsb], &mut [])?;
+        }
+        if result == ControlResult::PowerDown {
+            if let Some(seq) = seq {
+                if seq.state() != PowerState::A2 {
+                    // Every state can reach A2
+                    seq.set_state(PowerState::A2).unwrap();
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Reads back the duty cycle of the first fan, in percent
+    fn fan_pwm(&self, bus: &mut Bus) -> Result<u8, i2c::Error> {
+        let mut buf = [0u8; 2];
+        bus.write_read(self.fans, &[0x40], &mut buf)?;
+        let duty = u32::from(u16::from_be_bytes(buf) >> 7);
+        Ok(((duty * 100 + 255) / 511) as u8)
+    }
+}
+
+fn check_error(
+    actual: ExpectedError,
+    expected: Option<ExpectedError>,
+) -> Result<(), String> {
+    match expected {
+        Some(e) if e == actual => Ok(()),
+        Some(e) => Err(format!("expected {e:?}")),
+        None => Err("expected success".to_owned()),
+    }
+}
+
+fn parse_state(name: &str) -> Result<PowerState> {
+    (0..=u8::MAX)
+        .filter_map(PowerState::from_u8)
+        .find(|s| format!("{s:?}") == name)
+        .ok_or_else(|| anyhow!("no such power state {name}"))
+}
+
+fn parse_u8(s: &str) -> Result<u8> {
+    Ok(match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
+}
@@ -0,0 +1,336 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{dist::PackageConfig, elf};
+
+/// Minimal subset of an `.idol` interface definition
+///
+/// We only care about interface and operation names here, so the bodies of
+/// each operation are skipped.
+#[derive(Debug, Deserialize)]
+struct Interface {
+    name: String,
+    ops: IndexMap<String, serde::de::IgnoredAny>,
+}
+
+/// Summary of a single interface within the image
+#[derive(Debug, Default, Serialize)]
+struct InterfaceUsage {
+    /// Path to the `.idol` file
+    idol: String,
+    /// Tasks which serve this interface
+    servers: Vec<String>,
+    /// Map from operation name to the tasks which call it
+    ops: IndexMap<String, BTreeSet<String>>,
+    /// Operations which are not called by any task
+    unused: Vec<String>,
+    /// Operations which are restricted to specific callers, from the
+    /// server's `allowed-callers` configuration
+    allowed_callers: BTreeMap<String, Vec<String>>,
+}
+
+/// A client calling an operation that it shouldn't be able to call, either
+/// because it has no task slot for any task that serves the interface or
+/// because it isn't in the server's `allowed-callers` for that operation
+#[derive(Debug, Serialize)]
+struct Violation {
+    client: String,
+    interface: String,
+    op: String,
+    servers: Vec<String>,
+    reason: &'static str,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct IpcGraph {
+    interfaces: BTreeMap<String, InterfaceUsage>,
+    violations: Vec<Violation>,
+}
+
+/// Builds an operation-level graph of IPC calls between tasks
+///
+/// The interface served by each task is found by looking for calls to
+/// `idol::server::build_server_support` in the task's `build.rs`.  Calls to
+/// each client operation are found by looking for the client stub (e.g.
+/// `Sensor::get`) in the task's symbol table and DWARF strings, so this
+/// includes stubs that have been inlined.  Clients are checked against their
+/// task slots and the server's `allowed-callers` configuration (if any).
+///
+/// This must be run after `cargo xtask dist`, because it inspects the
+/// task ELF files.  Note that operations called dynamically (e.g. by `hiffy`
+/// on behalf of Humility) aren't visible here, so ops reported as unused may
+/// still be used from the debugger.
+pub fn run(
+    app_toml: &Path,
+    image_name: Option<String>,
+    dot_path: Option<&Path>,
+    json_path: Option<&Path>,
+) -> Result<()> {
+    let cfg = PackageConfig::new(app_toml, false, false)?;
+    let image_name = match image_name {
+        Some(name) => {
+            if !cfg.toml.check_image_name(&name) {
+                bail!("Image name {} not declared in TOML", name);
+            }
+            name
+        }
+        None => cfg.toml.image_names[0].clone(),
+    };
+
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path("./Cargo.toml")
+        .no_deps()
+        .exec()
+        .context("failed to run cargo metadata")?;
+
+    // Find the interfaces served by each task
+    let server_re = regex::Regex::new(
+        r#"build_(?:restricted_)?server_support\(\s*"([^"]+)""#,
+    )
+    .unwrap();
+    let mut graph = IpcGraph::default();
+    for (name, task) in cfg.toml.tasks.iter() {
+        let pkg = metadata
+            .packages
+            .iter()
+            .find(|p| p.name == task.name)
+            .ok_or_else(|| anyhow!("could not find package {}", task.name))?;
+        let dir = pkg.manifest_path.parent().unwrap();
+        let build_rs = dir.join("build.rs");
+        if !build_rs.exists() {
+            continue;
+        }
+        let text = std::fs::read_to_string(&build_rs)?;
+        for c in server_re.captures_iter(&text) {
+            let idol = dir.join(&c[1]);
+            let iface: Interface =
+                ron::de::from_str(&std::fs::read_to_string(&idol)?)
+                    .with_context(|| {
+                        format!("could not parse {}", idol.display())
+                    })?;
+            let usage =
+                graph.interfaces.entry(iface.name).or_insert_with(|| {
+                    InterfaceUsage {
+                        idol: c[1].trim_start_matches("../").to_owned(),
+                        ops: iface
+                            .ops
+                            .keys()
+                            .map(|op| (op.clone(), BTreeSet::new()))
+                            .collect(),
+                        ..Default::default()
+                    }
+                });
+            usage.servers.push(name.clone());
+
+            // Servers built with `build_restricted_server_support` take a
+            // map of op name -> allowed callers in their config block
+            if let Some(allowed) =
+                task.config.as_ref().and_then(|c| c.get("allowed-callers"))
+            {
+                let allowed: BTreeMap<String, Vec<String>> =
+                    allowed.clone().try_into().with_context(|| {
+                        format!("invalid allowed-callers for {name}")
+                    })?;
+                usage.allowed_callers.extend(allowed);
+            }
+        }
+    }
+
+    // Find the client operations called by each task
+    for (name, task) in cfg.toml.tasks.iter() {
+        let elf_path = cfg.img_file(name, &image_name);
+        let data = std::fs::read(&elf_path).with_context(|| {
+            format!(
+                "could not read {}; has the image been built?",
+                elf_path.display()
+            )
+        })?;
+        let elf = goblin::elf::Elf::parse(&data)?;
+
+        for (iface, op) in client_calls(&elf, &data) {
+            let usage = match graph.interfaces.get_mut(iface) {
+                Some(u) => u,
+                None => continue,
+            };
+            let callers = match usage.ops.get_mut(op) {
+                Some(c) => c,
+                None => continue,
+            };
+            if !callers.insert(name.clone()) {
+                continue;
+            }
+            let reason =
+                if !task.task_slots.values().any(|s| usage.servers.contains(s))
+                {
+                    Some("no task slot")
+                } else if usage
+                    .allowed_callers
+                    .get(op)
+                    .map_or(false, |allowed| !allowed.contains(name))
+                {
+                    Some("not in allowed-callers")
+                } else {
+                    None
+                };
+            if let Some(reason) = reason {
+                graph.violations.push(Violation {
+                    client: name.clone(),
+                    interface: iface.to_owned(),
+                    op: op.to_owned(),
+                    servers: usage.servers.clone(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    for usage in graph.interfaces.values_mut() {
+        usage.unused = usage
+            .ops
+            .iter()
+            .filter(|(_, callers)| callers.is_empty())
+            .map(|(op, _)| op.clone())
+            .collect();
+    }
+
+    for v in &graph.violations {
+        println!(
+            "warning: task {} calls {}::{} on {} ({})",
+            v.client,
+            v.interface,
+            v.op,
+            v.servers.join(" or "),
+            v.reason,
+        );
+    }
+    for (name, usage) in &graph.interfaces {
+        if !usage.unused.is_empty() {
+            println!(
+                "{} ({}): unused ops {}",
+                name,
+                usage.servers.join(", "),
+                usage.unused.join(", ")
+            );
+        }
+    }
+
+    if let Some(path) = dot_path {
+        write_dot(&cfg, &graph, path)?;
+    }
+    if let Some(path) = json_path {
+        let f = File::create(path)?;
+        serde_json::to_writer_pretty(f, &graph)?;
+    }
+
+    Ok(())
+}
+
+/// Returns every `(interface, op)` pair which looks like a client stub in the
+/// given ELF file.
+///
+/// Client stubs generated by `idol` are inherent methods on a type named
+/// after the interface (e.g. `task_sensor_api::Sensor::get`).  We look for
+/// mangled names in both the symbol table and the DWARF string table, since
+/// the latter also contains names for functions which were inlined.
+fn client_calls<'a>(
+    elf: &goblin::elf::Elf<'a>,
+    data: &'a [u8],
+) -> BTreeSet<(&'a str, &'a str)> {
+    let mut names: Vec<&str> = elf
+        .syms
+        .iter()
+        .filter_map(|sym| elf.strtab.get_at(sym.st_name))
+        .collect();
+    if let Some(section) = elf::get_section_by_name(elf, ".debug_str") {
+        let start = section.sh_offset as usize;
+        let end = start + section.sh_size as usize;
+        if let Some(strs) = data.get(start..end) {
+            names.extend(
+                strs.split(|&b| b == 0)
+                    .filter_map(|s| std::str::from_utf8(s).ok()),
+            );
+        }
+    }
+
+    names
+        .into_iter()
+        .filter_map(elf::demangle_path)
+        .filter_map(|path| match path.as_slice() {
+            [.., iface, op] => Some((*iface, *op)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn write_dot(cfg: &PackageConfig, graph: &IpcGraph, path: &Path) -> Result<()> {
+    let mut dot = File::create(path)?;
+    writeln!(dot, "digraph ipc {{")?;
+    writeln!(
+        dot,
+        "  labelloc=\"t\";\n  label=\"{}\";",
+        cfg.toml.app_toml_path.display()
+    )?;
+
+    for name in cfg.toml.tasks.keys() {
+        let unused: Vec<String> = graph
+            .interfaces
+            .iter()
+            .filter(|(_, u)| u.servers.contains(name))
+            .flat_map(|(iface, u)| {
+                u.unused.iter().map(move |op| format!("{iface}::{op}"))
+            })
+            .collect();
+        if unused.is_empty() {
+            writeln!(dot, "  {name} [ shape=box ];")?;
+        } else {
+            writeln!(
+                dot,
+                "  {name} [ shape=box, label=\"{name}\\nunused: {}\" ];",
+                unused.join("\\n")
+            )?;
+        }
+    }
+
+    // Collect the ops called along each client -> server edge
+    let mut edges: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+    for (iface, usage) in &graph.interfaces {
+        for (op, callers) in &usage.ops {
+            for client in callers {
+                for server in &usage.servers {
+                    edges
+                        .entry((client.as_str(), server.as_str()))
+                        .or_default()
+                        .push(format!("{iface}::{op}"));
+                }
+            }
+        }
+    }
+    for ((client, server), ops) in edges {
+        let bad = graph.violations.iter().any(|v| {
+            v.client == client && v.servers.iter().any(|s| s == server)
+        });
+        let attr = if bad {
+            "color=red, style=dashed, penwidth=3"
+        } else {
+            "color=green"
+        };
+        writeln!(
+            dot,
+            "  {client} -> {server} [ {attr}, label=\"{}\" ];",
+            ops.join("\\n")
+        )?;
+    }
+    writeln!(dot, "}}")?;
+
+    Ok(())
+}
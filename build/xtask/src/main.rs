@@ -14,12 +14,16 @@ mod clippy;
 mod config;
 mod dist;
 mod elf;
+mod emulate;
 mod flash;
 mod graph;
 mod humility;
+mod ipc_graph;
 mod lsp;
 mod print;
+mod signing;
 mod sizes;
+mod stack;
 mod task_slot;
 
 #[derive(Debug, Parser)]
@@ -110,6 +114,56 @@ enum Xtask {
         dirty: bool,
     },
 
+    /// Reports the worst-case stack usage of each task in a built image.
+    ///
+    /// This uses frame sizes from `-Z emit-stack-sizes` and a call graph
+    /// built from each task's ELF file, so the image must already be built
+    /// with `cargo xtask dist`, and the app TOML must set `stack-margin`
+    /// (which can be 0) for the frame sizes to be recorded.  Tasks whose
+    /// stack usage is unbounded, or whose stack size doesn't exceed their
+    /// worst-case usage by that margin, are reported as errors (and will also
+    /// fail `xtask dist`).  Functions that can't be analyzed (indirect calls,
+    /// precompiled `core`) can be given a worst-case depth in the task's
+    /// `stack-assumptions` table.
+    Stack {
+        /// Print the worst-case call chain for each task
+        #[clap(short)]
+        verbose: bool,
+        /// If there are multiple possible images, analyze this one
+        #[clap(long)]
+        image_name: Option<String>,
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
+
+    /// Verifies a build archive offline.
+    ///
+    /// This checks that the archive is intact, that the caboose (if any) is
+    /// well-formed and matches the archive's `app.toml`, and that the image
+    /// signature (if any) is valid.
+    Verify {
+        /// Public key (as hex) which must have signed the image.  If this is
+        /// omitted, the key stored in the archive is used.
+        #[clap(long)]
+        public_key: Option<PathBuf>,
+        /// Fail if the image is not signed
+        #[clap(long)]
+        require_signature: bool,
+        /// Path to the build archive
+        archive: PathBuf,
+    },
+
+    /// Runs the peripheral emulators on the host, driven by a script.
+    ///
+    /// The script is a TOML file describing the emulated I2C devices (and,
+    /// optionally, a mock sequencer) and a list of steps to run against them,
+    /// along with their expected results.  This fails if any step doesn't
+    /// produce the expected result.
+    Emulate {
+        /// Path to the emulation script, in TOML.
+        script: PathBuf,
+    },
+
     /// Runs `humility`, passing any arguments
     Humility {
         #[clap(flatten)]
@@ -166,6 +220,27 @@ enum Xtask {
         cfg: PathBuf,
     },
 
+    /// Generate a graph of IPC operations called between tasks.
+    ///
+    /// This uses the `idol` interface served by each task and the client
+    /// stubs present in each task's ELF file, so the image must already be
+    /// built with `cargo xtask dist`.  Server operations which are never
+    /// called are reported, as are clients which call an operation without
+    /// a task slot for the server or outside the server's `allowed-callers`.
+    IpcGraph {
+        /// Output file for Graphviz dot syntax graph.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Output file for a JSON summary of the graph.
+        #[clap(long)]
+        json: Option<PathBuf>,
+        /// If there are multiple possible images, analyze this one
+        #[clap(long)]
+        image_name: Option<String>,
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
+
     /// Print out information related to the build.
     ///
     /// Currently only useful to print the archive path, but may grow over time.
@@ -181,7 +256,7 @@ enum Xtask {
         #[clap(long)]
         image_name: Option<String>,
 
-        /// Print the expanded configuration
+        /// Print the configuration as TOML, with any inheritance resolved
         #[clap(long)]
         expanded_config: bool,
     },
@@ -293,6 +368,27 @@ fn run(xtask: Xtask) -> Result<()> {
                 sizes::run(&cfg, &a, false, compare, save)?;
             }
         }
+        Xtask::Stack {
+            verbose,
+            image_name,
+            cfg,
+        } => {
+            stack::run(&cfg, image_name, verbose)?;
+        }
+        Xtask::Verify {
+            public_key,
+            require_signature,
+            archive,
+        } => {
+            signing::verify(
+                &archive,
+                public_key.as_deref(),
+                require_signature,
+            )?;
+        }
+        Xtask::Emulate { script } => {
+            emulate::run(&script)?;
+        }
         Xtask::Humility { args } => {
             let toml = Config::from_file(&args.cfg)?;
             let image_name = if let Some(ref name) = args.image_name {
@@ -355,6 +451,19 @@ fn run(xtask: Xtask) -> Result<()> {
         Xtask::Graph { output, cfg } => {
             graph::task_graph(&cfg, &output)?;
         }
+        Xtask::IpcGraph {
+            output,
+            json,
+            image_name,
+            cfg,
+        } => {
+            ipc_graph::run(
+                &cfg,
+                image_name,
+                output.as_deref(),
+                json.as_deref(),
+            )?;
+        }
         Xtask::Print {
             cfg,
             archive,
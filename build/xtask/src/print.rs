@@ -6,7 +6,10 @@ use std::path::Path;
 
 use anyhow::{bail, Context, Error, Result};
 
-use crate::{config::Config, dist::PackageConfig};
+use crate::{
+    config::{expanded_toml, Config},
+    dist::PackageConfig,
+};
 
 pub fn run(
     cfg: &Path,
@@ -32,9 +35,9 @@ pub fn run(
 
         println!("{}", final_path.display());
     } else if expanded_config {
-        let config = Config::from_file(cfg)
-            .context("could not load build configuration")?;
-        println!("{:#?}", config);
+        // Load the config first, to check that the expanded TOML is valid
+        Config::from_file(cfg).context("could not load build configuration")?;
+        print!("{}", expanded_toml(cfg)?);
     } else {
         bail!("I'm not sure what to print. Currently supported: --archive");
     }
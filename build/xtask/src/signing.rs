@@ -0,0 +1,308 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Detached signatures for build archives
+//!
+//! Unlike the LPC55-specific signing in `dist` (which modifies the image
+//! itself), this signs the final image of any build archive.  The signature
+//! covers `img/final.bin`, which includes the caboose, so it must be applied
+//! after the caboose has been written.  The Ed25519 signature and the public
+//! half of the signing key are stored alongside the image in the archive.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use salty::constants::{
+    PUBLICKEY_SERIALIZED_LENGTH, SECRETKEY_SEED_LENGTH,
+    SIGNATURE_SERIALIZED_LENGTH,
+};
+use serde::Deserialize;
+use tlvc::{TlvcRead, TlvcReadError, TlvcReader};
+
+/// Path to the signed image within the archive
+const IMAGE_FILE: &str = "img/final.bin";
+/// Path to the detached signature within the archive
+const SIGNATURE_FILE: &str = "img/final.bin.sig";
+/// Path to the public key within the archive
+const PUBLIC_KEY_FILE: &str = "img/final.bin.pub";
+
+/// Files which must be present in every build archive
+const REQUIRED_FILES: &[&str] = &[
+    "app.toml",
+    "git-rev",
+    "elf/kernel",
+    "img/final.elf",
+    IMAGE_FILE,
+];
+
+/// Caboose keys which are printed (and checked, if possible) by `verify`
+const CABOOSE_KEYS: [[u8; 4]; 4] = [*b"GITC", *b"BORD", *b"NAME", *b"VERS"];
+
+/// Signs the final image in the given build archive
+///
+/// `private_key` is a file containing a hex-encoded 32-byte Ed25519 seed.
+pub fn sign_archive(archive_path: &Path, private_key: &Path) -> Result<()> {
+    let seed: [u8; SECRETKEY_SEED_LENGTH] = read_hex_key(private_key)
+        .with_context(|| {
+            format!("could not read private key {}", private_key.display())
+        })?;
+    let keypair = salty::Keypair::from(&seed);
+
+    let image = read_archive_file(archive_path, IMAGE_FILE)?;
+    let sig = keypair.sign(&image);
+
+    let f = File::options().read(true).write(true).open(archive_path)?;
+    let mut archive = zip::ZipWriter::new_append(f)?;
+    let opts = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+    for (name, data) in [
+        (SIGNATURE_FILE, &sig.to_bytes()[..]),
+        (PUBLIC_KEY_FILE, &keypair.public.as_bytes()[..]),
+    ] {
+        archive.start_file(name, opts)?;
+        archive.write_all(data)?;
+    }
+    archive.finish()?;
+
+    Ok(())
+}
+
+/// Verifies a build archive offline
+///
+/// This checks that every file in the archive can be read (which checks
+/// each file's CRC), that required files are present, that the caboose (if
+/// the image has one) is well-formed and matches the `app.toml`, and that
+/// the image signature (if present) is valid.
+///
+/// If `public_key` is provided, the signature must be present and must be
+/// made by that key; otherwise, the key stored in the archive is used, which
+/// only checks integrity rather than provenance.
+pub fn verify(
+    archive_path: &Path,
+    public_key: Option<&Path>,
+    require_signature: bool,
+) -> Result<()> {
+    let f = File::open(archive_path).with_context(|| {
+        format!("could not open {}", archive_path.display())
+    })?;
+    let mut archive = zip::ZipArchive::new(f)?;
+
+    // Reading each file to the end makes the zip crate check its CRC
+    let mut names = vec![];
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let mut data = vec![];
+        file.read_to_end(&mut data)
+            .with_context(|| format!("could not read {}", file.name()))?;
+        names.push(file.name().to_owned());
+    }
+    for f in REQUIRED_FILES {
+        if !names.iter().any(|n| n == f) {
+            bail!("archive is missing {f}");
+        }
+    }
+    println!("archive: {} files OK", names.len());
+
+    let image = read_file(&mut archive, IMAGE_FILE)?;
+    let app_toml = String::from_utf8(read_file(&mut archive, "app.toml")?)?;
+    check_caboose(&image, &app_toml)?;
+
+    let has_sig = names.iter().any(|n| n == SIGNATURE_FILE);
+    if !has_sig {
+        if require_signature || public_key.is_some() {
+            bail!("image is not signed");
+        }
+        println!("signature: image is not signed");
+        return Ok(());
+    }
+
+    let sig: [u8; SIGNATURE_SERIALIZED_LENGTH] =
+        read_file(&mut archive, SIGNATURE_FILE)?
+            .try_into()
+            .map_err(|_| anyhow!("signature has the wrong length"))?;
+    let archive_key: [u8; PUBLICKEY_SERIALIZED_LENGTH] =
+        read_file(&mut archive, PUBLIC_KEY_FILE)?
+            .try_into()
+            .map_err(|_| anyhow!("public key has the wrong length"))?;
+    let key = match public_key {
+        Some(path) => {
+            let key: [u8; PUBLICKEY_SERIALIZED_LENGTH] = read_hex_key(path)
+                .with_context(|| {
+                    format!("could not read public key {}", path.display())
+                })?;
+            if key != archive_key {
+                bail!("image was signed by a different key");
+            }
+            key
+        }
+        None => archive_key,
+    };
+    let key = salty::PublicKey::try_from(&key)
+        .map_err(|e| anyhow!("invalid public key: {e:?}"))?;
+    key.verify(&image, &salty::Signature::from(&sig))
+        .map_err(|e| anyhow!("bad image signature: {e:?}"))?;
+
+    if public_key.is_some() {
+        println!("signature: OK");
+    } else {
+        println!(
+            "signature: OK (using the key in the archive; \
+             pass --public-key to check provenance)"
+        );
+    }
+    Ok(())
+}
+
+/// Checks the caboose at the end of the image, if one is expected
+fn check_caboose(image: &[u8], app_toml: &str) -> Result<()> {
+    #[derive(Deserialize)]
+    struct AppToml {
+        name: String,
+        board: String,
+        caboose: Option<toml::Value>,
+    }
+    let app: AppToml = toml::from_str(app_toml)?;
+    if app.caboose.is_none() {
+        println!("caboose: none configured");
+        return Ok(());
+    }
+
+    // The last word of the caboose is its size, and the first is the magic
+    // number (see `dist::package` for details).
+    let size = image
+        .len()
+        .checked_sub(4)
+        .map(|i| u32::from_le_bytes(image[i..].try_into().unwrap()) as usize)
+        .ok_or_else(|| anyhow!("image is too small to contain a caboose"))?;
+    let start = image
+        .len()
+        .checked_sub(size)
+        .filter(|_| size >= 8)
+        .ok_or_else(|| anyhow!("invalid caboose size {size:#x}"))?;
+    let magic = u32::from_le_bytes(image[start..][..4].try_into().unwrap());
+    if magic != abi::CABOOSE_MAGIC {
+        bail!("missing caboose magic (found {magic:#x})");
+    }
+    let reader = CabooseReader(&image[start + 4..image.len() - 4]);
+
+    for key in CABOOSE_KEYS {
+        let name = std::str::from_utf8(&key).unwrap();
+        match reader.get(key)? {
+            Some(value) => {
+                let value = std::str::from_utf8(value).with_context(|| {
+                    format!("caboose {name} is not valid UTF-8")
+                })?;
+                let expected = match &key {
+                    b"BORD" => Some(&app.board),
+                    b"NAME" => Some(&app.name),
+                    _ => None,
+                };
+                if let Some(e) = expected {
+                    if value != e {
+                        bail!(
+                            "caboose {name} is {value:?}, \
+                             but app.toml expects {e:?}"
+                        );
+                    }
+                }
+                println!("caboose: {name} = {value:?}");
+            }
+            None => println!("caboose: {name} is not present"),
+        }
+    }
+    Ok(())
+}
+
+/// Wrapper around the TLV-C region of a caboose, following `drv-caboose`
+#[derive(Copy, Clone)]
+struct CabooseReader<'a>(&'a [u8]);
+
+impl<'a> CabooseReader<'a> {
+    /// Looks up the given key, checking its checksum
+    ///
+    /// An unprogrammed caboose (all `0xFF`) is treated as empty.
+    fn get(&self, key: [u8; 4]) -> Result<Option<&'a [u8]>> {
+        if self.0.iter().all(|b| *b == 0xFF) {
+            return Ok(None);
+        }
+        let mut reader = TlvcReader::begin(*self)
+            .map_err(|e| anyhow!("could not read caboose: {e:?}"))?;
+        while let Some(chunk) = reader
+            .next()
+            .map_err(|e| anyhow!("could not read caboose: {e:?}"))?
+        {
+            if chunk.header().tag == key {
+                let mut tmp = [0u8; 32];
+                chunk.check_body_checksum(&mut tmp).map_err(|e| {
+                    anyhow!("bad checksum in caboose chunk: {e:?}")
+                })?;
+                let (_reader, pos, _end) = reader.into_inner();
+                let data_len = chunk.header().len.get() as usize;
+                let data_start = pos as usize
+                    - chunk.header().total_len_in_bytes() as usize
+                    + std::mem::size_of::<tlvc::ChunkHeader>();
+                let data = self
+                    .0
+                    .get(data_start..)
+                    .and_then(|d| d.get(..data_len))
+                    .ok_or_else(|| anyhow!("caboose chunk is truncated"))?;
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl TlvcRead for CabooseReader<'_> {
+    fn extent(&self) -> Result<u64, TlvcReadError> {
+        Ok(self.0.len() as u64)
+    }
+
+    fn read_exact(
+        &self,
+        offset: u64,
+        dest: &mut [u8],
+    ) -> Result<(), TlvcReadError> {
+        let src = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.0.get(start..)?.get(..dest.len()))
+            .ok_or(TlvcReadError::Truncated)?;
+        dest.copy_from_slice(src);
+        Ok(())
+    }
+}
+
+fn read_file(
+    archive: &mut zip::ZipArchive<File>,
+    name: &str,
+) -> Result<Vec<u8>> {
+    let mut file = archive
+        .by_name(name)
+        .with_context(|| format!("archive is missing {name}"))?;
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn read_archive_file(archive_path: &Path, name: &str) -> Result<Vec<u8>> {
+    let f = File::open(archive_path)?;
+    read_file(&mut zip::ZipArchive::new(f)?, name)
+}
+
+/// Reads a fixed-size key from a file containing hex digits
+fn read_hex_key<const N: usize>(path: &Path) -> Result<[u8; N]> {
+    let text = std::fs::read_to_string(path)?;
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        bail!("expected {} hex digits, found {}", N * 2, text.len());
+    }
+    let mut out = [0u8; N];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16)
+            .context("invalid hex digit")?;
+    }
+    Ok(out)
+}
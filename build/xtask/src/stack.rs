@@ -0,0 +1,669 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use colored::Colorize;
+use indexmap::IndexMap;
+
+use crate::{config::Config, dist::PackageConfig, elf};
+
+/// Name of the section emitted by `-Z emit-stack-sizes`
+pub const STACK_SIZES_SECTION: &str = ".stack_sizes";
+
+/// Results of static stack analysis for a single task
+#[derive(Debug, Default)]
+pub struct StackUsage {
+    /// Worst-case stack depth from the task's entry point, in bytes, or
+    /// `None` if it's unbounded because of indirect calls, unknown functions,
+    /// or recursion
+    pub max_depth: Option<u64>,
+    /// Deepest stack usage along the calls that we could follow, in bytes;
+    /// this is only a lower bound if `max_depth` is `None`
+    pub known_depth: u64,
+    /// Call chain which produces `known_depth`, starting at the entry point
+    pub path: Vec<String>,
+    /// Reachable functions which make indirect calls or branches, or calls
+    /// which switch to the ARM instruction set, which we can't follow
+    pub indirect: BTreeSet<String>,
+    /// Reachable functions without a recorded frame size (e.g. functions in
+    /// the precompiled `core` library), or which call or branch to addresses
+    /// that aren't the start of a known function
+    pub unknown: BTreeSet<String>,
+    /// Reachable functions which are part of a recursive cycle
+    pub recursive: BTreeSet<String>,
+    /// Reachable functions whose depth was taken from the task's
+    /// `stack-assumptions` rather than analyzed
+    pub assumed: BTreeSet<String>,
+}
+
+impl StackUsage {
+    /// Summarizes why `max_depth` is unbounded, e.g. "2 functions with
+    /// indirect calls, 1 recursive function"
+    pub fn unbounded_reasons(&self) -> String {
+        [
+            (self.indirect.len(), "with indirect calls"),
+            (self.unknown.len(), "with unknown frames or callees"),
+            (self.recursive.len(), "in recursive cycles"),
+        ]
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| {
+            format!("{n} function{} {what}", if n == 1 { "" } else { "s" })
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+struct Function {
+    name: String,
+    frame: Option<u64>,
+    calls: BTreeSet<u32>,
+    /// Makes indirect calls or branches
+    indirect: bool,
+    /// Calls or branches to an address which isn't the start of a function
+    unknown_target: bool,
+    /// Worst-case depth of this function and its callees, as asserted by the
+    /// task's `stack-assumptions`
+    assumed: Option<u64>,
+}
+
+/// Branches found by decoding a function's instructions
+#[derive(Default)]
+struct Branches {
+    /// Targets of `BL` instructions
+    calls: BTreeSet<u32>,
+    /// Targets of direct branches, which are tail calls if they leave the
+    /// function
+    jumps: BTreeSet<u32>,
+    /// Whether there are any branches that we can't follow: `BLX`, `BX` to
+    /// anything but `LR`, or writes to `PC` other than returns
+    indirect: bool,
+}
+
+/// Computes the worst-case stack usage of a linked task ELF file
+///
+/// This uses the per-function frame sizes recorded by `-Z emit-stack-sizes`
+/// and a call graph built by decoding Thumb branch instructions, then walks
+/// the graph from the task's entry point. Anything that we can't follow
+/// makes the result unbounded, rather than being counted as zero.
+///
+/// `assumptions` maps function names to their worst-case depth (including
+/// callees), for functions that we can't analyze: precompiled `core`
+/// functions, formatting through `dyn Write`, and the like. Those functions
+/// aren't descended into.
+pub fn task_stack_usage(
+    elf_path: &Path,
+    assumptions: &IndexMap<String, u32>,
+) -> Result<StackUsage> {
+    let data = std::fs::read(elf_path)
+        .with_context(|| format!("could not read {}", elf_path.display()))?;
+    let elf = goblin::elf::Elf::parse(&data)?;
+
+    let frames = stack_sizes(&elf, &data)?;
+
+    let text = elf::get_section_by_name(&elf, ".text")
+        .ok_or_else(|| anyhow!("could not find .text section"))?;
+    let text_data = data
+        .get(text.sh_offset as usize..(text.sh_offset + text.sh_size) as usize)
+        .ok_or_else(|| anyhow!(".text section is out of bounds"))?;
+
+    let mut functions: BTreeMap<u32, Function> = BTreeMap::new();
+    let mut ranges = vec![];
+    for sym in elf.syms.iter() {
+        if sym.st_type() != goblin::elf::sym::STT_FUNC || sym.st_size == 0 {
+            continue;
+        }
+        let addr = (sym.st_value & !1) as u32;
+        let name = elf
+            .strtab
+            .get_at(sym.st_name)
+            .map(|s| match elf::demangle_path(s) {
+                Some(path) => path.join("::"),
+                None => s.to_owned(),
+            })
+            .unwrap_or_else(|| format!("{addr:#x}"));
+        ranges.push((addr, sym.st_size));
+        functions.insert(
+            addr,
+            Function {
+                frame: frames.get(&addr).cloned(),
+                calls: BTreeSet::new(),
+                indirect: false,
+                unknown_target: false,
+                assumed: assumptions.get(&name).map(|d| u64::from(*d)),
+                name,
+            },
+        );
+    }
+
+    for (addr, size) in ranges {
+        let start = u64::from(addr).checked_sub(text.sh_addr);
+        let code = match start
+            .and_then(|s| text_data.get(s as usize..(s + size) as usize))
+        {
+            Some(c) => c,
+            None => continue, // not in .text
+        };
+        let branches = decode_branches(addr, code);
+        let local = u64::from(addr)..u64::from(addr) + size;
+
+        // Branches within the function are ordinary control flow; anything
+        // else had better be the start of a function.
+        let mut calls = BTreeSet::new();
+        let mut unknown_target = false;
+        let jumps = branches
+            .jumps
+            .into_iter()
+            .filter(|t| !local.contains(&u64::from(*t)));
+        for target in branches.calls.into_iter().chain(jumps) {
+            if functions.contains_key(&target) {
+                calls.insert(target);
+            } else {
+                unknown_target = true;
+            }
+        }
+        let f = functions.get_mut(&addr).unwrap();
+        f.calls = calls;
+        f.indirect = branches.indirect;
+        f.unknown_target = unknown_target;
+    }
+
+    let entry = (elf.entry & !1) as u32;
+    if !functions.contains_key(&entry) {
+        bail!("could not find entry point {entry:#x}");
+    }
+    Ok(analyze(entry, &functions))
+}
+
+/// Walks the call graph from `entry`, finding the worst-case stack depth and
+/// the path that produces it
+fn analyze(entry: u32, functions: &BTreeMap<u32, Function>) -> StackUsage {
+    let mut usage = StackUsage::default();
+    let mut memo = BTreeMap::new();
+    let (known_depth, bounded) =
+        depth(entry, functions, &mut memo, &mut vec![], &mut usage);
+    usage.known_depth = known_depth;
+    usage.max_depth = bounded.then_some(known_depth);
+
+    // Reconstruct the worst-case path from the memoized callees
+    let mut addr = Some(entry);
+    while let Some(a) = addr {
+        if usage.path.len() > functions.len() {
+            break; // recursive cycle
+        }
+        usage.path.push(functions[&a].name.clone());
+        addr = memo.get(&a).and_then(|(_, _, next)| *next);
+    }
+    usage
+}
+
+/// Returns the stack depth of the function at `addr`, including its callees,
+/// and whether that's a true bound (i.e. we could follow every call from it)
+///
+/// `memo` stores the results and worst-case callee for each function which
+/// has been visited, and `stack` is the current call chain (used to detect
+/// recursion).
+fn depth(
+    addr: u32,
+    functions: &BTreeMap<u32, Function>,
+    memo: &mut BTreeMap<u32, (u64, bool, Option<u32>)>,
+    stack: &mut Vec<u32>,
+    usage: &mut StackUsage,
+) -> (u64, bool) {
+    if let Some((d, bounded, _)) = memo.get(&addr) {
+        return (*d, *bounded);
+    }
+    if let Some(i) = stack.iter().position(|a| *a == addr) {
+        for a in &stack[i..] {
+            usage.recursive.insert(functions[a].name.clone());
+        }
+        return (0, false);
+    }
+
+    let f = &functions[&addr];
+    if let Some(d) = f.assumed {
+        usage.assumed.insert(f.name.clone());
+        memo.insert(addr, (d, true, None));
+        return (d, true);
+    }
+
+    let mut bounded = true;
+    if f.indirect {
+        usage.indirect.insert(f.name.clone());
+        bounded = false;
+    }
+    if f.unknown_target {
+        usage.unknown.insert(f.name.clone());
+        bounded = false;
+    }
+    let frame = match f.frame {
+        Some(frame) => frame,
+        None => {
+            usage.unknown.insert(f.name.clone());
+            bounded = false;
+            0
+        }
+    };
+
+    stack.push(addr);
+    let mut worst = (0, None);
+    for &callee in &f.calls {
+        let (d, b) = depth(callee, functions, memo, stack, usage);
+        bounded &= b;
+        if d > worst.0 || worst.1.is_none() {
+            worst = (d, Some(callee));
+        }
+    }
+    stack.pop();
+
+    let total = frame + worst.0;
+    memo.insert(addr, (total, bounded, worst.1));
+    (total, bounded)
+}
+
+/// Parses the `.stack_sizes` section, returning a map from function address
+/// to frame size
+///
+/// Each entry is a 32-bit function address followed by a ULEB128 size.
+fn stack_sizes(
+    elf: &goblin::elf::Elf,
+    data: &[u8],
+) -> Result<BTreeMap<u32, u64>> {
+    let section = elf::get_section_by_name(elf, STACK_SIZES_SECTION)
+        .ok_or_else(|| {
+            anyhow!(
+                "no {STACK_SIZES_SECTION} section; frame sizes are only \
+                 recorded if `stack-margin` is set in the app TOML"
+            )
+        })?;
+    let mut bytes = data
+        .get(
+            section.sh_offset as usize
+                ..(section.sh_offset + section.sh_size) as usize,
+        )
+        .ok_or_else(|| anyhow!("{STACK_SIZES_SECTION} is out of bounds"))?;
+
+    let mut out = BTreeMap::new();
+    while !bytes.is_empty() {
+        let addr = bytes
+            .get(..4)
+            .ok_or_else(|| anyhow!("truncated {STACK_SIZES_SECTION}"))?;
+        let addr = u32::from_le_bytes(addr.try_into().unwrap()) & !1;
+        bytes = &bytes[4..];
+
+        let mut size = 0u64;
+        let mut shift = 0;
+        loop {
+            let (b, rest) = bytes
+                .split_first()
+                .ok_or_else(|| anyhow!("truncated {STACK_SIZES_SECTION}"))?;
+            bytes = rest;
+            size |= u64::from(b & 0x7f) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        out.insert(addr, size);
+    }
+    Ok(out)
+}
+
+/// Decodes the Thumb instructions in a function which starts at `addr`
+///
+/// This finds the targets of calls (`BL`) and of direct branches (`B`, in
+/// all of its forms), and notes any branches that we can't follow. Branches
+/// within the function are left for the caller to filter out, since it knows
+/// where the function ends.
+fn decode_branches(addr: u32, code: &[u8]) -> Branches {
+    let mut out = Branches::default();
+
+    let halfwords: Vec<u16> = code
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let mut i = 0;
+    while i < halfwords.len() {
+        let hw1 = halfwords[i];
+        let pc = addr.wrapping_add(2 * i as u32).wrapping_add(4);
+
+        // 32-bit Thumb-2 instructions start with 0b11101, 0b11110, 0b11111
+        if hw1 >> 11 < 0b11101 {
+            i += 1;
+            if hw1 & 0xf000 == 0xd000 && (hw1 >> 8) & 0xf < 0b1110 {
+                // B<cond> (T1); conditions 0b1110 and 0b1111 are UDF and SVC
+                let offset = (((hw1 & 0xff) as i8) as i32) << 1;
+                out.jumps.insert(pc.wrapping_add(offset as u32));
+            } else if hw1 & 0xf800 == 0xe000 {
+                // B (T2), sign-extended from 12 bits
+                let offset = ((i32::from(hw1 & 0x7ff) << 21) >> 20) as u32;
+                out.jumps.insert(pc.wrapping_add(offset));
+            } else if hw1 & 0xff87 == 0x4780 {
+                // BLX <Rm>
+                out.indirect = true;
+            } else if hw1 & 0xff87 == 0x4700 && (hw1 >> 3) & 0xf != 14 {
+                // BX <Rm>, other than the BX LR of a return
+                out.indirect = true;
+            } else if hw1 & 0xff87 == 0x4687 {
+                // MOV PC, <Rm>
+                out.indirect = true;
+            }
+            continue;
+        }
+        let hw2 = match halfwords.get(i + 1) {
+            Some(h) => *h,
+            None => break,
+        };
+        i += 2;
+
+        // LDR PC, [<Rn>, ...], in any of its forms, is a jump through memory
+        // unless it's popping a return address off the stack.
+        if hw1 & 0xff70 == 0xf850 && hw2 >> 12 == 0xf {
+            if hw1 & 0xf != 13 {
+                out.indirect = true;
+            }
+            continue;
+        }
+
+        if hw1 & 0xf800 != 0xf000 || hw2 & 0x8000 == 0 {
+            continue;
+        }
+        let s = u32::from((hw1 >> 10) & 1);
+        let j1 = u32::from((hw2 >> 13) & 1);
+        let j2 = u32::from((hw2 >> 11) & 1);
+        match hw2 & 0xd000 {
+            // BL (T1) or B.W (T4)
+            0xd000 | 0x9000 => {
+                let i1 = !(j1 ^ s) & 1;
+                let i2 = !(j2 ^ s) & 1;
+                let imm = (s << 24)
+                    | (i1 << 23)
+                    | (i2 << 22)
+                    | (u32::from(hw1 & 0x3ff) << 12)
+                    | (u32::from(hw2 & 0x7ff) << 1);
+                // Sign-extend from 25 bits
+                let target = pc.wrapping_add((((imm << 7) as i32) >> 7) as u32);
+                if hw2 & 0xd000 == 0xd000 {
+                    out.calls.insert(target);
+                } else {
+                    out.jumps.insert(target);
+                }
+            }
+            // BLX <imm> switches to the ARM instruction set, which we don't
+            // decode (and which Cortex-M can't execute anyway).
+            0xc000 => out.indirect = true,
+            // B<cond>.W (T3); conditions 0b111x are other instructions
+            0x8000 if (hw1 >> 6) & 0xf < 0b1110 => {
+                let imm = (s << 20)
+                    | (j2 << 19)
+                    | (j1 << 18)
+                    | (u32::from(hw1 & 0x3f) << 12)
+                    | (u32::from(hw2 & 0x7ff) << 1);
+                // Sign-extend from 21 bits
+                let target =
+                    pc.wrapping_add((((imm << 11) as i32) >> 11) as u32);
+                out.jumps.insert(target);
+            }
+            _ => (),
+        }
+    }
+    out
+}
+
+/// Checks that a task's configured stack size can accommodate its computed
+/// worst-case stack usage plus `margin` bytes
+pub fn check_task(
+    toml: &Config,
+    name: &str,
+    elf_path: &Path,
+    margin: u32,
+) -> Result<StackUsage> {
+    let task = &toml.tasks[name];
+    let stacksize = task.stacksize.or(toml.stacksize).ok_or_else(|| {
+        anyhow!("{}: no stack size specified and there is no default", name)
+    })?;
+    let usage = task_stack_usage(elf_path, &task.stack_assumptions)
+        .with_context(|| format!("could not analyze stack for {name}"))?;
+    let max_depth = usage.max_depth.ok_or_else(|| {
+        anyhow!(
+            "could not bound stack usage of {name}: {} \
+             (run `cargo xtask stack -v` for details)",
+            usage.unbounded_reasons()
+        )
+    })?;
+    let required = max_depth + u64::from(margin);
+    if u64::from(stacksize) < required {
+        bail!(
+            "{name} has insufficient stack: specified {stacksize} bytes, \
+             needs at least {} ({} + {margin} margin); worst-case path:\n  {}",
+            required,
+            max_depth,
+            usage.path.join("\n  "),
+        );
+    }
+    Ok(usage)
+}
+
+/// Prints a table of worst-case stack usage for each task in a built image
+pub fn run(
+    app_toml: &Path,
+    image_name: Option<String>,
+    verbose: bool,
+) -> Result<()> {
+    let cfg = PackageConfig::new(app_toml, false, false)?;
+    let image_name = match image_name {
+        Some(name) => {
+            if !cfg.toml.check_image_name(&name) {
+                bail!("Image name {} not declared in TOML", name);
+            }
+            name
+        }
+        None => cfg.toml.image_names[0].clone(),
+    };
+    let margin = cfg.toml.stack_margin.unwrap_or(0);
+    let mut unbounded = false;
+
+    println!(
+        "{:<20} {:>8} {:>9} {:>8}",
+        "TASK".bold(),
+        "STACK".bold(),
+        "WORST".bold(),
+        "SLACK".bold()
+    );
+    let mut failed = vec![];
+    for (name, task) in cfg.toml.tasks.iter() {
+        let stacksize = task.stacksize.or(cfg.toml.stacksize).unwrap_or(0);
+        let usage = task_stack_usage(
+            &cfg.img_file(name, &image_name),
+            &task.stack_assumptions,
+        )
+        .with_context(|| format!("could not analyze stack for {name}"))?;
+        let (worst, slack_str) = match usage.max_depth {
+            Some(max_depth) => {
+                let slack = i64::from(stacksize) - max_depth as i64;
+                let slack_str = format!("{slack}");
+                let slack_str = if slack < i64::from(margin) {
+                    failed.push(name.clone());
+                    slack_str.red()
+                } else {
+                    slack_str.normal()
+                };
+                (format!("{max_depth}"), slack_str)
+            }
+            None => {
+                unbounded = true;
+                failed.push(name.clone());
+                ("unbounded".to_owned(), "?".red())
+            }
+        };
+        println!("{name:<20} {stacksize:>8} {worst:>9} {slack_str:>8}");
+
+        if verbose {
+            println!("    worst-case path ({} bytes):", usage.known_depth);
+            for f in &usage.path {
+                println!("      {f}");
+            }
+            for (label, set) in [
+                ("indirect calls", &usage.indirect),
+                ("unknown frame sizes", &usage.unknown),
+                ("recursion", &usage.recursive),
+                ("assumed", &usage.assumed),
+            ] {
+                if !set.is_empty() {
+                    println!("    {label}:");
+                    for f in set {
+                        println!("      {f}");
+                    }
+                }
+            }
+        }
+    }
+    if unbounded {
+        println!(
+            "\nunbounded tasks make indirect calls, recurse, or call functions \
+             without recorded frame sizes (use -v for details); functions \
+             which can't be analyzed can be given a depth in the task's \
+             `stack-assumptions`"
+        );
+    }
+
+    if !failed.is_empty() {
+        bail!(
+            "tasks with unbounded stack usage or less than {margin} bytes of \
+             stack slack: {}",
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumb(halfwords: &[u16]) -> Vec<u8> {
+        halfwords.iter().flat_map(|h| h.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn decode_direct_branches() {
+        let code = thumb(&[
+            0xf000, 0xf8fe, // 0x0: bl 0x200
+            0xf000, 0xb8fc, // 0x4: b.w 0x200
+            0xd00c, //         0x8: beq 0x24
+            0xe00b, //         0xa: b 0x24
+            0xf040, 0x80f8, // 0xc: bne.w 0x200
+            0x4770, //         0x10: bx lr
+        ]);
+        let b = decode_branches(0, &code);
+        assert_eq!(b.calls, [0x200].into());
+        assert_eq!(b.jumps, [0x24, 0x200].into());
+        assert!(!b.indirect);
+    }
+
+    #[test]
+    fn decode_backwards_branches() {
+        let code = thumb(&[
+            0xf7ff, 0xff7e, // 0x100: bl 0x0
+            0xe77c, //         0x104: b 0x0
+            0xf6ff, 0xaf7b, // 0x106: blt.w 0x0
+        ]);
+        let b = decode_branches(0x100, &code);
+        assert_eq!(b.calls, [0].into());
+        assert_eq!(b.jumps, [0].into());
+        assert!(!b.indirect);
+    }
+
+    #[test]
+    fn decode_returns() {
+        let code = thumb(&[
+            0xbd10, //         pop {r4, pc}
+            0xf85d, 0xfb04, // ldr pc, [sp], #4
+            0x4770, //         bx lr
+        ]);
+        let b = decode_branches(0, &code);
+        assert!(b.calls.is_empty());
+        assert!(b.jumps.is_empty());
+        assert!(!b.indirect);
+    }
+
+    #[test]
+    fn decode_indirect() {
+        for code in [
+            &[0x4798][..],         // blx r3
+            &[0x4710][..],         // bx r2
+            &[0x468f][..],         // mov pc, r1
+            &[0xf8d0, 0xf004][..], // ldr.w pc, [r0, #4]
+        ] {
+            assert!(decode_branches(0, &thumb(code)).indirect, "{code:x?}");
+        }
+    }
+
+    fn function(name: &str, frame: Option<u64>, calls: &[u32]) -> Function {
+        Function {
+            name: name.to_owned(),
+            frame,
+            calls: calls.iter().cloned().collect(),
+            indirect: false,
+            unknown_target: false,
+            assumed: None,
+        }
+    }
+
+    #[test]
+    fn worst_case_path() {
+        let functions = [
+            (0, function("main", Some(16), &[1, 2])),
+            (1, function("shallow", Some(64), &[])),
+            (2, function("deep", Some(8), &[3])),
+            (3, function("leaf", Some(100), &[])),
+        ]
+        .into();
+        let usage = analyze(0, &functions);
+        assert_eq!(usage.max_depth, Some(124));
+        assert_eq!(usage.known_depth, 124);
+        assert_eq!(usage.path, ["main", "deep", "leaf"]);
+    }
+
+    #[test]
+    fn unbounded() {
+        let mut indirect = function("indirect", Some(8), &[]);
+        indirect.indirect = true;
+        let functions = [
+            (0, function("main", Some(16), &[1, 2, 3])),
+            (1, indirect),
+            (2, function("precompiled", None, &[])),
+            (3, function("recursive", Some(32), &[3])),
+        ]
+        .into();
+        let usage = analyze(0, &functions);
+        assert_eq!(usage.max_depth, None);
+        assert_eq!(usage.known_depth, 48);
+        assert_eq!(usage.indirect, ["indirect".to_owned()].into());
+        assert_eq!(usage.unknown, ["precompiled".to_owned()].into());
+        assert_eq!(usage.recursive, ["recursive".to_owned()].into());
+    }
+
+    #[test]
+    fn assumptions_bound_unanalyzable_functions() {
+        let mut fmt = function("core::fmt::write", None, &[2]);
+        fmt.indirect = true;
+        fmt.assumed = Some(200);
+        let functions = [
+            (0, function("main", Some(16), &[1])),
+            (1, fmt),
+            (2, function("never_reached", None, &[])),
+        ]
+        .into();
+        let usage = analyze(0, &functions);
+        assert_eq!(usage.max_depth, Some(216));
+        assert_eq!(usage.path, ["main", "core::fmt::write"]);
+        assert_eq!(usage.assumed, ["core::fmt::write".to_owned()].into());
+        assert!(usage.unknown.is_empty());
+    }
+}
@@ -7,7 +7,7 @@
 
 #![no_std]
 
-use userlib::FromPrimitive;
+use num_derive::FromPrimitive;
 use zerocopy::AsBytes;
 
 #[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, Eq, AsBytes)]
@@ -0,0 +1,315 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Batched I2C operations
+//!
+//! A batch is a list of operations against a device (or against several
+//! devices on the same bus segment) that is sent to the I2C server in a
+//! single IPC via [`Op::Batch`].  The server configures the port and any mux
+//! segment once, then performs each operation in turn without servicing any
+//! other client, recording a [`BatchResult`] for each.  If an operation
+//! fails, the server stops; later operations are reported as
+//! [`ResponseCode::BatchAborted`].
+//!
+//! The message payload is the same as for [`Op::WriteRead`]; its address is
+//! that of the first device targeted.  There are four leases:
+//!
+//! - The operations, as an array of [`BatchOp`] (read-only)
+//! - The bytes to write, consumed in order by each operation (read-only)
+//! - The buffer to read into; each read operation is allotted its maximum
+//!   length, in order (write-only)
+//! - The results, as an array of [`BatchResult`] with one entry per
+//!   operation (write-only)
+//!
+//! The reply is the number of operations that were attempted.
+
+use super::*;
+
+/// The kind of a [`BatchOp`], along with the meaning of its arguments
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq)]
+#[repr(u8)]
+pub enum BatchOpKind {
+    /// Targets subsequent operations at address `a` on the same segment
+    SetAddress = 1,
+    /// Writes `a` bytes, then reads `b` bytes (either may be zero)
+    WriteRead = 2,
+    /// Writes `a` bytes, then reads a block of at most `b` bytes whose
+    /// length is given by the first byte read
+    WriteReadBlock = 3,
+    /// Sleeps for `a` milliseconds, which must not exceed
+    /// [`MAX_BATCH_DELAY`]
+    Delay = 4,
+}
+
+/// Maximum delay for a single [`BatchOpKind::Delay`], in milliseconds.  The
+/// server can't service other clients while it sleeps, so this is kept short.
+pub const MAX_BATCH_DELAY: u8 = 50;
+
+/// A single operation within a batch
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct BatchOp {
+    pub kind: u8,
+    pub a: u8,
+    pub b: u8,
+}
+
+impl BatchOp {
+    pub fn set_address(address: u8) -> Self {
+        Self::new(BatchOpKind::SetAddress, address, 0)
+    }
+
+    pub fn write_read(write: u8, read: u8) -> Self {
+        Self::new(BatchOpKind::WriteRead, write, read)
+    }
+
+    pub fn write_read_block(write: u8, max: u8) -> Self {
+        Self::new(BatchOpKind::WriteReadBlock, write, max)
+    }
+
+    pub fn delay(ms: u8) -> Self {
+        Self::new(BatchOpKind::Delay, ms, 0)
+    }
+
+    fn new(kind: BatchOpKind, a: u8, b: u8) -> Self {
+        Self {
+            kind: kind as u8,
+            a,
+            b,
+        }
+    }
+
+    /// Returns the number of bytes that this operation reads at most
+    pub fn read_len(&self) -> usize {
+        match BatchOpKind::from_u8(self.kind) {
+            Some(BatchOpKind::WriteRead | BatchOpKind::WriteReadBlock) => {
+                self.b as usize
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// The result of a single operation within a batch
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct BatchResult {
+    /// Zero on success; otherwise, the [`ResponseCode`] of the failure
+    pub code: u8,
+    /// Number of bytes read
+    pub len: u8,
+}
+
+///
+/// Builds a batch of operations against an [`I2cDevice`] (and, optionally,
+/// other devices on the same segment), holding at most `N` operations that
+/// write at most `W` bytes in total.  Each method that adds an operation
+/// returns the index of that operation, which can be used to retrieve its
+/// result from the [`I2cBatchResults`] returned by [`I2cBatch::run`].
+///
+pub struct I2cBatch<'a, const N: usize, const W: usize> {
+    device: &'a I2cDevice,
+    ops: [BatchOp; N],
+    offsets: [usize; N],
+    nops: usize,
+    wbuf: [u8; W],
+    wlen: usize,
+    rlen: usize,
+}
+
+impl<'a, const N: usize, const W: usize> I2cBatch<'a, N, W> {
+    pub fn new(device: &'a I2cDevice) -> Self {
+        Self {
+            device,
+            ops: [BatchOp::default(); N],
+            offsets: [0; N],
+            nops: 0,
+            wbuf: [0; W],
+            wlen: 0,
+            rlen: 0,
+        }
+    }
+
+    /// Returns the size of the read buffer that must be passed to
+    /// [`I2cBatch::run`]
+    pub fn read_len(&self) -> usize {
+        self.rlen
+    }
+
+    fn push(
+        &mut self,
+        op: BatchOp,
+        write: &[u8],
+    ) -> Result<usize, ResponseCode> {
+        if self.nops == N || self.wlen + write.len() > W {
+            return Err(ResponseCode::BadArg);
+        }
+
+        self.wbuf[self.wlen..][..write.len()].copy_from_slice(write);
+        self.wlen += write.len();
+        self.ops[self.nops] = op;
+        self.offsets[self.nops] = self.rlen;
+        self.rlen += op.read_len();
+        self.nops += 1;
+
+        Ok(self.nops - 1)
+    }
+
+    ///
+    /// Targets subsequent operations at a different device on the same
+    /// segment.
+    ///
+    pub fn set_address(&mut self, address: u8) -> Result<usize, ResponseCode> {
+        self.push(BatchOp::set_address(address), &[])
+    }
+
+    ///
+    /// Adds a read of a register, with register address of type R and value
+    /// of type V; see [`I2cDevice::read_reg`].
+    ///
+    pub fn read_reg<R: AsBytes, V: AsBytes + FromBytes>(
+        &mut self,
+        reg: R,
+    ) -> Result<usize, ResponseCode> {
+        let write = reg.as_bytes();
+        let read = core::mem::size_of::<V>();
+
+        if write.len() > 255 || read > 255 {
+            return Err(ResponseCode::BadArg);
+        }
+
+        self.push(BatchOp::write_read(write.len() as u8, read as u8), write)
+    }
+
+    ///
+    /// Adds a read of a block of at most `max` bytes from a register; see
+    /// [`I2cDevice::read_block`].
+    ///
+    pub fn read_block<R: AsBytes>(
+        &mut self,
+        reg: R,
+        max: u8,
+    ) -> Result<usize, ResponseCode> {
+        let write = reg.as_bytes();
+
+        if write.len() > 255 {
+            return Err(ResponseCode::BadArg);
+        }
+
+        self.push(BatchOp::write_read_block(write.len() as u8, max), write)
+    }
+
+    /// Adds a write of the specified buffer
+    pub fn write(&mut self, buffer: &[u8]) -> Result<usize, ResponseCode> {
+        if buffer.is_empty() || buffer.len() > 255 {
+            return Err(ResponseCode::BadArg);
+        }
+
+        self.push(BatchOp::write_read(buffer.len() as u8, 0), buffer)
+    }
+
+    /// Adds a delay of `ms` milliseconds, up to [`MAX_BATCH_DELAY`]
+    pub fn delay(&mut self, ms: u8) -> Result<usize, ResponseCode> {
+        if ms > MAX_BATCH_DELAY {
+            return Err(ResponseCode::BadArg);
+        }
+
+        self.push(BatchOp::delay(ms), &[])
+    }
+
+    ///
+    /// Sends the batch to the server, reading into `rbuf` (which must be at
+    /// least [`I2cBatch::read_len`] bytes).  An error is returned only if
+    /// the batch as a whole fails (e.g., because the mux segment could not be
+    /// selected); failures of individual operations are reported by the
+    /// returned results.
+    ///
+    pub fn run<'b>(
+        &self,
+        rbuf: &'b mut [u8],
+    ) -> Result<I2cBatchResults<'b, N>, ResponseCode> {
+        if rbuf.len() < self.rlen {
+            return Err(ResponseCode::BadArg);
+        }
+
+        let mut results = [BatchResult::default(); N];
+        let mut response = 0_usize;
+
+        let (code, _) = sys_send(
+            self.device.task,
+            Op::Batch as u16,
+            &Marshal::marshal(&(
+                self.device.address,
+                self.device.controller,
+                self.device.port,
+                self.device.segment,
+            )),
+            response.as_bytes_mut(),
+            &[
+                Lease::from(self.ops[..self.nops].as_bytes()),
+                Lease::from(&self.wbuf[..self.wlen]),
+                Lease::from(&mut rbuf[..self.rlen]),
+                Lease::from(results[..self.nops].as_bytes_mut()),
+            ],
+        );
+
+        if code != 0 {
+            Err(ResponseCode::from_u32(code)
+                .ok_or(ResponseCode::BadResponse)?)
+        } else {
+            Ok(I2cBatchResults {
+                results,
+                offsets: self.offsets,
+                nops: self.nops,
+                attempted: response,
+                rbuf,
+            })
+        }
+    }
+}
+
+/// The results of running an [`I2cBatch`]
+pub struct I2cBatchResults<'b, const N: usize> {
+    results: [BatchResult; N],
+    offsets: [usize; N],
+    nops: usize,
+    attempted: usize,
+    rbuf: &'b [u8],
+}
+
+impl<'b, const N: usize> I2cBatchResults<'b, N> {
+    ///
+    /// Returns the bytes read by the specified operation, or the error with
+    /// which it failed.
+    ///
+    pub fn data(&self, op: usize) -> Result<&'b [u8], ResponseCode> {
+        if op >= self.nops {
+            return Err(ResponseCode::BadArg);
+        }
+
+        if op >= self.attempted {
+            return Err(ResponseCode::BatchAborted);
+        }
+
+        let result = self.results[op];
+
+        if result.code != 0 {
+            return Err(ResponseCode::from_u8(result.code)
+                .ok_or(ResponseCode::BadResponse)?);
+        }
+
+        Ok(&self.rbuf[self.offsets[op]..][..result.len as usize])
+    }
+
+    ///
+    /// Returns the value read by the specified operation, which must have
+    /// read exactly the size of V.
+    ///
+    pub fn value<V: AsBytes + FromBytes>(
+        &self,
+        op: usize,
+    ) -> Result<V, ResponseCode> {
+        V::read_from(self.data(op)?).ok_or(ResponseCode::BadResponse)
+    }
+}
@@ -29,11 +29,18 @@ use zerocopy::{AsBytes, FromBytes};
 use derive_idol_err::IdolError;
 use userlib::*;
 
+mod batch;
+mod stats;
+pub use batch::*;
+pub use stats::*;
+
 #[derive(FromPrimitive, Eq, PartialEq)]
 pub enum Op {
     WriteRead = 1,
     WriteReadBlock = 2,
     SelectedMuxSegment = 3,
+    Batch = 4,
+    Stats = 5,
 }
 
 /// The response code returned from the I2C server.  These response codes pretty
@@ -93,6 +100,11 @@ pub enum ResponseCode {
     OperationNotSupported = 25,
     /// Illegal number of leases
     IllegalLeaseCount = 26,
+    /// SMBus Packet Error Check (PEC) byte did not match the data
+    BadPec = 27,
+    /// Operation in a batch was not performed because an earlier operation
+    /// in the batch failed
+    BatchAborted = 28,
 }
 
 ///
@@ -0,0 +1,160 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! I2C bus health statistics
+//!
+//! The I2C server keeps counters for each bus segment that it has performed
+//! transactions on, and for each device address on each segment.  These are
+//! read one entry at a time via [`Op::Stats`], whose payload is the index of
+//! the entry (as a `u32`) and whose reply is an [`I2cStatsEntry`].  An index
+//! past the last entry results in [`ResponseCode::BadArg`].
+
+use super::*;
+
+///
+/// Counters for a bus segment or for a device on that segment.  Counters
+/// saturate rather than wrap.
+///
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct I2cStats {
+    /// Transactions attempted, including those that failed
+    pub transactions: u32,
+    /// Kernel timer ticks that elapsed during transactions.  This is only a
+    /// rough proxy for bus load, not a measure of time spent: most
+    /// transactions are shorter than a tick, and count as one only if a tick
+    /// happens to elapse during them.  Over many transactions, growth in this
+    /// relative to `transactions` suggests that targets are stretching the
+    /// clock (the bus speed being fixed), but no more than that.
+    pub busy_ticks: u32,
+    /// Transactions that were NACKed, either on the address or on a register
+    pub nacks: u16,
+    /// Transactions in which the controller lost arbitration
+    pub arbitration_lost: u16,
+    /// Transactions that timed out with the bus or controller busy
+    pub timeouts: u16,
+    /// Transactions that failed with a misplaced START or STOP
+    pub bus_errors: u16,
+    /// Resets of the controller (and of the mux, if any) after a failure
+    pub resets: u16,
+    /// Transactions whose SMBus PEC byte did not match the data
+    pub pec_errors: u16,
+    /// Attempts to free the bus from a target holding SDA low
+    pub recoveries: u16,
+    /// Recovery attempts that failed to free the bus, even after escalation
+    pub recovery_failures: u16,
+}
+
+impl I2cStats {
+    ///
+    /// Records the outcome of a single transaction that took `ticks` kernel
+    /// timer ticks.
+    ///
+    pub fn record(&mut self, result: Result<(), ResponseCode>, ticks: u32) {
+        self.transactions = self.transactions.saturating_add(1);
+        self.busy_ticks = self.busy_ticks.saturating_add(ticks);
+
+        let counter = match result {
+            Ok(()) => return,
+            Err(ResponseCode::NoDevice | ResponseCode::NoRegister) => {
+                &mut self.nacks
+            }
+            Err(ResponseCode::BusReset | ResponseCode::BusResetMux) => {
+                &mut self.arbitration_lost
+            }
+            Err(
+                ResponseCode::BusLocked
+                | ResponseCode::BusLockedMux
+                | ResponseCode::ControllerBusy,
+            ) => &mut self.timeouts,
+            Err(ResponseCode::BusError) => &mut self.bus_errors,
+            Err(ResponseCode::BadPec) => &mut self.pec_errors,
+            Err(_) => return,
+        };
+
+        *counter = counter.saturating_add(1);
+    }
+
+    /// Records a reset of the controller
+    pub fn record_reset(&mut self) {
+        self.resets = self.resets.saturating_add(1);
+    }
+
+    /// Records an attempt to recover the bus, and whether it succeeded
+    pub fn record_recovery(&mut self, released: bool) {
+        self.recoveries = self.recoveries.saturating_add(1);
+
+        if !released {
+            self.recovery_failures = self.recovery_failures.saturating_add(1);
+        }
+    }
+}
+
+///
+/// A single entry in the I2C server's statistics.  The key is marshalled in
+/// the same way as the payload of [`Op::WriteRead`]; an address of 0 (which
+/// is reserved, and therefore never that of a device) denotes the totals for
+/// the segment as a whole.
+///
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct I2cStatsEntry {
+    pub key: [u8; 4],
+    pub stats: I2cStats,
+}
+
+impl I2cStatsEntry {
+    ///
+    /// Returns the controller, port, mux and segment (if any), and device
+    /// address that this entry pertains to.  The address is `None` if this
+    /// entry contains the totals for the segment.
+    ///
+    pub fn location(
+        &self,
+    ) -> Result<
+        (Controller, PortIndex, Option<(Mux, Segment)>, Option<u8>),
+        ResponseCode,
+    > {
+        let (address, controller, port, segment) =
+            I2cMessage::unmarshal(&self.key)?;
+
+        Ok((
+            controller,
+            port,
+            segment,
+            if address == 0 { None } else { Some(address) },
+        ))
+    }
+}
+
+///
+/// Reads the entry at `index` in the statistics of the I2C server `task`,
+/// returning `None` if there is no such entry.  Entries are never removed, so
+/// all statistics can be read by starting at 0 and incrementing the index
+/// until `None` is returned.
+///
+pub fn read_stats(
+    task: TaskId,
+    index: u32,
+) -> Result<Option<I2cStatsEntry>, ResponseCode> {
+    let mut entry = I2cStatsEntry::default();
+
+    let (code, _) = sys_send(
+        task,
+        Op::Stats as u16,
+        index.as_bytes(),
+        entry.as_bytes_mut(),
+        &[],
+    );
+
+    match code {
+        0 => Ok(Some(entry)),
+        _ => match ResponseCode::from_u32(code)
+            .ok_or(ResponseCode::BadResponse)?
+        {
+            ResponseCode::BadArg => Ok(None),
+            code => Err(code),
+        },
+    }
+}
@@ -2,58 +2,16 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-//! Driver for the ADM1272 hot-swap controller
+//
+// The ADM1272's DIRECT format coefficients depend on its configuration and
+// on its sense resistor, and so are determined (and cached) here.
+//
 
 use core::cell::Cell;
-
-use crate::{
-    pmbus_validate, BadValidation, CurrentSensor, TempSensor, Validate,
-    VoltageSensor,
-};
-use drv_i2c_api::*;
 use num_traits::float::FloatCore;
-use pmbus::commands::*;
 use ringbuf::*;
-use userlib::units::*;
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum Error {
-    BadRead { cmd: u8, code: ResponseCode },
-    BadWrite { cmd: u8, code: ResponseCode },
-    BadData { cmd: u8 },
-    BadValidation { cmd: u8, code: ResponseCode },
-    InvalidData { err: pmbus::Error },
-    InvalidConfig,
-}
-
-impl From<BadValidation> for Error {
-    fn from(value: BadValidation) -> Self {
-        Self::BadValidation {
-            cmd: value.cmd,
-            code: value.code,
-        }
-    }
-}
-
-impl From<pmbus::Error> for Error {
-    fn from(err: pmbus::Error) -> Self {
-        Error::InvalidData { err }
-    }
-}
-
-impl From<Error> for ResponseCode {
-    fn from(err: Error) -> Self {
-        match err {
-            Error::BadRead { code, .. } => code,
-            Error::BadWrite { code, .. } => code,
-            Error::BadValidation { code, .. } => code,
-            _ => panic!(),
-        }
-    }
-}
 
 #[derive(Copy, Clone)]
-#[allow(dead_code)]
 struct Coefficients {
     voltage: pmbus::Coefficients,
     current: pmbus::Coefficients,
@@ -71,12 +29,6 @@ pub struct Adm1272 {
     config: Cell<Option<adm1272::PMON_CONFIG::CommandData>>,
 }
 
-impl core::fmt::Display for Adm1272 {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "adm1272: {}", &self.device)
-    }
-}
-
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
     Coefficients(pmbus::Coefficients),
@@ -212,6 +164,18 @@ impl Adm1272 {
         Ok(self.coefficients.get().unwrap())
     }
 
+    fn voltage_coefficients(&self) -> Result<pmbus::Coefficients, Error> {
+        Ok(self.load_coefficients()?.voltage)
+    }
+
+    fn current_coefficients(&self) -> Result<pmbus::Coefficients, Error> {
+        Ok(self.load_coefficients()?.current)
+    }
+
+    fn power_coefficients(&self) -> Result<pmbus::Coefficients, Error> {
+        Ok(self.load_coefficients()?.power)
+    }
+
     fn enable_vin_sampling(&self) -> Result<(), Error> {
         use adm1272::PMON_CONFIG::*;
         let mut config = self.read_config()?;
@@ -257,42 +221,11 @@ impl Adm1272 {
     pub fn read_vin(&self) -> Result<Volts, Error> {
         self.enable_vin_sampling()?;
         let vin = pmbus_read!(self.device, adm1272::READ_VIN)?;
-        Ok(Volts(vin.get(&self.load_coefficients()?.voltage)?.0))
+        Ok(Volts(vin.get(&self.voltage_coefficients()?)?.0))
     }
 
     pub fn peak_iout(&self) -> Result<Amperes, Error> {
         let iout = pmbus_read!(self.device, adm1272::PEAK_IOUT)?;
-        Ok(Amperes(iout.get(&self.load_coefficients()?.current)?.0))
-    }
-}
-
-impl Validate<Error> for Adm1272 {
-    fn validate(device: &I2cDevice) -> Result<bool, Error> {
-        let expected = b"ADM1272-2A";
-        pmbus_validate(device, CommandCode::MFR_MODEL, expected)
-            .map_err(Into::into)
-    }
-}
-
-impl TempSensor<Error> for Adm1272 {
-    fn read_temperature(&self) -> Result<Celsius, Error> {
-        self.enable_temp1_sampling()?;
-        let temp = pmbus_read!(self.device, adm1272::READ_TEMPERATURE_1)?;
-        Ok(Celsius(temp.get()?.0))
-    }
-}
-
-impl CurrentSensor<Error> for Adm1272 {
-    fn read_iout(&self) -> Result<Amperes, Error> {
-        let iout = pmbus_read!(self.device, adm1272::READ_IOUT)?;
-        Ok(Amperes(iout.get(&self.load_coefficients()?.current)?.0))
-    }
-}
-
-impl VoltageSensor<Error> for Adm1272 {
-    fn read_vout(&self) -> Result<Volts, Error> {
-        self.enable_vout_sampling()?;
-        let vout = pmbus_read!(self.device, adm1272::READ_VOUT)?;
-        Ok(Volts(vout.get(&self.load_coefficients()?.voltage)?.0))
+        Ok(Amperes(iout.get(&self.current_coefficients()?)?.0))
     }
 }
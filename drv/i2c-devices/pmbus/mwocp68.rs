@@ -0,0 +1,289 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//
+// Beyond the rail's voltage and current, the shelf has temperature sensors
+// and fans that aren't associated with a rail.  These are read by the same
+// index as the rail: the sensor index when reading temperature (0-2) or fan
+// speed (0-1).
+//
+
+use task_power_api::PmbusValue;
+
+impl Mwocp68 {
+    pub fn read_temperature(&self) -> Result<pmbus::units::Celsius, Error> {
+        // Temperatures are accessible on all pages
+        let r =
+            match self.rail {
+                0 => pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_1)?
+                    .get()?,
+                1 => pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_2)?
+                    .get()?,
+                2 => pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_3)?
+                    .get()?,
+                _ => {
+                    return Err(Error::InvalidData {
+                        err: pmbus::Error::InvalidCode,
+                    })
+                }
+            };
+        Ok(r)
+    }
+
+    pub fn read_speed(&self) -> Result<pmbus::units::Rpm, Error> {
+        let r = match self.rail {
+            0 => pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_1)?.get()?,
+            1 => pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_2)?.get()?,
+            _ => {
+                return Err(Error::InvalidData {
+                    err: pmbus::Error::InvalidCode,
+                })
+            }
+        };
+        Ok(r)
+    }
+
+    #[inline(always)]
+    fn read_block<const N: usize>(
+        &self,
+        cmd: CommandCode,
+    ) -> Result<PmbusValue, Error> {
+        // We can't use static_assertions with const generics (yet), so use
+        // a regular assert and hope that the compiler removes it since both
+        // of these are known constants.
+        assert!(N <= task_power_api::MAX_BLOCK_LEN);
+
+        // Pass through to the non-generic implementation.
+        self.read_block_impl(cmd, N)
+    }
+
+    #[inline(never)]
+    fn read_block_impl(
+        &self,
+        cmd: CommandCode,
+        len: usize,
+    ) -> Result<PmbusValue, Error> {
+        let cmd = cmd as u8;
+        let mut data = [0; task_power_api::MAX_BLOCK_LEN];
+        let len = self
+            .device
+            .read_block(cmd, &mut data[..len])
+            .map_err(|code| Error::BadRead { cmd, code })?;
+        Ok(PmbusValue::Block {
+            data,
+            len: len as u8,
+        })
+    }
+
+    pub fn pmbus_read(
+        &self,
+        op: task_power_api::Operation,
+    ) -> Result<PmbusValue, Error> {
+        use task_power_api::Operation;
+
+        self.set_rail()?;
+
+        let val = match op {
+            Operation::FanConfig1_2 => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::FAN_CONFIG_1_2)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::FanCommand1 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::FAN_COMMAND_1)?.get()?,
+            ),
+            Operation::FanCommand2 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::FAN_COMMAND_1)?.get()?,
+            ),
+            Operation::IoutOcFaultLimit => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::IOUT_OC_FAULT_LIMIT)?
+                    .get()?,
+            ),
+            Operation::IoutOcWarnLimit => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::IOUT_OC_WARN_LIMIT)?.get()?,
+            ),
+            Operation::OtWarnLimit => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::OT_WARN_LIMIT)?.get()?,
+            ),
+            Operation::IinOcWarnLimit => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::IIN_OC_WARN_LIMIT)?.get()?,
+            ),
+            Operation::PoutOpWarnLimit => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::POUT_OP_WARN_LIMIT)?.get()?,
+            ),
+            Operation::PinOpWarnLimit => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::PIN_OP_WARN_LIMIT)?.get()?,
+            ),
+            Operation::StatusByte => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_BYTE)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusWord => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_WORD)?.raw();
+                assert_eq!(width.0, 16);
+                PmbusValue::Raw16(val as u16)
+            }
+            Operation::StatusVout => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_VOUT)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusIout => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_IOUT)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusInput => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_INPUT)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusTemperature => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_TEMPERATURE)?
+                        .raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusCml => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_CML)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusMfrSpecific => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_MFR_SPECIFIC)?
+                        .raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::StatusFans1_2 => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::STATUS_FANS_1_2)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::ReadEin => {
+                self.read_block::<6>(CommandCode::READ_EIN)?
+            }
+            Operation::ReadEout => {
+                self.read_block::<6>(CommandCode::READ_EOUT)?
+            }
+            Operation::ReadVin => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_VIN)?.get()?,
+            ),
+            Operation::ReadIin => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_IIN)?.get()?,
+            ),
+            Operation::ReadVcap => {
+                let vcap = pmbus_read!(self.device, mwocp68::READ_VCAP)?;
+                PmbusValue::from(vcap.get(self.read_mode()?)?)
+            }
+            Operation::ReadVout => {
+                let vout = pmbus_read!(self.device, mwocp68::READ_VOUT)?;
+                PmbusValue::from(vout.get(self.read_mode()?)?)
+            }
+            Operation::ReadIout => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_IOUT)?.get()?,
+            ),
+            Operation::ReadTemperature1 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_1)?.get()?,
+            ),
+            Operation::ReadTemperature2 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_2)?.get()?,
+            ),
+            Operation::ReadTemperature3 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_TEMPERATURE_3)?.get()?,
+            ),
+            Operation::ReadFanSpeed1 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_1)?.get()?,
+            ),
+            Operation::ReadFanSpeed2 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_FAN_SPEED_2)?.get()?,
+            ),
+            Operation::ReadPout => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_POUT)?.get()?,
+            ),
+            Operation::ReadPin => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::READ_PIN)?.get()?,
+            ),
+            Operation::PmbusRevision => {
+                let (val, width) =
+                    pmbus_read!(self.device, mwocp68::PMBUS_REVISION)?.raw();
+                assert_eq!(width.0, 8);
+                PmbusValue::Raw8(val as u8)
+            }
+            Operation::MfrId => self.read_block::<9>(CommandCode::MFR_ID)?,
+            Operation::MfrModel => {
+                self.read_block::<17>(CommandCode::MFR_MODEL)?
+            }
+            Operation::MfrRevision => {
+                self.read_block::<14>(CommandCode::MFR_REVISION)?
+            }
+            Operation::MfrLocation => {
+                self.read_block::<5>(CommandCode::MFR_LOCATION)?
+            }
+            Operation::MfrDate => {
+                self.read_block::<4>(CommandCode::MFR_DATE)?
+            }
+            Operation::MfrSerial => {
+                self.read_block::<12>(CommandCode::MFR_SERIAL)?
+            }
+            Operation::MfrVinMin => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_VIN_MIN)?.get()?,
+            ),
+            Operation::MfrVinMax => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_VIN_MAX)?.get()?,
+            ),
+            Operation::MfrIinMax => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_IIN_MAX)?.get()?,
+            ),
+            Operation::MfrPinMax => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_PIN_MAX)?.get()?,
+            ),
+            Operation::MfrVoutMin => {
+                let vout = pmbus_read!(self.device, mwocp68::MFR_VOUT_MIN)?;
+                PmbusValue::from(vout.get(self.read_mode()?)?)
+            }
+            Operation::MfrVoutMax => {
+                let vout = pmbus_read!(self.device, mwocp68::MFR_VOUT_MAX)?;
+                PmbusValue::from(vout.get(self.read_mode()?)?)
+            }
+            Operation::MfrIoutMax => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_IOUT_MAX)?.get()?,
+            ),
+            Operation::MfrPoutMax => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_POUT_MAX)?.get()?,
+            ),
+            Operation::MfrTambientMax => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_TAMBIENT_MAX)?.get()?,
+            ),
+            Operation::MfrTambientMin => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_TAMBIENT_MIN)?.get()?,
+            ),
+            Operation::MfrEfficiencyHl => {
+                self.read_block::<14>(CommandCode::MFR_EFFICIENCY_HL)?
+            }
+            Operation::MfrMaxTemp1 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_MAX_TEMP_1)?.get()?,
+            ),
+            Operation::MfrMaxTemp2 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_MAX_TEMP_2)?.get()?,
+            ),
+            Operation::MfrMaxTemp3 => PmbusValue::from(
+                pmbus_read!(self.device, mwocp68::MFR_MAX_TEMP_3)?.get()?,
+            ),
+        };
+
+        Ok(val)
+    }
+}
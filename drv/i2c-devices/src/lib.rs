@@ -9,6 +9,7 @@
 //! - [`adm1272`]: ADM1272 hot swap controller
 //! - [`adt7420`]: ADT7420 temperature sensor
 //! - [`at24csw080`]: AT24CSW080 serial EEPROM
+//! - [`bmr491`]: BMR491 IBC
 //! - [`ds2482`]: DS2482-100 1-wire initiator
 //! - [`isl68224`]: ISL68224 power controller
 //! - [`ltc4282`]: LTC4282 high current hot swap controller
@@ -32,11 +33,18 @@
 //! - [`tmp451`]: TMP451 temperature sensor
 //! - [`tps546b24a`]: TPS546B24A buck converter
 //! - [`tse2004av`]: TSE2004av SPD EEPROM with temperature sensor
+//!
+//! The drivers for PMBus power parts (`adm1272`, `bmr491`, `isl68224`,
+//! `mwocp68`, `raa229618` and `tps546b24a`) are generated from the
+//! descriptions in `pmbus/`; a new part that needs nothing beyond the
+//! standard commands can be supported by adding a description there.  See
+//! the `build-pmbus` crate for the format.
 
 #![no_std]
 
 use drv_i2c_api::{I2cDevice, ResponseCode};
 use pmbus::commands::CommandCode;
+use task_power_api::PmbusStatus;
 
 macro_rules! pmbus_read {
     ($device:expr, $cmd:ident) => {
@@ -154,6 +162,34 @@ fn pmbus_validate<const N: usize>(
     }
 }
 
+/// Fills in a [`PmbusStatus`] from `word` (a `STATUS_WORD` value), using
+/// `read` to read each `STATUS_*` register whose summary bit is set in it.
+/// Registers whose summary bit is clear aren't read, which saves bus traffic
+/// in the common case that nothing is amiss.
+fn pmbus_status<E>(
+    word: u16,
+    mut read: impl FnMut(CommandCode) -> Result<u8, E>,
+) -> Result<PmbusStatus, E> {
+    let mut byte = |bit: u16, cmd| {
+        if word & (1 << bit) != 0 {
+            read(cmd)
+        } else {
+            Ok(0)
+        }
+    };
+
+    Ok(PmbusStatus {
+        word,
+        vout: byte(15, CommandCode::STATUS_VOUT)?,
+        iout: byte(14, CommandCode::STATUS_IOUT)?,
+        input: byte(13, CommandCode::STATUS_INPUT)?,
+        mfr: byte(12, CommandCode::STATUS_MFR_SPECIFIC)?,
+        fans: byte(10, CommandCode::STATUS_FANS_1_2)?,
+        temperature: byte(2, CommandCode::STATUS_TEMPERATURE)?,
+        cml: byte(1, CommandCode::STATUS_CML)?,
+    })
+}
+
 pub trait TempSensor<T: core::convert::Into<drv_i2c_api::ResponseCode>> {
     fn read_temperature(&self) -> Result<userlib::units::Celsius, T>;
 }
@@ -192,12 +228,9 @@ pub trait Validate<T: core::convert::Into<drv_i2c_api::ResponseCode>> {
     }
 }
 
-pub mod adm1272;
 pub mod adt7420;
 pub mod at24csw080;
-pub mod bmr491;
 pub mod ds2482;
-pub mod isl68224;
 pub mod ltc4282;
 pub mod m24c02;
 pub mod m2_hp_only;
@@ -205,15 +238,15 @@ pub mod max31790;
 pub mod max5970;
 pub mod max6634;
 pub mod mcp9808;
-pub mod mwocp68;
 pub mod nvme_bmc;
 pub mod pca9538;
 pub mod pca9956b;
 pub mod pct2075;
-pub mod raa229618;
 pub mod sbrmi;
 pub mod sbtsi;
 pub mod tmp117;
 pub mod tmp451;
-pub mod tps546b24a;
 pub mod tse2004av;
+
+// The generated PMBus drivers use the macros above, and so must follow them.
+include!(concat!(env!("OUT_DIR"), "/pmbus_drivers.rs"));
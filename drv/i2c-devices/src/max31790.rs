@@ -256,24 +256,49 @@ impl Max31790 {
     }
 
     pub fn initialize(&self) -> Result<(), ResponseCode> {
-        let device = &self.device;
+        //
+        // Reading the configuration registers and then writing them back
+        // one at a time costs twenty round trips through the I2C server, so
+        // we instead read every configuration register in one batch and
+        // write back all of our changes in a second.
+        //
+        const NFANS: usize = MAX_FANS as usize;
+        const READS: usize = NFANS + 1;
+        const WRITES: usize = 2 * NFANS + 1;
+
+        let mut batch = I2cBatch::<READS, READS>::new(&self.device);
+        let global =
+            batch.read_reg::<u8, u8>(Register::GlobalConfiguration as u8)?;
+        let mut fans = [0; NFANS];
+
+        for (fan, op) in fans.iter_mut().enumerate() {
+            let fan = Fan::try_from(fan as u8).unwrap();
+            *op = batch.read_reg::<u8, u8>(fan.configuration() as u8)?;
+        }
 
-        let mut config = GlobalConfiguration(read_reg8(
-            device,
-            Register::GlobalConfiguration,
-        )?);
+        let mut rbuf = [0u8; READS];
+        let results = batch.run(&mut rbuf)?;
+
+        let mut batch = I2cBatch::<WRITES, { 2 * WRITES }>::new(&self.device);
+
+        let mut config = GlobalConfiguration(results.value::<u8>(global)?);
         config.set_i2c_watchdog(I2cWatchdog::Disabled as u8);
-        write_reg8(device, Register::GlobalConfiguration, config.0)?;
+        batch.write(&[Register::GlobalConfiguration as u8, config.0])?;
 
-        for fan in 0..MAX_FANS {
-            let fan = Fan::try_from(fan).unwrap();
-            let reg = fan.configuration();
+        for (fan, op) in fans.iter().enumerate() {
+            let fan = Fan::try_from(fan as u8).unwrap();
 
-            let mut config = FanConfiguration(read_reg8(device, reg)?);
+            let mut config = FanConfiguration(results.value::<u8>(*op)?);
             config.set_tach_input_enable(true);
 
-            write_reg8(device, reg, config.0)?;
-            write_reg8(device, fan.pwm_target(), 0)?;
+            batch.write(&[fan.configuration() as u8, config.0])?;
+            batch.write(&[fan.pwm_target() as u8, 0])?;
+        }
+
+        let results = batch.run(&mut rbuf)?;
+
+        for op in 0..WRITES {
+            results.data(op)?;
         }
 
         Ok(())
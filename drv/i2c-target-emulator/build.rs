@@ -0,0 +1,265 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    devices: Vec<DeviceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DeviceConfig {
+    address: u8,
+    #[serde(flatten)]
+    model: Model,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "model", rename_all = "kebab-case")]
+enum Model {
+    /// TMP117 temperature sensor, at its power-on state
+    Tmp117,
+    /// Serial EEPROM of the given size, initially erased
+    Eeprom { size: usize },
+    /// Bank of fixed-width registers
+    #[serde(rename_all = "kebab-case")]
+    Registers {
+        registers: usize,
+        #[serde(default = "default_width")]
+        width: usize,
+        #[serde(default)]
+        big_endian: bool,
+        /// Initial register values, keyed by register number
+        #[serde(default)]
+        values: BTreeMap<String, u32>,
+    },
+    /// SMBus (or PMBus) device with a table of commands
+    Smbus { commands: Vec<CommandConfig> },
+}
+
+fn default_width() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct CommandConfig {
+    code: u8,
+    page: Option<u8>,
+    access: Access,
+    #[serde(default)]
+    writable: bool,
+    /// Value of a byte or word command
+    value: Option<u16>,
+    /// Contents of a block command, as a string
+    data: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Access {
+    Byte,
+    Word,
+    Block,
+}
+
+fn main() -> Result<()> {
+    build_util::expose_target_board();
+    build_util::build_notifications()?;
+
+    if let Err(e) = build_i2c::codegen(build_i2c::Disposition::Target) {
+        println!("code generation failed: {}", e);
+        std::process::exit(1);
+    }
+
+    let config = build_util::task_config::<TaskConfig>()?;
+    let out = generate(&config)?;
+
+    let dest = build_util::out_dir().join("emulated_devices.rs");
+    std::fs::write(dest, out)?;
+
+    Ok(())
+}
+
+fn generate(config: &TaskConfig) -> Result<String> {
+    let mut fields = String::new();
+    let mut init = String::new();
+    let mut values = String::new();
+    let mut arms = String::new();
+
+    for (i, d) in config.devices.iter().enumerate() {
+        if d.address < 0x08 || d.address > 0x77 {
+            bail!("device address {:#x} is reserved", d.address);
+        }
+
+        if config.devices[..i].iter().any(|o| o.address == d.address) {
+            bail!("duplicate device address {:#x}", d.address);
+        }
+
+        let (ty, ctor) = match &d.model {
+            Model::Tmp117 => (
+                "RegisterFile<[u8; 32]>".to_owned(),
+                "models::tmp117()".to_owned(),
+            ),
+            Model::Eeprom { size } => {
+                if *size == 0 || *size > 65536 {
+                    bail!("EEPROM at {:#x} has invalid size {size}", d.address);
+                }
+                (
+                    format!("Memory<[u8; {size}]>"),
+                    format!("Memory::new([0xff; {size}])"),
+                )
+            }
+            Model::Registers {
+                registers,
+                width,
+                big_endian,
+                values: v,
+            } => {
+                if *registers == 0 || *registers > 256 {
+                    bail!(
+                        "device at {:#x} must have 1-256 registers",
+                        d.address
+                    );
+                }
+
+                if *width == 0 || *width > 4 {
+                    bail!(
+                        "device at {:#x} has invalid width {width}",
+                        d.address
+                    );
+                }
+
+                for (reg, value) in v {
+                    let reg: u8 = match reg.strip_prefix("0x") {
+                        Some(hex) => u8::from_str_radix(hex, 16)?,
+                        None => reg.parse()?,
+                    };
+
+                    if reg as usize >= *registers {
+                        bail!(
+                            "device at {:#x} has no register {reg}",
+                            d.address
+                        );
+                    }
+
+                    writeln!(
+                        &mut values,
+                        "        devices.d{i}.set_register({reg}, {value:#x})\
+                        .unwrap();",
+                    )?;
+                }
+
+                let len = registers * width;
+                (
+                    format!("RegisterFile<[u8; {len}]>"),
+                    format!(
+                        "RegisterFile::new([0; {len}], {width}, {big_endian})"
+                    ),
+                )
+            }
+            Model::Smbus { commands } => {
+                let mut table = String::new();
+
+                for c in commands {
+                    let page = match c.page {
+                        Some(page) => format!("Some({page})"),
+                        None => "None".to_owned(),
+                    };
+
+                    let ctor = match (c.access, c.value, &c.data) {
+                        (Access::Byte, Some(v), None) if v <= 0xff => {
+                            format!(
+                                "SmbusCommand::byte({:#x}, {page}, {v:#x})",
+                                c.code
+                            )
+                        }
+                        (Access::Word, Some(v), None) => {
+                            format!(
+                                "SmbusCommand::word({:#x}, {page}, {v:#x})",
+                                c.code
+                            )
+                        }
+                        (Access::Block, None, Some(data))
+                            if data.len() <= 32
+                                && data.bytes().all(|b| {
+                                    b.is_ascii_graphic() || b == b' '
+                                }) =>
+                        {
+                            format!(
+                                "SmbusCommand::block({:#x}, {page}, b{data:?})",
+                                c.code
+                            )
+                        }
+                        _ => bail!(
+                            "command {:#x} of device at {:#x} needs a value \
+                            (for a byte or word) or printable data of at most \
+                            32 bytes (for a block)",
+                            c.code,
+                            d.address
+                        ),
+                    };
+
+                    let ctor = if c.writable {
+                        format!("{ctor}.writable()")
+                    } else {
+                        ctor
+                    };
+
+                    write!(&mut table, "\n                {ctor},")?;
+                }
+
+                (
+                    format!("Smbus<{}>", commands.len()),
+                    format!("Smbus::new([{table}\n            ])"),
+                )
+            }
+        };
+
+        writeln!(&mut fields, "    d{i}: {ty},")?;
+        writeln!(&mut init, "            d{i}: {ctor},")?;
+        writeln!(
+            &mut arms,
+            "            {:#x} => Some(&mut self.d{i}),",
+            d.address
+        )?;
+    }
+
+    Ok(format!(
+        r##"
+#[allow(unused_imports)]
+use peripheral_emulator::{{
+    i2c::{{RegisterFile, Target}},
+    models,
+    responder::{{Memory, Responder, Smbus, SmbusCommand}},
+}};
+
+pub(crate) struct Devices {{
+{fields}}}
+
+impl Devices {{
+    pub(crate) fn new() -> Self {{
+        #[allow(unused_mut)]
+        let mut devices = Self {{
+{init}        }};
+
+{values}
+        devices
+    }}
+
+    pub(crate) fn get(&mut self, address: u8) -> Option<&mut dyn Responder> {{
+        match address {{
+{arms}            _ => None,
+        }}
+    }}
+}}
+"##
+    ))
+}
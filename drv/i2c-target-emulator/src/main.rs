@@ -0,0 +1,132 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! I2C target-mode device emulator
+//!
+//! This task operates a controller (configured with `target = true` in the
+//! application's I2C configuration) as an I2C target, responding to each
+//! address in its task configuration as a modeled device.  This allows a board
+//! to stand in for devices (temperature sensors, EEPROMs, PMBus regulators)
+//! when testing the real drivers on another board.  Devices are described in
+//! the task's configuration:
+//!
+//! ```toml
+//! [[tasks.i2c_target.config.devices]]
+//! address = 0x48
+//! model = "tmp117"
+//!
+//! [[tasks.i2c_target.config.devices]]
+//! address = 0x50
+//! model = "eeprom"
+//! size = 256
+//!
+//! [[tasks.i2c_target.config.devices]]
+//! address = 0x20
+//! model = "registers"
+//! registers = 8
+//! width = 2
+//! big-endian = true
+//! values = { 0 = 0x1234 }
+//!
+//! [[tasks.i2c_target.config.devices]]
+//! address = 0x58
+//! model = "smbus"
+//! commands = [
+//!     { code = 0x20, access = "byte", value = 0x17 },
+//!     { code = 0x8b, access = "word", page = 0, value = 0x3000 },
+//!     { code = 0x8b, access = "word", page = 1, value = 0x1800 },
+//!     { code = 0x99, access = "block", data = "OXIDE", writable = true },
+//! ]
+//! ```
+//!
+//! The models themselves live in the `peripheral-emulator` crate, where they
+//! can be tested on the host; a model for another part need only implement
+//! its `Responder` trait.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+use drv_stm32xx_i2c::{I2cControl, I2cPin};
+use drv_stm32xx_sys_api::{OutputType, Pull, Speed, Sys};
+use ringbuf::{ringbuf, ringbuf_entry};
+use userlib::{sys_irq_control, sys_recv_closed, task_slot, TaskId};
+
+task_slot!(SYS, sys);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    Initiate(u8, bool),
+    None,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+
+mod emulated {
+    include!(concat!(env!("OUT_DIR"), "/emulated_devices.rs"));
+}
+
+fn configure_pins(pins: &[I2cPin]) {
+    let sys = SYS.get_task_id();
+    let sys = Sys::from(sys);
+
+    for pin in pins {
+        sys.gpio_configure_alternate(
+            pin.gpio_pins,
+            OutputType::OpenDrain,
+            Speed::High,
+            Pull::None,
+            pin.function,
+        );
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let controller = &i2c_config::controllers()[0];
+    let pins = i2c_config::pins();
+
+    let sys = Sys::from(SYS.get_task_id());
+    controller.enable(&sys);
+
+    configure_pins(&pins);
+
+    let devices = RefCell::new(emulated::Devices::new());
+
+    let mut initiate = |addr: u8| {
+        let rval = match devices.borrow_mut().get(addr) {
+            Some(device) => {
+                device.start();
+                true
+            }
+            None => false,
+        };
+
+        ringbuf_entry!(Trace::Initiate(addr, rval));
+        rval
+    };
+
+    let mut rx = |addr: u8, byte: u8| {
+        if let Some(device) = devices.borrow_mut().get(addr) {
+            device.rx(byte);
+        }
+    };
+
+    let mut tx = |addr: u8| devices.borrow_mut().get(addr)?.tx();
+
+    let ctrl = I2cControl {
+        enable: |notification| {
+            sys_irq_control(notification, true);
+        },
+        wfi: |notification| {
+            let _ = sys_recv_closed(&mut [], notification, TaskId::KERNEL);
+        },
+    };
+
+    controller.operate_as_target(&ctrl, &mut initiate, &mut rx, &mut tx);
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
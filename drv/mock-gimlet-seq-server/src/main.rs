@@ -9,6 +9,7 @@
 
 use drv_gimlet_seq_api::{PowerState, SeqError};
 use idol_runtime::RequestError;
+use peripheral_emulator::seq::{MockSequencer, GIMLET_TRANSITIONS};
 use task_jefe_api::Jefe;
 use userlib::{FromPrimitive, RecvMessage, UnwrapLite};
 
@@ -26,11 +27,15 @@ fn main() -> ! {
 
 struct ServerImpl {
     jefe: Jefe,
+    seq: MockSequencer<'static, PowerState>,
 }
 
 impl ServerImpl {
     fn init(jefe: Jefe) -> Self {
-        let me = Self { jefe };
+        let me = Self {
+            jefe,
+            seq: MockSequencer::new(PowerState::A2, GIMLET_TRANSITIONS),
+        };
         me.set_state_impl(PowerState::A2);
         me
     }
@@ -59,17 +64,11 @@ impl idl::InOrderSequencerImpl for ServerImpl {
         _: &RecvMessage,
         state: PowerState,
     ) -> Result<(), RequestError<SeqError>> {
-        match (self.get_state_impl(), state) {
-            (PowerState::A2, PowerState::A0)
-            | (PowerState::A0, PowerState::A2)
-            | (PowerState::A0PlusHP, PowerState::A2)
-            | (PowerState::A0Thermtrip, PowerState::A2) => {
-                self.set_state_impl(state);
-                Ok(())
-            }
-
-            _ => Err(RequestError::Runtime(SeqError::IllegalTransition)),
-        }
+        self.seq
+            .set_state(state)
+            .map_err(|_| RequestError::Runtime(SeqError::IllegalTransition))?;
+        self.set_state_impl(state);
+        Ok(())
     }
 
     fn fans_on(
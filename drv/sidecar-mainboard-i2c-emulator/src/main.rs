@@ -5,11 +5,18 @@
 //! An I2C device emulator for Sidecar Mainboard, intended to convince the
 //! sequencer task it is running on a Sidecar Mainboard rather than say a
 //! Gimletlet.
+//!
+//! The devices themselves are modeled by the `peripheral-emulator` crate, so
+//! the same model can be driven on the host by `cargo xtask emulate`.  Any
+//! task whose `i2c_driver` slot is pointed at this one will run its real
+//! drivers against them.
 
 #![no_std]
 #![no_main]
 
 use drv_i2c_api::*;
+use peripheral_emulator::i2c::{self, Address, Bus, Device, Null};
+use peripheral_emulator::models;
 use ringbuf::*;
 use userlib::*;
 
@@ -24,6 +31,23 @@ ringbuf!(Trace, 16, Trace::None);
 fn main() -> ! {
     let mut buffer = [0; 4];
 
+    // An LTC4282 where a DC2024 would put one, so that the real driver in
+    // `task-power` has something to talk to.  Every other device only needs
+    // an acknowledgement, which the fallback target provides.
+    let mut ltc4282 = models::ltc4282();
+    let mut devices = [Device {
+        address: Address {
+            controller: 4,
+            port: 0,
+            segment: None,
+            address: 0x55,
+        },
+        present: true,
+        target: &mut ltc4282,
+    }];
+    let mut fallback = Null;
+    let mut bus = Bus::new(&mut devices, Some(&mut fallback));
+
     loop {
         hl::recv_without_notification(&mut buffer, |op, msg| match op {
             Op::WriteRead | Op::WriteReadBlock => {
@@ -31,7 +55,8 @@ fn main() -> ! {
                     .fixed_with_leases::<[u8; 4], usize>(2)
                     .ok_or(ResponseCode::BadArg)?;
 
-                let (addr, _, _, _) = Marshal::unmarshal(payload)?;
+                let (addr, controller, port, segment) =
+                    Marshal::unmarshal(payload)?;
 
                 if let Some(_) = ReservedAddress::from_u8(addr) {
                     return Err(ResponseCode::ReservedAddress);
@@ -63,8 +88,30 @@ fn main() -> ! {
                     return Err(ResponseCode::BadArg);
                 }
 
-                // Send an empty reply back to the caller.
-                caller.reply(0);
+                let mut wdata = [0u8; 255];
+                let wdata = &mut wdata[..winfo.len];
+                wbuf.read_fully_at(0, wdata).ok_or(ResponseCode::BadArg)?;
+                let mut rdata = [0u8; 255];
+                let rdata = &mut rdata[..rinfo.len];
+
+                let address = Address {
+                    controller: controller as u8,
+                    port: port.0,
+                    segment: segment.map(|(m, s)| (m as u8, s as u8)),
+                    address: addr,
+                };
+                let n =
+                    bus.write_read(address, wdata, rdata).map_err(
+                        |e| match e {
+                            i2c::Error::NoDevice => ResponseCode::NoDevice,
+                            i2c::Error::NoRegister => ResponseCode::NoRegister,
+                            i2c::Error::BadArg => ResponseCode::BadArg,
+                        },
+                    )?;
+
+                rbuf.write_fully_at(0, &rdata[..n])
+                    .ok_or(ResponseCode::BadArg)?;
+                caller.reply(n);
                 Ok(())
             }
         });
@@ -12,6 +12,7 @@
 
 #![no_std]
 
+use core::cell::Cell;
 use core::convert::TryFrom;
 
 #[cfg(feature = "h743")]
@@ -29,7 +30,7 @@ pub struct Ethernet {
     /// Pointer to the MAC registers.
     mac: &'static device::ethernet_mac::RegisterBlock,
     /// Pointer to the MTL registers.
-    _mtl: &'static device::ethernet_mtl::RegisterBlock,
+    mtl: &'static device::ethernet_mtl::RegisterBlock,
     /// Pointer to the DMA registers.
     dma: &'static device::ethernet_dma::RegisterBlock,
     /// Control of the TX ring.
@@ -46,6 +47,36 @@ pub struct Ethernet {
     mdio_timer: &'static device::tim16::RegisterBlock,
     /// Notification mask for the timer interrupt.
     mdio_timer_irq_mask: u32,
+
+    /// Running totals of the hardware's drop counters, which clear on read
+    drops: Cell<HardwareDrops>,
+}
+
+/// Totals of the hardware's drop counters
+#[derive(Copy, Clone, Default)]
+struct HardwareDrops {
+    rx_queue_overflow: u32,
+    rx_missed: u32,
+    rx_dma_dropped: u32,
+}
+
+/// Counts of received packets that were lost, by where and why, since the
+/// driver was initialized.
+///
+/// The hardware counters behind the first three saturate after 2047 packets,
+/// so they'll undercount if `update_counters` isn't called often enough during
+/// a storm.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Counters {
+    /// Packets dropped because the MTL receive queue overflowed
+    pub rx_queue_overflow: u32,
+    /// Packets dropped because the receive ring was full (i.e. the DMA had no
+    /// descriptors to put them in)
+    pub rx_missed: u32,
+    /// Packets dropped by the receive DMA, e.g. due to a bus error
+    pub rx_dma_dropped: u32,
+    /// Packets dropped from the receive ring by the driver
+    pub rx_ring: ring::RxDrops,
 }
 
 /// As the name implies, this spins until a predicate becomes true, in a crappy
@@ -249,12 +280,13 @@ impl Ethernet {
 
         Self {
             mac,
-            _mtl: mtl,
+            mtl,
             dma,
             tx_ring,
             rx_ring,
             mdio_timer,
             mdio_timer_irq_mask,
+            drops: Cell::new(HardwareDrops::default()),
         }
     }
 
@@ -314,6 +346,38 @@ impl Ethernet {
         (packet_transmitted, packet_received)
     }
 
+    /// Adds the hardware's drop counters to our running totals. The counters
+    /// clear when they're read, and saturate, so this should be called
+    /// regularly.
+    pub fn update_counters(&self) {
+        let mpocr = self.mtl.mtlrx_qmpocr.read();
+        let mfcr = self.dma.dmacmfcr.read();
+
+        let mut drops = self.drops.get();
+        drops.rx_queue_overflow = drops
+            .rx_queue_overflow
+            .wrapping_add(mpocr.ovfpktcnt().bits().into());
+        drops.rx_missed = drops
+            .rx_missed
+            .wrapping_add(mpocr.mispktcnt().bits().into());
+        drops.rx_dma_dropped =
+            drops.rx_dma_dropped.wrapping_add(mfcr.mfc().bits().into());
+        self.drops.set(drops);
+    }
+
+    /// Returns counts of the received packets that have been lost since the
+    /// driver was initialized.
+    pub fn counters(&self) -> Counters {
+        self.update_counters();
+        let drops = self.drops.get();
+        Counters {
+            rx_queue_overflow: drops.rx_queue_overflow,
+            rx_missed: drops.rx_missed,
+            rx_dma_dropped: drops.rx_dma_dropped,
+            rx_ring: self.rx_ring.drops(),
+        }
+    }
+
     /// Notifies the DMA hardware that space is available in the Rx ring
     fn rx_notify(&self) {
         // We have dequeued a packet! The hardware might not realize there is
@@ -396,6 +396,17 @@ cfg_if::cfg_if! {
     }
 }
 
+/// Counts of received packets that the `RxRing` has dropped, by reason.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RxDrops {
+    /// Packets that the MAC flagged as having an error (e.g. a bad CRC)
+    pub errors: u32,
+    /// Packets that didn't fit in a single descriptor
+    pub fragmented: u32,
+    /// Packets without a VLAN tag, or with one outside our range
+    pub bad_vid: u32,
+}
+
 /// Control block for a ring of `RxDesc` records and associated `Buffer`s.
 pub struct RxRing {
     /// The descriptor ring storage.
@@ -406,6 +417,8 @@ pub struct RxRing {
     /// received packet. This must be in the range `0..storage.len()` at all
     /// times.
     next: Cell<usize>,
+    /// Packets that we've dropped, since the ring was created.
+    drops: Cell<RxDrops>,
 }
 
 impl RxRing {
@@ -439,6 +452,7 @@ impl RxRing {
             storage,
             buffers,
             next: Cell::new(0),
+            drops: Cell::new(RxDrops::default()),
         }
     }
 
@@ -462,6 +476,29 @@ impl RxRing {
         self.storage.len()
     }
 
+    /// Returns the number of packets that we've dropped, by reason.
+    pub fn drops(&self) -> RxDrops {
+        self.drops.get()
+    }
+
+    /// Records a dropped packet, given the RDES3 word of its descriptor. A
+    /// packet with no errors that's complete must have been dropped for its
+    /// VLAN tag.
+    fn count_drop(&self, rdes3: u32) {
+        let mut drops = self.drops.get();
+        let counter = if rdes3 & (1 << RDES3_ES_BIT) != 0 {
+            &mut drops.errors
+        } else if rdes3 & ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT))
+            != ((1 << RDES3_FD_BIT) | (1 << RDES3_LD_BIT))
+        {
+            &mut drops.fragmented
+        } else {
+            &mut drops.bad_vid
+        };
+        *counter = counter.wrapping_add(1);
+        self.drops.set(drops);
+    }
+
     /// Programs the words in `d` to prepare to receive into `buffer` and sets
     /// `d` accessible to hardware. The final write to make it accessible is
     /// performed with Release ordering to get a barrier.
@@ -507,6 +544,7 @@ impl RxRing {
             }
 
             // Otherwise, drop the packet by bumping our index
+            self.count_drop(rdes3);
             self.next.set(if self.next.get() + 1 == self.storage.len() {
                 0
             } else {
@@ -645,6 +683,8 @@ impl RxRing {
             //  (b) either has no VID or has an invalid VID
             // so we're going to drop it to avoid clogging the queue.
 
+            self.count_drop(rdes3);
+
             // Rewrite to an empty rx descriptor (owned by DMA)
             let buffer = self.buffers[self.next.get()].0.get();
             Self::set_descriptor(d, buffer);
@@ -69,12 +69,40 @@ fn find_mux(
     }
 }
 
+///
+/// Enables `segment` on `mux` (or disables all of its segments), recording
+/// the transaction against the mux itself -- which sits on the bus rather
+/// than on any segment -- and not against the device being addressed.
+///
+fn enable_segment(
+    mux: &I2cMux<'_>,
+    controller: &I2cController<'_>,
+    segment: Option<Segment>,
+    ctrl: &I2cControl,
+    stats: &mut StatsTable,
+) -> Result<(), ResponseCode> {
+    let start = sys_get_timer().now;
+    let rval = mux.driver.enable_segment(mux, controller, segment, ctrl);
+
+    stats.record(
+        controller.controller,
+        mux.port,
+        None,
+        mux.address,
+        rval,
+        sys_get_timer().now - start,
+    );
+
+    rval
+}
+
 fn configure_mux(
     map: &mut MuxMap,
     controller: &I2cController<'_>,
     port: PortIndex,
     mux: Option<(Mux, Segment)>,
     muxes: &[I2cMux<'_>],
+    stats: &mut StatsTable,
     ctrl: &I2cControl,
 ) -> Result<(), ResponseCode> {
     //
@@ -90,7 +118,7 @@ fn configure_mux(
     if mux.is_none() {
         if let Some(current) = map.get((controller.controller, port)) {
             find_mux(controller, port, muxes, Some(current), |old, _, _| {
-                old.driver.enable_segment(old, controller, None, ctrl)
+                enable_segment(old, controller, None, ctrl, stats)
             })?;
 
             map.remove((controller.controller, port));
@@ -117,7 +145,7 @@ fn configure_mux(
                     muxes,
                     Some(current),
                     |old, _, _| {
-                        old.driver.enable_segment(old, controller, None, ctrl)
+                        enable_segment(old, controller, None, ctrl, stats)
                     },
                 )?;
             }
@@ -133,8 +161,7 @@ fn configure_mux(
         // bus; if only for forensic purposes, we want to know what this mux +
         // segment was.
         //
-        mux.driver
-            .enable_segment(mux, controller, Some(segment), ctrl)?;
+        enable_segment(mux, controller, Some(segment), ctrl, stats)?;
         map.insert((controller.controller, port), (id, segment));
 
         Ok(())
@@ -148,18 +175,32 @@ enum Trace {
     ResetMux(Mux),
     SegmentFailed(ResponseCode),
     ConfigureFailed(ResponseCode),
+    Recovery {
+        controller: Controller,
+        port: PortIndex,
+        clocks: u8,
+        released: bool,
+    },
+    Escalation(I2cEscalation, bool),
+    EscalationFailed(I2cEscalation),
     None,
 }
 
-ringbuf!(Trace, 8, Trace::None);
+ringbuf!(Trace, 16, Trace::None);
+
+/// Time to hold power off (and then to wait after restoring it) when
+/// power-cycling the devices on a port to free the bus
+const POWER_CYCLE_MS: u64 = 100;
 
 fn reset(
     controller: &I2cController<'_>,
     port: PortIndex,
     muxes: &[I2cMux<'_>],
     mux: Option<(Mux, Segment)>,
+    stats: &mut StatsTable,
 ) {
     ringbuf_entry!(Trace::Reset(controller.controller, port));
+    stats.record_reset(controller.controller, port, mux);
 
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -173,6 +214,97 @@ fn reset(
         mux.driver.reset(mux, &sys)?;
         Ok(())
     });
+
+    // Finally, free the bus if a target is holding it.
+    recover(controller, port, muxes, mux, stats, &sys);
+}
+
+///
+/// Attempts to free a bus on which a target is holding SDA low, which no
+/// reset of the controller can fix.  We clock the target until it lets go,
+/// and if it won't, we escalate as the port's recovery policy dictates.
+/// Ports without recovery configured are left alone.
+///
+fn recover(
+    controller: &I2cController<'_>,
+    port: PortIndex,
+    muxes: &[I2cMux<'_>],
+    mux: Option<(Mux, Segment)>,
+    stats: &mut StatsTable,
+    sys: &Sys,
+) {
+    let recovery = i2c_config::recovery();
+    let pins = i2c_config::pins();
+
+    let r = match recovery
+        .iter()
+        .find(|r| r.controller == controller.controller && r.port == port)
+    {
+        Some(r) => r,
+        None => return,
+    };
+
+    //
+    // Clock out the target holding SDA (if any), returning whether the bus
+    // is released.
+    //
+    let clock_out = || match r.read(sys, &pins) {
+        I2cLines::Released => (0, true),
+        I2cLines::Held { scl, sda } => r.clock_out(sys, &pins, scl, sda),
+        I2cLines::Stuck => (0, false),
+    };
+
+    let (clocks, mut released) = match r.read(sys, &pins) {
+        I2cLines::Released => return,
+        _ => clock_out(),
+    };
+
+    ringbuf_entry!(Trace::Recovery {
+        controller: controller.controller,
+        port,
+        clocks,
+        released,
+    });
+
+    for &escalation in r.escalation {
+        if released {
+            break;
+        }
+
+        let escalated = match escalation {
+            I2cEscalation::MuxReset => {
+                let mut reset = false;
+
+                for m in muxes.iter().filter(|m| {
+                    m.controller == controller.controller && m.port == port
+                }) {
+                    ringbuf_entry!(Trace::ResetMux(m.id));
+                    reset |= m.driver.reset(m, sys).is_ok();
+                }
+
+                reset
+            }
+            I2cEscalation::PowerCycle => r.power_cycle(sys, POWER_CYCLE_MS),
+        };
+
+        if !escalated {
+            // Nothing was reset, so nothing will have changed.
+            ringbuf_entry!(Trace::EscalationFailed(escalation));
+            continue;
+        }
+
+        //
+        // A target that has been reset may have come back up in the middle
+        // of the bus traffic that we have generated, so clock it out too.
+        //
+        released = clock_out().1;
+        ringbuf_entry!(Trace::Escalation(escalation, released));
+    }
+
+    stats.record_recovery(controller.controller, port, mux, released);
+
+    // Lest our clocking have confused the controller, bounce it again.
+    controller.reset();
 }
 
 fn reset_needed(code: ResponseCode) -> bool {
@@ -193,14 +325,268 @@ fn reset_if_needed(
     port: PortIndex,
     muxes: &[I2cMux<'_>],
     mux: Option<(Mux, Segment)>,
+    stats: &mut StatsTable,
 ) {
     if reset_needed(code) {
-        reset(controller, port, muxes, mux)
+        reset(controller, port, muxes, mux, stats)
     }
 }
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
+type PecDevice = (Controller, PortIndex, Option<(Mux, Segment)>, u8);
+
+///
+/// Devices that use SMBus Packet Error Checking are configured as such in the
+/// application; the PEC is entirely handled here, and is invisible to the
+/// caller.
+///
+fn pec_enabled(
+    pec_devices: &[PecDevice],
+    controller: &I2cController<'_>,
+    port: PortIndex,
+    mux: Option<(Mux, Segment)>,
+    addr: u8,
+) -> bool {
+    pec_devices
+        .iter()
+        .any(|&d| d == (controller.controller, port, mux, addr))
+}
+
+///
+/// Statistics for each segment that we have performed transactions on, and
+/// for each device address on each segment, keyed by the marshalled 4-tuple
+/// (see `drv_i2c_api::I2cStatsEntry`).  The table is sized to hold every
+/// configured segment and device; once it's full, transactions to any other
+/// address are counted only in the totals for their segment.
+///
+struct StatsTable {
+    entries: [I2cStatsEntry; i2c_config::NSTATS],
+    len: usize,
+}
+
+impl Default for StatsTable {
+    fn default() -> Self {
+        Self {
+            entries: [I2cStatsEntry::default(); i2c_config::NSTATS],
+            len: 0,
+        }
+    }
+}
+
+impl StatsTable {
+    fn lookup(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+    ) -> Option<&mut I2cStats> {
+        let key = Marshal::marshal(&(addr, controller, port, mux));
+
+        let index =
+            match self.entries[..self.len].iter().position(|e| e.key == key) {
+                Some(index) => index,
+                None if self.len < self.entries.len() => {
+                    self.entries[self.len].key = key;
+                    self.len += 1;
+                    self.len - 1
+                }
+                None => return None,
+            };
+
+        Some(&mut self.entries[index].stats)
+    }
+
+    fn record(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+        result: Result<(), ResponseCode>,
+        ticks: u64,
+    ) {
+        let ticks = ticks.try_into().unwrap_or(u32::MAX);
+
+        // An address of 0 denotes the totals for the segment
+        for addr in [0, addr] {
+            if let Some(stats) = self.lookup(controller, port, mux, addr) {
+                stats.record(result, ticks);
+            }
+        }
+    }
+
+    fn record_reset(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+    ) {
+        if let Some(stats) = self.lookup(controller, port, mux, 0) {
+            stats.record_reset();
+        }
+    }
+
+    fn record_recovery(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        released: bool,
+    ) {
+        if let Some(stats) = self.lookup(controller, port, mux, 0) {
+            stats.record_recovery(released);
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<I2cStatsEntry> {
+        self.entries[..self.len].get(index).copied()
+    }
+}
+
+///
+/// Runs a batch of operations (see `drv_i2c_api::I2cBatch`) on an
+/// already-configured port and mux segment, returning the number of
+/// operations attempted.  We stop at the first failing operation, but the
+/// batch itself only fails if its leases are malformed.
+///
+fn run_batch(
+    controller: &I2cController<'_>,
+    port: PortIndex,
+    mux: Option<(Mux, Segment)>,
+    mut addr: u8,
+    muxes: &[I2cMux<'_>],
+    pec_devices: &[PecDevice],
+    stats: &mut StatsTable,
+    caller: &hl::Caller<usize>,
+    ctrl: &I2cControl,
+) -> Result<usize, ResponseCode> {
+    let ops = caller.borrow(0);
+    let wbuf = caller.borrow(1);
+    let rbuf = caller.borrow(2);
+    let results = caller.borrow(3);
+
+    let oinfo = ops.info().ok_or(ResponseCode::BadArg)?;
+    let rinfo = rbuf.info().ok_or(ResponseCode::BadArg)?;
+    let resinfo = results.info().ok_or(ResponseCode::BadArg)?;
+
+    if !oinfo.attributes.contains(LeaseAttributes::READ)
+        || !rinfo.attributes.contains(LeaseAttributes::WRITE)
+        || !resinfo.attributes.contains(LeaseAttributes::WRITE)
+    {
+        return Err(ResponseCode::BadArg);
+    }
+
+    let nops = oinfo.len / core::mem::size_of::<BatchOp>();
+
+    if resinfo.len < nops * core::mem::size_of::<BatchResult>() {
+        return Err(ResponseCode::BadArg);
+    }
+
+    let mut woffs = 0;
+    let mut roffs = 0;
+
+    for i in 0..nops {
+        let op: BatchOp = ops
+            .read_at(i * core::mem::size_of::<BatchOp>())
+            .ok_or(ResponseCode::BadArg)?;
+        let mut result = BatchResult::default();
+
+        let rval = match BatchOpKind::from_u8(op.kind) {
+            Some(BatchOpKind::SetAddress) => {
+                if ReservedAddress::from_u8(op.a).is_some() {
+                    Err(ResponseCode::ReservedAddress)
+                } else {
+                    addr = op.a;
+                    Ok(())
+                }
+            }
+            Some(BatchOpKind::Delay) => {
+                if op.a > MAX_BATCH_DELAY {
+                    Err(ResponseCode::BadArg)
+                } else {
+                    hl::sleep_for(op.a.into());
+                    Ok(())
+                }
+            }
+            Some(kind @ BatchOpKind::WriteRead)
+            | Some(kind @ BatchOpKind::WriteReadBlock) => {
+                let wlen = op.a as usize;
+                let rmax = op.b as usize;
+                let wstart = woffs;
+                let rstart = roffs;
+                woffs += wlen;
+                roffs += rmax;
+
+                let rlen = if kind == BatchOpKind::WriteRead {
+                    ReadLength::Fixed(rmax)
+                } else {
+                    ReadLength::Variable
+                };
+
+                if (wlen == 0 && rlen == ReadLength::Fixed(0))
+                    || (rlen == ReadLength::Variable && rmax == 0)
+                {
+                    Err(ResponseCode::BadArg)
+                } else {
+                    let mut nread = 0;
+                    let start = sys_get_timer().now;
+
+                    let rval = controller.write_read(
+                        addr,
+                        wlen,
+                        |pos| wbuf.read_at(wstart + pos),
+                        rlen,
+                        |pos, byte| {
+                            if pos >= rmax {
+                                return None;
+                            }
+
+                            if pos + 1 > nread {
+                                nread = pos + 1;
+                            }
+
+                            rbuf.write_at(rstart + pos, byte)
+                        },
+                        pec_enabled(pec_devices, controller, port, mux, addr),
+                        ctrl,
+                    );
+
+                    stats.record(
+                        controller.controller,
+                        port,
+                        mux,
+                        addr,
+                        rval,
+                        sys_get_timer().now - start,
+                    );
+
+                    result.len = nread as u8;
+                    rval
+                }
+            }
+            None => Err(ResponseCode::BadArg),
+        };
+
+        if let Err(code) = rval {
+            result.code = code as u8;
+        }
+
+        results
+            .write_at(i * core::mem::size_of::<BatchResult>(), result)
+            .ok_or(ResponseCode::BadArg)?;
+
+        if let Err(code) = rval {
+            ringbuf_entry!(Trace::Error);
+            reset_if_needed(code, controller, port, muxes, mux, stats);
+            return Ok(i + 1);
+        }
+    }
+
+    Ok(nops)
+}
+
 type PortMap = FixedMap<Controller, PortIndex, { i2c_config::NCONTROLLERS }>;
 
 type MuxMap = FixedMap<
@@ -214,10 +600,12 @@ fn main() -> ! {
     let controllers = i2c_config::controllers();
     let pins = i2c_config::pins();
     let muxes = i2c_config::muxes();
+    let pec_devices = i2c_config::pec_devices();
 
     // This is our actual mutable state
     let mut portmap = PortMap::default();
     let mut muxmap = MuxMap::default();
+    let mut stats = StatsTable::default();
 
     // Turn the actual peripheral on so that we can interact with it.
     turn_on_i2c(&controllers);
@@ -236,7 +624,14 @@ fn main() -> ! {
         },
     };
 
-    configure_muxes(&muxes, &controllers, &pins, &mut portmap, &ctrl);
+    configure_muxes(
+        &muxes,
+        &controllers,
+        &pins,
+        &mut portmap,
+        &mut stats,
+        &ctrl,
+    );
 
     loop {
         hl::recv_without_notification(&mut buffer, |op, msg| match op {
@@ -269,16 +664,22 @@ fn main() -> ! {
                     port,
                     mux,
                     &muxes,
+                    &mut stats,
                     &ctrl,
                 ) {
                     Ok(_) => {}
                     Err(code) => {
                         ringbuf_entry!(Trace::Error);
-                        reset_if_needed(code, controller, port, &muxes, mux);
+                        reset_if_needed(
+                            code, controller, port, &muxes, mux, &mut stats,
+                        );
                         return Err(code);
                     }
                 }
 
+                let pec =
+                    pec_enabled(&pec_devices, controller, port, mux, addr);
+
                 let mut total = 0;
 
                 //
@@ -311,8 +712,9 @@ fn main() -> ! {
                     }
 
                     let mut nread = 0;
+                    let start = sys_get_timer().now;
 
-                    match controller.write_read(
+                    let rval = controller.write_read(
                         addr,
                         winfo.len,
                         |pos| wbuf.read_at(pos),
@@ -328,12 +730,24 @@ fn main() -> ! {
 
                             rbuf.write_at(pos, byte)
                         },
+                        pec,
                         &ctrl,
-                    ) {
+                    );
+
+                    stats.record(
+                        controller.controller,
+                        port,
+                        mux,
+                        addr,
+                        rval,
+                        sys_get_timer().now - start,
+                    );
+
+                    match rval {
                         Err(code) => {
                             ringbuf_entry!(Trace::Error);
                             reset_if_needed(
-                                code, controller, port, &muxes, mux,
+                                code, controller, port, &muxes, mux, &mut stats,
                             );
                             return Err(code);
                         }
@@ -346,6 +760,65 @@ fn main() -> ! {
                 caller.reply(total);
                 Ok(())
             }
+            Op::Batch => {
+                let (payload, caller) = msg
+                    .fixed_with_leases::<[u8; 4], usize>(4)
+                    .ok_or(ResponseCode::IllegalLeaseCount)?;
+
+                let (addr, controller, port, mux) =
+                    Marshal::unmarshal(payload)?;
+
+                if ReservedAddress::from_u8(addr).is_some() {
+                    return Err(ResponseCode::ReservedAddress);
+                }
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                configure_port(&mut portmap, controller, port, &pins);
+
+                if let Err(code) = configure_mux(
+                    &mut muxmap,
+                    controller,
+                    port,
+                    mux,
+                    &muxes,
+                    &mut stats,
+                    &ctrl,
+                ) {
+                    ringbuf_entry!(Trace::Error);
+                    reset_if_needed(
+                        code, controller, port, &muxes, mux, &mut stats,
+                    );
+                    return Err(code);
+                }
+
+                let attempted = run_batch(
+                    controller,
+                    port,
+                    mux,
+                    addr,
+                    &muxes,
+                    &pec_devices,
+                    &mut stats,
+                    &caller,
+                    &ctrl,
+                )?;
+
+                caller.reply(attempted);
+                Ok(())
+            }
+            Op::Stats => {
+                let (&index, caller) = msg
+                    .fixed::<u32, I2cStatsEntry>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let entry =
+                    stats.get(index as usize).ok_or(ResponseCode::BadArg)?;
+
+                caller.reply(entry);
+                Ok(())
+            }
             Op::SelectedMuxSegment => {
                 let (payload, caller) = msg
                     .fixed::<[u8; 4], [u8; 4]>()
@@ -494,6 +967,7 @@ fn configure_muxes(
     controllers: &[I2cController<'_>],
     pins: &[I2cPin],
     map: &mut PortMap,
+    stats: &mut StatsTable,
     ctrl: &I2cControl,
 ) {
     let sys = SYS.get_task_id();
@@ -531,11 +1005,11 @@ fn configure_muxes(
                     // deal with the reset).
                     //
                     if let Err(code) =
-                        mux.driver.enable_segment(mux, controller, None, ctrl)
+                        enable_segment(mux, controller, None, ctrl, stats)
                     {
                         ringbuf_entry!(Trace::SegmentFailed(code));
                         if reset_needed(code) && !reset_attempted {
-                            reset(controller, mux.port, muxes, None);
+                            reset(controller, mux.port, muxes, None, stats);
                             reset_attempted = true;
                             continue;
                         }
@@ -545,7 +1019,9 @@ fn configure_muxes(
                 }
                 Err(code) => {
                     ringbuf_entry!(Trace::ConfigureFailed(code));
-                    reset_if_needed(code, controller, mux.port, muxes, None);
+                    reset_if_needed(
+                        code, controller, mux.port, muxes, None, stats,
+                    );
                 }
             }
         }
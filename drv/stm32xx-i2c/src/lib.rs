@@ -35,7 +35,9 @@ pub mod ltc4306;
 pub mod max7358;
 pub mod pca9548;
 
+use core::hash::Hasher;
 use ringbuf::*;
+use smbus_pec::Pec;
 use userlib::*;
 
 use drv_stm32xx_sys_api as sys_api;
@@ -52,6 +54,199 @@ pub struct I2cGpio {
     pub gpio_pins: sys_api::PinSet,
 }
 
+/// Action to take if clocking out a stuck target fails to free the bus
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum I2cEscalation {
+    /// Reset every mux on the port (that has a reset line)
+    MuxReset,
+    /// Power-cycle the devices on the port
+    PowerCycle,
+}
+
+///
+/// Policy for recovering a port whose SDA line is held low by a target --
+/// generally, one that was interrupted in the middle of sending a byte.
+/// Resetting the controller does nothing for such a target; instead, we must
+/// take the port's pins from the controller and clock SCL until the target
+/// has finished its byte and released SDA.
+///
+pub struct I2cRecovery {
+    pub controller: drv_i2c_api::Controller,
+    pub port: drv_i2c_api::PortIndex,
+
+    /// What to do, in order, if clocking doesn't free the bus
+    pub escalation: &'static [I2cEscalation],
+
+    /// Active-high enable for the power to the devices on the port, if any
+    pub power: Option<I2cGpio>,
+}
+
+/// State of a port's lines, as read from its pins
+#[derive(Copy, Clone, Debug)]
+pub enum I2cLines {
+    /// Both lines are high, and the bus is free
+    Released,
+    /// One line is held low, which we take to be SDA
+    Held {
+        scl: sys_api::PinSet,
+        sda: sys_api::PinSet,
+    },
+    /// Both lines are low, which no clocking can fix
+    Stuck,
+}
+
+impl I2cRecovery {
+    /// Maximum number of clocks needed to free SDA: a target can be in the
+    /// middle of at most eight data bits and an acknowledge.
+    pub const MAX_CLOCKS: u8 = 9;
+
+    /// Returns each of the port's pins, one at a time
+    fn lines<'a>(
+        &self,
+        pins: &'a [I2cPin],
+    ) -> impl Iterator<Item = sys_api::PinSet> + 'a {
+        let (controller, port) = (self.controller, self.port);
+
+        pins.iter()
+            .filter(move |p| p.controller == controller && p.port == port)
+            .flat_map(|p| {
+                (0..16)
+                    .filter(move |i| p.gpio_pins.pin_mask & (1 << i) != 0)
+                    .map(move |i| sys_api::PinSet {
+                        port: p.gpio_pins.port,
+                        pin_mask: 1 << i,
+                    })
+            })
+    }
+
+    ///
+    /// Reads the port's lines.  The configuration doesn't say which pin is
+    /// SCL and which is SDA, but we don't need it to: with the controller
+    /// reset, a line held low is SDA, held by a target.  (A target holding
+    /// SCL low looks the same, but clocking SDA while SCL is low generates
+    /// neither a START nor a STOP, and the bus will be found still held.)
+    ///
+    pub fn read(&self, sys: &sys_api::Sys, pins: &[I2cPin]) -> I2cLines {
+        let mut high = None;
+        let mut low = None;
+
+        for pin in self.lines(pins) {
+            if sys.gpio_read(pin) != 0 {
+                high = Some(pin);
+            } else {
+                low = Some(pin);
+            }
+        }
+
+        match (high, low) {
+            (Some(scl), Some(sda)) => I2cLines::Held { scl, sda },
+            (Some(_), None) => I2cLines::Released,
+            _ => I2cLines::Stuck,
+        }
+    }
+
+    ///
+    /// Clocks `scl` until `sda` is released (or we have clocked out a full
+    /// byte and acknowledge), and then issues a STOP to return any targets to
+    /// idle.  The port's pins are returned to the controller before returning
+    /// the number of clocks issued and whether SDA was released.  We run the
+    /// bus at well below its rated speed; one millisecond for each half-period
+    /// is much shorter than the SMBus timeout.
+    ///
+    pub fn clock_out(
+        &self,
+        sys: &sys_api::Sys,
+        pins: &[I2cPin],
+        scl: sys_api::PinSet,
+        sda: sys_api::PinSet,
+    ) -> (u8, bool) {
+        use sys_api::{OutputType, Pull, Speed};
+
+        let half_period = || hl::sleep_for(1);
+        let released = || sys.gpio_read(sda) != 0;
+
+        //
+        // Take both pins as open-drain outputs, setting them before we
+        // configure them to assure that we don't glitch either line low.
+        //
+        for pin in [scl, sda] {
+            sys.gpio_set(pin);
+            sys.gpio_configure_output(
+                pin,
+                OutputType::OpenDrain,
+                Speed::Low,
+                Pull::None,
+            );
+        }
+
+        let mut clocks = 0;
+
+        while clocks < Self::MAX_CLOCKS && !released() {
+            sys.gpio_reset(scl);
+            half_period();
+            sys.gpio_set(scl);
+            half_period();
+            clocks += 1;
+        }
+
+        // A STOP is SDA rising while SCL is high.
+        sys.gpio_reset(scl);
+        half_period();
+        sys.gpio_reset(sda);
+        half_period();
+        sys.gpio_set(scl);
+        half_period();
+        sys.gpio_set(sda);
+        half_period();
+
+        let released = released();
+
+        for pin in pins
+            .iter()
+            .filter(|p| p.controller == self.controller && p.port == self.port)
+        {
+            sys.gpio_configure_alternate(
+                pin.gpio_pins,
+                OutputType::OpenDrain,
+                Speed::Low,
+                Pull::None,
+                pin.function,
+            );
+        }
+
+        (clocks, released)
+    }
+
+    ///
+    /// Removes power from the devices on the port for `ms` milliseconds,
+    /// returning false if there is no power enable.  The enable is set before
+    /// it is configured as an output, so that taking it doesn't glitch power
+    /// off.
+    ///
+    pub fn power_cycle(&self, sys: &sys_api::Sys, ms: u64) -> bool {
+        use sys_api::{OutputType, Pull, Speed};
+
+        match &self.power {
+            Some(pin) => {
+                sys.gpio_set(pin.gpio_pins);
+                sys.gpio_configure_output(
+                    pin.gpio_pins,
+                    OutputType::PushPull,
+                    Speed::Low,
+                    Pull::None,
+                );
+
+                sys.gpio_reset(pin.gpio_pins);
+                hl::sleep_for(ms);
+                sys.gpio_set(pin.gpio_pins);
+                hl::sleep_for(ms);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 pub struct I2cController<'a> {
     pub controller: drv_i2c_api::Controller,
     pub peripheral: sys_api::Peripheral,
@@ -496,6 +691,12 @@ impl I2cController<'_> {
     /// be non-zero.  Additionally, both lengths must be less than 256 bytes:
     /// the device can support longer buffers, and the implementation could
     /// be extended in the future to allow them.
+    ///
+    /// If `pec` is set, the transaction uses SMBus Packet Error Checking: a
+    /// CRC-8 of every byte on the bus (including the address bytes) is sent
+    /// after a write that isn't followed by a read, or is read from the
+    /// device after the data and checked.  The PEC byte counts against the
+    /// 255-byte limit above.
     pub fn write_read(
         &self,
         addr: u8,
@@ -503,6 +704,7 @@ impl I2cController<'_> {
         getbyte: impl Fn(usize) -> Option<u8>,
         mut rlen: ReadLength,
         mut putbyte: impl FnMut(usize, u8) -> Option<()>,
+        pec: bool,
         ctrl: &I2cControl,
     ) -> Result<(), drv_i2c_api::ResponseCode> {
         // Assert our preconditions as described above
@@ -513,15 +715,38 @@ impl I2cController<'_> {
             assert!(rlen <= 255);
         }
 
+        //
+        // The PEC byte is sent at the end of the transaction -- so only by
+        // us if we are doing a write alone.
+        //
+        let wpec = pec && rlen == ReadLength::Fixed(0);
+        let wtotal = wlen + wpec as usize;
+
+        if pec {
+            if wtotal > 255 {
+                return Err(drv_i2c_api::ResponseCode::BadArg);
+            }
+
+            if let ReadLength::Fixed(rlen) = rlen {
+                if rlen + 1 > 255 {
+                    return Err(drv_i2c_api::ResponseCode::BadArg);
+                }
+            }
+        }
+
+        let mut crc = Pec::new();
+
         let i2c = self.registers;
         let notification = self.notification;
 
         self.wait_until_notbusy()?;
 
         if wlen > 0 {
+            crc.write(&[addr << 1]);
+
             #[rustfmt::skip]
             i2c.cr2.modify(|_, w| { w
-                .nbytes().bits(wlen as u8)
+                .nbytes().bits(wtotal as u8)
                 .autoend().clear_bit()
                 .add10().clear_bit()
                 .sadd().bits((addr << 1).into())
@@ -531,7 +756,7 @@ impl I2cController<'_> {
 
             let mut pos = 0;
 
-            while pos < wlen {
+            while pos < wtotal {
                 loop {
                     let isr = i2c.isr.read();
                     ringbuf_entry!(Trace::WriteISR(isr.bits()));
@@ -551,9 +776,16 @@ impl I2cController<'_> {
                     (ctrl.enable)(notification);
                 }
 
-                // Get a single byte.
-                let byte =
-                    getbyte(pos).ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+                // Get a single byte -- or, if we're past the end of our
+                // write, the PEC byte.
+                let byte = if pos < wlen {
+                    let byte = getbyte(pos)
+                        .ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+                    crc.write(&[byte]);
+                    byte
+                } else {
+                    crc.finish() as u8
+                };
 
                 // And send it!
                 i2c.txdr.write(|w| w.txdata().bits(byte));
@@ -582,7 +814,14 @@ impl I2cController<'_> {
             }
         }
 
+        let mut pec_ok = true;
+
         if rlen != ReadLength::Fixed(0) {
+            // Number of PEC bytes to read after the data
+            let mut rpec = pec as usize;
+
+            crc.write(&[(addr << 1) | 1]);
+
             //
             // If we have both a write and a read, we deliberately do not send
             // a STOP between them to force the RESTART (many devices do not
@@ -592,7 +831,7 @@ impl I2cController<'_> {
             if let ReadLength::Fixed(rlen) = rlen {
                 #[rustfmt::skip]
                 i2c.cr2.modify(|_, w| { w
-                    .nbytes().bits(rlen as u8)
+                    .nbytes().bits((rlen + rpec) as u8)
                     .autoend().clear_bit()
                     .add10().clear_bit()
                     .sadd().bits((addr << 1).into())
@@ -616,7 +855,7 @@ impl I2cController<'_> {
 
             loop {
                 if let ReadLength::Fixed(rlen) = rlen {
-                    if pos >= rlen {
+                    if pos >= rlen + rpec {
                         break;
                     }
                 }
@@ -644,9 +883,22 @@ impl I2cController<'_> {
                 let byte: u8 = i2c.rxdr.read().rxdata().bits();
 
                 if rlen == ReadLength::Variable {
+                    //
+                    // The byte count itself is covered by the PEC, but the
+                    // PEC byte is not included in the count.  If the count
+                    // leaves no room for the PEC byte, we read the data
+                    // without it and fail the check.
+                    //
+                    crc.write(&[byte]);
+
+                    if byte == 255 && rpec != 0 {
+                        rpec = 0;
+                        pec_ok = false;
+                    }
+
                     #[rustfmt::skip]
                     i2c.cr2.modify(|_, w| { w
-                        .nbytes().bits(byte)
+                        .nbytes().bits(byte + rpec as u8)
                         .reload().clear_bit()
                     });
 
@@ -654,6 +906,17 @@ impl I2cController<'_> {
                     continue;
                 }
 
+                if let ReadLength::Fixed(rlen) = rlen {
+                    if rpec != 0 && pos == rlen {
+                        // This is the PEC byte; it must match what we have
+                        // calculated from everything before it.
+                        pec_ok = byte == crc.finish() as u8;
+                        pos += 1;
+                        continue;
+                    }
+                }
+
+                crc.write(&[byte]);
                 putbyte(pos, byte).ok_or(drv_i2c_api::ResponseCode::BadArg)?;
                 pos += 1;
             }
@@ -680,6 +943,10 @@ impl I2cController<'_> {
         //
         i2c.cr2.modify(|_, w| w.stop().set_bit());
 
+        if !pec_ok {
+            return Err(drv_i2c_api::ResponseCode::BadPec);
+        }
+
         Ok(())
     }
 
@@ -102,6 +102,7 @@ fn read_reg_u8(
             rval = byte;
             Some(())
         },
+        false,
         ctrl,
     ) {
         Err(code) => Err(mux.error_code(code)),
@@ -122,6 +123,7 @@ fn write_reg_u8(
         |pos| Some(if pos == 0 { reg } else { val }),
         ReadLength::Fixed(0),
         |_, _| Some(()),
+        false,
         ctrl,
     ) {
         Err(code) => Err(mux.error_code(code)),
@@ -43,6 +43,13 @@ pub const MIN_SP_TO_HOST_FILL_DATA_LEN: usize =
 const CHECKSUM_SIZE: usize = core::mem::size_of::<u16>();
 
 pub mod version {
+    /// Version 1, as described in RFD 316.
+    ///
+    /// V1 has since gained the following additions, which don't change the
+    /// layout of any message and so don't warrant a new version; a host that
+    /// predates them sees status bits that it doesn't know, and ignores them:
+    ///
+    /// - [`Status::OVER_POWER_BUDGET`](super::Status::OVER_POWER_BUDGET)
     pub const V1: u32 = 1;
 }
 
@@ -255,6 +262,11 @@ bitflags::bitflags! {
 
         // Resync is a WIP; omit for now.
         // const READY_FOR_RESYNC  = 1 << 2;
+
+        // Set for as long as the system is drawing more power than its
+        // budget allows; the host should shed load until it clears.  This is
+        // an addition to V1 (see `version::V1`), and bit 3 must not be reused.
+        const OVER_POWER_BUDGET = 1 << 3;
     }
 
     // When adding fields to this struct, update the static assertions below to
@@ -678,6 +690,43 @@ mod tests {
         assert_eq!(expected_without_cksum, &buf[..n - CHECKSUM_SIZE]);
     }
 
+    // `Status::OVER_POWER_BUDGET` was added to V1 after the fact, so check
+    // that it lands in the bit that hosts were told about.
+    #[test]
+    fn status_over_power_budget() {
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let header = Header {
+            magic: MAGIC,
+            version: version::V1,
+            sequence: 0x1122_3344_5566_7788,
+        };
+
+        let message = SpToHost::Status {
+            status: Status::OVER_POWER_BUDGET,
+            startup: HostStartupOptions::empty(),
+        };
+        let n = serialize(&mut buf, &header, &message, |_| 0).unwrap();
+        #[rustfmt::skip]
+        let expected_without_cksum: &[u8] = &[
+            // magic
+            0xcc, 0x19, 0xde, 0x01,
+            // version
+            0x01, 0x00, 0x00, 0x00,
+            // sequence
+            0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11,
+            // command
+            0x06,
+            // payload
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(expected_without_cksum, &buf[..n - CHECKSUM_SIZE]);
+
+        let deserialized = deserialize::<SpToHost>(&buf[..n]).unwrap();
+        assert_eq!(header, deserialized.0);
+        assert_eq!(message, deserialized.1);
+    }
+
     #[test]
     fn bad_host_sp_command() {
         #[rustfmt::skip]
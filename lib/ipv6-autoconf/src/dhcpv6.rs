@@ -0,0 +1,578 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Minimal DHCPv6 client (RFC 8415), for a single non-temporary address.
+//!
+//! This only builds and parses messages; the caller runs it over a UDP socket
+//! bound to [`CLIENT_PORT`], sending everything to [`ALL_SERVERS`] port
+//! [`SERVER_PORT`]. It's deliberately simple: we take the first server that
+//! advertises an address, and if we can't renew our lease with that server
+//! before it expires we start again from scratch, rather than rebinding.
+
+/// UDP port that clients listen on
+pub const CLIENT_PORT: u16 = 546;
+/// UDP port that servers listen on
+pub const SERVER_PORT: u16 = 547;
+
+/// All_DHCP_Relay_Agents_and_Servers, `ff02::1:2`
+pub const ALL_SERVERS: [u8; 16] =
+    [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2];
+
+const SOLICIT: u8 = 1;
+const ADVERTISE: u8 = 2;
+const REQUEST: u8 = 3;
+const RENEW: u8 = 5;
+const REPLY: u8 = 7;
+
+const OPT_CLIENTID: u16 = 1;
+const OPT_SERVERID: u16 = 2;
+const OPT_IA_NA: u16 = 3;
+const OPT_IAADDR: u16 = 5;
+const OPT_ELAPSED_TIME: u16 = 8;
+const OPT_STATUS_CODE: u16 = 13;
+
+/// Identifies our one IA_NA; any value will do, as long as it's stable.
+const IAID: [u8; 4] = [0, 0, 0, 1];
+
+// Retransmission parameters, in milliseconds. The maximum solicit interval is
+// much shorter than RFC 8415's hour, so that a bench which gains a DHCPv6
+// server picks it up promptly.
+const SOL_TIMEOUT: u64 = 1_000;
+const SOL_MAX_RT: u64 = 120_000;
+const REQ_TIMEOUT: u64 = 1_000;
+const REQ_MAX_RT: u64 = 30_000;
+const REQ_MAX_RC: u8 = 10;
+const REN_TIMEOUT: u64 = 10_000;
+const REN_MAX_RT: u64 = 600_000;
+
+/// Longest server DUID we're prepared to remember
+const MAX_DUID_LEN: usize = 64;
+
+/// Length of the longest message that we send: the header, then client ID,
+/// server ID, elapsed time, and an IA_NA containing one address.
+pub const MAX_MESSAGE_LEN: usize =
+    4 + (4 + 10) + (4 + MAX_DUID_LEN) + (4 + 2) + (4 + 40);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    Soliciting,
+    Requesting,
+    Bound { renew_at: u64 },
+    Renewing,
+}
+
+/// An address leased to us by a server
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Lease {
+    pub address: [u8; 16],
+    /// Valid lifetime, in seconds
+    pub valid: u32,
+    /// Time after which we should renew, in seconds; 0 if the server left it
+    /// up to us
+    renew: u32,
+}
+
+pub struct Client {
+    duid: [u8; 10],
+    state: State,
+
+    /// Transaction ID of the current exchange
+    xid: [u8; 3],
+    /// When the current exchange started, for the elapsed time option
+    started: u64,
+    /// When to next send a message in the current exchange
+    next_tx: u64,
+    /// How long to wait for a reply after that
+    timeout: u64,
+    tries: u8,
+
+    server: [u8; MAX_DUID_LEN],
+    server_len: usize,
+    /// Address that we've been offered or have leased, which we ask for when
+    /// requesting or renewing
+    address: Option<[u8; 16]>,
+}
+
+impl Client {
+    pub fn new(mac: [u8; 6]) -> Self {
+        // DUID-LL: DUID type 3, hardware type 1 (Ethernet), then our MAC.
+        let mut duid = [0, 3, 0, 1, 0, 0, 0, 0, 0, 0];
+        duid[4..].copy_from_slice(&mac);
+
+        let mut client = Self {
+            duid,
+            state: State::Soliciting,
+            xid: [0; 3],
+            started: 0,
+            next_tx: 0,
+            timeout: 0,
+            tries: 0,
+            server: [0; MAX_DUID_LEN],
+            server_len: 0,
+            address: None,
+        };
+        client.restart(0);
+        client
+    }
+
+    /// Goes back to looking for a server, e.g. because our lease has expired.
+    pub fn restart(&mut self, now: u64) {
+        self.address = None;
+        self.begin(State::Soliciting, now);
+    }
+
+    /// Starts a new exchange, sending its first message at the next poll.
+    fn begin(&mut self, state: State, now: u64) {
+        // Transaction IDs only need to be unlikely to repeat, so mixing the
+        // time with the bottom of our MAC address is plenty.
+        let mac = u32::from_be_bytes(self.duid[6..].try_into().unwrap());
+        let xid = (now as u32 ^ mac).wrapping_mul(0x9e37_79b1);
+
+        self.state = state;
+        self.xid.copy_from_slice(&xid.to_be_bytes()[1..]);
+        self.started = now;
+        self.next_tx = now;
+        self.timeout = match state {
+            State::Requesting => REQ_TIMEOUT,
+            State::Renewing => REN_TIMEOUT,
+            _ => SOL_TIMEOUT,
+        };
+        self.tries = 0;
+    }
+
+    /// Returns the time at which we next need to do something.
+    pub fn poll_at(&self) -> u64 {
+        match self.state {
+            State::Bound { renew_at } => renew_at,
+            _ => self.next_tx,
+        }
+    }
+
+    /// If it's time to send a message, writes it into `buf` (which must be
+    /// at least `MAX_MESSAGE_LEN` long) and returns its length.
+    pub fn transmit(&mut self, now: u64, buf: &mut [u8]) -> Option<usize> {
+        let (kind, max_rt) = match self.state {
+            State::Bound { renew_at } => {
+                if now < renew_at {
+                    return None;
+                }
+                self.begin(State::Renewing, now);
+                (RENEW, REN_MAX_RT)
+            }
+            State::Soliciting => (SOLICIT, SOL_MAX_RT),
+            State::Requesting => {
+                if self.tries >= REQ_MAX_RC {
+                    // The server has gone quiet; find another.
+                    self.restart(now);
+                    (SOLICIT, SOL_MAX_RT)
+                } else {
+                    (REQUEST, REQ_MAX_RT)
+                }
+            }
+            State::Renewing => (RENEW, REN_MAX_RT),
+        };
+        if now < self.next_tx {
+            return None;
+        }
+
+        self.tries = self.tries.saturating_add(1);
+        self.next_tx = now + self.timeout;
+        self.timeout = (self.timeout * 2).min(max_rt);
+
+        let mut msg = Writer { buf, len: 0 };
+        msg.put(&[kind]);
+        msg.put(&self.xid);
+        msg.option(OPT_CLIENTID, &self.duid);
+        if kind != SOLICIT {
+            msg.option(OPT_SERVERID, &self.server[..self.server_len]);
+        }
+        // Elapsed time is in hundredths of a second.
+        let elapsed = ((now - self.started) / 10).min(0xffff) as u16;
+        msg.option(OPT_ELAPSED_TIME, &elapsed.to_be_bytes());
+
+        // IA_NA, with T1 and T2 left to the server, and the address we want if
+        // we have one in mind.
+        let mut ia = [0u8; 40];
+        ia[..4].copy_from_slice(&IAID);
+        let ia_len = match self.address {
+            Some(address) if kind != SOLICIT => {
+                ia[12..14].copy_from_slice(&OPT_IAADDR.to_be_bytes());
+                ia[14..16].copy_from_slice(&24u16.to_be_bytes());
+                ia[16..32].copy_from_slice(&address);
+                40
+            }
+            _ => 12,
+        };
+        msg.option(OPT_IA_NA, &ia[..ia_len]);
+
+        Some(msg.len)
+    }
+
+    /// Processes a message received from a server, returning a lease if the
+    /// message gave us one.
+    ///
+    /// If a request or renewal is refused, this goes back to soliciting; the
+    /// caller is expected to drop any address it has when its lifetime
+    /// expires, and to call `restart` then.
+    pub fn receive(&mut self, now: u64, msg: &[u8]) -> Option<Lease> {
+        if msg.len() < 4 || msg[1..4] != self.xid {
+            return None;
+        }
+
+        let mut ours = false;
+        let mut server = None;
+        let mut success = true;
+        let mut lease = None;
+        for (code, data) in options(&msg[4..]) {
+            match code {
+                OPT_CLIENTID => ours = data == self.duid,
+                OPT_SERVERID => server = Some(data),
+                OPT_STATUS_CODE => success = status_success(data),
+                OPT_IA_NA => lease = parse_ia_na(data),
+                _ => (),
+            }
+        }
+        if !ours {
+            return None;
+        }
+
+        match (self.state, msg[0]) {
+            (State::Soliciting, ADVERTISE) => {
+                let server = server.filter(|s| s.len() <= MAX_DUID_LEN)?;
+                let lease = lease.filter(|_| success)?;
+                self.server[..server.len()].copy_from_slice(server);
+                self.server_len = server.len();
+                self.address = Some(lease.address);
+                self.begin(State::Requesting, now);
+                None
+            }
+            (State::Requesting | State::Renewing, REPLY) => {
+                match lease.filter(|l| success && l.valid != 0) {
+                    Some(lease) => {
+                        let renew = match lease.renew {
+                            0 => lease.valid / 2,
+                            t => t,
+                        };
+                        self.address = Some(lease.address);
+                        self.state = State::Bound {
+                            renew_at: now
+                                .saturating_add(u64::from(renew) * 1000),
+                        };
+                        Some(lease)
+                    }
+                    None => {
+                        self.restart(now);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses an IA_NA option, returning the lease within it (if any).
+fn parse_ia_na(data: &[u8]) -> Option<Lease> {
+    if data.len() < 12 || data[..4] != IAID {
+        return None;
+    }
+    let renew = u32::from_be_bytes(data[4..8].try_into().unwrap());
+
+    let mut lease = None;
+    for (code, data) in options(&data[12..]) {
+        match code {
+            OPT_IAADDR if data.len() >= 24 => {
+                lease = Some(Lease {
+                    address: data[..16].try_into().unwrap(),
+                    valid: u32::from_be_bytes(data[20..24].try_into().unwrap()),
+                    renew,
+                });
+            }
+            OPT_STATUS_CODE if !status_success(data) => return None,
+            _ => (),
+        }
+    }
+    lease
+}
+
+fn status_success(data: &[u8]) -> bool {
+    data.get(..2) == Some(&[0, 0])
+}
+
+/// Iterates over the options in `data`, as `(code, data)` pairs.
+fn options(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> + '_ {
+    core::iter::from_fn(move || {
+        let code = u16::from_be_bytes(data.get(0..2)?.try_into().unwrap());
+        let len = u16::from_be_bytes(data.get(2..4)?.try_into().unwrap());
+        let body = data.get(4..4 + usize::from(len))?;
+        data = &data[4 + body.len()..];
+        Some((code, body))
+    })
+}
+
+/// Builds a message in a buffer; our messages are small and bounded, so
+/// running out of room is a bug.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, data: &[u8]) {
+        self.buf[self.len..][..data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+
+    fn option(&mut self, code: u16, data: &[u8]) {
+        self.put(&code.to_be_bytes());
+        self.put(&(data.len() as u16).to_be_bytes());
+        self.put(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0xa8, 0x40, 0x25, 1, 2, 3];
+    const DUID: [u8; 10] = [0, 3, 0, 1, 0xa8, 0x40, 0x25, 1, 2, 3];
+    const SERVER: [u8; 6] = [0, 3, 0, 1, 0xaa, 0xbb];
+    const ADDRESS: [u8; 16] = [
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x12, 0x34,
+    ];
+
+    /// Sends from `client` at `now`, returning the message
+    fn send(client: &mut Client, now: u64) -> Option<Vec<u8>> {
+        let mut buf = [0u8; MAX_MESSAGE_LEN];
+        let n = client.transmit(now, &mut buf)?;
+        Some(buf[..n].to_vec())
+    }
+
+    fn option(code: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = code.to_be_bytes().to_vec();
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// Builds an IA_NA holding `ADDRESS`, with the given T1 and valid
+    /// lifetime
+    fn ia_na(iaid: [u8; 4], t1: u32, valid: u32) -> Vec<u8> {
+        let mut addr = ADDRESS.to_vec();
+        addr.extend_from_slice(&valid.to_be_bytes());
+        addr.extend_from_slice(&valid.to_be_bytes());
+
+        let mut ia = iaid.to_vec();
+        ia.extend_from_slice(&t1.to_be_bytes());
+        ia.extend_from_slice(&(t1 * 2).to_be_bytes());
+        ia.extend(option(OPT_IAADDR, &addr));
+        option(OPT_IA_NA, &ia)
+    }
+
+    /// Builds a message from the server in reply to `request`
+    fn reply(kind: u8, request: &[u8], options: &[Vec<u8>]) -> Vec<u8> {
+        let mut msg = vec![kind];
+        msg.extend_from_slice(&request[1..4]);
+        msg.extend(option(OPT_CLIENTID, &DUID));
+        msg.extend(option(OPT_SERVERID, &SERVER));
+        for o in options {
+            msg.extend_from_slice(o);
+        }
+        msg
+    }
+
+    fn find(msg: &[u8], code: u16) -> Option<&[u8]> {
+        options(&msg[4..]).find(|(c, _)| *c == code).map(|(_, d)| d)
+    }
+
+    /// Runs a client through to having a lease, returning the time at which
+    /// it got it
+    fn bound(client: &mut Client, t1: u32) -> u64 {
+        let solicit = send(client, 0).unwrap();
+        let advertise = reply(ADVERTISE, &solicit, &[ia_na(IAID, t1, 3600)]);
+        assert_eq!(client.receive(10, &advertise), None);
+
+        let request = send(client, 10).unwrap();
+        let lease = client
+            .receive(20, &reply(REPLY, &request, &[ia_na(IAID, t1, 3600)]));
+        assert_eq!(
+            lease,
+            Some(Lease {
+                address: ADDRESS,
+                valid: 3600,
+                renew: t1,
+            })
+        );
+        20
+    }
+
+    #[test]
+    fn solicit() {
+        let mut client = Client::new(MAC);
+        let msg = send(&mut client, 0).unwrap();
+        assert_eq!(msg[0], SOLICIT);
+        assert_eq!(find(&msg, OPT_CLIENTID), Some(&DUID[..]));
+        assert_eq!(find(&msg, OPT_SERVERID), None);
+        assert_eq!(find(&msg, OPT_ELAPSED_TIME), Some(&[0, 0][..]));
+        assert_eq!(
+            find(&msg, OPT_IA_NA),
+            Some(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0][..])
+        );
+    }
+
+    #[test]
+    fn solicit_backs_off() {
+        let mut client = Client::new(MAC);
+        let first = send(&mut client, 0).unwrap();
+        assert_eq!(client.poll_at(), SOL_TIMEOUT);
+        assert_eq!(send(&mut client, SOL_TIMEOUT - 1), None);
+
+        // Retransmissions keep the transaction, and report the time since
+        // it began in hundredths of a second.
+        let second = send(&mut client, SOL_TIMEOUT).unwrap();
+        assert_eq!(second[1..4], first[1..4]);
+        assert_eq!(find(&second, OPT_ELAPSED_TIME), Some(&[0, 100][..]));
+        assert_eq!(client.poll_at(), SOL_TIMEOUT * 3);
+
+        // The interval doubles up to its limit.
+        let mut now = client.poll_at();
+        for _ in 0..20 {
+            send(&mut client, now).unwrap();
+            now = client.poll_at();
+        }
+        send(&mut client, now).unwrap();
+        assert_eq!(client.poll_at() - now, SOL_MAX_RT);
+    }
+
+    #[test]
+    fn request_follows_advertise() {
+        let mut client = Client::new(MAC);
+        let solicit = send(&mut client, 0).unwrap();
+        let advertise = reply(ADVERTISE, &solicit, &[ia_na(IAID, 600, 3600)]);
+        assert_eq!(client.receive(10, &advertise), None);
+
+        // The request goes to the server that advertised, for the address
+        // that it offered.
+        let request = send(&mut client, 10).unwrap();
+        assert_eq!(request[0], REQUEST);
+        assert_ne!(request[1..4], solicit[1..4]);
+        assert_eq!(find(&request, OPT_SERVERID), Some(&SERVER[..]));
+        let ia = find(&request, OPT_IA_NA).unwrap();
+        assert_eq!(ia.len(), 40);
+        assert_eq!(ia[16..32], ADDRESS);
+    }
+
+    #[test]
+    fn lease_and_renew() {
+        let mut client = Client::new(MAC);
+        let now = bound(&mut client, 600);
+        assert_eq!(client.poll_at(), now + 600_000);
+        assert_eq!(send(&mut client, now + 599_999), None);
+
+        let renew = send(&mut client, now + 600_000).unwrap();
+        assert_eq!(renew[0], RENEW);
+        assert_eq!(find(&renew, OPT_SERVERID), Some(&SERVER[..]));
+        let lease = client.receive(
+            now + 600_010,
+            &reply(REPLY, &renew, &[ia_na(IAID, 600, 7200)]),
+        );
+        assert_eq!(lease.map(|l| l.valid), Some(7200));
+    }
+
+    #[test]
+    fn renew_defaults_to_half_lifetime() {
+        let mut client = Client::new(MAC);
+        let now = bound(&mut client, 0);
+        assert_eq!(client.poll_at(), now + 1_800_000);
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        let mut client = Client::new(MAC);
+        let solicit = send(&mut client, 0).unwrap();
+
+        let mut other = solicit.clone();
+        other[3] ^= 1;
+        let advertise = reply(ADVERTISE, &other, &[ia_na(IAID, 0, 3600)]);
+        client.receive(10, &advertise);
+        assert_eq!(send(&mut client, 10), None);
+
+        // Someone else's client ID
+        let mut advertise = reply(ADVERTISE, &solicit, &[ia_na(IAID, 0, 3600)]);
+        advertise[8 + 9] ^= 1;
+        client.receive(10, &advertise);
+        assert_eq!(send(&mut client, 10), None);
+
+        // No server ID
+        let mut advertise = vec![ADVERTISE];
+        advertise.extend_from_slice(&solicit[1..4]);
+        advertise.extend(option(OPT_CLIENTID, &DUID));
+        advertise.extend(ia_na(IAID, 0, 3600));
+        client.receive(10, &advertise);
+        assert_eq!(send(&mut client, 10), None);
+
+        // Another IA
+        let advertise = reply(ADVERTISE, &solicit, &[ia_na([9; 4], 0, 3600)]);
+        client.receive(10, &advertise);
+        assert_eq!(send(&mut client, 10), None);
+    }
+
+    #[test]
+    fn refused_request_restarts() {
+        let mut client = Client::new(MAC);
+        let solicit = send(&mut client, 0).unwrap();
+        let advertise = reply(ADVERTISE, &solicit, &[ia_na(IAID, 0, 3600)]);
+        client.receive(10, &advertise);
+        let request = send(&mut client, 10).unwrap();
+
+        // NoAddrsAvail
+        let status = option(OPT_STATUS_CODE, &[0, 2]);
+        assert_eq!(
+            client.receive(20, &reply(REPLY, &request, &[status])),
+            None
+        );
+        assert_eq!(send(&mut client, 20).unwrap()[0], SOLICIT);
+    }
+
+    #[test]
+    fn silent_server_restarts() {
+        let mut client = Client::new(MAC);
+        let solicit = send(&mut client, 0).unwrap();
+        let advertise = reply(ADVERTISE, &solicit, &[ia_na(IAID, 0, 3600)]);
+        client.receive(10, &advertise);
+
+        let mut now = 10;
+        for _ in 0..REQ_MAX_RC {
+            assert_eq!(send(&mut client, now).unwrap()[0], REQUEST);
+            now = client.poll_at();
+        }
+        assert_eq!(send(&mut client, now).unwrap()[0], SOLICIT);
+    }
+
+    #[test]
+    fn ia_na_parsing() {
+        let ia = ia_na(IAID, 600, 3600);
+        let lease = parse_ia_na(&ia[4..]).unwrap();
+        assert_eq!(lease.address, ADDRESS);
+        assert_eq!(lease.valid, 3600);
+        assert_eq!(lease.renew, 600);
+
+        assert_eq!(parse_ia_na(&ia_na([0, 0, 0, 2], 600, 3600)[4..]), None);
+        assert_eq!(parse_ia_na(&ia[4..12]), None);
+
+        // A failure status inside the IA_NA
+        let mut ia = ia[4..].to_vec();
+        ia.extend(option(OPT_STATUS_CODE, &[0, 2]));
+        assert_eq!(parse_ia_na(&ia), None);
+    }
+
+    #[test]
+    fn options_stop_at_truncation() {
+        let mut data = option(1, &[1, 2]);
+        data.extend(option(2, &[]));
+        data.extend_from_slice(&[0, 3, 0, 4, 1]);
+        let parsed: Vec<_> = options(&data).collect();
+        assert_eq!(parsed, [(1, &[1, 2][..]), (2, &[][..])]);
+    }
+}
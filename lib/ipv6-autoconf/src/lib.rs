@@ -0,0 +1,38 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! IPv6 address autoconfiguration for the `net` task: router discovery, for
+//! SLAAC and default routes, and a minimal DHCPv6 client.
+//!
+//! smoltcp does neither, so the `net` task does them over its own sockets.
+//! The protocol logic lives here, working on plain byte arrays rather than
+//! smoltcp's types, so that it can be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod dhcpv6;
+pub mod ndisc;
+
+/// Checks whether `address` is link-local (`fe80::/10`).
+pub fn is_link_local(address: &[u8; 16]) -> bool {
+    address[0] == 0xfe && address[1] & 0xc0 == 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_local() {
+        let mut a = [0u8; 16];
+        a[0] = 0xfe;
+        a[1] = 0x80;
+        assert!(is_link_local(&a));
+        a[1] = 0xbf;
+        assert!(is_link_local(&a));
+        a[1] = 0xc0;
+        assert!(!is_link_local(&a));
+        assert!(!is_link_local(&[0; 16]));
+    }
+}
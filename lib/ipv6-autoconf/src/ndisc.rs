@@ -0,0 +1,297 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Router discovery (RFC 4861): parsing router advertisements, and building
+//! router solicitations.
+//!
+//! smoltcp does neighbor discovery itself, but ignores router advertisements,
+//! so these go over a raw ICMPv6 socket; that's why packets here include their
+//! IPv6 header.
+
+use crate::is_link_local;
+
+/// All-routers multicast address, `ff02::2`
+pub const ALL_ROUTERS: [u8; 16] =
+    [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+/// IPv6 next header value for ICMPv6
+const ICMPV6: u8 = 58;
+
+const ROUTER_SOLICIT: u8 = 133;
+const ROUTER_ADVERT: u8 = 134;
+
+const OPT_SOURCE_LL_ADDR: u8 = 1;
+const OPT_PREFIX_INFO: u8 = 3;
+
+/// Length of a router solicitation, including its IPv6 header
+pub const RS_LEN: usize = 56;
+
+/// The parts of a router advertisement that we care about
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RouterAdvert {
+    pub source: [u8; 16],
+    /// Router lifetime, in seconds; 0 means that it isn't a default router
+    pub lifetime: u16,
+    /// The first prefix that's usable for SLAAC, if any
+    pub prefix: Option<Prefix>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Prefix {
+    pub prefix: [u8; 16],
+    /// Valid lifetime, in seconds; `u32::MAX` is forever
+    pub valid: u32,
+}
+
+/// Parses `packet` (including its IPv6 header) as a router advertisement,
+/// checking it as RFC 4861 section 6.1.2 requires.
+pub fn parse_router_advert(packet: &[u8]) -> Option<RouterAdvert> {
+    let (header, msg) = (packet.get(..40)?, &packet[40..]);
+    let payload_len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+    let msg = msg.get(..payload_len)?;
+
+    let source: [u8; 16] = header[8..24].try_into().unwrap();
+    let dest: [u8; 16] = header[24..40].try_into().unwrap();
+    if header[6] != ICMPV6
+        || header[7] != 255
+        || !is_link_local(&source)
+        || msg.len() < 16
+        || msg[0] != ROUTER_ADVERT
+        || msg[1] != 0
+        || icmpv6_checksum(&source, &dest, msg) != 0
+    {
+        return None;
+    }
+
+    let lifetime = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut prefix = None;
+    let mut options = &msg[16..];
+    while options.len() >= 8 {
+        let len = usize::from(options[1]) * 8;
+        if len == 0 || len > options.len() {
+            // Malformed; RFC 4861 says to drop the whole thing.
+            return None;
+        }
+        let (option, rest) = options.split_at(len);
+        options = rest;
+
+        // Prefix information, with the autonomous flag set, for a /64 (which
+        // is the only length that works with our interface IDs).
+        if option[0] == OPT_PREFIX_INFO
+            && len == 32
+            && option[2] == 64
+            && option[3] & 0x40 != 0
+            && prefix.is_none()
+        {
+            let p: [u8; 16] = option[16..32].try_into().unwrap();
+            if !is_link_local(&p) {
+                prefix = Some(Prefix {
+                    prefix: p,
+                    valid: u32::from_be_bytes(option[4..8].try_into().unwrap()),
+                });
+            }
+        }
+    }
+
+    Some(RouterAdvert {
+        source,
+        lifetime,
+        prefix,
+    })
+}
+
+/// Builds a router solicitation from `source`, including the IPv6 header,
+/// with `mac` as the source link-layer address option.
+pub fn router_solicit(source: [u8; 16], mac: [u8; 6]) -> [u8; RS_LEN] {
+    let mut packet = [0u8; RS_LEN];
+    let (header, msg) = packet.split_at_mut(40);
+
+    header[0] = 0x60;
+    header[4..6].copy_from_slice(&16u16.to_be_bytes());
+    header[6] = ICMPV6;
+    header[7] = 255;
+    header[8..24].copy_from_slice(&source);
+    header[24..40].copy_from_slice(&ALL_ROUTERS);
+
+    msg[0] = ROUTER_SOLICIT;
+    // Source link-layer address option, which is one 8-byte unit long.
+    msg[8] = OPT_SOURCE_LL_ADDR;
+    msg[9] = 1;
+    msg[10..16].copy_from_slice(&mac);
+    let checksum = icmpv6_checksum(&source, &ALL_ROUTERS, msg);
+    msg[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    packet
+}
+
+/// Computes the checksum of an ICMPv6 message, which covers a pseudo-header
+/// made from the IPv6 header. A message whose checksum field is correct sums
+/// to zero.
+fn icmpv6_checksum(src: &[u8; 16], dst: &[u8; 16], msg: &[u8]) -> u16 {
+    let len = (msg.len() as u32).to_be_bytes();
+    let next_header = u32::from(ICMPV6).to_be_bytes();
+
+    let mut sum = 0u32;
+    for data in [&src[..], &dst[..], &len, &next_header, msg] {
+        for pair in data.chunks(2) {
+            let hi = pair[0];
+            let lo = pair.get(1).copied().unwrap_or(0);
+            sum += u32::from(u16::from_be_bytes([hi, lo]));
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTER: [u8; 16] =
+        [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const ALL_NODES: [u8; 16] =
+        [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const PREFIX: [u8; 16] =
+        [0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    /// Builds a prefix information option for `prefix`
+    fn prefix_option(prefix: [u8; 16], len: u8, flags: u8) -> [u8; 32] {
+        let mut option = [0u8; 32];
+        option[0] = OPT_PREFIX_INFO;
+        option[1] = 4;
+        option[2] = len;
+        option[3] = flags;
+        option[4..8].copy_from_slice(&3600u32.to_be_bytes());
+        option[8..12].copy_from_slice(&1800u32.to_be_bytes());
+        option[16..].copy_from_slice(&prefix);
+        option
+    }
+
+    /// Builds a router advertisement from `ROUTER`, with a correct checksum
+    fn advert(lifetime: u16, options: &[&[u8]]) -> Vec<u8> {
+        let mut msg = vec![0u8; 16];
+        msg[0] = ROUTER_ADVERT;
+        msg[4] = 64;
+        msg[6..8].copy_from_slice(&lifetime.to_be_bytes());
+        for option in options {
+            msg.extend_from_slice(option);
+        }
+        let checksum = icmpv6_checksum(&ROUTER, &ALL_NODES, &msg);
+        msg[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[ICMPV6, 255]);
+        packet.extend_from_slice(&ROUTER);
+        packet.extend_from_slice(&ALL_NODES);
+        packet.extend_from_slice(&msg);
+        packet
+    }
+
+    #[test]
+    fn solicit_checksum() {
+        let mut source = ROUTER;
+        source[15] = 2;
+        let rs = router_solicit(source, [0xa8, 0x40, 0x25, 1, 2, 3]);
+        assert_eq!(rs[0], 0x60);
+        assert_eq!(&rs[24..40], &ALL_ROUTERS);
+        assert_eq!(rs[40], ROUTER_SOLICIT);
+        assert_eq!(&rs[50..56], &[0xa8, 0x40, 0x25, 1, 2, 3]);
+        assert_eq!(icmpv6_checksum(&source, &ALL_ROUTERS, &rs[40..]), 0);
+    }
+
+    #[test]
+    fn checksum_odd_length() {
+        // A trailing odd byte is padded with zero; the pseudo-header adds the
+        // length and next header.
+        let sum = icmpv6_checksum(&[0; 16], &[0; 16], &[0x12]);
+        assert_eq!(sum, !(0x1200 + 1 + u16::from(ICMPV6)));
+    }
+
+    #[test]
+    fn advert_with_prefix() {
+        let packet = advert(1800, &[&prefix_option(PREFIX, 64, 0xc0)]);
+        assert_eq!(
+            parse_router_advert(&packet),
+            Some(RouterAdvert {
+                source: ROUTER,
+                lifetime: 1800,
+                prefix: Some(Prefix {
+                    prefix: PREFIX,
+                    valid: 3600,
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn advert_without_usable_prefix() {
+        // Not autonomous
+        let a = prefix_option(PREFIX, 64, 0x80);
+        // Wrong length for our interface IDs
+        let b = prefix_option(PREFIX, 48, 0xc0);
+        // Link-local
+        let c = prefix_option(ROUTER, 64, 0xc0);
+        // Some other option, which is skipped
+        let d = [OPT_SOURCE_LL_ADDR, 1, 0, 0, 0, 0, 0, 0];
+
+        let ra = parse_router_advert(&advert(0, &[&a, &b, &c, &d])).unwrap();
+        assert_eq!(ra.lifetime, 0);
+        assert_eq!(ra.prefix, None);
+    }
+
+    #[test]
+    fn advert_takes_first_prefix() {
+        let mut other = PREFIX;
+        other[7] = 3;
+        let a = prefix_option(PREFIX, 64, 0x40);
+        let b = prefix_option(other, 64, 0x40);
+        let ra = parse_router_advert(&advert(1800, &[&a, &b])).unwrap();
+        assert_eq!(ra.prefix.unwrap().prefix, PREFIX);
+    }
+
+    #[test]
+    fn advert_rejected() {
+        let good = advert(1800, &[&prefix_option(PREFIX, 64, 0xc0)]);
+        assert!(parse_router_advert(&good).is_some());
+
+        // Bad checksum
+        let mut p = good.clone();
+        p[50] ^= 1;
+        assert_eq!(parse_router_advert(&p), None);
+
+        // Forwarded by a router, so hop limit isn't 255
+        let mut p = good.clone();
+        p[7] = 254;
+        assert_eq!(parse_router_advert(&p), None);
+
+        // Not from a link-local address
+        let mut p = good.clone();
+        p[8..24].copy_from_slice(&PREFIX);
+        assert_eq!(parse_router_advert(&p), None);
+
+        // Truncated
+        assert_eq!(parse_router_advert(&good[..good.len() - 1]), None);
+        assert_eq!(parse_router_advert(&good[..30]), None);
+
+        // Some other ICMPv6 message
+        let mut p = good.clone();
+        p[40] = ROUTER_SOLICIT;
+        assert_eq!(parse_router_advert(&p), None);
+    }
+
+    #[test]
+    fn advert_malformed_option() {
+        let mut option = prefix_option(PREFIX, 64, 0xc0);
+        option[1] = 0;
+        assert_eq!(parse_router_advert(&advert(1800, &[&option])), None);
+
+        // An option that claims to run past the end of the message
+        option[1] = 5;
+        assert_eq!(parse_router_advert(&advert(1800, &[&option])), None);
+    }
+}
@@ -0,0 +1,302 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Emulated I2C targets and the bus that connects them
+
+use crate::responder::Responder;
+
+/// Errors returned by an emulated bus or target.  These map onto the
+/// corresponding `drv_i2c_api::ResponseCode` variants.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// No device acknowledged the address
+    NoDevice,
+    /// The device does not have the indicated register
+    NoRegister,
+    /// The transaction had neither a write nor a read
+    BadArg,
+}
+
+/// An emulated I2C target
+pub trait Target {
+    /// Performs a write followed by a read (with a repeated start between
+    /// them), returning the number of bytes read.  Either buffer may be empty.
+    fn write_read(
+        &mut self,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<usize, Error>;
+
+    /// Changes the value of a register out-of-band, e.g. to emulate a sensor
+    /// reading changing.  Targets without registers need not implement this.
+    fn set_register(&mut self, _reg: u8, _value: u32) -> Result<(), Error> {
+        Err(Error::NoRegister)
+    }
+}
+
+/// A target which acknowledges every transaction, ignoring writes and reading
+/// nothing.  This is sufficient to convince a driver that a device is present.
+pub struct Null;
+
+impl Target for Null {
+    fn write_read(&mut self, _: &[u8], _: &mut [u8]) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// A target with a bank of fixed-width registers, selected by writing the
+/// register number as the first byte of a transaction.
+///
+/// Any further bytes in the write are stored starting at the selected
+/// register.  Reads start at the selected register and may continue into
+/// subsequent registers; the register pointer is not advanced by reads, so
+/// repeated reads return the same register.
+///
+/// The same semantics apply when the register file is driven a byte at a time
+/// as a [`Responder`], with each START beginning a new transaction.
+pub struct RegisterFile<S> {
+    data: S,
+    width: usize,
+    big_endian: bool,
+    pointer: usize,
+    /// Byte offset from the pointer within the current transaction, when
+    /// driven as a [`Responder`]
+    offset: usize,
+    /// Whether the next byte received selects the register
+    select: bool,
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> RegisterFile<S> {
+    /// Creates a register file backed by `data`, which holds
+    /// `data.len() / width` registers of `width` bytes each.
+    pub fn new(data: S, width: usize, big_endian: bool) -> Self {
+        Self {
+            data,
+            width,
+            big_endian,
+            pointer: 0,
+            offset: 0,
+            select: false,
+        }
+    }
+
+    fn registers(&self) -> usize {
+        self.data.as_ref().len() / self.width
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Target for RegisterFile<S> {
+    fn write_read(
+        &mut self,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<usize, Error> {
+        let limit = self.registers() * self.width;
+
+        if let Some((&reg, payload)) = write.split_first() {
+            if reg as usize >= self.registers() {
+                return Err(Error::NoRegister);
+            }
+            self.pointer = reg as usize;
+
+            let start = self.pointer * self.width;
+            if start + payload.len() > limit {
+                return Err(Error::NoRegister);
+            }
+            self.data.as_mut()[start..][..payload.len()]
+                .copy_from_slice(payload);
+        }
+
+        let start = self.pointer * self.width;
+        let n = read.len().min(limit - start);
+        read[..n].copy_from_slice(&self.data.as_ref()[start..][..n]);
+        Ok(n)
+    }
+
+    fn set_register(&mut self, reg: u8, value: u32) -> Result<(), Error> {
+        let reg = reg as usize;
+        if reg >= self.registers() || self.width > 4 {
+            return Err(Error::NoRegister);
+        }
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        let bytes = if self.big_endian {
+            &bytes[4 - self.width..]
+        } else {
+            &bytes[..self.width]
+        };
+        self.data.as_mut()[reg * self.width..][..self.width]
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Responder for RegisterFile<S> {
+    fn start(&mut self) {
+        self.offset = 0;
+        self.select = true;
+    }
+
+    fn rx(&mut self, byte: u8) {
+        if self.select {
+            self.select = false;
+
+            // As with a real device, a bad register number is ignored (there
+            // being no way to NACK it after the fact).
+            if (byte as usize) < self.registers() {
+                self.pointer = byte as usize;
+            }
+        } else {
+            let pos = self.pointer * self.width + self.offset;
+
+            if let Some(b) = self.data.as_mut().get_mut(pos) {
+                *b = byte;
+                self.offset += 1;
+            }
+        }
+    }
+
+    fn tx(&mut self) -> Option<u8> {
+        // A read following a repeated START starts at the selected register.
+        self.select = false;
+
+        let byte = *self
+            .data
+            .as_ref()
+            .get(self.pointer * self.width + self.offset)?;
+        self.offset += 1;
+        Some(byte)
+    }
+}
+
+/// Identifies a device on an emulated bus, following the 5-tuple used by
+/// `drv_i2c_api::I2cDevice`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Address {
+    pub controller: u8,
+    pub port: u8,
+    pub segment: Option<(u8, u8)>,
+    pub address: u8,
+}
+
+/// A device attached to an emulated bus
+pub struct Device<'a> {
+    pub address: Address,
+    /// Whether the device acknowledges its address; clearing this emulates
+    /// the device being removed (or failing) without reconfiguring the bus.
+    pub present: bool,
+    pub target: &'a mut dyn Target,
+}
+
+/// A set of emulated devices, dispatching transactions by address
+pub struct Bus<'a, 'b> {
+    devices: &'b mut [Device<'a>],
+    fallback: Option<&'b mut dyn Target>,
+}
+
+impl<'a, 'b> Bus<'a, 'b> {
+    /// Creates a bus with the given devices.  If `fallback` is provided,
+    /// transactions to any other address are sent to it rather than being
+    /// NACKed.
+    pub fn new(
+        devices: &'b mut [Device<'a>],
+        fallback: Option<&'b mut dyn Target>,
+    ) -> Self {
+        Self { devices, fallback }
+    }
+
+    pub fn write_read(
+        &mut self,
+        address: Address,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<usize, Error> {
+        if write.is_empty() && read.is_empty() {
+            return Err(Error::BadArg);
+        }
+
+        match self.devices.iter_mut().find(|d| d.address == address) {
+            Some(d) if d.present => d.target.write_read(write, read),
+            Some(_) => Err(Error::NoDevice),
+            None => match &mut self.fallback {
+                Some(t) => t.write_read(write, read),
+                None => Err(Error::NoDevice),
+            },
+        }
+    }
+
+    pub fn device_mut(&mut self, address: Address) -> Option<&mut Device<'a>> {
+        self.devices.iter_mut().find(|d| d.address == address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(address: u8) -> Address {
+        Address {
+            controller: 2,
+            port: 0,
+            segment: None,
+            address,
+        }
+    }
+
+    #[test]
+    fn register_file() {
+        let mut regs = RegisterFile::new([0u8; 8], 2, true);
+        regs.set_register(1, 0x1234).unwrap();
+
+        let mut buf = [0u8; 2];
+        assert_eq!(regs.write_read(&[1], &mut buf), Ok(2));
+        assert_eq!(buf, [0x12, 0x34]);
+
+        // Bytes after the register number are written starting at that register
+        assert_eq!(regs.write_read(&[2, 0xab, 0xcd], &mut []), Ok(0));
+        let mut buf = [0u8; 8];
+        assert_eq!(regs.write_read(&[], &mut buf), Ok(4));
+        assert_eq!(&buf[..4], &[0xab, 0xcd, 0, 0]);
+
+        assert_eq!(regs.write_read(&[4], &mut buf), Err(Error::NoRegister));
+        assert_eq!(regs.set_register(4, 0), Err(Error::NoRegister));
+    }
+
+    #[test]
+    fn bus() {
+        let mut regs = RegisterFile::new([0u8; 4], 1, false);
+        let mut null = Null;
+        let mut devices = [Device {
+            address: addr(0x48),
+            present: true,
+            target: &mut regs,
+        }];
+        let mut bus = Bus::new(&mut devices, None);
+
+        let mut buf = [0u8; 1];
+        assert_eq!(bus.write_read(addr(0x48), &[3, 7], &mut []), Ok(0));
+        assert_eq!(bus.write_read(addr(0x48), &[3], &mut buf), Ok(1));
+        assert_eq!(buf, [7]);
+        assert_eq!(
+            bus.write_read(addr(0x49), &[0], &mut buf),
+            Err(Error::NoDevice)
+        );
+        assert_eq!(
+            bus.write_read(addr(0x48), &[], &mut []),
+            Err(Error::BadArg)
+        );
+
+        bus.device_mut(addr(0x48)).unwrap().present = false;
+        assert_eq!(
+            bus.write_read(addr(0x48), &[0], &mut buf),
+            Err(Error::NoDevice)
+        );
+
+        let mut bus = Bus::new(&mut devices, Some(&mut null));
+        assert_eq!(bus.write_read(addr(0x49), &[0], &mut buf), Ok(0));
+    }
+}
@@ -0,0 +1,20 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Models of emulated peripherals, shared between Hubris tasks and the host.
+//!
+//! The emulator tasks (e.g. `drv-sidecar-mainboard-i2c-emulator` and
+//! `drv-mock-gimlet-seq-server`) are thin IPC wrappers around the models in
+//! this crate.  Because the models have no dependency on `userlib`, they can
+//! also be built for the host, where `cargo xtask emulate` drives them from a
+//! scripted bus model.  Devices modeled a byte at a time (see [`responder`])
+//! can also be served from a controller in target mode, allowing a board to
+//! emulate devices for the real drivers on another board.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod i2c;
+pub mod models;
+pub mod responder;
+pub mod seq;
@@ -0,0 +1,62 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Preconfigured models of specific parts
+
+use crate::i2c::{RegisterFile, Target};
+
+/// A TMP117 temperature sensor at power-on, reading 25 °C
+pub fn tmp117() -> RegisterFile<[u8; 32]> {
+    let mut regs = RegisterFile::new([0; 32], 2, true);
+
+    // Temperature, in units of 7.8125 m°C
+    regs.set_register(0x00, 25 * 128).unwrap();
+    // Configuration, as at reset
+    regs.set_register(0x01, 0x0220).unwrap();
+    // High and low limits, as at reset
+    regs.set_register(0x02, 0x6000).unwrap();
+    regs.set_register(0x03, 0x8000).unwrap();
+    // Device ID
+    regs.set_register(0x0f, 0x0117).unwrap();
+
+    regs
+}
+
+/// An LTC4282 hot swap controller in 12 V mode, with the control register
+/// holding its defaults (which the driver checks to validate the part), and
+/// its ADCs reading 12 V and a sense voltage of 3 mV.
+pub fn ltc4282() -> RegisterFile<[u8; 0x4e]> {
+    // Registers are addressed by byte; 16-bit registers are big-endian, and
+    // span two addresses.
+    let mut regs = RegisterFile::new([0; 0x4e], 1, false);
+    let mut set = |reg: u8, value: u16| {
+        let [msb, lsb] = value.to_be_bytes();
+        regs.set_register(reg, msb.into()).unwrap();
+        regs.set_register(reg + 1, lsb.into()).unwrap();
+    };
+
+    // CONTROL
+    set(0x00, 0xbb02);
+    // VSOURCE, in units of 16.64 V / 65535
+    set(0x3a, 47260);
+    // VSENSE, in units of 40 mV / 65535
+    set(0x40, 4915);
+
+    regs
+}
+
+/// A MAX31790 fan controller at power-on.  Its PWM frequency register holds
+/// valid frequencies (which the driver checks to validate the part), and the
+/// duty cycle written to each fan can be read back from its PWMOUT target
+/// duty cycle register.
+pub fn max31790() -> RegisterFile<[u8; 0x60]> {
+    let mut regs = RegisterFile::new([0; 0x60], 1, false);
+
+    // Global configuration
+    regs.set_register(0x00, 0x20).unwrap();
+    // PWM frequency: 125 Hz for every output
+    regs.set_register(0x01, 0x44).unwrap();
+
+    regs
+}
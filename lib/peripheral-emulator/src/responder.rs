@@ -0,0 +1,483 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Byte-level models of I2C targets
+//!
+//! When a controller operates in target mode (see `drv-stm32xx-i2c`'s
+//! `operate_as_target`), it sees a transaction as it happens on the wire: a
+//! START addressed to us, followed by bytes written to us or read from us.  A
+//! [`Responder`] models a device at this level; [`ByteTarget`] adapts one to
+//! the transaction-level [`Target`] interface so that the same model can be
+//! placed on an emulated [`Bus`](crate::i2c::Bus).
+
+use crate::i2c::{Error, Target};
+
+/// A device modeled a byte at a time, as seen by an I2C target
+pub trait Responder {
+    /// Called on each START (or repeated START) addressed to this device
+    fn start(&mut self);
+
+    /// Called for each byte written to this device
+    fn rx(&mut self, byte: u8);
+
+    /// Called for each byte read from this device, returning `None` if there
+    /// is nothing to send (in which case the bus sees filler)
+    fn tx(&mut self) -> Option<u8>;
+
+    /// Called on a STOP ending a transaction with this device.  The target
+    /// loop in `drv-stm32xx-i2c` doesn't report STOPs, so a device must still
+    /// behave if this is never called.
+    fn stop(&mut self) {}
+}
+
+/// Adapts a [`Responder`] to the [`Target`] interface.  A write followed by a
+/// read is seen as the write, a repeated START and the read; reads stop at
+/// the first byte that the responder has nothing to send for.
+pub struct ByteTarget<R>(pub R);
+
+impl<R: Responder> Target for ByteTarget<R> {
+    fn write_read(
+        &mut self,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.0.start();
+
+        for &byte in write {
+            self.0.rx(byte);
+        }
+
+        if !write.is_empty() && !read.is_empty() {
+            self.0.start();
+        }
+
+        let mut n = 0;
+
+        for byte in read.iter_mut() {
+            match self.0.tx() {
+                Some(b) => *byte = b,
+                None => break,
+            }
+            n += 1;
+        }
+
+        self.0.stop();
+        Ok(n)
+    }
+}
+
+/// A serial EEPROM: a 1-byte address (or a 2-byte big-endian address, if
+/// larger than 256 bytes) followed by data, with the address incrementing
+/// after each byte and wrapping at the end of the memory.
+pub struct Memory<S> {
+    data: S,
+    address: usize,
+    /// Number of address bytes still to be received in this transaction
+    pending: usize,
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Memory<S> {
+    pub fn new(data: S) -> Self {
+        Self {
+            data,
+            address: 0,
+            pending: 0,
+        }
+    }
+
+    fn address_bytes(&self) -> usize {
+        if self.data.as_ref().len() > 256 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn advance(&mut self) {
+        self.address = (self.address + 1) % self.data.as_ref().len();
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Responder for Memory<S> {
+    fn start(&mut self) {
+        self.pending = self.address_bytes();
+    }
+
+    fn rx(&mut self, byte: u8) {
+        if self.pending == self.address_bytes() {
+            self.address = 0;
+        }
+
+        if self.pending > 0 {
+            self.pending -= 1;
+            self.address = ((self.address << 8) | byte as usize)
+                % self.data.as_ref().len();
+        } else {
+            self.data.as_mut()[self.address] = byte;
+            self.advance();
+        }
+    }
+
+    fn tx(&mut self) -> Option<u8> {
+        // A read without an address continues from the current address.
+        self.pending = 0;
+
+        let byte = self.data.as_ref()[self.address];
+        self.advance();
+        Some(byte)
+    }
+}
+
+/// The maximum length of an SMBus block
+pub const SMBUS_BLOCK_MAX: usize = 32;
+
+/// The PMBus `PAGE` command, which selects the page for paged commands
+pub const PMBUS_PAGE: u8 = 0x00;
+
+/// How an [`SmbusCommand`] is transferred
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Access {
+    Byte,
+    /// Little-endian 16-bit word
+    Word,
+    /// Block, preceded on the wire by its length
+    Block,
+}
+
+/// A single entry in the command table of an [`Smbus`] device
+#[derive(Copy, Clone, Debug)]
+pub struct SmbusCommand {
+    pub code: u8,
+    /// The page this command applies to, or `None` if it isn't paged
+    pub page: Option<u8>,
+    pub access: Access,
+    pub writable: bool,
+    data: [u8; SMBUS_BLOCK_MAX],
+    len: usize,
+}
+
+impl SmbusCommand {
+    pub fn byte(code: u8, page: Option<u8>, value: u8) -> Self {
+        Self::new(code, page, Access::Byte, &[value])
+    }
+
+    pub fn word(code: u8, page: Option<u8>, value: u16) -> Self {
+        Self::new(code, page, Access::Word, &value.to_le_bytes())
+    }
+
+    /// Returns a block command; data beyond [`SMBUS_BLOCK_MAX`] is dropped
+    pub fn block(code: u8, page: Option<u8>, value: &[u8]) -> Self {
+        Self::new(code, page, Access::Block, value)
+    }
+
+    /// Allows the command to be written by the initiator
+    pub fn writable(mut self) -> Self {
+        self.writable = true;
+        self
+    }
+
+    fn new(code: u8, page: Option<u8>, access: Access, value: &[u8]) -> Self {
+        let len = value.len().min(SMBUS_BLOCK_MAX);
+        let mut data = [0; SMBUS_BLOCK_MAX];
+        data[..len].copy_from_slice(&value[..len]);
+
+        Self {
+            code,
+            page,
+            access,
+            writable: false,
+            data,
+            len,
+        }
+    }
+
+    /// Returns the current value of the command
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    fn fixed_len(&self) -> Option<usize> {
+        match self.access {
+            Access::Byte => Some(1),
+            Access::Word => Some(2),
+            Access::Block => None,
+        }
+    }
+}
+
+/// Progress through an SMBus transaction
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum SmbusState {
+    /// Waiting for the command code
+    Idle,
+    /// `PAGE` selected; `done` once the page has been written or read
+    Page { done: bool },
+    /// A command in the table selected, with the byte offset within it
+    /// (including any length byte)
+    Command { index: usize, offset: usize },
+    /// A command not in the table selected
+    Unknown,
+}
+
+/// An SMBus device with a table of commands, e.g. a PMBus regulator.  If any
+/// command is paged, `PAGE` is handled by the device itself.  The selected
+/// command survives a repeated START, so that it can be read back; it is
+/// only reset by a START followed by a write, or by a STOP.
+pub struct Smbus<const N: usize> {
+    commands: [SmbusCommand; N],
+    page: u8,
+    state: SmbusState,
+    /// Set by a START; we don't know whether it begins a write until the
+    /// first byte arrives
+    started: bool,
+}
+
+impl<const N: usize> Smbus<N> {
+    pub fn new(commands: [SmbusCommand; N]) -> Self {
+        Self {
+            commands,
+            page: 0,
+            state: SmbusState::Idle,
+            started: false,
+        }
+    }
+
+    /// Returns the currently selected page
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+
+    /// Returns the command with the specified code for the specified page
+    pub fn command(&self, code: u8, page: u8) -> Option<&SmbusCommand> {
+        self.lookup(code, page).map(|i| &self.commands[i])
+    }
+
+    /// Changes the value of a command out-of-band, e.g. to emulate a reading
+    /// changing.  Returns `false` if there is no such command.
+    pub fn set(&mut self, code: u8, page: u8, value: &[u8]) -> bool {
+        match self.lookup(code, page) {
+            Some(i) => {
+                let cmd = &mut self.commands[i];
+                cmd.len = value.len().min(SMBUS_BLOCK_MAX);
+                cmd.data[..cmd.len].copy_from_slice(&value[..cmd.len]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn paged(&self) -> bool {
+        self.commands.iter().any(|c| c.page.is_some())
+    }
+
+    fn lookup(&self, code: u8, page: u8) -> Option<usize> {
+        self.commands
+            .iter()
+            .position(|c| c.code == code && c.page.map_or(true, |p| p == page))
+    }
+}
+
+impl<const N: usize> Responder for Smbus<N> {
+    fn start(&mut self) {
+        self.started = true;
+    }
+
+    fn rx(&mut self, byte: u8) {
+        if self.started {
+            self.started = false;
+            self.state = SmbusState::Idle;
+        }
+
+        self.state = match self.state {
+            SmbusState::Idle if byte == PMBUS_PAGE && self.paged() => {
+                SmbusState::Page { done: false }
+            }
+            SmbusState::Idle => match self.lookup(byte, self.page) {
+                Some(index) => SmbusState::Command { index, offset: 0 },
+                None => SmbusState::Unknown,
+            },
+            SmbusState::Page { done: false } => {
+                self.page = byte;
+                SmbusState::Page { done: true }
+            }
+            SmbusState::Command { index, offset } => {
+                let cmd = &mut self.commands[index];
+
+                if cmd.writable {
+                    match cmd.fixed_len() {
+                        Some(len) if offset < len => {
+                            cmd.data[offset] = byte;
+                            cmd.len = len;
+                        }
+                        Some(_) => {}
+                        None if offset == 0 => cmd.len = 0,
+                        None if offset <= SMBUS_BLOCK_MAX => {
+                            cmd.data[offset - 1] = byte;
+                            cmd.len = offset;
+                        }
+                        None => {}
+                    }
+                }
+
+                SmbusState::Command {
+                    index,
+                    offset: offset + 1,
+                }
+            }
+            state => state,
+        };
+    }
+
+    fn tx(&mut self) -> Option<u8> {
+        self.started = false;
+
+        match self.state {
+            SmbusState::Page { done: false } => {
+                self.state = SmbusState::Page { done: true };
+                Some(self.page)
+            }
+            SmbusState::Command { index, offset } => {
+                self.state = SmbusState::Command {
+                    index,
+                    offset: offset + 1,
+                };
+
+                let cmd = &self.commands[index];
+
+                match (cmd.access, offset) {
+                    (Access::Block, 0) => Some(cmd.len as u8),
+                    (Access::Block, _) => cmd.data().get(offset - 1).copied(),
+                    _ => cmd.data().get(offset).copied(),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn stop(&mut self) {
+        self.started = false;
+        self.state = SmbusState::Idle;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models;
+
+    fn transact(r: &mut impl Responder, write: &[u8], read: usize) -> Vec<u8> {
+        r.start();
+        write.iter().for_each(|&b| r.rx(b));
+
+        if !write.is_empty() && read > 0 {
+            r.start();
+        }
+
+        let data = (0..read).map_while(|_| r.tx()).collect();
+        r.stop();
+        data
+    }
+
+    #[test]
+    fn register_file() {
+        let mut tmp117 = models::tmp117();
+
+        assert_eq!(transact(&mut tmp117, &[0x0f], 2), [0x01, 0x17]);
+        assert_eq!(transact(&mut tmp117, &[0x00], 2), [0x0c, 0x80]);
+
+        // A read without a register number rereads the selected register
+        assert_eq!(transact(&mut tmp117, &[], 2), [0x0c, 0x80]);
+
+        transact(&mut tmp117, &[0x02, 0x12, 0x34], 0);
+        assert_eq!(transact(&mut tmp117, &[0x02], 4), [0x12, 0x34, 0x80, 0]);
+
+        // Reads run off the end of the register file
+        assert_eq!(transact(&mut tmp117, &[0x0f], 4), [0x01, 0x17]);
+    }
+
+    #[test]
+    fn memory() {
+        let mut small = Memory::new([0u8; 16]);
+        transact(&mut small, &[14, 1, 2, 3], 0);
+        assert_eq!(transact(&mut small, &[14], 3), [1, 2, 3]);
+        assert_eq!(transact(&mut small, &[], 2), [0, 0]);
+
+        let mut large = Memory::new([0u8; 512]);
+        transact(&mut large, &[0x01, 0x00, 0xaa], 0);
+        assert_eq!(transact(&mut large, &[0x01, 0x00], 1), [0xaa]);
+        assert_eq!(transact(&mut large, &[0x00, 0x00], 1), [0]);
+    }
+
+    #[test]
+    fn smbus() {
+        let mut dev = Smbus::new([
+            SmbusCommand::byte(0x20, None, 0x17),
+            SmbusCommand::word(0x8b, Some(0), 0x1234),
+            SmbusCommand::word(0x8b, Some(1), 0x5678),
+            SmbusCommand::block(0x99, None, b"OXIDE").writable(),
+        ]);
+
+        assert_eq!(transact(&mut dev, &[0x20], 1), [0x17]);
+        assert_eq!(transact(&mut dev, &[0x8b], 2), [0x34, 0x12]);
+
+        transact(&mut dev, &[PMBUS_PAGE, 1], 0);
+        assert_eq!(dev.page(), 1);
+        assert_eq!(transact(&mut dev, &[PMBUS_PAGE], 1), [1]);
+        assert_eq!(transact(&mut dev, &[0x8b], 2), [0x78, 0x56]);
+
+        assert_eq!(transact(&mut dev, &[0x99], 6), b"\x05OXIDE");
+        transact(&mut dev, &[0x99, 2, b'h', b'i'], 0);
+        assert_eq!(transact(&mut dev, &[0x99], 8), b"\x02hi");
+
+        // Unknown and read-only commands are ignored
+        assert!(transact(&mut dev, &[0x42], 2).is_empty());
+        transact(&mut dev, &[0x20, 0xff], 0);
+        assert_eq!(transact(&mut dev, &[0x20], 1), [0x17]);
+
+        // A read without a command after a STOP reads nothing
+        assert!(transact(&mut dev, &[], 1).is_empty());
+
+        let mut target = ByteTarget(dev);
+        let mut buf = [0u8; 4];
+        assert_eq!(target.write_read(&[0x20], &mut buf), Ok(1));
+        assert_eq!(buf[0], 0x17);
+    }
+
+    #[test]
+    fn smbus_repeated_start() {
+        let mut dev = Smbus::new([
+            SmbusCommand::word(0x8b, Some(0), 0x1234),
+            SmbusCommand::word(0x8b, Some(1), 0x5678),
+        ]);
+
+        // The command code survives the repeated START before the read...
+        dev.start();
+        dev.rx(0x8b);
+        dev.start();
+        assert_eq!(dev.tx(), Some(0x34));
+        assert_eq!(dev.tx(), Some(0x12));
+        dev.stop();
+
+        // ...as does a PAGE being read back...
+        dev.start();
+        dev.rx(PMBUS_PAGE);
+        dev.rx(1);
+        dev.stop();
+        dev.start();
+        dev.rx(PMBUS_PAGE);
+        dev.start();
+        assert_eq!(dev.tx(), Some(1));
+        dev.stop();
+
+        // ...but a START followed by a write selects a new command.
+        dev.start();
+        dev.rx(0x8b);
+        dev.start();
+        dev.rx(0x8b);
+        dev.start();
+        assert_eq!(dev.tx(), Some(0x78));
+        dev.stop();
+        assert!(dev.tx().is_none());
+    }
+}
@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Mock power sequencer
+
+use drv_gimlet_state::PowerState;
+
+/// Error returned when a requested transition isn't in the transition table
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IllegalTransition;
+
+/// A sequencer which moves between power states without touching any
+/// hardware, only allowing the transitions in its table.
+pub struct MockSequencer<'a, S> {
+    state: S,
+    transitions: &'a [(S, S)],
+}
+
+impl<'a, S: Copy + Eq> MockSequencer<'a, S> {
+    pub const fn new(initial: S, transitions: &'a [(S, S)]) -> Self {
+        Self {
+            state: initial,
+            transitions,
+        }
+    }
+
+    pub fn state(&self) -> S {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: S) -> Result<(), IllegalTransition> {
+        if self.transitions.contains(&(self.state, state)) {
+            self.state = state;
+            Ok(())
+        } else {
+            Err(IllegalTransition)
+        }
+    }
+}
+
+/// Transitions allowed by the mock Gimlet sequencer, shared by
+/// `drv-mock-gimlet-seq-server` and `cargo xtask emulate`
+pub const GIMLET_TRANSITIONS: &[(PowerState, PowerState)] = &[
+    (PowerState::A2, PowerState::A0),
+    (PowerState::A0, PowerState::A2),
+    (PowerState::A0PlusHP, PowerState::A2),
+    (PowerState::A0Thermtrip, PowerState::A2),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let mut seq = MockSequencer::new(0, &[(0, 1), (1, 0), (2, 0)]);
+        assert_eq!(seq.set_state(2), Err(IllegalTransition));
+        assert_eq!(seq.state(), 0);
+        assert_eq!(seq.set_state(1), Ok(()));
+        assert_eq!(seq.set_state(1), Err(IllegalTransition));
+        assert_eq!(seq.set_state(0), Ok(()));
+        assert_eq!(seq.state(), 0);
+    }
+
+    #[test]
+    fn gimlet() {
+        let mut seq = MockSequencer::new(PowerState::A2, GIMLET_TRANSITIONS);
+        assert_eq!(
+            seq.set_state(PowerState::A0Thermtrip),
+            Err(IllegalTransition)
+        );
+        assert_eq!(seq.set_state(PowerState::A0), Ok(()));
+        assert_eq!(seq.set_state(PowerState::A2), Ok(()));
+    }
+}
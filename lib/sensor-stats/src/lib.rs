@@ -0,0 +1,278 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading, statistics and alarm types shared by the `sensor` task and its
+//! clients.
+//!
+//! These live apart from `task-sensor-api` so that the arithmetic in them can
+//! be tested on the host.
+
+#![cfg_attr(not(test), no_std)]
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
+pub struct Reading {
+    pub timestamp: u64,
+    pub value: f32,
+}
+
+impl Reading {
+    pub fn new(value: f32, timestamp: u64) -> Self {
+        Self { timestamp, value }
+    }
+
+    /// Returns the rate of change from `earlier` to this reading, in units
+    /// per second (timestamps being in milliseconds), or `None` if `earlier`
+    /// is not in fact earlier.
+    pub fn rate_since(&self, earlier: &Reading) -> Option<f32> {
+        if self.timestamp <= earlier.timestamp {
+            return None;
+        }
+
+        let dt = (self.timestamp - earlier.timestamp) as f32 / 1000.0;
+        Some((self.value - earlier.value) / dt)
+    }
+}
+
+/// Running statistics over the readings posted for a single sensor.
+#[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
+pub struct Stats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub count: u32,
+}
+
+impl Stats {
+    pub const EMPTY: Self = Self {
+        min: f32::NAN,
+        max: f32::NAN,
+        mean: f32::NAN,
+        count: 0,
+    };
+
+    /// Folds `value` into the statistics.  The mean is kept as a running
+    /// mean rather than a sum, so it stays accurate as `count` grows.
+    pub fn record(&mut self, value: f32) {
+        if self.count == 0 {
+            *self = Self {
+                min: value,
+                max: value,
+                mean: value,
+                count: 1,
+            };
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.count = self.count.saturating_add(1);
+            self.mean += (value - self.mean) / self.count as f32;
+        }
+    }
+}
+
+/// Alarm thresholds for a sensor.
+///
+/// A sensor enters [`Alarm::High`] when a reading exceeds `upper`, and
+/// leaves it only once a reading falls below `upper - hysteresis`; likewise
+/// for [`Alarm::Low`] and `lower`.  An unbounded side is expressed with an
+/// infinity, which is also the default.
+#[derive(
+    Copy, Clone, Debug, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub struct Threshold {
+    pub lower: f32,
+    pub upper: f32,
+    pub hysteresis: f32,
+}
+
+impl Threshold {
+    pub const NONE: Self = Self {
+        lower: f32::NEG_INFINITY,
+        upper: f32::INFINITY,
+        hysteresis: 0.0,
+    };
+
+    /// Returns true if the threshold is well-formed: no NaNs, a
+    /// non-negative hysteresis, and `lower` strictly below `upper`.
+    pub fn is_valid(&self) -> bool {
+        !self.lower.is_nan()
+            && !self.upper.is_nan()
+            && self.hysteresis >= 0.0
+            && self.lower < self.upper
+    }
+
+    /// Computes the alarm state for `value`, given the current state.
+    pub fn check(&self, alarm: Alarm, value: f32) -> Alarm {
+        match alarm {
+            Alarm::High if value >= self.upper - self.hysteresis => Alarm::High,
+            Alarm::Low if value <= self.lower + self.hysteresis => Alarm::Low,
+            _ if value > self.upper => Alarm::High,
+            _ if value < self.lower => Alarm::Low,
+            _ => Alarm::Clear,
+        }
+    }
+}
+
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub enum Alarm {
+    Clear,
+    Low,
+    High,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(values: &[f32]) -> Stats {
+        let mut stats = Stats::EMPTY;
+        for &v in values {
+            stats.record(v);
+        }
+        stats
+    }
+
+    #[test]
+    fn stats_empty() {
+        let s = Stats::EMPTY;
+        assert_eq!(s.count, 0);
+        assert!(s.min.is_nan() && s.max.is_nan() && s.mean.is_nan());
+    }
+
+    #[test]
+    fn stats_first_reading() {
+        let s = stats(&[42.0]);
+        assert_eq!((s.min, s.max, s.mean, s.count), (42.0, 42.0, 42.0, 1));
+    }
+
+    #[test]
+    fn stats_min_max_mean() {
+        let s = stats(&[3.0, -1.0, 10.0, 4.0]);
+        assert_eq!(s.min, -1.0);
+        assert_eq!(s.max, 10.0);
+        assert_eq!(s.mean, 4.0);
+        assert_eq!(s.count, 4);
+    }
+
+    #[test]
+    fn stats_mean_is_stable() {
+        //
+        // A naive sum in f32 would stop growing long before this; the
+        // running mean should stay put.
+        //
+        let mut s = Stats::EMPTY;
+        for _ in 0..1_000_000 {
+            s.record(55.5);
+        }
+        assert_eq!(s.mean, 55.5);
+        assert_eq!(s.count, 1_000_000);
+    }
+
+    #[test]
+    fn stats_count_saturates() {
+        let mut s = stats(&[1.0, 2.0]);
+        s.count = u32::MAX;
+        s.record(3.0);
+        assert_eq!(s.count, u32::MAX);
+        assert_eq!(s.max, 3.0);
+    }
+
+    const T: Threshold = Threshold {
+        lower: 10.0,
+        upper: 80.0,
+        hysteresis: 5.0,
+    };
+
+    #[test]
+    fn threshold_validity() {
+        assert!(T.is_valid());
+        assert!(Threshold::NONE.is_valid());
+        assert!(!Threshold { upper: 10.0, ..T }.is_valid());
+        assert!(!Threshold {
+            lower: f32::NAN,
+            ..T
+        }
+        .is_valid());
+        assert!(!Threshold {
+            upper: f32::NAN,
+            ..T
+        }
+        .is_valid());
+        assert!(!Threshold {
+            hysteresis: -1.0,
+            ..T
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn threshold_none_never_alarms() {
+        for v in [f32::MIN, -1e9, 0.0, 1e9, f32::MAX] {
+            assert_eq!(Threshold::NONE.check(Alarm::Clear, v), Alarm::Clear);
+        }
+    }
+
+    #[test]
+    fn threshold_enters_alarm() {
+        assert_eq!(T.check(Alarm::Clear, 50.0), Alarm::Clear);
+        assert_eq!(T.check(Alarm::Clear, 80.0), Alarm::Clear);
+        assert_eq!(T.check(Alarm::Clear, 80.5), Alarm::High);
+        assert_eq!(T.check(Alarm::Clear, 10.0), Alarm::Clear);
+        assert_eq!(T.check(Alarm::Clear, 9.5), Alarm::Low);
+    }
+
+    #[test]
+    fn threshold_high_hysteresis() {
+        assert_eq!(T.check(Alarm::High, 79.0), Alarm::High);
+        assert_eq!(T.check(Alarm::High, 75.0), Alarm::High);
+        assert_eq!(T.check(Alarm::High, 74.9), Alarm::Clear);
+    }
+
+    #[test]
+    fn threshold_low_hysteresis() {
+        assert_eq!(T.check(Alarm::Low, 11.0), Alarm::Low);
+        assert_eq!(T.check(Alarm::Low, 15.0), Alarm::Low);
+        assert_eq!(T.check(Alarm::Low, 15.1), Alarm::Clear);
+    }
+
+    #[test]
+    fn threshold_change_holds_alarm() {
+        //
+        // Re-evaluating an active alarm against a new threshold must start
+        // from the current state: inside the hysteresis band, an alarm
+        // should be held rather than cleared (and later raised again).
+        //
+        let t = Threshold {
+            hysteresis: 10.0,
+            ..T
+        };
+        assert_eq!(t.check(Alarm::High, 75.0), Alarm::High);
+        assert_eq!(t.check(Alarm::Clear, 75.0), Alarm::Clear);
+        assert_eq!(t.check(Alarm::High, 69.0), Alarm::Clear);
+    }
+
+    #[test]
+    fn threshold_swings_through() {
+        //
+        // A reading that jumps straight from one extreme to the other
+        // should switch alarms without stopping at `Clear`.
+        //
+        assert_eq!(T.check(Alarm::High, 0.0), Alarm::Low);
+        assert_eq!(T.check(Alarm::Low, 100.0), Alarm::High);
+    }
+
+    #[test]
+    fn rate() {
+        let a = Reading::new(40.0, 1000);
+        let b = Reading::new(45.0, 3000);
+        assert_eq!(b.rate_since(&a), Some(2.5));
+        assert_eq!(a.rate_since(&b), None);
+        assert_eq!(a.rate_since(&a), None);
+        assert_eq!(Reading::new(30.0, 1500).rate_since(&a), Some(-20.0));
+    }
+}
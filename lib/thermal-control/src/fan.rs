@@ -0,0 +1,313 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fan health monitoring, judging each fan's tach reading against its
+//! commanded duty cycle.
+
+use serde::{Deserialize, Serialize};
+use units::{PWMDuty, Rpm};
+
+/// A fan, as an index into the BSP's list of fans.  We define our own type,
+/// as we may have more fans than any single controller supports.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Fan(pub u8);
+
+impl From<usize> for Fan {
+    fn from(index: usize) -> Self {
+        Fan(index as u8)
+    }
+}
+
+/// Health of a single fan, judged by comparing its tach reading against its
+/// commanded duty cycle
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FanHealth {
+    /// The fan hasn't been commanded fast enough for us to judge it
+    Unknown,
+    /// The fan's speed is plausible for its duty cycle
+    Ok,
+    /// The fan has never been seen turning, despite being commanded to
+    Missing,
+    /// The fan was turning, but has stopped despite being commanded to turn
+    Stalled,
+    /// The fan is turning, but at an implausible speed for its duty cycle
+    OutOfRange,
+}
+
+impl FanHealth {
+    pub fn is_faulty(self) -> bool {
+        matches!(
+            self,
+            FanHealth::Missing | FanHealth::Stalled | FanHealth::OutOfRange
+        )
+    }
+}
+
+/// Expected behavior of the fans in a system, used to decide whether a fan's
+/// tach reading is plausible for its commanded duty cycle.
+///
+/// Fan speed is modeled as linear in duty cycle, which is crude but good
+/// enough to spot a fan that's badly wrong.
+#[derive(Copy, Clone, Debug)]
+pub struct FanModel {
+    /// Speed at 100% duty cycle
+    max_rpm: Rpm,
+
+    /// Lowest duty cycle at which the fan is guaranteed to turn; we don't
+    /// judge fans which are commanded below this.
+    min_pwm: PWMDuty,
+
+    /// How far the speed may stray from the linear model before it's
+    /// implausible, as a fraction of `max_rpm`
+    tolerance: f32,
+}
+
+impl FanModel {
+    pub const fn new(max_rpm: Rpm, min_pwm: PWMDuty, tolerance: f32) -> Self {
+        Self {
+            max_rpm,
+            min_pwm,
+            tolerance,
+        }
+    }
+
+    /// Judges a single tach reading, returning `None` if the commanded duty
+    /// cycle is too low to tell.
+    pub fn check(
+        &self,
+        pwm: PWMDuty,
+        rpm: Rpm,
+        spun: bool,
+    ) -> Option<FanHealth> {
+        if pwm.0 < self.min_pwm.0 {
+            return None;
+        }
+        let max_rpm = self.max_rpm.0 as f32;
+        let expected = max_rpm * pwm.0 as f32 / 100.0;
+        let health = if rpm.0 == 0 {
+            if spun {
+                FanHealth::Stalled
+            } else {
+                FanHealth::Missing
+            }
+        } else if (rpm.0 as f32 - expected).abs() > self.tolerance * max_rpm {
+            FanHealth::OutOfRange
+        } else {
+            FanHealth::Ok
+        };
+        Some(health)
+    }
+}
+
+/// Number of consecutive bad readings before we declare a fan faulty, which
+/// gives fans time to settle after a change in duty cycle
+const FAN_FAULT_THRESHOLD: u8 = 5;
+
+/// Number of consecutive good readings before a faulty fan is declared
+/// healthy again, so that a fan on the edge of its tolerance doesn't flap
+const FAN_RECOVERY_THRESHOLD: u8 = 3;
+
+/// Runtime health tracking for a single fan
+#[derive(Copy, Clone, Debug)]
+pub struct FanMonitor {
+    /// Most recent duty cycle that we successfully sent to this fan
+    pwm: Option<PWMDuty>,
+
+    /// Most recent tach reading
+    rpm: Option<Rpm>,
+
+    /// Whether we've ever seen this fan turning
+    spun: bool,
+
+    /// Consecutive readings which disagree with `health`
+    strikes: u8,
+
+    health: FanHealth,
+}
+
+impl Default for FanMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FanMonitor {
+    pub const fn new() -> Self {
+        Self {
+            pwm: None,
+            rpm: None,
+            spun: false,
+            strikes: 0,
+            health: FanHealth::Unknown,
+        }
+    }
+
+    pub fn pwm(&self) -> Option<PWMDuty> {
+        self.pwm
+    }
+
+    pub fn rpm(&self) -> Option<Rpm> {
+        self.rpm
+    }
+
+    pub fn health(&self) -> FanHealth {
+        self.health
+    }
+
+    pub fn is_faulty(&self) -> bool {
+        self.health.is_faulty()
+    }
+
+    /// Records a duty cycle that was successfully sent to the fan
+    pub fn set_pwm(&mut self, pwm: PWMDuty) {
+        self.pwm = Some(pwm);
+    }
+
+    /// Records a tach reading, returning the new health if it has changed
+    pub fn record(&mut self, model: &FanModel, rpm: Rpm) -> Option<FanHealth> {
+        self.rpm = Some(rpm);
+        self.spun |= rpm.0 > 0;
+
+        let health = self.pwm.and_then(|pwm| model.check(pwm, rpm, self.spun));
+        match health {
+            Some(h) if h != self.health => {
+                // A fault must persist for a while before we declare it, and
+                // so must a recovery from one; a fan that we haven't yet
+                // judged is declared healthy straight away.
+                let threshold = if h.is_faulty() {
+                    FAN_FAULT_THRESHOLD
+                } else if self.is_faulty() {
+                    FAN_RECOVERY_THRESHOLD
+                } else {
+                    1
+                };
+                self.strikes = self.strikes.saturating_add(1);
+                if self.strikes >= threshold {
+                    self.strikes = 0;
+                    self.health = h;
+                    return Some(h);
+                }
+            }
+            _ => self.strikes = 0,
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: FanModel = FanModel::new(Rpm(10000), PWMDuty(20), 0.2);
+
+    /// Records `n` readings, returning the last change in health (if any)
+    fn feed(m: &mut FanMonitor, rpm: u16, n: usize) -> Option<FanHealth> {
+        let mut change = None;
+        for _ in 0..n {
+            if let Some(h) = m.record(&MODEL, Rpm(rpm)) {
+                change = Some(h);
+            }
+        }
+        change
+    }
+
+    fn running(pwm: u8) -> FanMonitor {
+        let mut m = FanMonitor::new();
+        m.set_pwm(PWMDuty(pwm));
+        assert_eq!(
+            m.record(&MODEL, Rpm(pwm as u16 * 100)),
+            Some(FanHealth::Ok)
+        );
+        m
+    }
+
+    #[test]
+    fn model_check() {
+        let spun = true;
+        assert_eq!(MODEL.check(PWMDuty(10), Rpm(0), spun), None);
+        assert_eq!(
+            MODEL.check(PWMDuty(50), Rpm(5000), spun),
+            Some(FanHealth::Ok)
+        );
+        assert_eq!(
+            MODEL.check(PWMDuty(50), Rpm(6900), spun),
+            Some(FanHealth::Ok)
+        );
+        assert_eq!(
+            MODEL.check(PWMDuty(50), Rpm(7100), spun),
+            Some(FanHealth::OutOfRange)
+        );
+        assert_eq!(
+            MODEL.check(PWMDuty(50), Rpm(0), spun),
+            Some(FanHealth::Stalled)
+        );
+        assert_eq!(
+            MODEL.check(PWMDuty(50), Rpm(0), !spun),
+            Some(FanHealth::Missing)
+        );
+    }
+
+    #[test]
+    fn unknown_until_commanded() {
+        let mut m = FanMonitor::new();
+        assert_eq!(feed(&mut m, 0, 10), None);
+        assert_eq!(m.health(), FanHealth::Unknown);
+
+        m.set_pwm(PWMDuty(10));
+        assert_eq!(feed(&mut m, 0, 10), None);
+        assert_eq!(m.health(), FanHealth::Unknown);
+    }
+
+    #[test]
+    fn missing_fan() {
+        let mut m = FanMonitor::new();
+        m.set_pwm(PWMDuty(50));
+        assert_eq!(feed(&mut m, 0, FAN_FAULT_THRESHOLD as usize - 1), None);
+        assert_eq!(feed(&mut m, 0, 1), Some(FanHealth::Missing));
+        assert!(m.is_faulty());
+    }
+
+    #[test]
+    fn stall_is_debounced() {
+        let mut m = running(50);
+        assert_eq!(feed(&mut m, 0, FAN_FAULT_THRESHOLD as usize - 1), None);
+        assert_eq!(m.health(), FanHealth::Ok);
+
+        // A good reading resets the count
+        assert_eq!(feed(&mut m, 5000, 1), None);
+        assert_eq!(feed(&mut m, 0, FAN_FAULT_THRESHOLD as usize - 1), None);
+        assert_eq!(feed(&mut m, 0, 1), Some(FanHealth::Stalled));
+    }
+
+    #[test]
+    fn recovery_is_debounced() {
+        let mut m = running(50);
+        assert_eq!(
+            feed(&mut m, 9000, FAN_FAULT_THRESHOLD as usize),
+            Some(FanHealth::OutOfRange)
+        );
+
+        // A fan flapping around the edge of its tolerance stays faulty
+        for _ in 0..10 {
+            assert_eq!(feed(&mut m, 5000, 1), None);
+            assert_eq!(feed(&mut m, 9000, 1), None);
+        }
+        assert_eq!(m.health(), FanHealth::OutOfRange);
+
+        assert_eq!(
+            feed(&mut m, 5000, FAN_RECOVERY_THRESHOLD as usize - 1),
+            None
+        );
+        assert_eq!(feed(&mut m, 5000, 1), Some(FanHealth::Ok));
+        assert!(!m.is_faulty());
+    }
+
+    #[test]
+    fn records_last_reading() {
+        let mut m = running(30);
+        m.record(&MODEL, Rpm(2900));
+        assert_eq!(m.pwm(), Some(PWMDuty(30)));
+        assert_eq!(m.rpm(), Some(Rpm(2900)));
+    }
+}
@@ -0,0 +1,521 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Thermal control loop, independent of any particular sensors or fans.
+//!
+//! The `thermal` task reads temperatures, hands them to a [`ThermalController`]
+//! per [`Zone`] and applies the resulting [`ControlResult`] to the fans (or
+//! sequencer), judging each fan's health with a [`FanMonitor`].
+//! Keeping the side effects out of this crate means that the control logic can
+//! be built and tested on the host, where the `sim` module provides a thermal
+//! plant to run it against.
+
+#![cfg_attr(not(test), no_std)]
+
+use num_derive::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use units::{Celsius, PWMDuty};
+use zerocopy::{AsBytes, FromBytes};
+
+mod fan;
+#[cfg(test)]
+mod sim;
+mod zone;
+
+pub use fan::{Fan, FanHealth, FanModel, FanMonitor};
+pub use zone::{fan_pwm, Zone};
+
+/// Substates when running in automatic mode
+///
+/// These are based on `enum ThermalControlState`, but stripped of the
+/// associated state data.
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub enum ThermalAutoState {
+    Boot,
+    Running,
+    Overheated,
+    Uncontrollable,
+}
+
+/// Properties for a particular part in the system
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ThermalProperties {
+    /// Target temperature for this part
+    pub target_temperature: Celsius,
+
+    /// At the critical temperature, we should turn the fans up to 100% power in
+    /// an attempt to cool the part.
+    pub critical_temperature: Celsius,
+
+    /// Temperature at which we drop into the A2 power state.  This should be
+    /// below the part's nonrecoverable temperature.
+    pub power_down_temperature: Celsius,
+
+    /// Maximum slew rate of temperature, measured in °C per second
+    ///
+    /// The slew rate is used to model worst-case temperature if we haven't
+    /// heard from a chip in a while (e.g. due to dropped samples)
+    pub temperature_slew_deg_per_sec: f32,
+}
+
+/// All of these functions take an **instantaneous** temperature; to convert a
+/// timestamped reading into an instantaneous temperature (using a thermal
+/// model), see `TimestampedTemperatureReading::worst_case`.
+impl ThermalProperties {
+    /// Returns whether this part is exceeding its power-down temperature
+    pub fn should_power_down(&self, t: Celsius) -> bool {
+        t.0 >= self.power_down_temperature.0
+    }
+
+    /// Returns whether this part is exceeding its critical temperature
+    pub fn is_critical(&self, t: Celsius) -> bool {
+        t.0 >= self.critical_temperature.0
+    }
+
+    /// Returns whether this part is below its critical temperature, with
+    /// a user-configured hysteresis band.
+    pub fn is_sub_critical(&self, t: Celsius, hysteresis: Celsius) -> bool {
+        t.0 < self.critical_temperature.0 - hysteresis.0
+    }
+
+    /// Returns the margin of this part, given a current temperature reading.
+    ///
+    /// Positive margin means that the part is below its max temperature;
+    /// negative means that it's overheating.
+    pub fn margin(&self, t: Celsius) -> Celsius {
+        Celsius(self.target_temperature.0 - t.0)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Represents the state of a temperature sensor, which either has a valid
+/// reading or is marked as inactive (due to power state or being missing)
+#[derive(Copy, Clone, Debug)]
+enum TemperatureReading {
+    /// Normal reading, timestamped using monotonic system time
+    Valid(TimestampedTemperatureReading),
+
+    /// This sensor is not used in the current power state
+    Inactive,
+}
+
+/// Represents a temperature reading at the time at which it was taken
+#[derive(Copy, Clone, Debug)]
+struct TimestampedTemperatureReading {
+    time_ms: u64,
+    value: Celsius,
+}
+
+impl TimestampedTemperatureReading {
+    /// Returns the worst-case temperature, given a current time and thermal
+    /// model for this part.
+    ///
+    /// This only matters when samples are dropped or if there is significant
+    /// lag in the sensors system; if we received a reading on this control
+    /// cycle, then time_ms ≈ now_ms, so this is close to v.value (i.e. the most
+    /// recent reading).
+    ///
+    /// Typically, time_ms is earlier (less) than now_ms, so this subtraction is
+    /// safe.  If there's invalid data in the sensors task (i.e. readings
+    /// claiming to be from the future), then this will saturate instead of
+    /// underflowing.
+    fn worst_case(&self, now_ms: u64, model: &ThermalProperties) -> Celsius {
+        Celsius(
+            self.value.0
+                + now_ms.saturating_sub(self.time_ms) as f32 / 1000.0
+                    * model.temperature_slew_deg_per_sec,
+        )
+    }
+}
+
+/// Configuration for a PID controller
+#[derive(Copy, Clone)]
+pub struct PidConfig {
+    pub zero: f32,
+    pub gain_p: f32,
+    pub gain_i: f32,
+    pub gain_d: f32,
+}
+
+/// Represents a PID controller that can only push in one direction (i.e. the
+/// output must always be positive).
+struct OneSidedPidState {
+    /// Previous (time, input) tuple, for derivative term
+    prev_error: Option<f32>,
+
+    /// Accumulated integral term, pre-multiplied by gain
+    integral: f32,
+}
+
+impl OneSidedPidState {
+    /// Attempts to drive the error to zero.
+    ///
+    /// The error and output are expected to have the same signs, i.e. a large
+    /// positive error will produce a large positive output.
+    fn run(&mut self, cfg: &PidConfig, error: f32, output_limit: f32) -> f32 {
+        let p_contribution = cfg.gain_p * error;
+
+        // Pre-multiply accumulated integral by gain, to make clamping easier
+        // (this also means we can change the gain_i without glitches)
+        self.integral += error * cfg.gain_i;
+
+        // Calculate the derivative term if there was a previous error
+        let d_contribution = if let Some(prev_error) = self.prev_error {
+            (error - prev_error) * cfg.gain_d
+        } else {
+            0.0
+        };
+        self.prev_error = Some(error);
+
+        // To prevent integral windup, integral term needs to be clamped to values
+        // can effect the output.
+        let out_pd = cfg.zero + p_contribution + d_contribution;
+        let (integral_min, integral_max) = if out_pd > output_limit {
+            (-out_pd, 0.0)
+        } else if out_pd < 0.0 {
+            (0.0, -out_pd + output_limit)
+        } else {
+            (-out_pd, output_limit - out_pd)
+        };
+        self.integral = self.integral.clamp(integral_min, integral_max);
+
+        // Clamp output values to valid range.
+        let out = out_pd + self.integral;
+        out.clamp(0.0, output_limit)
+    }
+}
+
+impl Default for OneSidedPidState {
+    fn default() -> Self {
+        Self {
+            prev_error: None,
+            integral: 0.0,
+        }
+    }
+}
+
+/// This corresponds to states shown in RFD 276
+///
+/// Note that the canonical temperatures are stored in the `sensors` task; the
+/// `thermal` task copies them into these arrays for local operations.
+enum ThermalControlState<const N: usize> {
+    /// Wait for each sensor to report in at least once
+    ///
+    /// (inputs without a model must not report in, as they are ignored)
+    Boot {
+        values: [Option<TemperatureReading>; N],
+    },
+
+    /// Normal happy control loop
+    Running {
+        values: [TemperatureReading; N],
+        pid: OneSidedPidState,
+    },
+
+    /// In the overheated state, one or more components has entered their
+    /// critical temperature ranges.  We turn on fans at high power and record
+    /// the time at which we entered this state; at a certain point, we will
+    /// timeout and drop into `Uncontrolled` if components do not recover.
+    Overheated {
+        values: [TemperatureReading; N],
+        start_time: u64,
+    },
+
+    /// The system cannot control the temperature; power down and wait for
+    /// intervention from higher up the stack.
+    Uncontrollable,
+}
+
+/// What the caller should do with the fans after a control iteration
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ControlResult {
+    Pwm(PWMDuty),
+    PowerDown,
+}
+
+impl<const N: usize> ThermalControlState<N> {
+    fn write_temperature(&mut self, index: usize, r: TemperatureReading) {
+        match self {
+            ThermalControlState::Boot { values } => {
+                values[index] = Some(r);
+            }
+            ThermalControlState::Running { values, .. }
+            | ThermalControlState::Overheated { values, .. } => {
+                values[index] = r;
+            }
+            ThermalControlState::Uncontrollable => (),
+        }
+    }
+}
+
+/// The thermal control loop, over `N` temperature inputs.
+pub struct ThermalController<const N: usize> {
+    /// Target temperature margin. This must be >= 0; as it increases, parts
+    /// are kept cooler than their target temperature value.
+    target_margin: Celsius,
+
+    /// Controller state
+    state: ThermalControlState<N>,
+
+    /// How long to wait in the `Overheated` state before powering down
+    overheat_timeout_ms: u64,
+
+    /// Once we're in `Overheated`, how much does the temperature have to drop
+    /// by before we return to `Normal`
+    overheat_hysteresis: Celsius,
+
+    /// PID parameters
+    pid_config: PidConfig,
+}
+
+impl<const N: usize> ThermalController<N> {
+    pub fn new(pid_config: PidConfig) -> Self {
+        Self {
+            target_margin: Celsius(0.0f32),
+            state: ThermalControlState::Boot { values: [None; N] },
+            pid_config,
+
+            overheat_hysteresis: Celsius(1.0),
+            overheat_timeout_ms: 60_000,
+        }
+    }
+
+    /// Sets the PID parameters; the caller is responsible for validating
+    /// them.
+    pub fn set_pid_config(&mut self, cfg: PidConfig) {
+        // If the incoming integral gain is zero, then it will never be able
+        // to wind down the integral accumulator (which is pre-multiplied),
+        // so clear it here.
+        if let ThermalControlState::Running { pid, .. } = &mut self.state {
+            if cfg.gain_i == 0.0 {
+                pid.integral = 0.0;
+            }
+        }
+
+        self.pid_config = cfg;
+    }
+
+    /// Sets the target margin, which must be >= 0; the caller is responsible
+    /// for validating it.
+    pub fn set_target_margin(&mut self, margin: Celsius) {
+        self.target_margin = margin;
+    }
+
+    pub fn target_margin(&self) -> Celsius {
+        self.target_margin
+    }
+
+    pub fn pid_config(&self) -> PidConfig {
+        self.pid_config
+    }
+
+    /// Resets the control state, waiting for every input to report in again
+    pub fn reset_state(&mut self) {
+        self.state = ThermalControlState::Boot { values: [None; N] };
+    }
+
+    /// Records a temperature reading for the given input
+    pub fn write_temperature(
+        &mut self,
+        index: usize,
+        time_ms: u64,
+        value: Celsius,
+    ) {
+        let r = TemperatureReading::Valid(TimestampedTemperatureReading {
+            time_ms,
+            value,
+        });
+        self.state.write_temperature(index, r);
+    }
+
+    /// Marks the given input as inactive, e.g. because it is not powered or
+    /// is a removable part that isn't present.
+    pub fn write_temperature_inactive(&mut self, index: usize) {
+        self.state
+            .write_temperature(index, TemperatureReading::Inactive);
+    }
+
+    pub fn state(&self) -> ThermalAutoState {
+        match self.state {
+            ThermalControlState::Boot { .. } => ThermalAutoState::Boot,
+            ThermalControlState::Running { .. } => ThermalAutoState::Running,
+            ThermalControlState::Overheated { .. } => {
+                ThermalAutoState::Overheated
+            }
+            ThermalControlState::Uncontrollable => {
+                ThermalAutoState::Uncontrollable
+            }
+        }
+    }
+
+    /// Returns an iterator over tuples of `(value, thermal model)`, skipping
+    /// inputs for which `model` returns `None`.
+    fn zip_temperatures<'b, T>(
+        values: &'b [T; N],
+        model: &'b impl Fn(usize) -> Option<ThermalProperties>,
+    ) -> impl Iterator<Item = (&'b T, ThermalProperties)> + 'b {
+        values
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, v)| model(i).map(|m| (v, m)))
+    }
+
+    /// Runs a single iteration of the control loop at time `now_ms`.
+    ///
+    /// `model` returns the thermal model for each input; inputs for which it
+    /// returns `None` (e.g. absent dynamic inputs) are ignored entirely.
+    pub fn run(
+        &mut self,
+        now_ms: u64,
+        model: impl Fn(usize) -> Option<ThermalProperties>,
+    ) -> ControlResult {
+        match &mut self.state {
+            ThermalControlState::Boot { values } => {
+                let mut all_some = true;
+                let mut any_power_down = false;
+                let mut worst_margin = f32::MAX;
+                for (v, model) in Self::zip_temperatures(values, &model) {
+                    match v {
+                        Some(TemperatureReading::Valid(v)) => {
+                            let temperature = v.worst_case(now_ms, &model);
+                            any_power_down |=
+                                model.should_power_down(temperature);
+                            worst_margin =
+                                worst_margin.min(model.margin(temperature).0);
+                        }
+                        Some(TemperatureReading::Inactive) => {
+                            // Inactive sensors are ignored, but do not gate us
+                            // from transitioning to `Running`
+                        }
+
+                        None => all_some = false,
+                    }
+                }
+
+                if any_power_down {
+                    self.state = ThermalControlState::Uncontrollable;
+                    ControlResult::PowerDown
+                } else if all_some {
+                    // Transition to the Running state and run a single
+                    // iteration of the PID control loop.
+                    //
+                    // Inputs without a model never report in, so fill them
+                    // with `Inactive` rather than unwrapping.
+                    let mut pid = OneSidedPidState::default();
+                    let pwm = pid.run(
+                        &self.pid_config,
+                        self.target_margin.0 - worst_margin,
+                        100.0,
+                    );
+                    self.state = ThermalControlState::Running {
+                        values: values
+                            .map(|v| v.unwrap_or(TemperatureReading::Inactive)),
+                        pid,
+                    };
+
+                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                } else {
+                    ControlResult::Pwm(PWMDuty(100))
+                }
+            }
+            ThermalControlState::Running { values, pid } => {
+                let mut any_power_down = false;
+                let mut any_critical = false;
+                let mut worst_margin = f32::MAX;
+
+                // Remember, positive margin means that all parts are happily
+                // below their max temperature; negative means someone is
+                // overheating.  We want to pick the _smallest_ margin, since
+                // that's the part which is most overheated.
+                for (v, model) in Self::zip_temperatures(values, &model) {
+                    if let TemperatureReading::Valid(v) = v {
+                        let temperature = v.worst_case(now_ms, &model);
+                        any_power_down |= model.should_power_down(temperature);
+                        any_critical |= model.is_critical(temperature);
+
+                        worst_margin =
+                            worst_margin.min(model.margin(temperature).0);
+                    }
+                }
+
+                if any_power_down {
+                    self.state = ThermalControlState::Uncontrollable;
+                    ControlResult::PowerDown
+                } else if any_critical {
+                    self.state = ThermalControlState::Overheated {
+                        values: *values,
+                        start_time: now_ms,
+                    };
+                    ControlResult::Pwm(PWMDuty(100))
+                } else {
+                    // We adjust the worst component margin by our target
+                    // margin, which must be > 0.  This effectively tells the
+                    // control loop to overcool the system.
+                    //
+                    // `PidControl::run` expects the sign of the input and
+                    // output to match, so we negate things here: if the worst
+                    // margin is negative (i.e. the system is overheating), then
+                    // the input to `run` is positive, because we want a
+                    // positive fan speed.
+                    let pwm = pid.run(
+                        &self.pid_config,
+                        self.target_margin.0 - worst_margin,
+                        100.0,
+                    );
+                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                }
+            }
+            ThermalControlState::Overheated { values, start_time } => {
+                let mut all_subcritical = true;
+                let mut any_power_down = false;
+                let mut worst_margin = f32::MAX;
+
+                for (v, model) in Self::zip_temperatures(values, &model) {
+                    if let TemperatureReading::Valid(v) = v {
+                        let temperature = v.worst_case(now_ms, &model);
+                        all_subcritical &= model.is_sub_critical(
+                            temperature,
+                            self.overheat_hysteresis,
+                        );
+                        any_power_down |= model.should_power_down(temperature);
+                        worst_margin =
+                            worst_margin.min(model.margin(temperature).0);
+                    }
+                }
+
+                if any_power_down {
+                    self.state = ThermalControlState::Uncontrollable;
+                    ControlResult::PowerDown
+                } else if all_subcritical {
+                    // Transition to the Running state and run a single
+                    // iteration of the PID control loop.
+                    let mut pid = OneSidedPidState::default();
+                    let pwm = pid.run(
+                        &self.pid_config,
+                        self.target_margin.0 - worst_margin,
+                        100.0,
+                    );
+                    self.state = ThermalControlState::Running {
+                        values: *values,
+                        pid,
+                    };
+
+                    ControlResult::Pwm(PWMDuty(pwm as u8))
+                } else if now_ms > *start_time + self.overheat_timeout_ms {
+                    // If blasting the fans hasn't cooled us down in this amount
+                    // of time, then something is terribly wrong - abort!
+                    self.state = ThermalControlState::Uncontrollable;
+                    ControlResult::PowerDown
+                } else {
+                    ControlResult::Pwm(PWMDuty(100))
+                }
+            }
+            ThermalControlState::Uncontrollable => ControlResult::PowerDown,
+        }
+    }
+}
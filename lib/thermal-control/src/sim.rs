@@ -0,0 +1,374 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A crude thermal plant, for running the controller in the loop on the host.
+//!
+//! Each component is a lumped thermal mass heated by its own power draw and
+//! cooled by the air around it.  Its conductance to ambient grows with the
+//! total airflow, which is the sum of each fan's speed as a fraction of its
+//! maximum; fans chase their commanded speed with a short lag, and a failed
+//! fan stops.  Each component has one sensor, which may report late (with a
+//! correspondingly old timestamp) or drop out entirely.
+//!
+//! Scenarios are scripted as a closure that is called once per control period
+//! (one second, as in the `thermal` task) and may poke at the plant.
+
+use crate::{
+    ControlResult, PidConfig, ThermalAutoState, ThermalController,
+    ThermalProperties,
+};
+use units::Celsius;
+
+/// Length of a control period, matching the `thermal` task's timer
+const CONTROL_PERIOD_MS: u64 = 1000;
+
+/// Number of integration steps per control period
+const SUBSTEPS: u64 = 10;
+
+/// Time constant with which fans reach their commanded speed
+const FAN_TIME_CONSTANT_S: f32 = 2.0;
+
+/// Power drawn by each component after we've powered down (i.e. in A2)
+const POWERED_DOWN_W: f32 = 1.0;
+
+struct Component {
+    model: ThermalProperties,
+    power_w: f32,
+    heat_capacity_j_per_deg: f32,
+
+    /// Conductance to ambient with no airflow at all (W/°C)
+    still_w_per_deg: f32,
+
+    /// Additional conductance to ambient at full airflow (W/°C)
+    forced_w_per_deg: f32,
+
+    temperature: f32,
+}
+
+struct Fan {
+    /// Speed as a fraction of maximum
+    speed: f32,
+    failed: bool,
+}
+
+struct Sensor {
+    /// How stale each reading is by the time the controller sees it
+    latency_ms: u64,
+
+    /// Whether the sensor is currently reporting `NoData`
+    dropped: bool,
+
+    /// Temperatures of the component, one per control period, most recent
+    /// last
+    history: Vec<f32>,
+}
+
+struct Plant {
+    ambient: f32,
+    components: Vec<Component>,
+    fans: Vec<Fan>,
+    sensors: Vec<Sensor>,
+    pwm: f32,
+    powered_down: bool,
+}
+
+impl Plant {
+    fn airflow(&self) -> f32 {
+        self.fans.iter().map(|f| f.speed).sum::<f32>() / self.fans.len() as f32
+    }
+
+    fn step(&mut self, dt_s: f32) {
+        for f in &mut self.fans {
+            let target = if f.failed { 0.0 } else { self.pwm / 100.0 };
+            f.speed += (target - f.speed) * (dt_s / FAN_TIME_CONSTANT_S);
+        }
+
+        let airflow = self.airflow();
+
+        for c in &mut self.components {
+            let power = if self.powered_down {
+                POWERED_DOWN_W
+            } else {
+                c.power_w
+            };
+            let g = c.still_w_per_deg + c.forced_w_per_deg * airflow;
+            let flux = power - g * (c.temperature - self.ambient);
+            c.temperature += flux * dt_s / c.heat_capacity_j_per_deg;
+        }
+    }
+
+    /// Returns the reading (timestamp and value) that sensor `i` would give
+    /// at `now_ms`, or `None` if it has dropped out.
+    fn read(&self, i: usize, now_ms: u64) -> Option<(u64, Celsius)> {
+        let s = &self.sensors[i];
+        if s.dropped {
+            return None;
+        }
+
+        let periods = (s.latency_ms / CONTROL_PERIOD_MS) as usize;
+        let index = s.history.len().checked_sub(periods + 1)?;
+        let time_ms = now_ms - periods as u64 * CONTROL_PERIOD_MS;
+        Some((time_ms, Celsius(s.history[index])))
+    }
+}
+
+/// Thermal properties from the Gimlet DIMMs
+const DIMM: ThermalProperties = ThermalProperties {
+    target_temperature: Celsius(80.0),
+    critical_temperature: Celsius(90.0),
+    power_down_temperature: Celsius(95.0),
+    temperature_slew_deg_per_sec: 0.5,
+};
+
+/// Thermal properties from the Gimlet U.2 drives
+const U2: ThermalProperties = ThermalProperties {
+    target_temperature: Celsius(65.0),
+    critical_temperature: Celsius(70.0),
+    power_down_temperature: Celsius(75.0),
+    temperature_slew_deg_per_sec: 0.5,
+};
+
+/// PID configuration from the Gimlet BSP
+const PID: PidConfig = PidConfig {
+    zero: 35.0,
+    gain_p: 1.75,
+    gain_i: 0.0135,
+    gain_d: 0.4,
+};
+
+const NUM_COMPONENTS: usize = 2;
+
+/// A hot component (that needs more than a third of full airflow at 25°C
+/// ambient) and a cooler one with a lower target, on two fans.
+fn plant() -> Plant {
+    let component = |model, power_w, forced_w_per_deg| Component {
+        model,
+        power_w,
+        heat_capacity_j_per_deg: 40.0,
+        still_w_per_deg: 0.15,
+        forced_w_per_deg,
+        temperature: 25.0,
+    };
+    let sensor = || Sensor {
+        latency_ms: 0,
+        dropped: false,
+        history: vec![],
+    };
+
+    Plant {
+        ambient: 25.0,
+        components: vec![component(DIMM, 20.0, 0.65), component(U2, 8.0, 0.4)],
+        fans: (0..2)
+            .map(|_| Fan {
+                speed: 0.0,
+                failed: false,
+            })
+            .collect(),
+        sensors: (0..NUM_COMPONENTS).map(|_| sensor()).collect(),
+        pwm: 100.0,
+        powered_down: false,
+    }
+}
+
+/// What happened over the course of a scenario
+struct Outcome {
+    states: Vec<ThermalAutoState>,
+
+    /// Time at which we first powered down, if we did
+    power_down_s: Option<u64>,
+
+    /// Furthest any component got above its power-down temperature
+    worst_excursion: f32,
+
+    plant: Plant,
+}
+
+impl Outcome {
+    fn reached(&self, state: ThermalAutoState) -> bool {
+        self.states.contains(&state)
+    }
+}
+
+fn run(
+    mut plant: Plant,
+    seconds: u64,
+    mut script: impl FnMut(u64, &mut Plant),
+) -> Outcome {
+    let mut control = ThermalController::<NUM_COMPONENTS>::new(PID);
+    let mut states = vec![];
+    let mut power_down_s = None;
+    let mut worst_excursion = f32::MIN;
+
+    for t in 0..seconds {
+        script(t, &mut plant);
+
+        let now_ms = t * CONTROL_PERIOD_MS;
+        for (c, s) in plant.components.iter().zip(plant.sensors.iter_mut()) {
+            s.history.push(c.temperature);
+        }
+
+        for i in 0..NUM_COMPONENTS {
+            if let Some((time_ms, value)) = plant.read(i, now_ms) {
+                control.write_temperature(i, time_ms, value);
+            }
+        }
+
+        let models: Vec<_> = plant.components.iter().map(|c| c.model).collect();
+        match control.run(now_ms, |i| models.get(i).copied()) {
+            ControlResult::Pwm(pwm) => {
+                assert!(pwm.0 <= 100);
+                plant.pwm = pwm.0 as f32;
+            }
+            ControlResult::PowerDown => {
+                plant.powered_down = true;
+                plant.pwm = 0.0;
+                power_down_s.get_or_insert(t);
+            }
+        }
+        states.push(control.state());
+
+        for c in &plant.components {
+            worst_excursion = worst_excursion
+                .max(c.temperature - c.model.power_down_temperature.0);
+        }
+
+        for _ in 0..SUBSTEPS {
+            plant.step(1.0 / SUBSTEPS as f32);
+        }
+    }
+
+    Outcome {
+        states,
+        power_down_s,
+        worst_excursion,
+        plant,
+    }
+}
+
+#[test]
+fn nominal() {
+    let o = run(plant(), 1800, |_, _| ());
+
+    assert!(!o.reached(ThermalAutoState::Overheated));
+    assert_eq!(o.power_down_s, None);
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Running));
+
+    // Once settled, the hot part should sit near its target, and neither
+    // should be anywhere near critical.
+    let dimm = &o.plant.components[0];
+    assert!(
+        (dimm.temperature - 80.0).abs() < 2.0,
+        "{}",
+        dimm.temperature
+    );
+    for c in &o.plant.components {
+        assert!(!c.model.is_critical(Celsius(c.temperature)));
+    }
+}
+
+#[test]
+fn boot_waits_for_every_sensor() {
+    let o = run(plant(), 120, |t, p| p.sensors[1].dropped = t < 60);
+
+    assert!(o.states[..60].iter().all(|s| *s == ThermalAutoState::Boot));
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Running));
+    assert_eq!(o.power_down_s, None);
+}
+
+#[test]
+fn one_fan_failure() {
+    let o = run(plant(), 1800, |t, p| p.fans[1].failed = t >= 600);
+
+    assert!(!o.reached(ThermalAutoState::Uncontrollable));
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Running));
+
+    // The remaining fan has to do the work of two, but the hot part should
+    // still settle at its target.
+    assert!(o.plant.pwm > 60.0, "{}", o.plant.pwm);
+    let dimm = &o.plant.components[0];
+    assert!(
+        (dimm.temperature - 80.0).abs() < 2.0,
+        "{}",
+        dimm.temperature
+    );
+}
+
+#[test]
+fn all_fans_fail() {
+    let o = run(plant(), 1800, |t, p| {
+        for f in &mut p.fans {
+            f.failed = t >= 600;
+        }
+    });
+
+    assert!(o.reached(ThermalAutoState::Overheated));
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Uncontrollable));
+    assert!(o.power_down_s.unwrap() > 600);
+
+    // We should have powered down before anything got much past its
+    // power-down temperature.
+    assert!(o.worst_excursion < 1.0, "{}", o.worst_excursion);
+}
+
+#[test]
+fn sensor_latency() {
+    let mut p = plant();
+    for s in &mut p.sensors {
+        s.latency_ms = 3000;
+    }
+    let o = run(p, 1800, |_, _| ());
+
+    assert_eq!(o.power_down_s, None);
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Running));
+}
+
+#[test]
+fn short_sensor_dropout() {
+    let o = run(plant(), 1800, |t, p| {
+        p.sensors[0].dropped = (900..910).contains(&t);
+    });
+
+    assert!(!o.reached(ThermalAutoState::Overheated));
+    assert_eq!(o.power_down_s, None);
+}
+
+#[test]
+fn long_sensor_dropout() {
+    // If we don't hear from a hot part for long enough, its modeled
+    // worst-case temperature climbs past power-down, and we have to assume
+    // that it's real.
+    let o = run(plant(), 1800, |t, p| {
+        p.sensors[0].dropped = t >= 900;
+    });
+
+    assert!(o.reached(ThermalAutoState::Overheated));
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Uncontrollable));
+    assert!(o.power_down_s.unwrap() > 900);
+}
+
+#[test]
+fn ambient_ramp_within_capacity() {
+    // 25°C to 40°C over 20 minutes
+    let o = run(plant(), 2400, |t, p| {
+        p.ambient = 25.0 + 15.0 * (t.min(1200) as f32 / 1200.0);
+    });
+
+    assert!(!o.reached(ThermalAutoState::Uncontrollable));
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Running));
+}
+
+#[test]
+fn ambient_ramp_beyond_capacity() {
+    // 25°C to 70°C over 20 minutes, at which even full airflow can't keep
+    // the cooler part below critical
+    let o = run(plant(), 2400, |t, p| {
+        p.ambient = 25.0 + 45.0 * (t.min(1200) as f32 / 1200.0);
+    });
+
+    assert_eq!(o.states.last(), Some(&ThermalAutoState::Uncontrollable));
+
+    // Ambient keeps climbing while we wait out the overheat timeout, so
+    // allow for a little more overshoot than when the fans fail outright.
+    assert!(o.worst_excursion < 2.5, "{}", o.worst_excursion);
+}
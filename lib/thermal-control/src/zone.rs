@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Thermal zones, each of which maps a set of inputs to the fans that cool
+//! them, and the arithmetic that turns per-zone duty cycles into per-fan ones.
+
+use crate::{Fan, FanMonitor, PidConfig};
+use core::ops::Range;
+use units::PWMDuty;
+
+/// A `Zone` is a group of inputs which are cooled by a particular group of
+/// fans, and which has its own PID loop and margin.
+///
+/// A fan may cool more than one zone, in which case it runs at the highest
+/// duty cycle that any of them requests (see [`fan_pwm`]).
+pub struct Zone {
+    /// Inputs in this zone, as indices into the BSP's inputs followed by its
+    /// dynamic inputs (i.e. the first dynamic input has index
+    /// `inputs.len()`)
+    pub inputs: Range<usize>,
+
+    /// Fans which cool this zone
+    pub fans: &'static [Fan],
+
+    /// Tuning for this zone's PID controller
+    pub pid_config: PidConfig,
+}
+
+impl Zone {
+    pub const fn new(
+        inputs: Range<usize>,
+        fans: &'static [Fan],
+        pid_config: PidConfig,
+    ) -> Self {
+        Self {
+            inputs,
+            fans,
+            pid_config,
+        }
+    }
+
+    /// Returns the duty cycle for this zone's fans, compensating for any that
+    /// have failed by raising the duty cycle of the rest.
+    ///
+    /// This assumes that airflow is roughly proportional to the total duty
+    /// cycle of working fans; if every fan in the zone has failed, then we
+    /// run them all at 100% in the hope that some of them come back.
+    pub fn compensated_pwm(
+        &self,
+        pwm: PWMDuty,
+        monitors: &[FanMonitor],
+    ) -> PWMDuty {
+        let total = self.fans.len();
+        let working = self
+            .fans
+            .iter()
+            .filter(|f| !monitors[f.0 as usize].is_faulty())
+            .count();
+        if working == total {
+            pwm
+        } else if working == 0 {
+            PWMDuty(100)
+        } else {
+            let pwm = pwm.0 as usize * total / working;
+            PWMDuty(pwm.min(100) as u8)
+        }
+    }
+}
+
+/// Returns the duty cycle for a single fan, given the duty cycle requested by
+/// each zone: the highest of those requested by the zones that it cools, or
+/// the highest of all if it cools none (so that an unassigned fan is never
+/// left idle).
+pub fn fan_pwm(fan: Fan, zones: &[Zone], zone_pwm: &[PWMDuty]) -> PWMDuty {
+    let pwms = zones.iter().zip(zone_pwm.iter());
+    pwms.clone()
+        .filter(|(zone, _)| zone.fans.contains(&fan))
+        .map(|(_, pwm)| pwm.0)
+        .max()
+        .or_else(|| pwms.map(|(_, pwm)| pwm.0).max())
+        .map(PWMDuty)
+        .unwrap_or(PWMDuty(100))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FanModel;
+    use units::Rpm;
+
+    const PID: PidConfig = PidConfig {
+        zero: 35.0,
+        gain_p: 1.75,
+        gain_i: 0.0135,
+        gain_d: 0.4,
+    };
+
+    const MODEL: FanModel = FanModel::new(Rpm(10000), PWMDuty(20), 0.2);
+
+    /// Returns monitors for `n` fans, of which those in `failed` have stalled
+    fn monitors<const N: usize>(failed: &[u8]) -> [FanMonitor; N] {
+        let mut m = [FanMonitor::new(); N];
+        for (i, m) in m.iter_mut().enumerate() {
+            m.set_pwm(PWMDuty(50));
+            let rpm = if failed.contains(&(i as u8)) { 0 } else { 5000 };
+            for _ in 0..10 {
+                m.record(&MODEL, Rpm(rpm));
+            }
+        }
+        m
+    }
+
+    #[test]
+    fn no_compensation_when_healthy() {
+        let zone = Zone::new(0..2, &[Fan(0), Fan(1), Fan(2), Fan(3)], PID);
+        let m = monitors::<4>(&[]);
+        assert_eq!(zone.compensated_pwm(PWMDuty(40), &m), PWMDuty(40));
+    }
+
+    #[test]
+    fn compensates_for_failed_fans() {
+        let zone = Zone::new(0..2, &[Fan(0), Fan(1), Fan(2), Fan(3)], PID);
+        let m = monitors::<4>(&[1]);
+        assert_eq!(zone.compensated_pwm(PWMDuty(30), &m), PWMDuty(40));
+
+        // Capped at 100%
+        let m = monitors::<4>(&[0, 1, 2]);
+        assert_eq!(zone.compensated_pwm(PWMDuty(40), &m), PWMDuty(100));
+    }
+
+    #[test]
+    fn all_failed_runs_flat_out() {
+        let zone = Zone::new(0..2, &[Fan(0), Fan(1)], PID);
+        let m = monitors::<2>(&[0, 1]);
+        assert_eq!(zone.compensated_pwm(PWMDuty(0), &m), PWMDuty(100));
+    }
+
+    #[test]
+    fn failures_outside_zone_ignored() {
+        let zone = Zone::new(0..2, &[Fan(0), Fan(1)], PID);
+        let m = monitors::<4>(&[2, 3]);
+        assert_eq!(zone.compensated_pwm(PWMDuty(30), &m), PWMDuty(30));
+    }
+
+    #[test]
+    fn fan_takes_highest_zone() {
+        let zones = [
+            Zone::new(0..2, &[Fan(0), Fan(1)], PID),
+            Zone::new(2..4, &[Fan(1), Fan(2)], PID),
+        ];
+        let pwm = [PWMDuty(30), PWMDuty(60)];
+        assert_eq!(fan_pwm(Fan(0), &zones, &pwm), PWMDuty(30));
+        assert_eq!(fan_pwm(Fan(1), &zones, &pwm), PWMDuty(60));
+        assert_eq!(fan_pwm(Fan(2), &zones, &pwm), PWMDuty(60));
+    }
+
+    #[test]
+    fn unassigned_fan_takes_worst() {
+        let zones = [
+            Zone::new(0..2, &[Fan(0)], PID),
+            Zone::new(2..4, &[Fan(1)], PID),
+        ];
+        let pwm = [PWMDuty(70), PWMDuty(20)];
+        assert_eq!(fan_pwm(Fan(5), &zones, &pwm), PWMDuty(70));
+        assert_eq!(fan_pwm(Fan(5), &[], &[]), PWMDuty(100));
+    }
+}
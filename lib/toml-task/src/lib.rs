@@ -60,6 +60,11 @@ pub struct Task<T = ordered_toml::Value> {
     pub sections: IndexMap<String, String>,
     #[serde(default)]
     pub max_sizes: IndexMap<String, u32>,
+
+    /// Worst-case stack depth, in bytes, of functions that stack analysis
+    /// can't follow (keyed by demangled path, e.g. `core::fmt::write`)
+    #[serde(default)]
+    pub stack_assumptions: IndexMap<String, u32>,
 }
 
 impl<T> Task<T> {
@@ -5,6 +5,11 @@
 //!
 //! Tuple structs for units that are useful in the real world
 //!
+//! These are re-exported as `userlib::units`; they live in their own crate so
+//! that code which doesn't otherwise need `userlib` can be built for the host.
+//!
+
+#![no_std]
 
 use core::convert::TryFrom;
 use zerocopy::{AsBytes, FromBytes};
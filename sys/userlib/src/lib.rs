@@ -34,6 +34,7 @@ pub mod macros;
 pub use abi::*;
 pub use num_derive::{FromPrimitive, ToPrimitive};
 pub use num_traits::{FromPrimitive, ToPrimitive};
+pub use units;
 pub use unwrap_lite::UnwrapLite;
 
 use core::arch;
@@ -42,7 +43,6 @@ use core::marker::PhantomData;
 pub mod hl;
 pub mod kipc;
 pub mod task_slot;
-pub mod units;
 
 #[derive(Debug)]
 #[repr(transparent)]
@@ -10,9 +10,11 @@ use derive_idol_err::IdolError;
 use serde::{Deserialize, Serialize};
 use userlib::*;
 
+pub use drv_i2c_api::I2cStatsEntry;
 pub use host_sp_messages::HostStartupOptions;
 pub use oxide_barcode::ParseError as BarcodeParseError;
 pub use oxide_barcode::VpdIdentity;
+pub use task_validate_api::I2cScan;
 
 /// Maximum length (in bytes) allowed for installinator image ID blobs.
 pub const MAX_INSTALLINATOR_IMAGE_ID_LEN: usize = 512;
@@ -26,6 +28,10 @@ pub enum ControlPlaneAgentError {
 
     #[idol(server_death)]
     ServerRestarted,
+
+    InvalidI2cBus,
+    I2cBusUnavailable,
+    I2cScanFailed,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -12,8 +12,8 @@ use gateway_messages::{
 };
 use task_sensor_api::Sensor as SensorTask;
 use task_sensor_api::SensorError;
+use task_validate_api::{I2cScan, Validate, ValidateError, ValidateOk};
 use task_validate_api::{Sensor, DEVICES as VALIDATE_DEVICES};
-use task_validate_api::{Validate, ValidateError, ValidateOk};
 use userlib::UnwrapLite;
 
 userlib::task_slot!(VALIDATE, validate);
@@ -135,6 +135,14 @@ impl Inventory {
             presence,
         }
     }
+
+    ///
+    /// Scans the `bus`th I2C bus, as enumerated by `validate`, for devices
+    /// that are missing, unexpected or misidentified.
+    ///
+    pub(crate) fn i2c_scan(&self, bus: u32) -> Result<I2cScan, ValidateError> {
+        self.validate_task.scan_i2c(bus)
+    }
 }
 
 // Our parent deals primarily in overall device indices (`0..num_devices()`),
@@ -17,12 +17,14 @@ use mutable_statics::mutable_statics;
 use ringbuf::{ringbuf, ringbuf_entry};
 use task_control_plane_agent_api::MAX_INSTALLINATOR_IMAGE_ID_LEN;
 use task_control_plane_agent_api::{
-    BarcodeParseError, ControlPlaneAgentError, UartClient, VpdIdentity,
+    BarcodeParseError, ControlPlaneAgentError, I2cScan, I2cStatsEntry,
+    UartClient, VpdIdentity,
 };
 use task_net_api::{
     Address, LargePayloadBehavior, Net, RecvError, SendError, SocketName,
     UdpMetadata,
 };
+use task_validate_api::ValidateError;
 use userlib::{sys_set_timer, task_slot};
 
 mod inventory;
@@ -40,6 +42,7 @@ mod mgs_handler;
 
 use self::mgs_handler::MgsHandler;
 
+task_slot!(I2C, i2c_driver);
 task_slot!(JEFE, jefe);
 task_slot!(NET, net);
 task_slot!(SYS, sys);
@@ -377,6 +380,46 @@ impl idl::InOrderControlPlaneAgentImpl for ServerImpl {
             ControlPlaneAgentError::OperationUnsupported,
         ))
     }
+
+    fn i2c_stats(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<I2cStatsEntry, RequestError<ControlPlaneAgentError>> {
+        // We only relay the I2C server's statistics; any failure to read
+        // them (including running off the end) is reported as unavailable.
+        match drv_i2c_api::read_stats(I2C.get_task_id(), index) {
+            Ok(Some(entry)) => Ok(entry),
+            Ok(None) | Err(_) => {
+                Err(ControlPlaneAgentError::DataUnavailable.into())
+            }
+        }
+    }
+
+    fn i2c_scan(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        bus: u32,
+    ) -> Result<I2cScan, RequestError<ControlPlaneAgentError>> {
+        self.mgs_handler.inventory().i2c_scan(bus).map_err(|err| {
+            match err {
+                ValidateError::InvalidDevice => {
+                    ControlPlaneAgentError::InvalidI2cBus
+                }
+                ValidateError::NotPresent
+                | ValidateError::Unavailable
+                | ValidateError::DeviceOff => {
+                    ControlPlaneAgentError::I2cBusUnavailable
+                }
+                ValidateError::BadValidation
+                | ValidateError::DeviceError
+                | ValidateError::DeviceTimeout => {
+                    ControlPlaneAgentError::I2cScanFailed
+                }
+            }
+            .into()
+        })
+    }
 }
 
 struct NetHandler {
@@ -491,7 +534,11 @@ impl NetHandler {
     ) {
         ringbuf_entry!(Log::Rx(meta));
 
-        let Address::Ipv6(addr) = meta.addr;
+        let addr = match meta.addr {
+            Address::Ipv6(addr) => addr,
+            // MGS only speaks IPv6.
+            Address::Ipv4(_) => return,
+        };
         let sender = gateway_messages::sp_impl::SocketAddrV6 {
             ip: addr.into(),
             port: meta.port,
@@ -548,7 +595,8 @@ const fn usize_max(a: usize, b: usize) -> usize {
 
 mod idl {
     use task_control_plane_agent_api::{
-        ControlPlaneAgentError, HostStartupOptions, UartClient, VpdIdentity,
+        ControlPlaneAgentError, HostStartupOptions, I2cScan, I2cStatsEntry,
+        UartClient, VpdIdentity,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -3,9 +3,10 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    mgs_common::MgsCommon, notifications, update::host_flash::HostFlashUpdate,
-    update::rot::RotUpdate, update::sp::SpUpdate, update::ComponentUpdater,
-    usize_max, vlan_id_from_sp_port, Log, MgsMessage, SYS,
+    inventory::Inventory, mgs_common::MgsCommon, notifications,
+    update::host_flash::HostFlashUpdate, update::rot::RotUpdate,
+    update::sp::SpUpdate, update::ComponentUpdater, usize_max,
+    vlan_id_from_sp_port, Log, MgsMessage, SYS,
 };
 use core::convert::Infallible;
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -155,6 +156,10 @@ impl MgsHandler {
         self.common.identity()
     }
 
+    pub(crate) fn inventory(&self) -> &Inventory {
+        self.common.inventory()
+    }
+
     pub(crate) fn installinator_image_id(&self) -> &[u8] {
         self.installinator_image_id
     }
@@ -5,8 +5,8 @@
 use core::convert::Infallible;
 
 use crate::{
-    mgs_common::MgsCommon, update::rot::RotUpdate, update::sp::SpUpdate,
-    update::ComponentUpdater, Log, MgsMessage,
+    inventory::Inventory, mgs_common::MgsCommon, update::rot::RotUpdate,
+    update::sp::SpUpdate, update::ComponentUpdater, Log, MgsMessage,
 };
 use gateway_messages::sp_impl::{
     BoundsChecked, DeviceDescription, SocketAddrV6, SpHandler,
@@ -61,6 +61,10 @@ impl MgsHandler {
         self.common.identity()
     }
 
+    pub(crate) fn inventory(&self) -> &Inventory {
+        self.common.inventory()
+    }
+
     /// If we want to be woken by the system timer, we return a deadline here.
     /// `main()` is responsible for calling this method and actually setting the
     /// timer.
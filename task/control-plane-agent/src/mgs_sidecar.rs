@@ -3,8 +3,8 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    mgs_common::MgsCommon, update::rot::RotUpdate, update::sp::SpUpdate,
-    update::ComponentUpdater, Log, MgsMessage,
+    inventory::Inventory, mgs_common::MgsCommon, update::rot::RotUpdate,
+    update::sp::SpUpdate, update::ComponentUpdater, Log, MgsMessage,
 };
 use core::convert::Infallible;
 use drv_ignition_api::IgnitionError;
@@ -81,6 +81,10 @@ impl MgsHandler {
         self.common.identity()
     }
 
+    pub(crate) fn inventory(&self) -> &Inventory {
+        self.common.inventory()
+    }
+
     /// If we want to be woken by the system timer, we return a deadline here.
     /// `main()` is responsible for calling this method and actually setting the
     /// timer.
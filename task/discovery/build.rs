@@ -0,0 +1,60 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TaskConfig {
+    /// Names of the sockets that we advertise, from `config.net.sockets`
+    services: Vec<String>,
+}
+
+/// Longest service name that we can advertise; names are sent padded with
+/// zeros to this length.
+const SERVICE_NAME_LEN: usize = 32;
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+
+    let config = build_util::task_config::<TaskConfig>()?;
+    let net = build_net::load_net_config()?;
+
+    let dest_path = build_util::out_dir().join("services.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+
+    writeln!(out, "const SERVICE_NAME_LEN: usize = {};", SERVICE_NAME_LEN)?;
+    writeln!(
+        out,
+        "const SERVICES: [Service; {}] = [",
+        config.services.len()
+    )?;
+    for name in &config.services {
+        let socket = net
+            .sockets
+            .get(name)
+            .ok_or_else(|| anyhow!("service {} is not a socket", name))?;
+        if name.len() > SERVICE_NAME_LEN {
+            bail!("service name {} is too long", name);
+        }
+        let protocol = match socket.kind.as_str() {
+            "udp" => "Udp",
+            "tcp" => "Tcp",
+            kind => bail!("unknown socket kind {}", kind),
+        };
+
+        let mut padded = [0u8; SERVICE_NAME_LEN];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        writeln!(
+            out,
+            "    Service {{ name: {:?}, protocol: Protocol::{}, port: {} }},",
+            padded, protocol, socket.port
+        )?;
+    }
+    writeln!(out, "];")?;
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Discovery responder
+//!
+//! This answers queries sent to a link-local multicast group, so that
+//! things on the management network (MGS, in particular) can find SPs
+//! without knowing their addresses ahead of time. It's in the spirit of
+//! mDNS, but much simpler: a query names a service (or asks for any), and if
+//! we offer it, we reply directly to the sender with our identity, our MAC
+//! address, and the ports of the services that matched.
+//!
+//! The services that we advertise are sockets, chosen by name in this task's
+//! config.
+//!
+//! Queries are sent to `ff02::d15c`, which we never join: `net` doesn't speak
+//! MLD, so it has no way to announce a listener, and doesn't need to for
+//! itself, since smoltcp hands IPv6 multicast to any socket bound to the
+//! destination port. We therefore rely on every switch between us and the
+//! querier flooding the group to all of its ports. A switch that does MLD
+//! snooping will see no listeners and drop the queries, so any such switch
+//! on the management network must be configured to flood `ff02::d15c` (or
+//! all link-local multicast) instead.
+
+#![no_std]
+#![no_main]
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+use task_net_api::*;
+use task_packrat_api::{Packrat, VpdIdentity};
+use userlib::*;
+
+task_slot!(NET, net);
+task_slot!(PACKRAT, packrat);
+
+const SOCKET: SocketName = SocketName::discovery;
+
+/// Version of the query and response formats. Adding new fields to the end of
+/// either is okay, but changing the order, size, or meaning of existing
+/// fields should result in a version bump.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Deserialize, SerializedSize)]
+struct Query {
+    version: u32,
+    /// Name of the service being looked for, padded with zeros; all zeros
+    /// matches any service, and so any SP.
+    service: [u8; SERVICE_NAME_LEN],
+}
+
+/// The start of a response, which is followed by `service_count` `Service`s.
+#[derive(Debug, Clone, Copy, Serialize, SerializedSize)]
+struct Response {
+    version: u32,
+
+    mac_address: [u8; 6],
+    image_id: [u8; 8],
+
+    // As in `udpbroadcast`: if false, we have no VPD or failed to read it,
+    // and the following three fields are all zero.
+    identity_valid: bool,
+    part_number: [u8; VpdIdentity::PART_NUMBER_LEN],
+    revision: u32,
+    serial: [u8; VpdIdentity::SERIAL_LEN],
+
+    service_count: u8,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, SerializedSize)]
+struct Service {
+    name: [u8; SERVICE_NAME_LEN],
+    protocol: Protocol,
+    port: u16,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, SerializedSize)]
+enum Protocol {
+    Udp,
+    Tcp,
+}
+
+// Ensure our serialized sizes don't change unexpectedly: if you land here
+// because compilation has failed, consider whether you need to update
+// `VERSION`!
+//
+// Query: version (4), service (32)
+// Response: version (4), mac_address (6), image_id (8), identity_valid (1),
+// part_number (11), revision (4), serial (11), service_count (1)
+// Service: name (32), protocol (1), port (2)
+static_assertions::const_assert_eq!(Query::MAX_SIZE, 36);
+static_assertions::const_assert_eq!(Response::MAX_SIZE, 46);
+static_assertions::const_assert_eq!(Service::MAX_SIZE, 35);
+
+include!(concat!(env!("OUT_DIR"), "/services.rs"));
+
+#[export_name = "main"]
+fn main() -> ! {
+    let net = NET.get_task_id();
+    let net = Net::from(net);
+
+    let packrat = PACKRAT.get_task_id();
+    let packrat = Packrat::from(packrat);
+
+    // As in `udpbroadcast`, asking `net` for our MAC address first also waits
+    // for `packrat` to be loaded, on boards with VPD.
+    let mac_address = net.get_mac_address().0;
+
+    let identity = packrat.get_identity().ok();
+    let identity_valid = identity.is_some();
+    let identity = identity.unwrap_or_default();
+
+    let response = Response {
+        version: VERSION,
+        mac_address,
+        image_id: kipc::read_image_id().to_le_bytes(),
+        identity_valid,
+        part_number: identity.part_number,
+        revision: identity.revision,
+        serial: identity.serial,
+        service_count: 0,
+    };
+
+    loop {
+        // Leave room for queries from newer versions, which may have grown
+        // fields that we don't know about.
+        let mut rx_data_buf = [0u8; 64];
+        match net.recv_packet(
+            SOCKET,
+            LargePayloadBehavior::Discard,
+            &mut rx_data_buf,
+        ) {
+            Ok(meta) => {
+                QUERY_COUNT.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+
+                let mut tx_data_buf = [0u8; Response::MAX_SIZE
+                    + SERVICES.len() * Service::MAX_SIZE];
+                let query = &rx_data_buf[..meta.size as usize];
+                let n = match respond(response, query, &mut tx_data_buf) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let meta = UdpMetadata {
+                    size: n as u32,
+                    ..meta
+                };
+
+                // Like mDNS, we don't try very hard: if we can't reply right
+                // now, the querier will ask again.
+                match net.send_packet(SOCKET, meta, &tx_data_buf[..n]) {
+                    Ok(()) => {
+                        RESPONSE_COUNT.fetch_add(
+                            1,
+                            core::sync::atomic::Ordering::Relaxed,
+                        );
+                    }
+                    Err(SendError::QueueFull | SendError::Other) => {
+                        DROP_COUNT.fetch_add(
+                            1,
+                            core::sync::atomic::Ordering::Relaxed,
+                        );
+                    }
+                    Err(SendError::ServerRestarted) => (),
+                    Err(SendError::NotYours | SendError::InvalidVLan) => {
+                        panic!()
+                    }
+                }
+            }
+            Err(RecvError::QueueEmpty) => {
+                // Our incoming queue is empty. Wait for more packets.
+                sys_recv_closed(
+                    &mut [],
+                    notifications::SOCKET_MASK,
+                    TaskId::KERNEL,
+                )
+                .unwrap();
+            }
+            Err(RecvError::ServerRestarted) => (),
+            Err(RecvError::NotYours) => panic!(),
+            Err(RecvError::Other) => panic!(),
+        }
+    }
+}
+
+/// Writes our response to `query` into `out`, returning its length, or `None`
+/// if we shouldn't respond at all.
+fn respond(
+    mut response: Response,
+    query: &[u8],
+    out: &mut [u8],
+) -> Option<usize> {
+    let (query, _) = hubpack::deserialize::<Query>(query).ok()?;
+    // We can't know what a query from some other version is asking for.
+    if query.version != VERSION {
+        return None;
+    }
+
+    let any = query.service == [0; SERVICE_NAME_LEN];
+    let services = SERVICES.iter().filter(|s| any || s.name == query.service);
+    response.service_count = services.clone().count() as u8;
+    // Someone looking for a particular service only wants to hear from SPs
+    // that offer it.
+    if !any && response.service_count == 0 {
+        return None;
+    }
+
+    let mut n = hubpack::serialize(out, &response).ok()?;
+    for service in services {
+        n += hubpack::serialize(&mut out[n..], service).ok()?;
+    }
+    Some(n)
+}
+
+static QUERY_COUNT: core::sync::atomic::AtomicU32 =
+    core::sync::atomic::AtomicU32::new(0);
+static RESPONSE_COUNT: core::sync::atomic::AtomicU32 =
+    core::sync::atomic::AtomicU32::new(0);
+static DROP_COUNT: core::sync::atomic::AtomicU32 =
+    core::sync::atomic::AtomicU32::new(0);
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -44,6 +44,9 @@ task_slot!(PACKRAT, packrat);
 task_slot!(NET, net);
 task_slot!(SYS, sys);
 
+#[cfg(feature = "power-budget")]
+task_slot!(POWER, power);
+
 // TODO: When rebooting the host, we need to wait for the relevant power rails
 // to decay. We ought to do this properly by monitoring the rails, but for now,
 // we'll simply wait a fixed period of time. This time is a WAG - we should
@@ -95,6 +98,10 @@ enum Trace {
     ResponseBufferReset {
         now: u64,
     },
+    PowerBudget {
+        now: u64,
+        over_budget: bool,
+    },
     Response {
         now: u64,
         sequence: u64,
@@ -223,6 +230,27 @@ impl ServerImpl {
         }
     }
 
+    /// Called when `power` tells us that we've gone over the power budget or
+    /// come back under it; we pass this along to the host, which is
+    /// responsible for shedding load.
+    #[cfg(feature = "power-budget")]
+    fn handle_power_budget_notification(&mut self) {
+        let power = task_power_api::Power::from(POWER.get_task_id());
+        let over_budget = power.power_budget_status().over_budget;
+
+        ringbuf_entry!(Trace::PowerBudget {
+            now: sys_get_timer().now,
+            over_budget,
+        });
+
+        let status = if over_budget {
+            self.status | Status::OVER_POWER_BUDGET
+        } else {
+            self.status.difference(Status::OVER_POWER_BUDGET)
+        };
+        self.set_status_impl(status);
+    }
+
     /// Power off the host (i.e., transition to A2).
     ///
     /// If `reboot` is true and we successfully instruct the sequencer to
@@ -862,12 +890,21 @@ impl ServerImpl {
     }
 }
 
+const BASE_NOTIFICATION_MASK: u32 = notifications::USART_IRQ_MASK
+    | notifications::JEFE_STATE_CHANGE_MASK
+    | notifications::MULTITIMER_MASK
+    | notifications::CONTROL_PLANE_AGENT_MASK;
+
+#[cfg(feature = "power-budget")]
+const NOTIFICATION_MASK: u32 =
+    BASE_NOTIFICATION_MASK | notifications::POWER_BUDGET_MASK;
+
+#[cfg(not(feature = "power-budget"))]
+const NOTIFICATION_MASK: u32 = BASE_NOTIFICATION_MASK;
+
 impl NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
-        notifications::USART_IRQ_MASK
-            | notifications::JEFE_STATE_CHANGE_MASK
-            | notifications::MULTITIMER_MASK
-            | notifications::CONTROL_PLANE_AGENT_MASK
+        NOTIFICATION_MASK
     }
 
     fn handle_notification(&mut self, bits: u32) {
@@ -886,6 +923,11 @@ impl NotificationHandler for ServerImpl {
             self.handle_control_plane_agent_notification();
         }
 
+        #[cfg(feature = "power-budget")]
+        if bits & notifications::POWER_BUDGET_MASK != 0 {
+            self.handle_power_budget_notification();
+        }
+
         // We may want to clear our TX periodic zero byte timer (if the TX FIFO
         // is full), but we can't modify the timers while iterating over them.
         // We'll record whether or not we want to clear the timer in this
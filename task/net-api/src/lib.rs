@@ -47,6 +47,51 @@ pub enum RecvError {
     ServerRestarted = 4,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum TcpError {
+    /// The selected socket is not owned by this task
+    NotYours = 1,
+
+    /// The specified VID is not in the configured range
+    InvalidVLan = 2,
+
+    /// There is no new connection waiting to be accepted
+    NoConnection = 3,
+
+    /// The connection was never accepted, or has since been closed or reset
+    NotConnected = 4,
+
+    /// The outgoing tx queue is full
+    QueueFull = 5,
+
+    /// The incoming rx queue is empty
+    QueueEmpty = 6,
+
+    /// The remote end has closed the connection, and everything it sent has
+    /// been received
+    Closed = 7,
+
+    Other = 8,
+
+    #[idol(server_death)]
+    ServerRestarted = 9,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum AddressError {
+    /// The VLAN index is out of range
+    InvalidVLan = 1,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
+#[repr(u32)]
+pub enum CountersError {
+    /// The VLAN index is out of range
+    InvalidVLan = 1,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
 #[repr(u32)]
 pub enum PhyError {
@@ -149,6 +194,119 @@ pub struct ManagementCounters {
     pub vsc85x2_mac_valid: bool,
 }
 
+/// Traffic through a socket since `net` started, summed over all VLANs, as
+/// returned by `get_socket_counters`
+///
+/// For TCP sockets, a "packet" is a `tcp_recv` or `tcp_send` call that moved
+/// some data.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct SocketCounters {
+    /// Packets delivered to the socket's owner
+    pub rx_packets: u32,
+    pub rx_bytes: u64,
+    /// Packets queued for sending by the socket's owner
+    pub tx_packets: u32,
+    pub tx_bytes: u64,
+
+    /// Receive calls that found nothing to receive. These aren't lost
+    /// packets (see `rx_dropped` for those): owners call until there's
+    /// nothing left, so this counts polls.
+    pub rx_empty_polls: u32,
+    /// Send calls that were refused because the tx queue was full
+    pub tx_queue_full: u32,
+    /// Received packets discarded because they were larger than the owner's
+    /// buffer (under `LargePayloadBehavior::Discard`)
+    pub rx_oversize: u32,
+    /// Received packets discarded because the socket's rx queue was full,
+    /// i.e. because its owner wasn't keeping up. Only UDP sockets count these;
+    /// TCP's flow control keeps a peer from overrunning the queue.
+    pub rx_dropped: u32,
+}
+
+/// Frames sent and received on a VLAN (or on the sole interface, without
+/// VLANs) since `net` started, as returned by `get_vlan_counters`
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct VLanCounters {
+    pub rx_packets: u32,
+    pub rx_bytes: u64,
+    pub tx_packets: u32,
+    pub tx_bytes: u64,
+}
+
+/// Received packets that were lost by the Ethernet MAC and its descriptor
+/// rings before reaching any VLAN, as returned by `get_mac_counters`
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
+)]
+pub struct MacCounters {
+    /// Packets dropped because the MAC's receive queue overflowed
+    pub rx_queue_overflow: u32,
+    /// Packets dropped because the receive ring was full
+    pub rx_missed: u32,
+    /// Packets dropped by the receive DMA, e.g. due to a bus error
+    pub rx_dma_dropped: u32,
+    /// Packets that the MAC flagged as having an error (e.g. a bad CRC)
+    pub rx_errors: u32,
+    /// Packets that were too large for a receive buffer
+    pub rx_fragmented: u32,
+    /// Packets without a VLAN tag, or with one outside our range; always zero
+    /// without VLANs
+    pub rx_bad_vid: u32,
+}
+
+/// How an interface's IPv4 address is configured
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub enum Ipv4Mode {
+    /// No IPv4 address
+    None,
+    /// A static address, from the app config
+    Static,
+    /// An address from DHCP
+    Dhcp,
+}
+
+/// How an interface's global IPv6 address is configured
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub enum Ipv6Mode {
+    /// No global address; only the link-local address
+    LinkLocal,
+    /// Stateless address autoconfiguration, from router advertisements
+    Slaac,
+    /// An address from DHCPv6
+    Dhcpv6,
+}
+
+/// Addresses of an interface, as returned by `get_address_state`
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct AddressState {
+    pub ipv4_mode: Ipv4Mode,
+    /// IPv4 address, if we have one
+    pub ipv4: Option<Ipv4Address>,
+    /// Prefix length of the network of `ipv4`
+    pub ipv4_prefix_len: u8,
+    /// IPv4 default gateway, if we have one
+    pub ipv4_gateway: Option<Ipv4Address>,
+
+    pub ipv6_mode: Ipv6Mode,
+    pub ipv6_link_local: Ipv6Address,
+    /// Global IPv6 address, if we have one
+    pub ipv6_global: Option<Ipv6Address>,
+    /// Prefix length of the network of `ipv6_global`
+    pub ipv6_prefix_len: u8,
+    /// IPv6 default router, from router advertisements, if we have one
+    pub ipv6_gateway: Option<Ipv6Address>,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError)]
 #[repr(u32)]
 pub enum MgmtError {
@@ -201,6 +359,20 @@ impl From<UdpMetadata> for smoltcp::wire::IpEndpoint {
     }
 }
 
+/// A connection on a TCP socket, as returned by `tcp_accept`
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct TcpConnection {
+    /// Address of the remote end
+    pub addr: Address,
+    /// Port of the remote end
+    pub port: u16,
+
+    #[cfg(feature = "vlan")]
+    pub vid: u16,
+}
+
 // This must be repr(C); otherwise Rust cleverly optimizes out the enum tag,
 // which breaks ssmarshal's assumptions about struct sizes.
 #[derive(
@@ -209,6 +381,7 @@ impl From<UdpMetadata> for smoltcp::wire::IpEndpoint {
 #[repr(C)]
 pub enum Address {
     Ipv6(Ipv6Address),
+    Ipv4(Ipv4Address),
 }
 
 #[cfg(feature = "use-smoltcp")]
@@ -216,6 +389,12 @@ impl From<Address> for smoltcp::wire::IpAddress {
     fn from(a: Address) -> Self {
         match a {
             Address::Ipv6(a) => Self::Ipv6(a.into()),
+            #[cfg(feature = "ipv4")]
+            Address::Ipv4(a) => Self::Ipv4(a.into()),
+            // Without IPv4 support, there's nowhere that an IPv4 address
+            // could go; smoltcp refuses to send to the unspecified address.
+            #[cfg(not(feature = "ipv4"))]
+            Address::Ipv4(_) => Self::Unspecified,
         }
     }
 }
@@ -229,6 +408,8 @@ impl TryFrom<smoltcp::wire::IpAddress> for Address {
 
         match a {
             IpAddress::Ipv6(a) => Ok(Self::Ipv6(a.into())),
+            #[cfg(feature = "ipv4")]
+            IpAddress::Ipv4(a) => Ok(Self::Ipv4(a.into())),
             _ => Err(AddressUnspecified),
         }
     }
@@ -257,5 +438,25 @@ impl From<Ipv6Address> for smoltcp::wire::Ipv6Address {
     }
 }
 
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+#[serde(transparent)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+#[cfg(all(feature = "use-smoltcp", feature = "ipv4"))]
+impl From<smoltcp::wire::Ipv4Address> for Ipv4Address {
+    fn from(a: smoltcp::wire::Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
+#[cfg(all(feature = "use-smoltcp", feature = "ipv4"))]
+impl From<Ipv4Address> for smoltcp::wire::Ipv4Address {
+    fn from(a: Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 include!(concat!(env!("OUT_DIR"), "/net_config.rs"));
@@ -3,7 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use anyhow::{anyhow, bail, Result};
-use build_net::{BufSize, NetConfig, SocketConfig};
+use build_net::{AddressConfig, BufSize, NetConfig, SocketConfig};
 use proc_macro2::TokenStream;
 use std::io::Write;
 
@@ -28,15 +28,31 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     let out_dir = build_util::out_dir();
     let dest_path = out_dir.join("net_config.rs");
 
+    // TCP support costs flash and RAM, so only tasks with TCP sockets get it;
+    // likewise IPv4, and the sockets for IPv6 address autoconfiguration.
+    let tcp = config.sockets.values().any(|s| s.kind == "tcp");
+    check_feature("tcp", tcp, "TCP sockets")?;
+    let mut addresses = config.vlan.iter().flat_map(|v| v.addresses.iter());
+    let ipv4 = addresses.clone().any(|a| a.ipv4.is_some());
+    check_feature("ipv4", ipv4, "IPv4 addresses")?;
+    let autoconf = addresses.any(|a| a.ipv6.is_some());
+    check_feature("ipv6-autoconf", autoconf, "global IPv6 addresses")?;
+
     let mut out = std::fs::File::create(dest_path)?;
 
     let socket_count = config.sockets.len();
+    let tcp_imports = tcp.then(|| {
+        quote::quote! {
+            use smoltcp::socket::{TcpSocket, TcpSocketBuffer};
+        }
+    });
     writeln!(
         out,
         "{}",
         quote::quote! {
             use core::sync::atomic::{AtomicBool, Ordering};
             use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+            #tcp_imports
 
             pub const SOCKET_COUNT: usize = #socket_count;
         }
@@ -53,14 +69,18 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
             generate_socket_state(
                 name,
                 socket,
-                config.vlan.map(|v| v.count).unwrap_or(1)
+                config.vlan.as_ref().map(|v| v.count).unwrap_or(1)
             )?
         )?;
     }
-    writeln!(out, "{}", generate_state_struct(config))?;
-    writeln!(out, "{}", generate_constructor(config)?)?;
+    writeln!(out, "{}", generate_state_struct(config, tcp))?;
+    writeln!(out, "{}", generate_constructor(config, tcp)?)?;
     writeln!(out, "{}", generate_owner_info(config)?)?;
     writeln!(out, "{}", generate_port_table(config)?)?;
+    if tcp {
+        writeln!(out, "{}", generate_kind_table(config))?;
+    }
+    writeln!(out, "{}", generate_address_config(config)?)?;
 
     build_net::generate_socket_enum(config, &mut out)?;
 
@@ -71,6 +91,20 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     Ok(())
 }
 
+/// Checks that `feature` is enabled exactly when the configuration has
+/// `what`.
+fn check_feature(feature: &str, needed: bool, what: &str) -> Result<()> {
+    if needed != build_util::has_feature(feature) {
+        bail!(
+            "the net task's {} feature must be enabled exactly when there \
+             are {}",
+            feature,
+            what
+        );
+    }
+    Ok(())
+}
+
 fn generate_port_table(config: &NetConfig) -> Result<TokenStream> {
     let consts = config.sockets.values().map(|socket| {
         let port = socket.port;
@@ -86,6 +120,97 @@ fn generate_port_table(config: &NetConfig) -> Result<TokenStream> {
     })
 }
 
+fn generate_kind_table(config: &NetConfig) -> TokenStream {
+    let kinds = config.sockets.values().map(|socket| {
+        if socket.kind == "tcp" {
+            quote::quote! { SocketKind::Tcp }
+        } else {
+            quote::quote! { SocketKind::Udp }
+        }
+    });
+
+    let n = config.sockets.len();
+
+    quote::quote! {
+        pub(crate) const SOCKET_KINDS: [SocketKind; #n] = [
+            #( #kinds ),*
+        ];
+    }
+}
+
+fn generate_address_config(config: &NetConfig) -> Result<TokenStream> {
+    let (count, addresses) = match &config.vlan {
+        Some(vlan) => (vlan.count, vlan.addresses.as_slice()),
+        None => (1, &[][..]),
+    };
+    let configs = (0..count)
+        .map(|i| {
+            generate_address(&addresses.get(i).cloned().unwrap_or_default())
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(quote::quote! {
+        pub(crate) const ADDRESS_CONFIG: [crate::address::Config; #count] = [
+            #( #configs ),*
+        ];
+    })
+}
+
+fn generate_address(config: &AddressConfig) -> Result<TokenStream> {
+    let has_static =
+        config.ipv4_address.is_some() || config.ipv4_gateway.is_some();
+    let ipv4 = match config.ipv4.as_deref() {
+        None | Some("dhcp") if has_static => {
+            bail!("IPv4 address and gateway are only allowed for static IPv4")
+        }
+        None => quote::quote! { crate::address::Ipv4Config::None },
+        Some("dhcp") => quote::quote! { crate::address::Ipv4Config::Dhcp },
+        Some("static") => {
+            let cidr = config
+                .ipv4_address
+                .as_deref()
+                .ok_or_else(|| anyhow!("static IPv4 needs an ipv4-address"))?;
+            let (address, prefix_len) = cidr
+                .split_once('/')
+                .ok_or_else(|| anyhow!("ipv4-address needs a prefix length"))?;
+            let address = address.parse::<std::net::Ipv4Addr>()?.octets();
+            let prefix_len = prefix_len.parse::<u8>()?;
+            if prefix_len > 32 {
+                bail!("bad IPv4 prefix length {}", prefix_len);
+            }
+            let gateway = match config.ipv4_gateway.as_deref() {
+                Some(gateway) => {
+                    let gateway =
+                        gateway.parse::<std::net::Ipv4Addr>()?.octets();
+                    quote::quote! { Some([ #( #gateway ),* ]) }
+                }
+                None => quote::quote! { None },
+            };
+            quote::quote! {
+                crate::address::Ipv4Config::Static {
+                    address: [ #( #address ),* ],
+                    prefix_len: #prefix_len,
+                    gateway: #gateway,
+                }
+            }
+        }
+        Some(other) => bail!("unknown IPv4 configuration {:?}", other),
+    };
+    let ipv6 = match config.ipv6.as_deref() {
+        None => quote::quote! { crate::address::Ipv6Config::LinkLocal },
+        Some("slaac") => quote::quote! { crate::address::Ipv6Config::Slaac },
+        Some("dhcpv6") => quote::quote! { crate::address::Ipv6Config::Dhcpv6 },
+        Some(other) => bail!("unknown IPv6 configuration {:?}", other),
+    };
+
+    Ok(quote::quote! {
+        crate::address::Config {
+            ipv4: #ipv4,
+            ipv6: #ipv6,
+        }
+    })
+}
+
 fn generate_owner_info(config: &NetConfig) -> Result<TokenStream> {
     let consts: Vec<_> = config
         .sockets
@@ -123,48 +248,89 @@ fn generate_socket_state(
     config: &SocketConfig,
     vlan_count: usize,
 ) -> Result<TokenStream> {
-    if config.kind != "udp" {
-        bail!("unsupported socket kind");
-    }
+    let (tx_packets, rx_packets) = match config.kind.as_str() {
+        "udp" => {
+            let packets = |buf: &BufSize| {
+                buf.packets.ok_or_else(|| {
+                    anyhow!("UDP socket {} must specify packets", name)
+                })
+            };
+            (Some(packets(&config.tx)?), Some(packets(&config.rx)?))
+        }
+        "tcp" => {
+            if config.tx.packets.is_some() || config.rx.packets.is_some() {
+                bail!("TCP socket {} must not specify packets", name);
+            }
+            (None, None)
+        }
+        _ => bail!("unsupported socket kind"),
+    };
 
-    let tx = generate_buffers(name, "TX", &config.tx, vlan_count);
-    let rx = generate_buffers(name, "RX", &config.rx, vlan_count);
+    let tx = generate_buffers(name, "TX", tx_packets, &config.tx, vlan_count);
+    let rx = generate_buffers(name, "RX", rx_packets, &config.rx, vlan_count);
     Ok(quote::quote! {
         #tx
         #rx
     })
 }
 
+/// Generates buffers for one direction of a socket. `packets` is the number of
+/// packet headers to allocate alongside the data, which only UDP sockets need.
 fn generate_buffers(
     name: &str,
     dir: &str,
+    packets: Option<usize>,
     config: &BufSize,
     vlan_count: usize,
 ) -> TokenStream {
-    let pktcnt = config.packets;
     let bytecnt = config.bytes;
     let upname = name.to_ascii_uppercase();
     let hdrname: syn::Ident =
         syn::parse_str(&format!("SOCK_{}_HDR_{}", dir, upname)).unwrap();
     let bufname: syn::Ident =
         syn::parse_str(&format!("SOCK_{}_DAT_{}", dir, upname)).unwrap();
+    let hdrs = packets.map(|pktcnt| {
+        quote::quote! {
+            static mut #hdrname: [[UdpPacketMetadata; #pktcnt]; #vlan_count] = [
+                [UdpPacketMetadata::EMPTY; #pktcnt]; #vlan_count
+            ];
+        }
+    });
     quote::quote! {
-        static mut #hdrname: [[UdpPacketMetadata; #pktcnt]; #vlan_count] = [
-            [UdpPacketMetadata::EMPTY; #pktcnt]; #vlan_count
-        ];
+        #hdrs
         static mut #bufname: [[u8; #bytecnt]; #vlan_count] = [[0u8; #bytecnt]; #vlan_count];
     }
 }
 
-fn generate_state_struct(config: &NetConfig) -> TokenStream {
+fn generate_state_struct(config: &NetConfig, tcp: bool) -> TokenStream {
     let n = config.sockets.len();
+    if !tcp {
+        return quote::quote! {
+            pub(crate) struct Sockets<'a, const N: usize>(pub [[UdpSocket<'a>; #n]; N]);
+        };
+    }
     quote::quote! {
-        pub(crate) struct Sockets<'a, const N: usize>(pub [[UdpSocket<'a>; #n]; N]);
+        // The sockets only live in this enum between their construction and
+        // being handed to the interface at startup, so the difference in size
+        // between the variants costs us nothing.
+        #[allow(clippy::large_enum_variant)]
+        pub(crate) enum Socket<'a> {
+            Udp(UdpSocket<'a>),
+            Tcp(TcpSocket<'a>),
+        }
+
+        #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+        pub(crate) enum SocketKind {
+            Udp,
+            Tcp,
+        }
+
+        pub(crate) struct Sockets<'a, const N: usize>(pub [[Socket<'a>; #n]; N]);
     }
 }
 
-fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
-    let name_to_sockets = |name: &String, i: usize| {
+fn generate_constructor(config: &NetConfig, tcp: bool) -> Result<TokenStream> {
+    let name_to_sockets = |name: &String, socket: &SocketConfig, i: usize| {
         let upname = name.to_ascii_uppercase();
         let rxhdrs: syn::Ident =
             syn::parse_str(&format!("SOCK_RX_HDR_{}", upname)).unwrap();
@@ -175,7 +341,15 @@ fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
         let txbytes: syn::Ident =
             syn::parse_str(&format!("SOCK_TX_DAT_{}", upname)).unwrap();
 
-        quote::quote! {
+        if socket.kind == "tcp" {
+            return quote::quote! {
+                Socket::Tcp(TcpSocket::new(
+                    TcpSocketBuffer::new(unsafe { &mut #rxbytes[#i][..] }),
+                    TcpSocketBuffer::new(unsafe { &mut #txbytes[#i][..] }),
+                ))
+            };
+        }
+        let udp = quote::quote! {
             UdpSocket::new(
                 UdpSocketBuffer::new(
                     unsafe { &mut #rxhdrs[#i][..] },
@@ -186,15 +360,20 @@ fn generate_constructor(config: &NetConfig) -> Result<TokenStream> {
                     unsafe { &mut #txbytes[#i][..] },
                 ),
             )
+        };
+        if tcp {
+            quote::quote! { Socket::Udp(#udp) }
+        } else {
+            udp
         }
     };
-    let vlan_count = config.vlan.map(|v| v.count).unwrap_or(1);
+    let vlan_count = config.vlan.as_ref().map(|v| v.count).unwrap_or(1);
     let sockets = (0..vlan_count)
         .map(|i| {
             let s = config
                 .sockets
-                .keys()
-                .map(|n| name_to_sockets(n, i))
+                .iter()
+                .map(|(n, s)| name_to_sockets(n, s, i))
                 .collect::<Vec<_>>();
             quote::quote! {
                 [
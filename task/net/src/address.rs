@@ -0,0 +1,670 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Address configuration for each interface
+//!
+//! Every interface has an IPv6 link-local address, derived from its MAC
+//! address. Beyond that, each can be configured (per VLAN, in `app.toml`) with
+//! an IPv4 address, which is either static or from DHCP, and a global IPv6
+//! address, from SLAAC or DHCPv6. These need the `ipv4` and `ipv6-autoconf`
+//! features respectively, so that boards which don't use them don't pay for
+//! them.
+//!
+//! smoltcp runs the DHCPv4 client for us, but it ignores router
+//! advertisements and has no DHCPv6 support, so those are handled here: router
+//! advertisements arrive on a raw ICMPv6 socket (the interface still does
+//! neighbor discovery itself), and the protocols themselves are in the
+//! `ipv6-autoconf` crate.
+//!
+//! This is deliberately minimal. We use the first router and prefix that we
+//! hear about, and don't do duplicate address detection.
+//!
+//! On source addresses: smoltcp sends from whatever address a socket is
+//! bound to, and a socket bound to no address in particular sends from the
+//! first address of the right family in the interface's list. On an interface
+//! with only a link-local address, sockets are bound to it, as they always
+//! have been. Otherwise, they're bound to no address, so that they see traffic
+//! to all of ours, and we keep the global IPv6 address (when we have one)
+//! ahead of the link-local one, so that traffic can be routed back to us.
+//! That does mean that replies to peers who reached us on our link-local
+//! address come from the global address instead, since smoltcp can't tell us
+//! which address a packet was sent to.
+
+#[cfg(feature = "ipv6-autoconf")]
+use ipv6_autoconf::{dhcpv6, ndisc};
+use smoltcp::iface::Interface;
+#[cfg(any(feature = "ipv4", feature = "ipv6-autoconf"))]
+use smoltcp::iface::SocketHandle;
+#[cfg(feature = "ipv4")]
+use smoltcp::socket::{Dhcpv4Event, Dhcpv4Socket};
+#[cfg(feature = "ipv6-autoconf")]
+use smoltcp::socket::{
+    RawPacketMetadata, RawSocket, RawSocketBuffer, UdpPacketMetadata,
+    UdpSocket, UdpSocketBuffer,
+};
+use smoltcp::wire::{EthernetAddress, IpCidr, Ipv6Address, Ipv6Cidr};
+#[cfg(feature = "ipv6-autoconf")]
+use smoltcp::wire::{IpEndpoint, IpProtocol, IpVersion};
+#[cfg(feature = "ipv4")]
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+use task_net_api::{AddressState, Ipv4Mode, Ipv6Mode};
+#[cfg(any(feature = "ipv4", feature = "ipv6-autoconf"))]
+use userlib::UnwrapLite;
+
+/// Address configuration for an interface, from `app.toml`
+#[derive(Copy, Clone)]
+pub(crate) struct Config {
+    pub ipv4: Ipv4Config,
+    pub ipv6: Ipv6Config,
+}
+
+impl Config {
+    /// Checks whether the interface only ever has its link-local address.
+    pub(crate) fn link_local_only(&self) -> bool {
+        matches!(self.ipv4, Ipv4Config::None)
+            && matches!(self.ipv6, Ipv6Config::LinkLocal)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum Ipv4Config {
+    None,
+    #[cfg(feature = "ipv4")]
+    Dhcp,
+    #[cfg(feature = "ipv4")]
+    Static {
+        address: [u8; 4],
+        prefix_len: u8,
+        gateway: Option<[u8; 4]>,
+    },
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum Ipv6Config {
+    LinkLocal,
+    #[cfg(feature = "ipv6-autoconf")]
+    Slaac,
+    #[cfg(feature = "ipv6-autoconf")]
+    Dhcpv6,
+}
+
+// Slots in each interface's address list. smoltcp uses the first IPv6
+// address as the source address, so that's the global one if we have one,
+// and the link-local one otherwise; the other goes in the spare slot.
+const ADDR_IPV6: usize = 0;
+#[cfg(feature = "ipv6-autoconf")]
+const ADDR_IPV6_SPARE: usize = 1;
+#[cfg(feature = "ipv4")]
+const ADDR_IPV4: usize = ADDR_COUNT - 1;
+pub(crate) const ADDR_COUNT: usize = 1
+    + cfg!(feature = "ipv6-autoconf") as usize
+    + cfg!(feature = "ipv4") as usize;
+
+/// Number of sockets that we may add to each interface: one for DHCPv4, and
+/// one each for router advertisements and DHCPv6.
+pub(crate) const SOCKETS: usize = cfg!(feature = "ipv4") as usize
+    + 2 * cfg!(feature = "ipv6-autoconf") as usize;
+
+/// Number of attempts at router solicitation when we start up; after that we
+/// wait for routers to advertise of their own accord.
+#[cfg(feature = "ipv6-autoconf")]
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+#[cfg(feature = "ipv6-autoconf")]
+const RTR_SOLICITATION_INTERVAL: u64 = 4_000;
+
+/// Placeholder for an unused IPv6 address slot. This has the longest possible
+/// prefix, so that it doesn't make anything look like it's on-link.
+#[cfg(feature = "ipv6-autoconf")]
+fn no_ipv6() -> IpCidr {
+    Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 128).into()
+}
+
+/// Placeholder for an unused IPv4 address slot, as for `no_ipv6`
+#[cfg(feature = "ipv4")]
+fn no_ipv4() -> IpCidr {
+    Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 32).into()
+}
+
+/// Returns the initial address list for an interface.
+pub(crate) fn initial_addrs(link_local: Ipv6Address) -> [IpCidr; ADDR_COUNT] {
+    let mut addrs = [Ipv6Cidr::new(link_local, 64).into(); ADDR_COUNT];
+    #[cfg(feature = "ipv6-autoconf")]
+    {
+        addrs[ADDR_IPV6_SPARE] = no_ipv6();
+    }
+    #[cfg(feature = "ipv4")]
+    {
+        addrs[ADDR_IPV4] = no_ipv4();
+    }
+    addrs
+}
+
+/// Buffers for the sockets that we add to an interface
+#[derive(Default)]
+pub(crate) struct Storage {
+    #[cfg(feature = "ipv6-autoconf")]
+    autoconf: AutoconfStorage,
+}
+
+#[cfg(feature = "ipv6-autoconf")]
+struct AutoconfStorage {
+    ra_rx_meta: [RawPacketMetadata; 2],
+    ra_rx: [u8; 256],
+    ra_tx_meta: [RawPacketMetadata; 1],
+    ra_tx: [u8; ndisc::RS_LEN],
+    dhcpv6_rx_meta: [UdpPacketMetadata; 2],
+    dhcpv6_rx: [u8; 512],
+    dhcpv6_tx_meta: [UdpPacketMetadata; 1],
+    dhcpv6_tx: [u8; dhcpv6::MAX_MESSAGE_LEN],
+}
+
+#[cfg(feature = "ipv6-autoconf")]
+impl Default for AutoconfStorage {
+    fn default() -> Self {
+        Self {
+            ra_rx_meta: [RawPacketMetadata::EMPTY; 2],
+            ra_rx: [0; 256],
+            ra_tx_meta: [RawPacketMetadata::EMPTY; 1],
+            ra_tx: [0; ndisc::RS_LEN],
+            dhcpv6_rx_meta: [UdpPacketMetadata::EMPTY; 2],
+            dhcpv6_rx: [0; 512],
+            dhcpv6_tx_meta: [UdpPacketMetadata::EMPTY; 1],
+            dhcpv6_tx: [0; dhcpv6::MAX_MESSAGE_LEN],
+        }
+    }
+}
+
+/// A global IPv6 address
+#[cfg(feature = "ipv6-autoconf")]
+#[derive(Copy, Clone)]
+struct Global {
+    address: Ipv6Address,
+    prefix_len: u8,
+    /// When the address stops being valid, or `None` if it's valid forever
+    expires: Option<u64>,
+}
+
+/// Address state for one interface
+pub(crate) struct Addresses {
+    config: Config,
+    link_local: Ipv6Address,
+
+    #[cfg(feature = "ipv4")]
+    dhcpv4: Option<SocketHandle>,
+    #[cfg(feature = "ipv4")]
+    ipv4: Option<Ipv4Cidr>,
+    #[cfg(feature = "ipv4")]
+    ipv4_gateway: Option<Ipv4Address>,
+
+    #[cfg(feature = "ipv6-autoconf")]
+    mac: EthernetAddress,
+    #[cfg(feature = "ipv6-autoconf")]
+    ra: Option<SocketHandle>,
+    #[cfg(feature = "ipv6-autoconf")]
+    dhcpv6: Option<(SocketHandle, dhcpv6::Client)>,
+    /// Default router, and when it stops being one
+    #[cfg(feature = "ipv6-autoconf")]
+    router: Option<(Ipv6Address, u64)>,
+    #[cfg(feature = "ipv6-autoconf")]
+    global: Option<Global>,
+    #[cfg(feature = "ipv6-autoconf")]
+    solicits_left: u8,
+    #[cfg(feature = "ipv6-autoconf")]
+    next_solicit: u64,
+}
+
+impl Addresses {
+    /// Sets up addressing for `iface`, adding whatever sockets its
+    /// configuration needs. The interface must have been built with an address
+    /// list from `initial_addrs` and room for two routes.
+    #[allow(unused_variables)]
+    pub(crate) fn new<E>(
+        config: Config,
+        iface: &mut Interface<'static, E>,
+        storage: &'static mut Storage,
+        mac: EthernetAddress,
+        link_local: Ipv6Address,
+    ) -> Self
+    where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        #[allow(unused_mut)]
+        let mut out = Self {
+            config,
+            link_local,
+            #[cfg(feature = "ipv4")]
+            dhcpv4: None,
+            #[cfg(feature = "ipv4")]
+            ipv4: None,
+            #[cfg(feature = "ipv4")]
+            ipv4_gateway: None,
+            #[cfg(feature = "ipv6-autoconf")]
+            mac,
+            #[cfg(feature = "ipv6-autoconf")]
+            ra: None,
+            #[cfg(feature = "ipv6-autoconf")]
+            dhcpv6: None,
+            #[cfg(feature = "ipv6-autoconf")]
+            router: None,
+            #[cfg(feature = "ipv6-autoconf")]
+            global: None,
+            #[cfg(feature = "ipv6-autoconf")]
+            solicits_left: 0,
+            #[cfg(feature = "ipv6-autoconf")]
+            next_solicit: 0,
+        };
+
+        match config.ipv4 {
+            Ipv4Config::None => (),
+            #[cfg(feature = "ipv4")]
+            Ipv4Config::Dhcp => {
+                out.dhcpv4 = Some(iface.add_socket(Dhcpv4Socket::new()));
+            }
+            #[cfg(feature = "ipv4")]
+            Ipv4Config::Static {
+                address,
+                prefix_len,
+                gateway,
+            } => {
+                let cidr = Ipv4Cidr::new(Ipv4Address(address), prefix_len);
+                out.set_ipv4(iface, Some(cidr), gateway.map(Ipv4Address));
+            }
+        }
+
+        #[cfg(feature = "ipv6-autoconf")]
+        out.start_autoconf(iface, &mut storage.autoconf);
+
+        out
+    }
+
+    /// Processes anything received on our sockets, sends anything that's
+    /// due, and expires any addresses or routers whose time is up. `now` is
+    /// in milliseconds.
+    #[allow(unused_variables)]
+    pub(crate) fn poll<E>(
+        &mut self,
+        iface: &mut Interface<'static, E>,
+        now: u64,
+    ) where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        #[cfg(feature = "ipv4")]
+        self.poll_dhcpv4(iface);
+
+        #[cfg(feature = "ipv6-autoconf")]
+        {
+            self.poll_ra(iface, now);
+            self.poll_dhcpv6(iface, now);
+            self.expire(iface, now);
+        }
+    }
+
+    /// Returns the next time at which `poll` has something to do, beyond
+    /// handling traffic. DHCPv4 has its own timers, which the interface knows
+    /// about, so only IPv6 autoconfiguration ever has anything.
+    #[cfg(not(feature = "ipv6-autoconf"))]
+    pub(crate) fn poll_at(&self) -> Option<u64> {
+        None
+    }
+
+    /// Reports our current addresses.
+    pub(crate) fn state(&self) -> AddressState {
+        AddressState {
+            ipv4_mode: match self.config.ipv4 {
+                Ipv4Config::None => Ipv4Mode::None,
+                #[cfg(feature = "ipv4")]
+                Ipv4Config::Dhcp => Ipv4Mode::Dhcp,
+                #[cfg(feature = "ipv4")]
+                Ipv4Config::Static { .. } => Ipv4Mode::Static,
+            },
+            #[cfg(feature = "ipv4")]
+            ipv4: self.ipv4.map(|c| c.address().into()),
+            #[cfg(feature = "ipv4")]
+            ipv4_prefix_len: self.ipv4.map(|c| c.prefix_len()).unwrap_or(0),
+            #[cfg(feature = "ipv4")]
+            ipv4_gateway: self.ipv4_gateway.map(Into::into),
+            #[cfg(not(feature = "ipv4"))]
+            ipv4: None,
+            #[cfg(not(feature = "ipv4"))]
+            ipv4_prefix_len: 0,
+            #[cfg(not(feature = "ipv4"))]
+            ipv4_gateway: None,
+
+            ipv6_mode: match self.config.ipv6 {
+                Ipv6Config::LinkLocal => Ipv6Mode::LinkLocal,
+                #[cfg(feature = "ipv6-autoconf")]
+                Ipv6Config::Slaac => Ipv6Mode::Slaac,
+                #[cfg(feature = "ipv6-autoconf")]
+                Ipv6Config::Dhcpv6 => Ipv6Mode::Dhcpv6,
+            },
+            ipv6_link_local: self.link_local.into(),
+            #[cfg(feature = "ipv6-autoconf")]
+            ipv6_global: self.global.map(|g| g.address.into()),
+            #[cfg(feature = "ipv6-autoconf")]
+            ipv6_prefix_len: self.global.map(|g| g.prefix_len).unwrap_or(0),
+            #[cfg(feature = "ipv6-autoconf")]
+            ipv6_gateway: self.router.map(|(r, _)| r.into()),
+            #[cfg(not(feature = "ipv6-autoconf"))]
+            ipv6_global: None,
+            #[cfg(not(feature = "ipv6-autoconf"))]
+            ipv6_prefix_len: 0,
+            #[cfg(not(feature = "ipv6-autoconf"))]
+            ipv6_gateway: None,
+        }
+    }
+}
+
+#[cfg(feature = "ipv4")]
+impl Addresses {
+    fn poll_dhcpv4<E>(&mut self, iface: &mut Interface<'static, E>)
+    where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        let handle = match self.dhcpv4 {
+            Some(handle) => handle,
+            None => return,
+        };
+        // Pull what we need out of the event before touching the interface,
+        // since the event borrows from it.
+        let change = match iface.get_socket::<Dhcpv4Socket>(handle).poll() {
+            None => return,
+            Some(Dhcpv4Event::Configured(config)) => {
+                (Some(config.address), config.router)
+            }
+            Some(Dhcpv4Event::Deconfigured) => (None, None),
+        };
+        self.set_ipv4(iface, change.0, change.1);
+    }
+
+    fn set_ipv4<E>(
+        &mut self,
+        iface: &mut Interface<'static, E>,
+        cidr: Option<Ipv4Cidr>,
+        gateway: Option<Ipv4Address>,
+    ) where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        iface.update_ip_addrs(|addrs| {
+            addrs[ADDR_IPV4] = cidr.map(Into::into).unwrap_or_else(no_ipv4);
+        });
+        match gateway {
+            Some(gateway) => {
+                iface
+                    .routes_mut()
+                    .add_default_ipv4_route(gateway)
+                    .unwrap_lite();
+            }
+            None => {
+                iface.routes_mut().remove_default_ipv4_route();
+            }
+        }
+        self.ipv4 = cidr;
+        self.ipv4_gateway = gateway;
+    }
+}
+
+#[cfg(feature = "ipv6-autoconf")]
+impl Addresses {
+    /// Returns the next time at which `poll` has something to do, beyond
+    /// handling traffic.
+    pub(crate) fn poll_at(&self) -> Option<u64> {
+        let solicit = (self.solicits_left > 0).then_some(self.next_solicit);
+        let router = self.router.map(|(_, expires)| expires);
+        let global = self.global.and_then(|g| g.expires);
+        let dhcpv6 = self.dhcpv6.as_ref().map(|(_, c)| c.poll_at());
+
+        [solicit, router, global, dhcpv6]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Adds the sockets for router discovery and DHCPv6, as the configuration
+    /// needs them.
+    fn start_autoconf<E>(
+        &mut self,
+        iface: &mut Interface<'static, E>,
+        storage: &'static mut AutoconfStorage,
+    ) where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        if let Ipv6Config::LinkLocal = self.config.ipv6 {
+            return;
+        }
+
+        // We listen for router advertisements whether or not we're doing
+        // SLAAC, since they're also how we find a default router.
+        let socket = RawSocket::new(
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            RawSocketBuffer::new(
+                &mut storage.ra_rx_meta[..],
+                &mut storage.ra_rx[..],
+            ),
+            RawSocketBuffer::new(
+                &mut storage.ra_tx_meta[..],
+                &mut storage.ra_tx[..],
+            ),
+        );
+        self.ra = Some(iface.add_socket(socket));
+        self.solicits_left = MAX_RTR_SOLICITATIONS;
+
+        if let Ipv6Config::Dhcpv6 = self.config.ipv6 {
+            let mut socket = UdpSocket::new(
+                UdpSocketBuffer::new(
+                    &mut storage.dhcpv6_rx_meta[..],
+                    &mut storage.dhcpv6_rx[..],
+                ),
+                UdpSocketBuffer::new(
+                    &mut storage.dhcpv6_tx_meta[..],
+                    &mut storage.dhcpv6_tx[..],
+                ),
+            );
+            // DHCPv6 clients must use their link-local address.
+            socket
+                .bind((self.link_local, dhcpv6::CLIENT_PORT))
+                .unwrap_lite();
+            let handle = iface.add_socket(socket);
+            self.dhcpv6 = Some((handle, dhcpv6::Client::new(self.mac.0)));
+        }
+    }
+
+    /// Drops any router or global address whose time is up.
+    fn expire<E>(&mut self, iface: &mut Interface<'static, E>, now: u64)
+    where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        if let Some((_, expires)) = self.router {
+            if now >= expires {
+                self.set_router(iface, None);
+            }
+        }
+        if let Some(Global {
+            expires: Some(expires),
+            ..
+        }) = self.global
+        {
+            if now >= expires {
+                self.set_global(iface, None);
+                if let Some((_, client)) = &mut self.dhcpv6 {
+                    client.restart(now);
+                }
+            }
+        }
+    }
+
+    fn set_router<E>(
+        &mut self,
+        iface: &mut Interface<'static, E>,
+        router: Option<(Ipv6Address, u64)>,
+    ) where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        match router {
+            Some((router, _)) => {
+                iface
+                    .routes_mut()
+                    .add_default_ipv6_route(router)
+                    .unwrap_lite();
+            }
+            None => {
+                iface.routes_mut().remove_default_ipv6_route();
+            }
+        }
+        self.router = router;
+    }
+
+    fn set_global<E>(
+        &mut self,
+        iface: &mut Interface<'static, E>,
+        global: Option<Global>,
+    ) where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        let link_local = Ipv6Cidr::new(self.link_local, 64).into();
+        iface.update_ip_addrs(|addrs| match global {
+            Some(g) => {
+                addrs[ADDR_IPV6] =
+                    Ipv6Cidr::new(g.address, g.prefix_len).into();
+                addrs[ADDR_IPV6_SPARE] = link_local;
+            }
+            None => {
+                addrs[ADDR_IPV6] = link_local;
+                addrs[ADDR_IPV6_SPARE] = no_ipv6();
+            }
+        });
+        self.global = global;
+    }
+
+    /// Handles router advertisements, and sends router solicitations while we
+    /// have some left to send.
+    fn poll_ra<E>(&mut self, iface: &mut Interface<'static, E>, now: u64)
+    where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        let handle = match self.ra {
+            Some(handle) => handle,
+            None => return,
+        };
+
+        // This socket sees all ICMPv6 traffic, most of which isn't for us.
+        loop {
+            let socket = iface.get_socket::<RawSocket<'_>>(handle);
+            let ra = match socket.recv() {
+                Ok(packet) => ndisc::parse_router_advert(packet),
+                Err(_) => break,
+            };
+            if let Some(ra) = ra {
+                self.on_router_advert(iface, now, ra);
+            }
+        }
+
+        if self.router.is_some() {
+            self.solicits_left = 0;
+        }
+        if self.solicits_left > 0 && now >= self.next_solicit {
+            let rs = ndisc::router_solicit(self.link_local.0, self.mac.0);
+            let socket = iface.get_socket::<RawSocket<'_>>(handle);
+            if socket.send_slice(&rs).is_ok() {
+                self.solicits_left -= 1;
+                self.next_solicit = now + RTR_SOLICITATION_INTERVAL;
+            }
+        }
+    }
+
+    fn on_router_advert<E>(
+        &mut self,
+        iface: &mut Interface<'static, E>,
+        now: u64,
+        ra: ndisc::RouterAdvert,
+    ) where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        // A lifetime of zero means that the router is no longer a default
+        // router; otherwise, take it if we don't already have one.
+        let source = Ipv6Address(ra.source);
+        let current = self.router.map(|(r, _)| r);
+        if ra.lifetime == 0 {
+            if current == Some(source) {
+                self.set_router(iface, None);
+            }
+        } else if current.is_none() || current == Some(source) {
+            let expires = now + u64::from(ra.lifetime) * 1000;
+            self.set_router(iface, Some((source, expires)));
+        }
+
+        let prefix = match ra.prefix {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        if !matches!(self.config.ipv6, Ipv6Config::Slaac) {
+            return;
+        }
+        // Form our address from the prefix and the interface ID of our
+        // link-local address.
+        let mut address = Ipv6Address(prefix.prefix);
+        address.0[8..].copy_from_slice(&self.link_local.0[8..]);
+
+        let ours = self.global.map(|g| g.address);
+        if ours.is_some() && ours != Some(address) {
+            // First prefix wins.
+            return;
+        }
+        if prefix.valid == 0 {
+            if ours.is_some() {
+                self.set_global(iface, None);
+            }
+            return;
+        }
+        let expires = match prefix.valid {
+            u32::MAX => None,
+            valid => Some(now + u64::from(valid) * 1000),
+        };
+        self.set_global(
+            iface,
+            Some(Global {
+                address,
+                prefix_len: 64,
+                expires,
+            }),
+        );
+    }
+
+    fn poll_dhcpv6<E>(&mut self, iface: &mut Interface<'static, E>, now: u64)
+    where
+        E: for<'d> smoltcp::phy::Device<'d>,
+    {
+        let (handle, client) = match &mut self.dhcpv6 {
+            Some((handle, client)) => (*handle, client),
+            None => return,
+        };
+        let socket = iface.get_socket::<UdpSocket<'_>>(handle);
+
+        let mut lease = None;
+        while let Ok((msg, _)) = socket.recv() {
+            lease = client.receive(now, msg).or(lease);
+        }
+        let mut buf = [0u8; dhcpv6::MAX_MESSAGE_LEN];
+        if let Some(n) = client.transmit(now, &mut buf) {
+            let servers = IpEndpoint::new(
+                Ipv6Address(dhcpv6::ALL_SERVERS).into(),
+                dhcpv6::SERVER_PORT,
+            );
+            // If the tx queue is full, the retransmission timer will send it
+            // again.
+            let _ = socket.send_slice(&buf[..n], servers);
+        }
+
+        if let Some(lease) = lease {
+            self.set_global(
+                iface,
+                Some(Global {
+                    address: Ipv6Address(lease.address),
+                    prefix_len: 128,
+                    expires: Some(now + u64::from(lease.valid) * 1000),
+                }),
+            );
+        }
+    }
+}
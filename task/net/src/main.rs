@@ -7,6 +7,7 @@
 
 pub mod pins;
 
+mod address;
 mod bsp_support;
 mod buf;
 mod miim_bridge;
@@ -48,9 +49,11 @@ pub(crate) mod mgmt;
 
 mod idl {
     use task_net_api::{
-        KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-        MacAddressBlock, ManagementCounters, ManagementLinkStatus, MgmtError,
-        PhyError, RecvError, SendError, SocketName, UdpMetadata,
+        AddressError, AddressState, CountersError, KszError, KszMacTableEntry,
+        LargePayloadBehavior, MacAddress, MacAddressBlock, MacCounters,
+        ManagementCounters, ManagementLinkStatus, MgmtError, PhyError,
+        RecvError, SendError, SocketCounters, SocketName, TcpConnection,
+        TcpError, UdpMetadata, VLanCounters,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -145,6 +148,16 @@ const NEIGHBORS: usize = 4;
 /// b0rked and restart it.
 const RX_WATCHDOG_INTERVAL: u64 = 60_000;
 
+/// How long data sent on a TCP connection may go unacknowledged before we
+/// give up on the connection.
+#[cfg(feature = "tcp")]
+const TCP_TIMEOUT: u64 = 30_000;
+
+/// How long a TCP connection may be idle before we send a keep-alive, so that
+/// we notice (through `TCP_TIMEOUT`) if the remote end has gone away.
+#[cfg(feature = "tcp")]
+const TCP_KEEP_ALIVE: u64 = 10_000;
+
 /////////////////////////////////////////////////////////////////////////////
 // Main driver loop.
 
@@ -213,11 +226,12 @@ fn main() -> ! {
     // Turn on our IRQ.
     userlib::sys_irq_control(notifications::ETH_IRQ_MASK, true);
 
-    // We use two timers:
+    // We use three timers:
     #[derive(Copy, Clone, Enum)]
     enum Timers {
         Wake,
         Watchdog,
+        Poll,
     }
     let mut multitimer =
         Multitimer::<Timers>::new(notifications::WAKE_TIMER_BIT);
@@ -267,6 +281,12 @@ fn main() -> ! {
             // Ask the server to iterate over sockets looking for work
             server.wake_sockets();
         } else {
+            // Make sure that we come back around when the IP stack next needs
+            // to do something without being prompted by traffic (e.g. TCP
+            // retransmission).
+            if let Some(t) = server.poll_at(now) {
+                multitimer.set_timer(Timers::Poll, t, None);
+            }
             multitimer.poll_now();
             for t in multitimer.iter_fired() {
                 match t {
@@ -275,6 +295,8 @@ fn main() -> ! {
                         // timer is set to auto-repeat
                     }
                     Timers::Watchdog => panic!("MAC RX watchdog"),
+                    // Nothing to do here: we poll at the top of the loop.
+                    Timers::Poll => (),
                 }
             }
             let mut msgbuf = [0u8; idl::INCOMING_SIZE];
@@ -2,26 +2,40 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::address::{self, Addresses};
 use crate::bsp_support;
 use crate::generated::{self, SOCKET_COUNT};
 use crate::notifications;
 use crate::{idl, link_local_iface_addr, MacAddressBlock, NEIGHBORS};
 
+#[cfg(feature = "tcp")]
+use crate::generated::SocketKind;
 #[cfg(feature = "vlan")]
 use crate::generated::VLAN_RANGE;
+#[cfg(feature = "tcp")]
+use crate::{TCP_KEEP_ALIVE, TCP_TIMEOUT};
 
 use drv_stm32h7_eth as eth;
 use idol_runtime::{ClientError, RequestError};
 use task_net_api::{
-    KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-    ManagementCounters, ManagementLinkStatus, MgmtError, PhyError, RecvError,
-    SendError, SocketName, UdpMetadata,
+    AddressError, AddressState, CountersError, KszError, KszMacTableEntry,
+    LargePayloadBehavior, MacAddress, MacCounters, ManagementCounters,
+    ManagementLinkStatus, MgmtError, PhyError, RecvError, SendError,
+    SocketCounters, SocketName, TcpConnection, TcpError, UdpMetadata,
+    VLanCounters,
 };
 
+use core::cell::Cell;
 use core::iter::zip;
 use heapless::Vec;
-use smoltcp::iface::{Interface, Neighbor, SocketHandle, SocketStorage};
+use smoltcp::iface::{
+    Interface, Neighbor, Route, Routes, SocketHandle, SocketStorage,
+};
 use smoltcp::socket::UdpSocket;
+#[cfg(feature = "tcp")]
+use smoltcp::socket::{TcpSocket, TcpState};
+#[cfg(feature = "tcp")]
+use smoltcp::time::Duration;
 use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv6Cidr};
 use userlib::{sys_post, sys_refresh_task_id, UnwrapLite};
 use zerocopy::byteorder::U16;
@@ -52,6 +66,93 @@ where
         self.net_send_packet(msg, socket, metadata, payload)
     }
 
+    ////////////////////////////////////////////////////////////////////////////
+    // Stubs for TCP functions when there are no TCP sockets; every socket is
+    // a UDP socket, which the TCP functions reject.
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_accept(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+    ) -> Result<TcpConnection, RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_recv(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _connection: TcpConnection,
+        _payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_send(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _connection: TcpConnection,
+        _payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    #[cfg(not(feature = "tcp"))]
+    fn tcp_close(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _socket: SocketName,
+        _connection: TcpConnection,
+    ) -> Result<(), RequestError<TcpError>> {
+        Err(RequestError::Fail(ClientError::BadMessageContents))
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Main TCP functions
+    #[cfg(feature = "tcp")]
+    fn tcp_accept(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<TcpConnection, RequestError<TcpError>> {
+        self.net_tcp_accept(msg, socket)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_recv(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+        payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        self.net_tcp_recv(msg, socket, connection, payload)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_send(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+        payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        self.net_tcp_send(msg, socket, connection, payload)
+    }
+
+    #[cfg(feature = "tcp")]
+    fn tcp_close(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+    ) -> Result<(), RequestError<TcpError>> {
+        self.net_tcp_close(msg, socket, connection)
+    }
+
     fn smi_read(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -119,6 +220,18 @@ where
         Ok(self.spare_macs)
     }
 
+    fn get_address_state(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u16,
+    ) -> Result<AddressState, RequestError<AddressError>> {
+        let vlan = self
+            .vlan_state
+            .get(usize::from(index))
+            .ok_or(AddressError::InvalidVLan)?;
+        Ok(vlan.addresses.state())
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Stubs for KSZ8463 functions when it's not present
     #[cfg(not(feature = "ksz8463"))]
@@ -239,6 +352,46 @@ where
         let out = bsp.management_counters(eth).map_err(MgmtError::from)?;
         Ok(out)
     }
+
+    fn get_socket_counters(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<SocketCounters, RequestError<core::convert::Infallible>> {
+        let i = socket as usize;
+        let mut c = self.socket_counters[i];
+        c.rx_dropped = self.vlan_state.iter().fold(0, |n, v| {
+            n.wrapping_add(v.iface.device().counters().rx_dropped(i))
+        });
+        Ok(c)
+    }
+
+    fn get_vlan_counters(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u16,
+    ) -> Result<VLanCounters, RequestError<CountersError>> {
+        let vlan = self
+            .vlan_state
+            .get(usize::from(index))
+            .ok_or(CountersError::InvalidVLan)?;
+        Ok(vlan.iface.device().counters().get())
+    }
+
+    fn get_mac_counters(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<MacCounters, RequestError<core::convert::Infallible>> {
+        let c = self.eth.counters();
+        Ok(MacCounters {
+            rx_queue_overflow: c.rx_queue_overflow,
+            rx_missed: c.rx_missed,
+            rx_dma_dropped: c.rx_dma_dropped,
+            rx_errors: c.rx_ring.errors,
+            rx_fragmented: c.rx_ring.fragmented,
+            rx_bad_vid: c.rx_ring.bad_vid,
+        })
+    }
 }
 
 pub trait DeviceExt: for<'d> smoltcp::phy::Device<'d> {
@@ -250,6 +403,124 @@ pub trait DeviceExt: for<'d> smoltcp::phy::Device<'d> {
         size: usize,
         addr: task_net_api::Address,
     ) -> UdpMetadata;
+
+    fn make_connection(
+        &self,
+        port: u16,
+        addr: task_net_api::Address,
+    ) -> TcpConnection;
+
+    fn counters(&self) -> &DeviceCounters;
+}
+
+/// Frames sent and received by a device, which its tokens count as they go
+/// through, and received packets that a socket had no room for.
+pub struct DeviceCounters {
+    frames: Cell<VLanCounters>,
+    rx_dropped: [Cell<u32>; SOCKET_COUNT],
+}
+
+impl Default for DeviceCounters {
+    fn default() -> Self {
+        Self {
+            frames: Cell::default(),
+            rx_dropped: core::array::from_fn(|_| Cell::new(0)),
+        }
+    }
+}
+
+impl DeviceCounters {
+    pub fn count_rx(&self, len: usize) {
+        let mut c = self.frames.get();
+        c.rx_packets = c.rx_packets.wrapping_add(1);
+        c.rx_bytes = c.rx_bytes.wrapping_add(len as u64);
+        self.frames.set(c);
+    }
+
+    /// Checks the result of handing the received `frame` to smoltcp.
+    ///
+    /// smoltcp refuses a UDP packet with `Exhausted` when the rx buffer of
+    /// the socket it's addressed to is full, so we count that as a drop
+    /// against the socket bound to the packet's destination port.
+    pub fn count_rx_result<R>(
+        &self,
+        frame: &[u8],
+        result: &smoltcp::Result<R>,
+    ) {
+        if !matches!(result, Err(smoltcp::Error::Exhausted)) {
+            return;
+        }
+        if let Some(i) = udp_dst_port(frame).and_then(udp_socket_index) {
+            let c = &self.rx_dropped[i];
+            c.set(c.get().wrapping_add(1));
+        }
+    }
+
+    pub fn count_tx(&self, len: usize) {
+        let mut c = self.frames.get();
+        c.tx_packets = c.tx_packets.wrapping_add(1);
+        c.tx_bytes = c.tx_bytes.wrapping_add(len as u64);
+        self.frames.set(c);
+    }
+
+    pub fn get(&self) -> VLanCounters {
+        self.frames.get()
+    }
+
+    pub fn rx_dropped(&self, socket: usize) -> u32 {
+        self.rx_dropped[socket].get()
+    }
+}
+
+/// Returns the destination port of the UDP packet carried by the Ethernet
+/// frame `frame`, if that's what it carries.
+fn udp_dst_port(frame: &[u8]) -> Option<u16> {
+    use smoltcp::wire::{
+        EthernetFrame, EthernetProtocol, IpProtocol, Ipv6Packet, UdpPacket,
+    };
+
+    let frame = EthernetFrame::new_checked(frame).ok()?;
+    let payload = match frame.ethertype() {
+        EthernetProtocol::Ipv6 => {
+            let packet = Ipv6Packet::new_checked(frame.payload()).ok()?;
+            if packet.next_header() != IpProtocol::Udp {
+                return None;
+            }
+            packet.payload()
+        }
+        #[cfg(feature = "ipv4")]
+        EthernetProtocol::Ipv4 => {
+            let packet =
+                smoltcp::wire::Ipv4Packet::new_checked(frame.payload()).ok()?;
+            if packet.protocol() != IpProtocol::Udp {
+                return None;
+            }
+            packet.payload()
+        }
+        _ => return None,
+    };
+    UdpPacket::new_checked(payload).ok().map(|p| p.dst_port())
+}
+
+/// Returns the index of the UDP socket bound to `port`, if there is one.
+fn udp_socket_index(port: u16) -> Option<usize> {
+    (0..SOCKET_COUNT).find(|&i| {
+        #[cfg(feature = "tcp")]
+        if generated::SOCKET_KINDS[i] != SocketKind::Udp {
+            return false;
+        }
+        generated::SOCKET_PORTS[i] == port
+    })
+}
+
+fn count_socket_rx(counters: &mut SocketCounters, len: usize) {
+    counters.rx_packets = counters.rx_packets.wrapping_add(1);
+    counters.rx_bytes = counters.rx_bytes.wrapping_add(len as u64);
+}
+
+fn count_socket_tx(counters: &mut SocketCounters, len: usize) {
+    counters.tx_packets = counters.tx_packets.wrapping_add(1);
+    counters.tx_bytes = counters.tx_bytes.wrapping_add(len as u64);
 }
 
 /// State for the running network server
@@ -261,6 +532,7 @@ where
 
     vlan_state: [VLanState<E>; N],
     client_waiting_to_send: [bool; SOCKET_COUNT],
+    socket_counters: [SocketCounters; SOCKET_COUNT],
     bsp: B,
 
     mac: EthernetAddress,
@@ -273,6 +545,13 @@ where
 {
     socket_handles: [SocketHandle; SOCKET_COUNT],
     iface: Interface<'static, E>,
+
+    /// Whether the connection on each TCP socket has been handed to the
+    /// socket's owner by `tcp_accept`.
+    #[cfg(feature = "tcp")]
+    tcp_accepted: [bool; SOCKET_COUNT],
+
+    addresses: Addresses,
 }
 
 impl<E: DeviceExt> VLanState<E> {
@@ -280,19 +559,52 @@ impl<E: DeviceExt> VLanState<E> {
         self.socket_handles.get(index).cloned()
     }
 
-    /// Gets the socket `index`. If `index` is out of range, returns
-    /// `None`.
-    ///
-    /// Sockets are currently assumed to be UDP.
+    /// Gets the UDP socket `index`. If `index` is out of range, or isn't a
+    /// UDP socket, returns `None`.
     pub(crate) fn get_socket_mut(
         &mut self,
         index: usize,
     ) -> Option<&mut UdpSocket<'static>> {
+        #[cfg(feature = "tcp")]
+        if *generated::SOCKET_KINDS.get(index)? != SocketKind::Udp {
+            return None;
+        }
         Some(
             self.iface
                 .get_socket::<UdpSocket<'_>>(self.get_handle(index)?),
         )
     }
+
+    /// Gets the TCP socket `index`. If `index` is out of range, or isn't a
+    /// TCP socket, returns `None`.
+    #[cfg(feature = "tcp")]
+    pub(crate) fn get_tcp_socket_mut(
+        &mut self,
+        index: usize,
+    ) -> Option<&mut TcpSocket<'static>> {
+        if *generated::SOCKET_KINDS.get(index)? != SocketKind::Tcp {
+            return None;
+        }
+        Some(
+            self.iface
+                .get_socket::<TcpSocket<'_>>(self.get_handle(index)?),
+        )
+    }
+}
+
+/// Puts a TCP socket into the listening state, ready for a new connection.
+#[cfg(feature = "tcp")]
+fn tcp_listen(socket: &mut TcpSocket<'_>, port: u16) {
+    socket.listen(port).unwrap_lite();
+    // Listening resets these, so they must be set afterwards.
+    socket.set_timeout(Some(Duration::from_millis(TCP_TIMEOUT)));
+    socket.set_keep_alive(Some(Duration::from_millis(TCP_KEEP_ALIVE)));
+}
+
+/// Checks whether a TCP socket has a connection that could be accepted.
+#[cfg(feature = "tcp")]
+fn tcp_connected(socket: &TcpSocket<'_>) -> bool {
+    matches!(socket.state(), TcpState::Established | TcpState::CloseWait)
 }
 
 impl<'a, B, E, const N: usize> GenServerImpl<'a, B, E, N>
@@ -321,9 +633,6 @@ where
             let mac_addr = EthernetAddress::from_bytes(&mac);
             let ipv6_addr = link_local_iface_addr(mac_addr);
 
-            // Make some types explicit to try and make this clearer.
-            let sockets: [UdpSocket<'_>; SOCKET_COUNT] = sockets;
-
             let neighbor_cache =
                 smoltcp::iface::NeighborCache::new(&mut storage.neighbors[..]);
 
@@ -332,27 +641,58 @@ where
                 &mut storage.sockets[..],
             );
 
-            storage.net = Ipv6Cidr::new(ipv6_addr, 64).into();
+            storage.ip_addrs = address::initial_addrs(ipv6_addr);
             let mut iface = builder
                 .hardware_addr(mac_addr.into())
                 .neighbor_cache(neighbor_cache)
-                .ip_addrs(core::slice::from_mut(&mut storage.net))
+                .ip_addrs(&mut storage.ip_addrs[..])
+                .routes(Routes::new(&mut storage.routes[..]))
                 .finalize();
 
             // Associate sockets with this interface.
+            #[cfg(feature = "tcp")]
+            let socket_handles = sockets.map(|s| match s {
+                generated::Socket::Udp(s) => iface.add_socket(s),
+                generated::Socket::Tcp(s) => iface.add_socket(s),
+            });
+            #[cfg(not(feature = "tcp"))]
             let socket_handles = sockets.map(|s| iface.add_socket(s));
-            // Bind sockets to their ports.
-            for (&h, port) in zip(&socket_handles, generated::SOCKET_PORTS) {
-                iface
-                    .get_socket::<UdpSocket<'_>>(h)
-                    .bind((ipv6_addr, port))
-                    .unwrap_lite();
+
+            // Bind sockets to their ports. UDP sockets on an interface with
+            // more than a link-local address are bound to every address, so
+            // that they see traffic to all of them; see the `address` module
+            // for what that means for source addresses.
+            let config = generated::ADDRESS_CONFIG[i];
+            for (j, &h) in socket_handles.iter().enumerate() {
+                let port = generated::SOCKET_PORTS[j];
+                #[cfg(feature = "tcp")]
+                if generated::SOCKET_KINDS[j] == SocketKind::Tcp {
+                    tcp_listen(iface.get_socket::<TcpSocket<'_>>(h), port);
+                    continue;
+                }
+                let socket = iface.get_socket::<UdpSocket<'_>>(h);
+                if config.link_local_only() {
+                    socket.bind((ipv6_addr, port)).unwrap_lite();
+                } else {
+                    socket.bind(port).unwrap_lite();
+                }
             }
 
+            let addresses = Addresses::new(
+                config,
+                &mut iface,
+                &mut storage.address,
+                mac_addr,
+                ipv6_addr,
+            );
+
             vlan_state
                 .push(VLanState {
                     socket_handles,
                     iface,
+                    #[cfg(feature = "tcp")]
+                    tcp_accepted: [false; SOCKET_COUNT],
+                    addresses,
                 })
                 .unwrap_lite();
 
@@ -386,6 +726,7 @@ where
         Self {
             eth,
             client_waiting_to_send: [false; SOCKET_COUNT],
+            socket_counters: [SocketCounters::default(); SOCKET_COUNT],
             vlan_state: vlan_state.into_array().unwrap_lite(),
             bsp,
             mac: EthernetAddress::from_bytes(&mac_address_block.base_mac),
@@ -397,21 +738,74 @@ where
         }
     }
 
-    pub(crate) fn poll(&mut self, t: u64) -> smoltcp::Result<crate::Activity> {
-        let t = smoltcp::time::Instant::from_millis(t as i64);
+    pub(crate) fn poll(
+        &mut self,
+        now: u64,
+    ) -> smoltcp::Result<crate::Activity> {
+        let t = smoltcp::time::Instant::from_millis(now as i64);
         // Do not be tempted to use `Iterator::any` here, it short circuits and
         // we really do want to poll all of them.
         let mut ip = false;
         let mut mac_rx = false;
         for vlan in &mut self.vlan_state {
             ip |= vlan.iface.poll(t)?;
+            vlan.addresses.poll(&mut vlan.iface, now);
             // Test and clear our receive activity flag.
             mac_rx |= vlan.iface.device().read_and_clear_activity_flag();
         }
+        // The MAC's drop counters saturate, so we collect them every time
+        // around, rather than waiting for someone to ask.
+        self.eth.update_counters();
+        #[cfg(feature = "tcp")]
+        self.relisten_tcp_sockets();
 
         Ok(crate::Activity { ip, mac_rx })
     }
 
+    /// Returns the time at which we next need to poll, whether or not there's
+    /// any traffic, for things like TCP retransmissions and address leases.
+    pub(crate) fn poll_at(&mut self, now: u64) -> Option<u64> {
+        let t = smoltcp::time::Instant::from_millis(now as i64);
+        self.vlan_state
+            .iter_mut()
+            .flat_map(|vlan| {
+                let iface = vlan.iface.poll_at(t);
+                let iface = iface.map(|t| t.total_millis() as u64);
+                [iface, vlan.addresses.poll_at()]
+            })
+            .flatten()
+            .min()
+    }
+
+    /// Puts any TCP sockets whose connections have finished back into the
+    /// listening state. If the owner had accepted the connection, we wake it,
+    /// so that it finds out that the connection has gone.
+    ///
+    /// A socket that we closed first sits in `TimeWait` for a while, and we
+    /// don't wait for that to end: we'd refuse new connections in the
+    /// meantime, and anything still in flight from the old one will be
+    /// rejected by the new one's sequence numbers anyway.
+    #[cfg(feature = "tcp")]
+    fn relisten_tcp_sockets(&mut self) {
+        for i in 0..SOCKET_COUNT {
+            if generated::SOCKET_KINDS[i] != SocketKind::Tcp {
+                continue;
+            }
+            for vlan in &mut self.vlan_state {
+                let socket = vlan.get_tcp_socket_mut(i).unwrap_lite();
+                if matches!(
+                    socket.state(),
+                    TcpState::Closed | TcpState::TimeWait
+                ) {
+                    tcp_listen(socket, generated::SOCKET_PORTS[i]);
+                    if core::mem::take(&mut vlan.tcp_accepted[i]) {
+                        wake_socket_owner(i);
+                    }
+                }
+            }
+        }
+    }
+
     /// Iterate over sockets, waking any that can do work.
     ///
     /// A task can do work if...
@@ -422,8 +816,18 @@ where
     ///   across all VLANs can accept an outgoing packet. (The "all" is
     ///   important here since we don't keep track of which one it's trying to
     ///   send through.)
+    ///
+    /// For TCP sockets, see `tcp_can_work`.
     pub fn wake_sockets(&mut self) {
         for i in 0..SOCKET_COUNT {
+            #[cfg(feature = "tcp")]
+            if generated::SOCKET_KINDS[i] == SocketKind::Tcp {
+                if self.tcp_can_work(i) {
+                    wake_socket_owner(i);
+                }
+                continue;
+            }
+
             // recv wake depends only on the state of the sockets.
             let recv_wake = self
                 .vlan_state
@@ -437,13 +841,29 @@ where
                     .all(|v| v.get_socket_mut(i).unwrap().can_send());
 
             if recv_wake || send_wake {
-                let (task_id, notification) = generated::SOCKET_OWNERS[i];
-                let task_id = sys_refresh_task_id(task_id);
-                sys_post(task_id, notification);
+                wake_socket_owner(i);
             }
         }
     }
 
+    /// Checks whether the owner of TCP socket `i` can do work: that is,
+    /// whether the socket has a new connection to accept, or an accepted
+    /// connection with data (or the end of the data) to receive, or room to
+    /// send if the owner is waiting to.
+    #[cfg(feature = "tcp")]
+    fn tcp_can_work(&mut self, i: usize) -> bool {
+        let waiting = self.client_waiting_to_send[i];
+        self.vlan_state.iter_mut().any(|v| {
+            let accepted = v.tcp_accepted[i];
+            let s = v.get_tcp_socket_mut(i).unwrap();
+            if accepted {
+                s.can_recv() || !s.may_recv() || (waiting && s.can_send())
+            } else {
+                tcp_connected(s)
+            }
+        })
+    }
+
     pub fn wake(&self) {
         self.bsp.wake(self.eth)
     }
@@ -487,6 +907,8 @@ where
                 match socket.recv() {
                     Ok((body, endp)) => {
                         if payload.len() < body.len() {
+                            let c = &mut self.socket_counters[socket_index];
+                            c.rx_oversize = c.rx_oversize.wrapping_add(1);
                             match large_payload_behavior {
                                 LargePayloadBehavior::Discard => continue,
                                 // If we add a `::Fail` case, we will need to
@@ -500,6 +922,10 @@ where
 
                         // Release borrow on self/socket
                         let body_len = body.len();
+                        count_socket_rx(
+                            &mut self.socket_counters[socket_index],
+                            body_len,
+                        );
 
                         return Ok(vlan.iface.device().make_meta(
                             endp.port,
@@ -519,6 +945,8 @@ where
                 }
             }
         }
+        let c = &mut self.socket_counters[socket_index];
+        c.rx_empty_polls = c.rx_empty_polls.wrapping_add(1);
         Err(RecvError::QueueEmpty.into())
     }
 
@@ -559,10 +987,16 @@ where
                     .read_range(0..payload.len(), buf)
                     .map_err(|_| RequestError::went_away())?;
                 self.client_waiting_to_send[socket_index] = false;
+                count_socket_tx(
+                    &mut self.socket_counters[socket_index],
+                    payload.len(),
+                );
                 Ok(())
             }
             Err(smoltcp::Error::Exhausted) => {
                 self.client_waiting_to_send[socket_index] = true;
+                let c = &mut self.socket_counters[socket_index];
+                c.tx_queue_full = c.tx_queue_full.wrapping_add(1);
                 Err(SendError::QueueFull.into())
             }
             Err(_e) => {
@@ -571,6 +1005,188 @@ where
             }
         }
     }
+
+    /// Hands the owner of TCP socket `socket` a connection that has been
+    /// established on it (on any VLAN), and that it hasn't yet accepted.
+    #[cfg(feature = "tcp")]
+    fn net_tcp_accept(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<TcpConnection, RequestError<TcpError>> {
+        let socket_index = socket as usize;
+        if generated::SOCKET_OWNERS[socket_index].0.index()
+            != msg.sender.index()
+        {
+            return Err(TcpError::NotYours.into());
+        }
+
+        for vlan in &mut self.vlan_state {
+            if vlan.tcp_accepted[socket_index] {
+                continue;
+            }
+            let socket = vlan
+                .get_tcp_socket_mut(socket_index)
+                .ok_or(RequestError::Fail(ClientError::BadMessageContents))?;
+            if !tcp_connected(socket) {
+                continue;
+            }
+            let endp = socket.remote_endpoint();
+            let addr = match endp.addr.try_into() {
+                Ok(addr) => addr,
+                Err(_) => {
+                    // We can't name the peer to our client, so the connection
+                    // can never be used; reset it, and the socket will go back
+                    // to listening.
+                    socket.abort();
+                    return Err(TcpError::Other.into());
+                }
+            };
+            vlan.tcp_accepted[socket_index] = true;
+
+            return Ok(vlan.iface.device().make_connection(endp.port, addr));
+        }
+        Err(TcpError::NoConnection.into())
+    }
+
+    /// Looks up the TCP socket `socket` that is carrying `connection`, checking
+    /// that the caller owns it and has accepted the connection.
+    #[cfg(feature = "tcp")]
+    fn tcp_socket_mut(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+    ) -> Result<&mut TcpSocket<'static>, RequestError<TcpError>> {
+        let socket_index = socket as usize;
+        if generated::SOCKET_OWNERS[socket_index].0.index()
+            != msg.sender.index()
+        {
+            return Err(TcpError::NotYours.into());
+        }
+
+        #[cfg(feature = "vlan")]
+        let vlan_index = {
+            // Convert from absolute VID to an index in our VLAN array
+            if !VLAN_RANGE.contains(&connection.vid) {
+                return Err(TcpError::InvalidVLan.into());
+            }
+            usize::from(connection.vid - VLAN_RANGE.start)
+        };
+        #[cfg(not(feature = "vlan"))]
+        let vlan_index = 0;
+
+        let vlan = &mut self.vlan_state[vlan_index];
+        let accepted = vlan.tcp_accepted[socket_index];
+        let socket = vlan
+            .get_tcp_socket_mut(socket_index)
+            .ok_or(RequestError::Fail(ClientError::BadMessageContents))?;
+
+        // If the connection has been reset and a new one made in its place,
+        // the remote end won't match.
+        let endp = socket.remote_endpoint();
+        if !accepted
+            || endp.port != connection.port
+            || task_net_api::Address::try_from(endp.addr).ok()
+                != Some(connection.addr)
+        {
+            return Err(TcpError::NotConnected.into());
+        }
+        Ok(socket)
+    }
+
+    /// Copies data received on `connection` into loaned memory at `payload`,
+    /// returning the number of bytes copied.
+    #[cfg(feature = "tcp")]
+    fn net_tcp_recv(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+        payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let socket_index = socket as usize;
+        let socket = self.tcp_socket_mut(msg, socket, connection)?;
+        let r = socket.recv(|buf| {
+            let n = buf.len().min(payload.len());
+            match payload.write_range(0..n, &buf[..n]) {
+                Ok(()) => (n, Ok(n)),
+                Err(()) => (0, Err(())),
+            }
+        });
+        let c = &mut self.socket_counters[socket_index];
+        match r {
+            Ok(Ok(0)) => {
+                c.rx_empty_polls = c.rx_empty_polls.wrapping_add(1);
+                Err(TcpError::QueueEmpty.into())
+            }
+            Ok(Ok(n)) => {
+                count_socket_rx(c, n);
+                Ok(n as u32)
+            }
+            Ok(Err(())) => Err(RequestError::went_away()),
+            Err(smoltcp::Error::Finished) => Err(TcpError::Closed.into()),
+            Err(smoltcp::Error::Illegal) => Err(TcpError::NotConnected.into()),
+            Err(_) => Err(TcpError::Other.into()),
+        }
+    }
+
+    /// Copies as much of the loaned memory at `payload` as will fit into the
+    /// tx queue of `connection`, returning the number of bytes copied.
+    #[cfg(feature = "tcp")]
+    fn net_tcp_send(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+        payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let socket_index = socket as usize;
+        let socket = self.tcp_socket_mut(msg, socket, connection)?;
+        let r = socket.send(|buf| {
+            let n = buf.len().min(payload.len());
+            match payload.read_range(0..n, &mut buf[..n]) {
+                Ok(()) => (n, Ok(n)),
+                Err(()) => (0, Err(())),
+            }
+        });
+        let c = &mut self.socket_counters[socket_index];
+        match r {
+            Ok(Ok(0)) => {
+                self.client_waiting_to_send[socket_index] = true;
+                c.tx_queue_full = c.tx_queue_full.wrapping_add(1);
+                Err(TcpError::QueueFull.into())
+            }
+            Ok(Ok(n)) => {
+                self.client_waiting_to_send[socket_index] = false;
+                count_socket_tx(c, n);
+                Ok(n as u32)
+            }
+            Ok(Err(())) => Err(RequestError::went_away()),
+            Err(smoltcp::Error::Illegal) => Err(TcpError::NotConnected.into()),
+            Err(_) => Err(TcpError::Other.into()),
+        }
+    }
+
+    /// Closes `connection`. Data already queued is still sent, after which the
+    /// socket goes back to listening for a new connection.
+    #[cfg(feature = "tcp")]
+    fn net_tcp_close(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+        connection: TcpConnection,
+    ) -> Result<(), RequestError<TcpError>> {
+        self.tcp_socket_mut(msg, socket, connection)?.close();
+        Ok(())
+    }
+}
+
+/// Posts the notification of the task that owns socket `index`.
+fn wake_socket_owner(index: usize) {
+    let (task_id, notification) = generated::SOCKET_OWNERS[index];
+    let task_id = sys_refresh_task_id(task_id);
+    sys_post(task_id, notification);
 }
 
 impl<B, E, const N: usize> idol_runtime::NotificationHandler
@@ -596,8 +1212,11 @@ type NeighborStorage = Option<(IpAddress, Neighbor)>;
 
 pub struct Storage {
     neighbors: [NeighborStorage; NEIGHBORS],
-    sockets: [SocketStorage<'static>; SOCKET_COUNT],
-    net: IpCidr,
+    sockets: [SocketStorage<'static>; SOCKET_COUNT + address::SOCKETS],
+    ip_addrs: [IpCidr; address::ADDR_COUNT],
+    /// Default routes, one for each of IPv4 and IPv6
+    routes: [Option<(IpCidr, Route)>; 2],
+    address: address::Storage,
 }
 
 impl Default for Storage {
@@ -605,7 +1224,9 @@ impl Default for Storage {
         Self {
             neighbors: Default::default(),
             sockets: Default::default(),
-            net: Ipv6Cidr::default().into(),
+            ip_addrs: [Ipv6Cidr::default().into(); address::ADDR_COUNT],
+            routes: Default::default(),
+            address: Default::default(),
         }
     }
 }
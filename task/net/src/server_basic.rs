@@ -11,12 +11,12 @@ use drv_stm32h7_eth as eth;
 use crate::bsp_support;
 use crate::generated;
 use crate::{
-    server::{DeviceExt, GenServerImpl, Storage},
+    server::{DeviceCounters, DeviceExt, GenServerImpl, Storage},
     MacAddressBlock,
 };
 use core::cell::Cell;
 use mutable_statics::mutable_statics;
-use task_net_api::UdpMetadata;
+use task_net_api::{TcpConnection, UdpMetadata};
 
 /// Grabs references to the server storage arrays.  Can only be called once!
 fn claim_server_storage_statics() -> &'static mut [Storage; 1] {
@@ -56,6 +56,7 @@ where
 pub struct Smol<'d> {
     eth: &'d eth::Ethernet,
     mac_rx: Cell<bool>,
+    counters: DeviceCounters,
 }
 
 impl<'d> From<&'d eth::Ethernet> for Smol<'d> {
@@ -63,6 +64,7 @@ impl<'d> From<&'d eth::Ethernet> for Smol<'d> {
         Self {
             eth,
             mac_rx: Cell::new(false),
+            counters: DeviceCounters::default(),
         }
     }
 }
@@ -77,7 +79,12 @@ impl<'d> smoltcp::phy::RxToken for OurRxToken<'d> {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        self.0.eth.recv(f)
+        self.0.eth.recv(|buf| {
+            self.0.counters.count_rx(buf.len());
+            let result = f(buf);
+            self.0.counters.count_rx_result(buf, &result);
+            result
+        })
     }
 }
 
@@ -92,10 +99,13 @@ impl<'d> smoltcp::phy::TxToken for OurTxToken<'d> {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        self.0
+        let result = self
+            .0
             .eth
             .try_send(len, f)
-            .expect("TX token existed without descriptor available")
+            .expect("TX token existed without descriptor available");
+        self.0.counters.count_tx(len);
+        result
     }
 }
 
@@ -154,4 +164,16 @@ impl DeviceExt for Smol<'_> {
             addr,
         }
     }
+
+    fn make_connection(
+        &self,
+        port: u16,
+        addr: task_net_api::Address,
+    ) -> TcpConnection {
+        TcpConnection { port, addr }
+    }
+
+    fn counters(&self) -> &DeviceCounters {
+        &self.counters
+    }
 }
@@ -11,12 +11,12 @@ use drv_stm32h7_eth as eth;
 
 use core::cell::Cell;
 use mutable_statics::mutable_statics;
-use task_net_api::UdpMetadata;
+use task_net_api::{TcpConnection, UdpMetadata};
 
 use crate::bsp_support;
 use crate::generated::{self, VLAN_COUNT, VLAN_RANGE};
 use crate::{
-    server::{DeviceExt, GenServerImpl, Storage},
+    server::{DeviceCounters, DeviceExt, GenServerImpl, Storage},
     MacAddressBlock,
 };
 
@@ -33,6 +33,7 @@ pub struct VLanEthernet<'a> {
     pub eth: &'a eth::Ethernet,
     pub vid: u16,
     mac_rx: Cell<bool>,
+    counters: DeviceCounters,
 }
 
 impl<'a, 'b> smoltcp::phy::Device<'a> for VLanEthernet<'b> {
@@ -43,8 +44,8 @@ impl<'a, 'b> smoltcp::phy::Device<'a> for VLanEthernet<'b> {
         if self.eth.vlan_can_recv(self.vid, VLAN_RANGE) && self.eth.can_send() {
             self.mac_rx.set(true);
             Some((
-                VLanRxToken(self.eth, self.vid),
-                VLanTxToken(self.eth, self.vid),
+                VLanRxToken(self.eth, self.vid, &self.counters),
+                VLanTxToken(self.eth, self.vid, &self.counters),
             ))
         } else {
             None
@@ -52,7 +53,7 @@ impl<'a, 'b> smoltcp::phy::Device<'a> for VLanEthernet<'b> {
     }
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
         if self.eth.can_send() {
-            Some(VLanTxToken(self.eth, self.vid))
+            Some(VLanTxToken(self.eth, self.vid, &self.counters))
         } else {
             None
         }
@@ -80,11 +81,27 @@ impl DeviceExt for VLanEthernet<'_> {
             vid: self.vid,
         }
     }
+
+    fn make_connection(
+        &self,
+        port: u16,
+        addr: task_net_api::Address,
+    ) -> TcpConnection {
+        TcpConnection {
+            port,
+            addr,
+            vid: self.vid,
+        }
+    }
+
+    fn counters(&self) -> &DeviceCounters {
+        &self.counters
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct VLanRxToken<'a>(&'a eth::Ethernet, u16);
+pub struct VLanRxToken<'a>(&'a eth::Ethernet, u16, &'a DeviceCounters);
 impl<'a> smoltcp::phy::RxToken for VLanRxToken<'a> {
     fn consume<R, F>(
         self,
@@ -94,11 +111,16 @@ impl<'a> smoltcp::phy::RxToken for VLanRxToken<'a> {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        self.0.vlan_recv(self.1, f)
+        self.0.vlan_recv(self.1, |buf| {
+            self.2.count_rx(buf.len());
+            let result = f(buf);
+            self.2.count_rx_result(buf, &result);
+            result
+        })
     }
 }
 
-pub struct VLanTxToken<'a>(&'a eth::Ethernet, u16);
+pub struct VLanTxToken<'a>(&'a eth::Ethernet, u16, &'a DeviceCounters);
 impl<'a> smoltcp::phy::TxToken for VLanTxToken<'a> {
     fn consume<R, F>(
         self,
@@ -109,9 +131,12 @@ impl<'a> smoltcp::phy::TxToken for VLanTxToken<'a> {
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
     {
-        self.0
+        let result = self
+            .0
             .vlan_try_send(len, self.1, f)
-            .expect("TX token existed without descriptor available")
+            .expect("TX token existed without descriptor available");
+        self.2.count_tx(len);
+        result
     }
 }
 
@@ -137,6 +162,7 @@ where
             eth,
             vid: generated::VLAN_RANGE.start + i as u16,
             mac_rx: Cell::new(false),
+            counters: DeviceCounters::default(),
         },
     )
 }
@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Debug shell, reachable over TCP
+//!
+//! This task accepts connections on its TCP socket, one at a time, and runs a
+//! very small line-oriented shell on each, so that a system can be poked at
+//! from the lab without Humility.  Try `telnet` or `nc` to its port.
+//!
+//! The shell is unauthenticated, so it belongs only in lab images (see
+//! `app/gimletlet/app-netshell.toml`), never in production ones.
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use task_net_api::*;
+use userlib::*;
+
+task_slot!(NET, net);
+
+const SOCKET: SocketName = SocketName::shell;
+
+/// Longest line we accept; longer lines are discarded.
+const LINE_LEN: usize = 128;
+
+const BANNER: &[u8] = b"hubris debug shell; type 'help' for commands\r\n";
+const PROMPT: &[u8] = b"> ";
+const HELP: &[u8] = b"\
+help          show this message\r\n\
+echo <text>   repeat <text> back\r\n\
+uptime        show time since boot\r\n\
+mac           show our MAC address\r\n\
+quit          close the connection\r\n";
+
+#[export_name = "main"]
+fn main() -> ! {
+    let net = NET.get_task_id();
+    let net = Net::from(net);
+
+    loop {
+        match net.tcp_accept(SOCKET) {
+            Ok(conn) => {
+                let session = Session { net: &net, conn };
+                // The session only fails if the connection goes away (which
+                // includes `net` restarting), in which case there's nothing
+                // else to do with it.
+                let _ = session.run();
+                let _ = net.tcp_close(SOCKET, conn);
+            }
+            Err(TcpError::NoConnection) => wait(),
+            Err(TcpError::ServerRestarted) => {
+                // `net` restarted (probably due to the watchdog); just retry.
+            }
+            Err(_) => panic!(),
+        }
+    }
+}
+
+/// Waits for `net` to tell us that there's something to do on our socket.
+fn wait() {
+    sys_recv_closed(&mut [], notifications::SOCKET_MASK, TaskId::KERNEL)
+        .unwrap();
+}
+
+struct Session<'a> {
+    net: &'a Net,
+    conn: TcpConnection,
+}
+
+impl Session<'_> {
+    /// Runs commands until the remote end asks us to quit or closes the
+    /// connection, or the connection fails.
+    fn run(&self) -> Result<(), TcpError> {
+        self.send(BANNER)?;
+        self.send(PROMPT)?;
+
+        let mut line = [0u8; LINE_LEN];
+        let mut len = 0;
+        let mut too_long = false;
+        loop {
+            let mut rx = [0u8; 64];
+            let n = match self.net.tcp_recv(SOCKET, self.conn, &mut rx) {
+                Ok(n) => n as usize,
+                Err(TcpError::QueueEmpty) => {
+                    wait();
+                    continue;
+                }
+                Err(TcpError::Closed) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            for &b in &rx[..n] {
+                match b {
+                    b'\n' => {
+                        if too_long {
+                            self.send(b"line too long\r\n")?;
+                        } else if !self.command(&line[..len])? {
+                            return Ok(());
+                        }
+                        self.send(PROMPT)?;
+                        len = 0;
+                        too_long = false;
+                    }
+                    // Tolerate CRLF line endings.
+                    b'\r' => (),
+                    _ if len < LINE_LEN => {
+                        line[len] = b;
+                        len += 1;
+                    }
+                    _ => too_long = true,
+                }
+            }
+        }
+    }
+
+    /// Runs the command in `line`, returning false if the session should end.
+    fn command(&self, line: &[u8]) -> Result<bool, TcpError> {
+        let line = core::str::from_utf8(line).unwrap_or("?").trim();
+        let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+
+        let mut out = Output::new();
+        match cmd {
+            "" => (),
+            "help" => self.send(HELP)?,
+            "echo" => {
+                self.send(args.trim().as_bytes())?;
+                self.send(b"\r\n")?;
+            }
+            "uptime" => {
+                let now = sys_get_timer().now;
+                let _ = write!(out, "{}.{:03}s\r\n", now / 1000, now % 1000);
+            }
+            "mac" => {
+                let mac = self.net.get_mac_address();
+                for (i, b) in mac.0.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ":" };
+                    let _ = write!(out, "{}{:02x}", sep, b);
+                }
+                let _ = out.write_str("\r\n");
+            }
+            "quit" | "exit" => return Ok(false),
+            _ => {
+                let _ = write!(out, "unknown command '{}'\r\n", cmd);
+            }
+        }
+        self.send(out.as_bytes())?;
+
+        Ok(true)
+    }
+
+    /// Sends all of `data`, waiting for room in the tx queue as needed.
+    fn send(&self, mut data: &[u8]) -> Result<(), TcpError> {
+        while !data.is_empty() {
+            match self.net.tcp_send(SOCKET, self.conn, data) {
+                Ok(n) => data = &data[n as usize..],
+                Err(TcpError::QueueFull) => wait(),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Buffer for formatting a line of output; anything that doesn't fit is
+/// dropped.
+struct Output {
+    buf: [u8; 64],
+    len: usize,
+}
+
+impl Output {
+    fn new() -> Self {
+        Self {
+            buf: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
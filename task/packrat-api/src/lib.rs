@@ -25,6 +25,35 @@ pub struct MacAddressBlock {
     pub stride: u8,
 }
 
+/// Maximum number of thermal zones whose tuning can be cached
+pub const MAX_THERMAL_ZONES: usize = 4;
+
+/// Tuning for a single thermal zone
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, AsBytes, Default)]
+#[repr(C)]
+pub struct ThermalZoneTuning {
+    /// PID parameters, in the order zero, P, I, D
+    pub pid: [f32; 4],
+    /// Target margin, in °C
+    pub margin: f32,
+}
+
+/// Runtime tuning of the `thermal` task, cached so that it survives a restart
+/// of that task
+///
+/// Packrat doesn't interpret this; it's up to the `thermal` task to validate
+/// it when it's loaded.
+#[derive(Copy, Clone, Debug, PartialEq, FromBytes, AsBytes, Default)]
+#[repr(C)]
+pub struct ThermalTuning {
+    pub zones: [ThermalZoneTuning; MAX_THERMAL_ZONES],
+    /// Number of valid entries in `zones`
+    pub zone_count: u8,
+    /// Fan controller watchdog timeout in seconds, or 0 if disabled
+    pub watchdog_s: u8,
+    pub _pad: [u8; 2],
+}
+
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 pub enum CacheGetError {
     ValueNotSet = 1,
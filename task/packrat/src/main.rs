@@ -34,7 +34,7 @@ use idol_runtime::RequestError;
 use ringbuf::{ringbuf, ringbuf_entry};
 use task_packrat_api::{
     CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
-    VpdIdentity,
+    ThermalTuning, VpdIdentity,
 };
 use userlib::RecvMessage;
 
@@ -44,6 +44,8 @@ enum Trace {
     MacAddressBlockSet(TraceSet<MacAddressBlock>),
     VpdIdentitySet(TraceSet<VpdIdentity>),
     SetNextBootHostStartupOptions(HostStartupOptions),
+    ThermalTuningSet,
+    ThermalTuningCleared,
 }
 
 impl From<TraceSet<MacAddressBlock>> for Trace {
@@ -75,6 +77,7 @@ struct ServerImpl {
     mac_address_block: Option<MacAddressBlock>,
     identity: Option<VpdIdentity>,
     host_startup_options: HostStartupOptions,
+    thermal_tuning: Option<ThermalTuning>,
 }
 
 impl Default for ServerImpl {
@@ -88,6 +91,7 @@ impl Default for ServerImpl {
             mac_address_block: None,
             identity: None,
             host_startup_options,
+            thermal_tuning: None,
         }
     }
 }
@@ -180,6 +184,33 @@ impl idl::InOrderPackratImpl for ServerImpl {
         self.host_startup_options = host_startup_options;
         Ok(())
     }
+
+    fn get_thermal_tuning(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ThermalTuning, RequestError<CacheGetError>> {
+        let tuning = self.thermal_tuning.ok_or(CacheGetError::ValueNotSet)?;
+        Ok(tuning)
+    }
+
+    fn set_thermal_tuning(
+        &mut self,
+        _: &RecvMessage,
+        tuning: ThermalTuning,
+    ) -> Result<(), RequestError<Infallible>> {
+        ringbuf_entry!(Trace::ThermalTuningSet);
+        self.thermal_tuning = Some(tuning);
+        Ok(())
+    }
+
+    fn clear_thermal_tuning(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<Infallible>> {
+        ringbuf_entry!(Trace::ThermalTuningCleared);
+        self.thermal_tuning = None;
+        Ok(())
+    }
 }
 
 #[export_name = "main"]
@@ -195,7 +226,7 @@ fn main() -> ! {
 mod idl {
     use super::{
         CacheGetError, CacheSetError, HostStartupOptions, MacAddressBlock,
-        VpdIdentity,
+        ThermalTuning, VpdIdentity,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
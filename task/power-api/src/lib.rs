@@ -129,6 +129,91 @@ impl From<pmbus::units::Percent> for PmbusValue {
     }
 }
 
+/// Snapshot of a PMBus device's status registers
+///
+/// `word` is `STATUS_WORD`; each of the other registers is only read if its
+/// summary bit is set in `word`, and is zero otherwise.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    SerializedSize,
+)]
+pub struct PmbusStatus {
+    pub word: u16,
+    pub vout: u8,
+    pub iout: u8,
+    pub input: u8,
+    pub mfr: u8,
+    pub fans: u8,
+    pub temperature: u8,
+    pub cml: u8,
+}
+
+impl PmbusStatus {
+    /// Bits of `STATUS_WORD` that indicate a fault or warning, rather than
+    /// merely reflecting whether the output is on (`OFF`, `POWER_GOOD#`) or
+    /// the device is busy (`BUSY`)
+    pub const WORD_FAULTS: u16 = !((1 << 11) | (1 << 7) | (1 << 6));
+
+    /// Returns true if any fault or warning is set in `self` that wasn't set
+    /// in `prev`
+    pub fn has_new_fault(&self, prev: &PmbusStatus) -> bool {
+        let rose = |now: u8, then: u8| now & !then != 0;
+
+        (self.word & !prev.word & Self::WORD_FAULTS) != 0
+            || rose(self.vout, prev.vout)
+            || rose(self.iout, prev.iout)
+            || rose(self.input, prev.input)
+            || rose(self.mfr, prev.mfr)
+            || rose(self.fans, prev.fans)
+            || rose(self.temperature, prev.temperature)
+            || rose(self.cml, prev.cml)
+    }
+}
+
+/// A fault seen on a power rail, as recorded in the `power` task's fault log
+#[derive(
+    Debug, Clone, Copy, Default, Deserialize, Serialize, SerializedSize,
+)]
+pub struct PowerFault {
+    /// Voltage sensor of the rail, which identifies it
+    pub sensor: u32,
+
+    /// Time at which the fault was seen, in milliseconds since boot
+    pub timestamp: u64,
+
+    /// Status when the fault was seen
+    pub status: PmbusStatus,
+
+    /// Status when last polled before the fault
+    pub previous: PmbusStatus,
+
+    /// Readings taken just after the fault was seen, where available
+    pub vout: Option<f32>,
+    pub iout: Option<f32>,
+    pub temperature: Option<f32>,
+}
+
+/// State of the power budget, as returned by `power_budget_status`
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, SerializedSize)]
+pub struct PowerBudgetStatus {
+    /// Budget for total input power in watts, if one is set
+    pub budget: Option<f32>,
+
+    /// Total input power in watts at the last poll, if every budgeted rail
+    /// could be read
+    pub power: Option<f32>,
+
+    /// Whether we're over budget, and have asked for load to be shed
+    pub over_budget: bool,
+}
+
 /// Simple wrapper type for the BMR491 event log
 ///
 /// To simplify the implementation, this is the result of a raw PMBus read;
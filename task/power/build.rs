@@ -2,6 +2,10 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
@@ -14,5 +18,36 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     build_i2c::codegen(build_i2c::Disposition::Sensors)?;
 
+    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    let dest_path = build_util::out_dir().join("power_config.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = cfg.on_budget_change.len();
+
+    writeln!(
+        out,
+        "pub(crate) const MAILING_LIST: [({task}, u32); {count}] = [",
+    )?;
+    for (name, rec) in cfg.on_budget_change {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
+        )?;
+    }
+    writeln!(out, "];")?;
+
     Ok(())
 }
+
+/// Power task-level configuration.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Tasks to notify when we go over or back under the power budget, as a
+    /// map from task name to notification name (in the target task)
+    #[serde(default)]
+    on_budget_change: BTreeMap<String, String>,
+}
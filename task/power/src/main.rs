@@ -18,15 +18,18 @@ use drv_i2c_devices::max5970::*;
 use drv_i2c_devices::mwocp68::*;
 use drv_i2c_devices::raa229618::*;
 use drv_i2c_devices::tps546b24a::*;
-use task_power_api::{Bmr491Event, PmbusValue};
+use ringbuf::*;
+use task_power_api::{
+    Bmr491Event, PmbusStatus, PmbusValue, PowerBudgetStatus, PowerFault,
+};
 use task_sensor_api as sensor_api;
 use userlib::units::*;
 use userlib::*;
 
 use drv_i2c_api::{I2cDevice, ResponseCode};
 use drv_i2c_devices::{
-    CurrentSensor, InputCurrentSensor, InputVoltageSensor, TempSensor,
-    VoltageSensor,
+    CurrentSensor, InputCurrentSensor, InputVoltageSensor, PowerSensor,
+    TempSensor, VoltageSensor,
 };
 
 use sensor_api::{NoData, SensorId};
@@ -39,6 +42,34 @@ enum PowerState {
 
 const TIMER_INTERVAL: u64 = 1000;
 
+/// Number of faults kept in the fault log; once it's full, the oldest fault
+/// is overwritten.
+const FAULT_LOG_SIZE: usize = 8;
+
+/// Number of consecutive polls for which total power must exceed the budget
+/// before we declare ourselves over budget
+const OVER_BUDGET_POLLS: u8 = 3;
+
+/// Number of consecutive polls for which total power must be below the
+/// budget (less `BUDGET_HYSTERESIS`) before we declare ourselves back under
+/// budget
+const UNDER_BUDGET_POLLS: u8 = 10;
+
+/// Fraction of the budget by which total power must fall below it before we
+/// can be back under budget, lest we flap when running right at the budget
+const BUDGET_HYSTERESIS: f32 = 0.05;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Fault { sensor: SensorId, word: u16 },
+    BudgetSet(Option<Watts>),
+    OverBudget(Watts),
+    UnderBudget(Watts),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
 task_slot!(I2C, i2c_driver);
 task_slot!(SENSOR, sensor);
 
@@ -153,6 +184,40 @@ impl Device {
         Ok(r)
     }
 
+    fn read_status(&self) -> Result<PmbusStatus, ResponseCode> {
+        let r = match &self {
+            Device::Bmr491(dev) => dev.read_status()?,
+            Device::Raa229618(dev) => dev.read_status()?,
+            Device::Isl68224(dev) => dev.read_status()?,
+            Device::Tps546B24A(dev) => dev.read_status()?,
+            Device::Adm1272(dev) => dev.read_status()?,
+            Device::Mwocp68(dev) => dev.read_status()?,
+            Device::Max5970(..) | Device::Ltc4282(..) => {
+                // These aren't PMBus devices, and have no STATUS_WORD.
+                return Err(ResponseCode::OperationNotSupported);
+            }
+        };
+        Ok(r)
+    }
+
+    fn read_power(&mut self) -> Result<Watts, ResponseCode> {
+        let r = match self {
+            Device::Adm1272(dev) => dev.read_power()?,
+            Device::Mwocp68(dev) => dev.read_power()?,
+            // Only the controllers that count towards the power budget (see
+            // `PowerControllerConfig::is_budgeted`) need to read power.
+            Device::Bmr491(..)
+            | Device::Raa229618(..)
+            | Device::Isl68224(..)
+            | Device::Tps546B24A(..)
+            | Device::Max5970(..)
+            | Device::Ltc4282(..) => {
+                return Err(ResponseCode::OperationNotSupported)
+            }
+        };
+        Ok(r)
+    }
+
     fn pmbus_read(
         &self,
         op: task_power_api::Operation,
@@ -188,6 +253,35 @@ impl Device {
 }
 
 impl PowerControllerConfig {
+    /// Returns true if this controller's power counts towards the budget.
+    ///
+    /// Only controllers through which power enters the system count, as the
+    /// power through any other rail has already been counted upstream.  Any
+    /// controller that counts must support `Device::read_power`.
+    fn is_budgeted(&self, task: TaskId) -> bool {
+        match self.device {
+            // An ADM1272 on the system's input
+            DeviceType::HotSwap(..) => true,
+
+            // An MWOCP68 reports the same input power on each of its rails,
+            // so we count only the first of them.
+            DeviceType::PowerShelf => {
+                let (_device, rail) = (self.builder)(task);
+                rail == 0
+            }
+
+            DeviceType::IBC
+            | DeviceType::Core
+            | DeviceType::SerDes
+            | DeviceType::Mem
+            | DeviceType::MemVpp
+            | DeviceType::Sys
+            | DeviceType::Fan(..)
+            | DeviceType::HotSwapIO(..)
+            | DeviceType::HotSwapQSFP(..) => false,
+        }
+    }
+
     fn get_device(&self, task: TaskId) -> Device {
         let (dev, rail) = (self.builder)(task);
         match &self.device {
@@ -518,10 +612,23 @@ fn main() -> ! {
 
     let i2c_task = I2C.get_task_id();
 
+    let (status, faults) = claim_fault_buffers();
+
     let mut server = ServerImpl {
         i2c_task,
         sensor: sensor_api::Sensor::from(SENSOR.get_task_id()),
         devices: claim_devices(i2c_task),
+        status,
+        faults: FaultLog {
+            entries: faults,
+            count: 0,
+        },
+        budget: PowerBudget {
+            budget: None,
+            power: None,
+            over: false,
+            polls: 0,
+        },
     };
     let mut buffer = [0; idl::INCOMING_SIZE];
 
@@ -538,15 +645,178 @@ struct ServerImpl {
     i2c_task: TaskId,
     sensor: sensor_api::Sensor,
     devices: &'static mut [Device; CONTROLLER_CONFIG.len()],
+
+    /// Status of each device when it was last polled
+    status: &'static mut [PmbusStatus; CONTROLLER_CONFIG.len()],
+
+    faults: FaultLog,
+    budget: PowerBudget,
+}
+
+/// Total input power, and the budget against which we hold it
+struct PowerBudget {
+    budget: Option<Watts>,
+
+    /// Total power at the last poll, if every budgeted rail could be read
+    power: Option<Watts>,
+
+    /// Whether we're over budget
+    over: bool,
+
+    /// Number of consecutive polls that have argued for changing `over`
+    polls: u8,
+}
+
+impl PowerBudget {
+    /// Sets (or clears) the budget, returning true if doing so changed
+    /// whether we're over budget.
+    fn set(&mut self, budget: Option<Watts>) -> bool {
+        ringbuf_entry!(Trace::BudgetSet(budget));
+        self.budget = budget;
+        self.polls = 0;
+
+        // Whether we're over a new budget is for subsequent polls to decide;
+        // if there's no budget at all, we're certainly not over it.
+        let changed = self.over && budget.is_none();
+        if changed {
+            self.over = false;
+        }
+        changed
+    }
+
+    /// Records total power from the latest poll, returning true if we have
+    /// gone over budget or come back under it.
+    fn update(&mut self, power: Option<Watts>) -> bool {
+        self.power = power;
+
+        let (budget, power) = match (self.budget, power) {
+            (Some(budget), Some(power)) => (budget, power),
+            _ => {
+                self.polls = 0;
+                return false;
+            }
+        };
+
+        let (crossed, needed) = if self.over {
+            (
+                power.0 < budget.0 * (1.0 - BUDGET_HYSTERESIS),
+                UNDER_BUDGET_POLLS,
+            )
+        } else {
+            (power.0 > budget.0, OVER_BUDGET_POLLS)
+        };
+
+        if !crossed {
+            self.polls = 0;
+            return false;
+        }
+
+        self.polls += 1;
+        if self.polls < needed {
+            return false;
+        }
+
+        self.over = !self.over;
+        self.polls = 0;
+        ringbuf_entry!(if self.over {
+            Trace::OverBudget(power)
+        } else {
+            Trace::UnderBudget(power)
+        });
+        true
+    }
+}
+
+/// Notifies the tasks that care (as configured in our app.toml) that we've
+/// gone over budget or come back under it; they're expected to ask us which.
+fn notify_budget_change() {
+    for (task, mask) in generated::MAILING_LIST {
+        let taskid = TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+        let taskid = sys_refresh_task_id(taskid);
+        sys_post(taskid, mask);
+    }
+}
+
+/// A ring of the most recent faults seen
+///
+/// Faults are numbered from boot, so that a reader can tell if any were
+/// overwritten since it last looked.
+struct FaultLog {
+    entries: &'static mut [PowerFault; FAULT_LOG_SIZE],
+
+    /// Number of faults logged since boot
+    count: u32,
+}
+
+impl FaultLog {
+    fn push(&mut self, fault: PowerFault) {
+        self.entries[self.count as usize % FAULT_LOG_SIZE] = fault;
+        self.count += 1;
+    }
+
+    fn get(&self, index: u32) -> Option<&PowerFault> {
+        if index < self.count && self.count - index <= FAULT_LOG_SIZE as u32 {
+            Some(&self.entries[index as usize % FAULT_LOG_SIZE])
+        } else {
+            None
+        }
+    }
+}
+
+/// Polls the status of `dev`, logging a fault (along with whatever readings
+/// we can get) if any fault or warning has been raised since the last poll.
+/// Faults that remain set are only logged once.
+fn check_status(
+    c: &PowerControllerConfig,
+    dev: &Device,
+    prev: &mut PmbusStatus,
+    log: &mut FaultLog,
+) {
+    let status = match dev.read_status() {
+        Ok(status) => status,
+        Err(_) => return,
+    };
+
+    if status.has_new_fault(prev) {
+        ringbuf_entry!(Trace::Fault {
+            sensor: c.voltage,
+            word: status.word,
+        });
+
+        log.push(PowerFault {
+            sensor: c.voltage.into(),
+            timestamp: sys_get_timer().now,
+            status,
+            previous: *prev,
+            vout: dev.read_vout().ok().map(|v| v.0),
+            iout: dev.read_iout().ok().map(|i| i.0),
+            temperature: c
+                .temperature
+                .and_then(|_| dev.read_temperature().ok())
+                .map(|t| t.0),
+        });
+    }
+
+    *prev = status;
 }
 
 impl ServerImpl {
     fn handle_timer_fired(&mut self) {
         let state = get_state();
         let sensor = &self.sensor;
+        let i2c_task = self.i2c_task;
+        let mut power = Some(0.0);
 
-        for (c, dev) in CONTROLLER_CONFIG.iter().zip(self.devices.iter_mut()) {
+        for ((c, dev), prev) in CONTROLLER_CONFIG
+            .iter()
+            .zip(self.devices.iter_mut())
+            .zip(self.status.iter_mut())
+        {
             if c.state == PowerState::A0 && state != PowerState::A0 {
+                // The device is off, so forget its status; any fault that is
+                // still set when it comes back is worth logging anew.
+                *prev = PmbusStatus::default();
+
                 let now = sys_get_timer().now;
                 sensor.nodata(c.voltage, NoData::DeviceOff, now).unwrap();
                 sensor.nodata(c.current, NoData::DeviceOff, now).unwrap();
@@ -558,6 +828,15 @@ impl ServerImpl {
                 continue;
             }
 
+            check_status(c, dev, prev, &mut self.faults);
+
+            if c.is_budgeted(i2c_task) {
+                power = match (power, dev.read_power()) {
+                    (Some(total), Ok(reading)) => Some(total + reading.0),
+                    _ => None,
+                };
+            }
+
             if let Some(id) = c.temperature {
                 match dev.read_temperature() {
                     Ok(reading) => {
@@ -609,6 +888,10 @@ impl ServerImpl {
                 }
             }
         }
+
+        if self.budget.update(power.map(Watts)) {
+            notify_budget_change();
+        }
     }
 
     /// Find the BMR491 and return an `I2cDevice` handle
@@ -761,6 +1044,68 @@ impl idl::InOrderPowerImpl for ServerImpl {
         )?;
         Ok(out)
     }
+
+    fn fault_log_count(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<u32, idol_runtime::RequestError<core::convert::Infallible>>
+    {
+        Ok(self.faults.count)
+    }
+
+    fn fault_log_read(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<PowerFault, idol_runtime::RequestError<ResponseCode>> {
+        let fault = self.faults.get(index).ok_or(ResponseCode::BadArg)?;
+        Ok(*fault)
+    }
+
+    fn set_power_budget(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        watts: f32,
+    ) -> Result<(), idol_runtime::RequestError<ResponseCode>> {
+        if !watts.is_finite() || watts <= 0.0 {
+            return Err(ResponseCode::BadArg.into());
+        }
+
+        // A budget is meaningless if nothing counts towards it.
+        let i2c_task = self.i2c_task;
+        if !CONTROLLER_CONFIG.iter().any(|c| c.is_budgeted(i2c_task)) {
+            return Err(ResponseCode::NoDevice.into());
+        }
+
+        if self.budget.set(Some(Watts(watts))) {
+            notify_budget_change();
+        }
+        Ok(())
+    }
+
+    fn clear_power_budget(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), idol_runtime::RequestError<core::convert::Infallible>> {
+        if self.budget.set(None) {
+            notify_budget_change();
+        }
+        Ok(())
+    }
+
+    fn power_budget_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<
+        PowerBudgetStatus,
+        idol_runtime::RequestError<core::convert::Infallible>,
+    > {
+        Ok(PowerBudgetStatus {
+            budget: self.budget.budget.map(|w| w.0),
+            power: self.budget.power.map(|w| w.0),
+            over_budget: self.budget.over,
+        })
+    }
 }
 
 /// Claims a mutable buffer of Devices, built from CONTROLLER_CONFIG.
@@ -777,6 +1122,26 @@ fn claim_devices(
     dev
 }
 
+/// Claims the buffers in which we keep each device's last status and our log
+/// of faults.
+///
+/// This function can only be called once, and will panic otherwise!
+fn claim_fault_buffers() -> (
+    &'static mut [PmbusStatus; CONTROLLER_CONFIG.len()],
+    &'static mut [PowerFault; FAULT_LOG_SIZE],
+) {
+    mutable_statics::mutable_statics!(
+        static mut STATUS: [PmbusStatus; CONTROLLER_CONFIG.len()] =
+            [Default::default; _];
+        static mut FAULTS: [PowerFault; FAULT_LOG_SIZE] = [Default::default; _];
+    )
+}
+
+// Place to namespace all the bits generated by our config processor.
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/power_config.rs"));
+}
+
 mod idl {
     use task_power_api::*;
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
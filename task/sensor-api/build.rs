@@ -30,6 +30,47 @@ struct Sensor {
     sensors: BTreeMap<String, usize>,
 }
 
+//
+// These are the lengths of the corresponding fields in `SensorMetadata`, and
+// are emitted into our generated code.
+//
+const DEVICE_LEN: usize = 16;
+const NAME_LEN: usize = 32;
+const REFDES_LEN: usize = 16;
+
+fn write_sensor_info(
+    out: &mut String,
+    kind: build_i2c::Sensor,
+    device: &str,
+    name: Option<&str>,
+    refdes: Option<&str>,
+    i2c: &str,
+) -> Result<()> {
+    for (what, s, max) in [
+        ("device", Some(device), DEVICE_LEN),
+        ("name", name, NAME_LEN),
+        ("refdes", refdes, REFDES_LEN),
+    ] {
+        if let Some(s) = s {
+            if s.len() > max {
+                bail!("sensor {what} \"{s}\" is longer than {max} bytes");
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "        crate::SensorInfo {{
+            kind: crate::SensorKind::{kind:?},
+            device: {device:?},
+            name: {name:?},
+            refdes: {refdes:?},
+            i2c: {i2c},
+        }},"
+    )?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     idol::client::build_client_stub("../../idl/sensor.idol", "client_stub.rs")
         .map_err(|e| anyhow!("idol error: {e}"))?;
@@ -38,9 +79,31 @@ fn main() -> Result<()> {
 
     let config: GlobalConfig = build_util::config()?;
 
+    let mut info_text = String::new();
+    for (index, s) in build_i2c::sensor_metadata().into_iter().enumerate() {
+        assert_eq!(index, s.id);
+        let i2c = format!(
+            "Some(crate::I2cPath {{ \
+                controller: {}, port: {}, mux: {:?}, segment: {:?}, \
+                address: {:#x} }})",
+            s.controller, s.port, s.mux, s.segment, s.address
+        );
+        write_sensor_info(
+            &mut info_text,
+            s.kind,
+            &s.device,
+            s.name.as_deref(),
+            s.refdes.as_deref(),
+            &i2c,
+        )?;
+    }
+
     let (count, text) = if let Some(config_sensor) = &config.sensor {
-        let sensor_count: usize =
-            config_sensor.devices.iter().map(|d| d.sensors.len()).sum();
+        let sensor_count: usize = config_sensor
+            .devices
+            .iter()
+            .flat_map(|d| d.sensors.values())
+            .sum();
 
         let mut by_device: BTreeMap<String, Vec<usize>> = BTreeMap::new();
         let mut names = BTreeSet::new();
@@ -95,6 +158,21 @@ fn main() -> Result<()> {
                     }
                     writeln!(&mut sensors_text, "        ];").unwrap();
                 }
+
+                let kind: build_i2c::Sensor =
+                    sensor_type.parse().map_err(|e| {
+                        anyhow!("bad sensor kind for {}: {e}", d.name)
+                    })?;
+                for _ in 0..sensor_count {
+                    write_sensor_info(
+                        &mut info_text,
+                        kind,
+                        &d.device,
+                        Some(&d.name),
+                        None,
+                        "None",
+                    )?;
+                }
             }
         }
         (sensor_count, sensors_text)
@@ -105,6 +183,13 @@ fn main() -> Result<()> {
     let out_dir = build_util::out_dir();
     let dest_path = out_dir.join("sensor_config.rs");
     let mut file = std::fs::File::create(dest_path)?;
+    writeln!(
+        &mut file,
+        "pub const DEVICE_LEN: usize = {DEVICE_LEN};
+pub const NAME_LEN: usize = {NAME_LEN};
+pub const REFDES_LEN: usize = {REFDES_LEN};
+"
+    )?;
     writeln!(
         &mut file,
         r#"pub mod config {{
@@ -129,6 +214,10 @@ fn main() -> Result<()> {
 
     // Here's what we actually care about:
     pub const NUM_SENSORS: usize = NUM_I2C_SENSORS + NUM_OTHER_SENSORS;
+
+    #[allow(dead_code)]
+    pub const SENSOR_INFO: [crate::SensorInfo; NUM_SENSORS] = [
+{info_text}    ];
 }}"#
     )
     .unwrap();
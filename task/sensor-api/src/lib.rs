@@ -12,6 +12,8 @@ use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
 use userlib::*;
 
+pub use sensor_stats::{Alarm, Reading, Stats, Threshold};
+
 #[derive(
     zerocopy::AsBytes,
     Copy,
@@ -38,15 +40,129 @@ impl From<SensorId> for u32 {
     }
 }
 
+/// Statistics for a sensor since boot and since they were last cleared.
 #[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
-pub struct Reading {
-    pub timestamp: u64,
-    pub value: f32,
+pub struct SensorStats {
+    pub boot: Stats,
+    pub cleared: Stats,
+    /// Timestamp of the last `clear_stats` call, or 0 if never cleared.
+    pub cleared_time: u64,
+}
+
+/// The condition under which a subscriber is notified of a sensor's change.
+#[derive(
+    Copy, Clone, Debug, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub enum Condition {
+    /// A reading differs from the one that last triggered a notification by
+    /// at least this much; the first reading always notifies.
+    Delta(f32),
+    /// Consecutive readings fall on opposite sides of this level.
+    Crosses(f32),
+    /// The rate of change between consecutive readings, in units per
+    /// second, is at least this much in either direction.
+    Rate(f32),
+    /// The sensor's [`Alarm`] state changes.
+    Alarm,
+    /// The sensor goes from having a reading to [`NoData`].
+    NoData,
 }
 
-impl Reading {
-    pub fn new(value: f32, timestamp: u64) -> Self {
-        Self { timestamp, value }
+/// What a sensor measures.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub enum SensorKind {
+    Temperature,
+    Power,
+    Current,
+    Voltage,
+    InputCurrent,
+    InputVoltage,
+    Speed,
+}
+
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub enum Units {
+    Celsius,
+    Watts,
+    Amperes,
+    Volts,
+    Rpm,
+}
+
+impl SensorKind {
+    pub const fn units(self) -> Units {
+        match self {
+            SensorKind::Temperature => Units::Celsius,
+            SensorKind::Power => Units::Watts,
+            SensorKind::Current | SensorKind::InputCurrent => Units::Amperes,
+            SensorKind::Voltage | SensorKind::InputVoltage => Units::Volts,
+            SensorKind::Speed => Units::Rpm,
+        }
+    }
+}
+
+/// The location of a sensor's device on I2C.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, SerializedSize, Serialize, Deserialize,
+)]
+pub struct I2cPath {
+    pub controller: u8,
+    pub port: u8,
+    pub mux: Option<u8>,
+    pub segment: Option<u8>,
+    pub address: u8,
+}
+
+/// Static description of a sensor, as generated from the app.toml.
+#[derive(Copy, Clone, Debug)]
+pub struct SensorInfo {
+    pub kind: SensorKind,
+    pub device: &'static str,
+    pub name: Option<&'static str>,
+    pub refdes: Option<&'static str>,
+    pub i2c: Option<I2cPath>,
+}
+
+/// A [`SensorInfo`] in a form that can be sent over IPC, with its strings
+/// NUL-padded into fixed-size arrays.  The array lengths (`DEVICE_LEN`,
+/// `NAME_LEN` and `REFDES_LEN`) are generated by our build script, which
+/// also enforces them on the app.toml.
+#[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
+pub struct SensorMetadata {
+    pub kind: SensorKind,
+    pub units: Units,
+    /// Part name of the source device (e.g., `tmp117`)
+    pub device: [u8; DEVICE_LEN],
+    /// Name of the sensor or rail, or all NULs if it has none
+    pub name: [u8; NAME_LEN],
+    /// Reference designator of the source device, or all NULs if unknown
+    pub refdes: [u8; REFDES_LEN],
+    pub i2c: Option<I2cPath>,
+}
+
+impl From<&SensorInfo> for SensorMetadata {
+    fn from(info: &SensorInfo) -> Self {
+        fn padded<const N: usize>(s: Option<&str>) -> [u8; N] {
+            let mut buf = [0; N];
+            if let Some(s) = s {
+                let len = s.len().min(N);
+                buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+            }
+            buf
+        }
+
+        Self {
+            kind: info.kind,
+            units: info.kind.units(),
+            device: padded(Some(info.device)),
+            name: padded(info.name),
+            refdes: padded(info.refdes),
+            i2c: info.i2c,
+        }
     }
 }
 
@@ -115,6 +231,9 @@ pub enum SensorError {
     DeviceUnavailable = 5,
     DeviceTimeout = 6,
     DeviceOff = 7,
+    InvalidThreshold = 8,
+    NotSubscriber = 9,
+    TooManySubscriptions = 10,
 
     #[idol(server_death)]
     ServerDied,
@@ -2,13 +2,133 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+const DEFAULT_SUBSCRIPTIONS_PER_TASK: usize = 8;
+
+fn main() -> Result<()> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
     idol::server::build_server_support(
         "../../idl/sensor.idol",
         "server_stub.rs",
         idol::server::ServerStyle::InOrder,
+    )
+    .map_err(|e| anyhow!(e))?;
+
+    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    //
+    // We need at least two readings in the history to compute a rate of
+    // change.
+    //
+    if cfg.history_depth < 2 || cfg.history_depth > u8::MAX as usize {
+        bail!(
+            "history-depth must be between 2 and {}, not {}",
+            u8::MAX,
+            cfg.history_depth
+        );
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("task_config.rs");
+    let mut out =
+        std::fs::File::create(dest_path).context("creating task_config.rs")?;
+
+    writeln!(
+        out,
+        "pub(crate) const HISTORY_DEPTH: usize = {};",
+        cfg.history_depth
+    )?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = cfg.on_alarm.len();
+    writeln!(
+        out,
+        "pub(crate) const ALARM_SUBSCRIBERS: [({task}, u32); {count}] = [",
     )?;
+    for (name, rec) in cfg.on_alarm {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
+        )?;
+    }
+    writeln!(out, "];")?;
+
+    let count = cfg.subscribers.len();
+    let max = cfg
+        .max_subscriptions
+        .unwrap_or(count * DEFAULT_SUBSCRIPTIONS_PER_TASK);
+    writeln!(out, "pub(crate) const MAX_SUBSCRIPTIONS: usize = {max};")?;
+    writeln!(
+        out,
+        "pub(crate) const SUBSCRIBERS: [({task}, u32); {count}] = [",
+    )?;
+    for (name, notifications) in cfg.subscribers {
+        if notifications.is_empty() {
+            bail!("subscriber {name} must allow at least one notification");
+        }
+        let mask = notifications
+            .iter()
+            .map(|n| {
+                format!(
+                    "crate::notifications::{name}::{}_MASK",
+                    n.to_ascii_uppercase().replace('-', "_")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        writeln!(out, "    ({task}::{name}, {mask}),")?;
+    }
+    writeln!(out, "];")?;
+
     Ok(())
 }
+
+/// Sensor task-level configuration.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Number of readings to keep in each sensor's history
+    ///
+    /// This dominates the task's RAM.  Each sensor needs 77 bytes, plus 8
+    /// bytes for each reading of history: 109 bytes at the default depth of
+    /// 4.  A board with 133 sensors thus needs 14.2 KiB before the stack and
+    /// subscriptions, and the task's `max-sizes` in the app must allow for
+    /// that.
+    #[serde(default = "default_history_depth")]
+    history_depth: usize,
+    /// Tasks to be notified when any sensor's alarm state changes, as a map
+    /// from task name to notification name (in the target task).  The
+    /// notification doesn't say which sensor changed; `next_alarm` finds
+    /// those that are in alarm.
+    #[serde(default)]
+    on_alarm: BTreeMap<String, String>,
+    /// Tasks that may call `subscribe`, as a map from task name to the
+    /// notifications (in the target task) that it may subscribe with
+    #[serde(default)]
+    subscribers: BTreeMap<String, Vec<String>>,
+    /// Total number of subscriptions that can be held at once; defaults to
+    /// `DEFAULT_SUBSCRIPTIONS_PER_TASK` for each subscriber
+    #[serde(default)]
+    max_subscriptions: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            history_depth: default_history_depth(),
+            on_alarm: BTreeMap::new(),
+            subscribers: BTreeMap::new(),
+            max_subscriptions: None,
+        }
+    }
+}
+
+fn default_history_depth() -> usize {
+    4
+}
@@ -8,11 +8,29 @@
 #![no_main]
 
 use idol_runtime::{NotificationHandler, RequestError};
-use task_sensor_api::{NoData, Reading, SensorError, SensorId};
+use ringbuf::*;
+use task_sensor_api::{
+    Alarm, Condition, NoData, Reading, SensorError, SensorId, SensorInfo,
+    SensorMetadata, SensorStats, Stats, Threshold,
+};
 use userlib::*;
 
 use task_sensor_api::config::NUM_SENSORS;
 
+static SENSOR_INFO: [SensorInfo; NUM_SENSORS] =
+    task_sensor_api::config::SENSOR_INFO;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Alarm(SensorId, Alarm),
+    Threshold(SensorId, Threshold),
+    Subscribe(SensorId, u8, Condition),
+    Unsubscribe(SensorId, u8),
+}
+
+ringbuf!(Trace, 8, Trace::None);
+
 #[derive(Copy, Clone)]
 enum LastReading {
     Data,
@@ -26,20 +44,166 @@ struct ServerImpl {
     //
     // The compiler is smart enough to present `None` with an invalid
     // `LastReading` variant tag, so we don't need to store presence separately.
+    //
+    // When this is `LastReading::Data`, the reading itself is the latest entry
+    // in the history.
+    //
     last_reading: &'static mut [Option<LastReading>; NUM_SENSORS],
 
-    data_value: &'static mut [f32; NUM_SENSORS],
-    data_time: &'static mut [u64; NUM_SENSORS],
-
     err_value: &'static mut [NoData; NUM_SENSORS],
     err_time: &'static mut [u64; NUM_SENSORS],
 
     nerrors: &'static mut [u32; NUM_SENSORS],
+
+    // The history is a ring of the last `HISTORY_DEPTH` readings per sensor;
+    // `history_next` is the slot that the next reading will be written to.
+    // To save RAM, only the low 32 bits of each timestamp are kept, and the
+    // full timestamp of the most recent reading is kept in `history_last`.
+    history_value: &'static mut [[f32; HISTORY_DEPTH]; NUM_SENSORS],
+    history_time: &'static mut [[u32; HISTORY_DEPTH]; NUM_SENSORS],
+    history_last: &'static mut [u64; NUM_SENSORS],
+    history_len: &'static mut [u8; NUM_SENSORS],
+    history_next: &'static mut [u8; NUM_SENSORS],
+
+    boot_stats: &'static mut [Stats; NUM_SENSORS],
+    cleared_stats: &'static mut [Stats; NUM_SENSORS],
+    cleared_time: &'static mut [u64; NUM_SENSORS],
+
+    threshold: &'static mut [Threshold; NUM_SENSORS],
+    alarm: &'static mut [Alarm; NUM_SENSORS],
+
+    subscriptions: &'static mut [Option<Subscription>; MAX_SUBSCRIPTIONS],
+
     deadline: u64,
 }
 
+#[derive(Copy, Clone)]
+struct Subscription {
+    task: TaskId,
+    sensor: SensorId,
+    bit: u8,
+    condition: Condition,
+
+    // For `Condition::Delta`, the value that last triggered a notification;
+    // for `Condition::Crosses`, the last value posted.  NaN if there is none,
+    // and unused for other conditions.
+    last: f32,
+}
+
+impl Subscription {
+    fn notify(&self) {
+        let taskid = sys_refresh_task_id(self.task);
+        sys_post(taskid, 1 << self.bit);
+    }
+}
+
 const TIMER_INTERVAL: u64 = 1000;
 
+impl ServerImpl {
+    /// Returns the reading posted `age` posts ago, if the history goes back
+    /// that far.
+    fn history(&self, index: usize, age: usize) -> Option<Reading> {
+        if age >= self.history_len[index] as usize {
+            return None;
+        }
+
+        //
+        // `history_next` is one past the most recent reading, so walk
+        // backwards from there (wrapping around the ring).
+        //
+        let next = self.history_next[index] as usize;
+        let slot = (next + HISTORY_DEPTH - 1 - age) % HISTORY_DEPTH;
+
+        //
+        // Recover the full timestamp from how long before the most recent
+        // reading this one was taken.  This is exact unless the history
+        // spans more than 2^32 ticks (about 49 days).
+        //
+        let last = self.history_last[index];
+        let before = (last as u32).wrapping_sub(self.history_time[index][slot]);
+
+        Some(Reading::new(
+            self.history_value[index][slot],
+            last - before as u64,
+        ))
+    }
+
+    fn record(&mut self, index: usize, value: f32, timestamp: u64) {
+        let rate = self
+            .history(index, 0)
+            .and_then(|prev| Reading::new(value, timestamp).rate_since(&prev));
+
+        let next = self.history_next[index] as usize;
+        self.history_value[index][next] = value;
+        self.history_time[index][next] = timestamp as u32;
+        self.history_last[index] = timestamp;
+        self.history_next[index] = ((next + 1) % HISTORY_DEPTH) as u8;
+
+        if (self.history_len[index] as usize) < HISTORY_DEPTH {
+            self.history_len[index] += 1;
+        }
+
+        self.boot_stats[index].record(value);
+        self.cleared_stats[index].record(value);
+
+        let alarm = self.threshold[index].check(self.alarm[index], value);
+        self.set_alarm(index, alarm);
+
+        for sub in self.subscriptions.iter_mut().flatten() {
+            if sub.sensor.0 as usize != index {
+                continue;
+            }
+
+            let notify = match sub.condition {
+                Condition::Delta(delta) => {
+                    sub.last.is_nan() || (value - sub.last).abs() >= delta
+                }
+                Condition::Crosses(level) => {
+                    !sub.last.is_nan() && (sub.last < level) != (value < level)
+                }
+                Condition::Rate(limit) => {
+                    rate.map_or(false, |rate| rate.abs() >= limit)
+                }
+                Condition::Alarm | Condition::NoData => continue,
+            };
+
+            if notify || matches!(sub.condition, Condition::Crosses(_)) {
+                sub.last = value;
+            }
+
+            if notify {
+                sub.notify();
+            }
+        }
+    }
+
+    fn notify_subscribers(&self, index: usize, condition: Condition) {
+        for sub in self.subscriptions.iter().flatten() {
+            if sub.sensor.0 as usize == index && sub.condition == condition {
+                sub.notify();
+            }
+        }
+    }
+
+    fn set_alarm(&mut self, index: usize, alarm: Alarm) {
+        if self.alarm[index] == alarm {
+            return;
+        }
+
+        self.alarm[index] = alarm;
+        ringbuf_entry!(Trace::Alarm(SensorId(index as u32), alarm));
+
+        for (task, mask) in ALARM_SUBSCRIBERS {
+            let taskid =
+                TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+            let taskid = sys_refresh_task_id(taskid);
+            sys_post(taskid, mask);
+        }
+
+        self.notify_subscribers(index, Condition::Alarm);
+    }
+}
+
 impl idl::InOrderSensorImpl for ServerImpl {
     fn get(
         &mut self,
@@ -63,10 +227,9 @@ impl idl::InOrderSensorImpl for ServerImpl {
                     let err: SensorError = self.err_value[index].into();
                     Err(err.into())
                 }
-                Some(LastReading::Data) => Ok(Reading::new(
-                    self.data_value[index],
-                    self.data_time[index],
-                )),
+                Some(LastReading::Data) => self
+                    .history(index, 0)
+                    .ok_or_else(|| SensorError::NoReading.into()),
             }
         } else {
             Err(SensorError::InvalidSensor.into())
@@ -83,9 +246,8 @@ impl idl::InOrderSensorImpl for ServerImpl {
         let index = id.0 as usize;
 
         if index < NUM_SENSORS {
+            self.record(index, value, timestamp);
             self.last_reading[index] = Some(LastReading::Data);
-            self.data_value[index] = value;
-            self.data_time[index] = timestamp;
             Ok(())
         } else {
             Err(SensorError::InvalidSensor.into())
@@ -102,6 +264,14 @@ impl idl::InOrderSensorImpl for ServerImpl {
         let index = id.0 as usize;
 
         if index < NUM_SENSORS {
+            //
+            // Only a sensor that had a reading can lose it; one that has never
+            // reported doesn't notify until it has.
+            //
+            if matches!(self.last_reading[index], Some(LastReading::Data)) {
+                self.notify_subscribers(index, Condition::NoData);
+            }
+
             self.last_reading[index] = Some(LastReading::Error);
             self.err_value[index] = nodata;
             self.err_time[index] = timestamp;
@@ -141,6 +311,264 @@ impl idl::InOrderSensorImpl for ServerImpl {
             Err(SensorError::InvalidSensor.into())
         }
     }
+
+    fn get_history(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        age: u32,
+    ) -> Result<Reading, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index >= NUM_SENSORS {
+            return Err(SensorError::InvalidSensor.into());
+        }
+
+        self.history(index, age as usize)
+            .ok_or_else(|| SensorError::NoReading.into())
+    }
+
+    fn get_rate(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<f32, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index >= NUM_SENSORS {
+            return Err(SensorError::InvalidSensor.into());
+        }
+
+        match (self.history(index, 0), self.history(index, 1)) {
+            (Some(now), Some(prev)) => now
+                .rate_since(&prev)
+                .ok_or_else(|| SensorError::NoReading.into()),
+            _ => Err(SensorError::NoReading.into()),
+        }
+    }
+
+    fn get_stats(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<SensorStats, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            Ok(SensorStats {
+                boot: self.boot_stats[index],
+                cleared: self.cleared_stats[index],
+                cleared_time: self.cleared_time[index],
+            })
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn clear_stats(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<(), RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            self.cleared_stats[index] = Stats::EMPTY;
+            self.cleared_time[index] = sys_get_timer().now;
+            Ok(())
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn set_threshold(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        threshold: Threshold,
+    ) -> Result<(), RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index >= NUM_SENSORS {
+            return Err(SensorError::InvalidSensor.into());
+        }
+
+        if !threshold.is_valid() {
+            return Err(SensorError::InvalidThreshold.into());
+        }
+
+        ringbuf_entry!(Trace::Threshold(id, threshold));
+        self.threshold[index] = threshold;
+
+        //
+        // Re-evaluate the alarm against the new threshold, starting from the
+        // current state so that an active alarm is held (or released) with
+        // the new hysteresis rather than being cleared and raised again.  If
+        // we don't have a current reading, the alarm stays where it is until
+        // we do.
+        //
+        if let Some(LastReading::Data) = self.last_reading[index] {
+            if let Some(reading) = self.history(index, 0) {
+                let alarm = threshold.check(self.alarm[index], reading.value);
+                self.set_alarm(index, alarm);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_threshold(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<Threshold, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            Ok(self.threshold[index])
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn get_alarm(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<Alarm, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            Ok(self.alarm[index])
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+
+    fn next_alarm(
+        &mut self,
+        _: &RecvMessage,
+        start: u32,
+    ) -> Result<Option<SensorId>, RequestError<SensorError>> {
+        let start = (start as usize).min(NUM_SENSORS);
+
+        Ok(self.alarm[start..]
+            .iter()
+            .position(|&a| a != Alarm::Clear)
+            .map(|i| SensorId((start + i) as u32)))
+    }
+
+    fn subscribe(
+        &mut self,
+        msg: &RecvMessage,
+        id: SensorId,
+        notification_bit: u8,
+        condition: Condition,
+    ) -> Result<(), RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index >= NUM_SENSORS {
+            return Err(SensorError::InvalidSensor.into());
+        }
+
+        if !may_subscribe(msg.sender, notification_bit) {
+            return Err(SensorError::NotSubscriber.into());
+        }
+
+        let valid = match condition {
+            Condition::Delta(delta) => delta >= 0.0,
+            Condition::Crosses(level) => !level.is_nan(),
+            Condition::Rate(limit) => limit >= 0.0,
+            Condition::Alarm | Condition::NoData => true,
+        };
+
+        if !valid {
+            return Err(SensorError::InvalidThreshold.into());
+        }
+
+        let last = match (self.last_reading[index], self.history(index, 0)) {
+            (Some(LastReading::Data), Some(reading)) => reading.value,
+            _ => f32::NAN,
+        };
+
+        let sub = Subscription {
+            task: msg.sender,
+            sensor: id,
+            bit: notification_bit,
+            condition,
+            last,
+        };
+
+        //
+        // A task that restarts will subscribe again, so match on task index
+        // rather than on the full task ID.
+        //
+        let slot = self
+            .subscriptions
+            .iter()
+            .position(|s| match s {
+                Some(s) => {
+                    s.task.index() == msg.sender.index()
+                        && s.sensor == id
+                        && s.bit == notification_bit
+                }
+                None => false,
+            })
+            .or_else(|| self.subscriptions.iter().position(Option::is_none))
+            .ok_or(SensorError::TooManySubscriptions)?;
+
+        ringbuf_entry!(Trace::Subscribe(id, notification_bit, condition));
+        self.subscriptions[slot] = Some(sub);
+        Ok(())
+    }
+
+    fn unsubscribe(
+        &mut self,
+        msg: &RecvMessage,
+        id: SensorId,
+        notification_bit: u8,
+    ) -> Result<(), RequestError<SensorError>> {
+        if id.0 as usize >= NUM_SENSORS {
+            return Err(SensorError::InvalidSensor.into());
+        }
+
+        for slot in self.subscriptions.iter_mut() {
+            if let Some(s) = slot {
+                if s.task.index() == msg.sender.index()
+                    && s.sensor == id
+                    && s.bit == notification_bit
+                {
+                    ringbuf_entry!(Trace::Unsubscribe(id, notification_bit));
+                    *slot = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_metadata(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<SensorMetadata, RequestError<SensorError>> {
+        let index = id.0 as usize;
+
+        if index < NUM_SENSORS {
+            Ok(SensorMetadata::from(&SENSOR_INFO[index]))
+        } else {
+            Err(SensorError::InvalidSensor.into())
+        }
+    }
+}
+
+/// Returns true if `task` is configured as a subscriber that may be sent the
+/// given notification.
+fn may_subscribe(task: TaskId, bit: u8) -> bool {
+    bit < 32
+        && SUBSCRIBERS.iter().any(|&(t, mask)| {
+            t as usize == task.index() && mask & (1 << bit) != 0
+        })
 }
 
 impl NotificationHandler for ServerImpl {
@@ -163,22 +591,53 @@ fn main() -> ! {
     //
     sys_set_timer(Some(deadline), notifications::TIMER_MASK);
 
-    let (last_reading, data_value, data_time, err_value, err_time, nerrors) = mutable_statics::mutable_statics! {
+    let (last_reading, err_value, err_time, nerrors) = mutable_statics::mutable_statics! {
         static mut LAST_READING: [Option<LastReading>; NUM_SENSORS] = [|| None; _];
-        static mut DATA_VALUE: [f32; NUM_SENSORS] = [|| f32::NAN; _];
-        static mut DATA_TIME: [u64; NUM_SENSORS] = [|| 0u64; _];
         static mut ERR_VALUE: [NoData; NUM_SENSORS] = [|| NoData::DeviceUnavailable; _];
         static mut ERR_TIME: [u64; NUM_SENSORS] = [|| 0; _];
         static mut NERRORS: [u32; NUM_SENSORS] = [|| 0; _];
     };
 
+    let (history_value, history_time, history_last, history_len, history_next) = mutable_statics::mutable_statics! {
+        static mut HISTORY_VALUE: [[f32; HISTORY_DEPTH]; NUM_SENSORS] = [|| [f32::NAN; HISTORY_DEPTH]; _];
+        static mut HISTORY_TIME: [[u32; HISTORY_DEPTH]; NUM_SENSORS] = [|| [0; HISTORY_DEPTH]; _];
+        static mut HISTORY_LAST: [u64; NUM_SENSORS] = [|| 0; _];
+        static mut HISTORY_LEN: [u8; NUM_SENSORS] = [|| 0; _];
+        static mut HISTORY_NEXT: [u8; NUM_SENSORS] = [|| 0; _];
+    };
+
+    let (
+        boot_stats,
+        cleared_stats,
+        cleared_time,
+        threshold,
+        alarm,
+        subscriptions,
+    ) = mutable_statics::mutable_statics! {
+        static mut BOOT_STATS: [Stats; NUM_SENSORS] = [|| Stats::EMPTY; _];
+        static mut CLEARED_STATS: [Stats; NUM_SENSORS] = [|| Stats::EMPTY; _];
+        static mut CLEARED_TIME: [u64; NUM_SENSORS] = [|| 0; _];
+        static mut THRESHOLD: [Threshold; NUM_SENSORS] = [|| Threshold::NONE; _];
+        static mut ALARM: [Alarm; NUM_SENSORS] = [|| Alarm::Clear; _];
+        static mut SUBSCRIPTIONS: [Option<Subscription>; MAX_SUBSCRIPTIONS] = [|| None; _];
+    };
+
     let mut server = ServerImpl {
         last_reading,
-        data_value,
-        data_time,
         err_value,
         err_time,
         nerrors,
+        history_value,
+        history_time,
+        history_last,
+        history_len,
+        history_next,
+        boot_stats,
+        cleared_stats,
+        cleared_time,
+        threshold,
+        alarm,
+        subscriptions,
         deadline,
     };
 
@@ -190,9 +649,13 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{NoData, Reading, SensorError, SensorId};
+    use super::{
+        Alarm, Condition, NoData, Reading, SensorError, SensorId,
+        SensorMetadata, SensorStats, Threshold,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
 
 include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
+include!(concat!(env!("OUT_DIR"), "/task_config.rs"));
@@ -8,8 +8,9 @@
 
 use derive_idol_err::IdolError;
 use serde::{Deserialize, Serialize};
-use userlib::{units::Celsius, *};
-use zerocopy::{AsBytes, FromBytes};
+use userlib::*;
+
+pub use thermal_control::{FanHealth, ThermalAutoState, ThermalProperties};
 
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
 pub enum ThermalError {
@@ -42,69 +43,29 @@ pub enum ThermalMode {
     Auto = 2,
 }
 
-/// Substates when running in automatic mode
-///
-/// These are based on `enum ThermalControlState`, but stripped of the
-/// associated state data.
-#[derive(
-    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, Serialize, Deserialize,
-)]
-pub enum ThermalAutoState {
-    Boot,
-    Running,
-    Overheated,
-    Uncontrollable,
-}
-
-/// Properties for a particular part in the system
-#[derive(Clone, Copy, AsBytes, FromBytes)]
-#[repr(C)]
-pub struct ThermalProperties {
-    /// Target temperature for this part
-    pub target_temperature: Celsius,
+/// State of a single thermal zone, i.e. a group of inputs cooled by a
+/// particular group of fans
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThermalZoneState {
+    pub auto_state: ThermalAutoState,
 
-    /// At the critical temperature, we should turn the fans up to 100% power in
-    /// an attempt to cool the part.
-    pub critical_temperature: Celsius,
+    /// Most recent duty cycle requested by this zone's control loop
+    pub pwm: u8,
 
-    /// Temperature at which we drop into the A2 power state.  This should be
-    /// below the part's nonrecoverable temperature.
-    pub power_down_temperature: Celsius,
-
-    /// Maximum slew rate of temperature, measured in °C per second
-    ///
-    /// The slew rate is used to model worst-case temperature if we haven't
-    /// heard from a chip in a while (e.g. due to dropped samples)
-    pub temperature_slew_deg_per_sec: f32,
+    /// Target margin, which is >= 0 and controls over-cooling
+    pub margin: f32,
 }
 
-/// All of these functions take an **instantaneous** temperature; to convert a
-/// timestamped reading into an instantaneous temperature (using a thermal
-/// model), see `TimestampedTemperatureReading::worst_case`.
-impl ThermalProperties {
-    /// Returns whether this part is exceeding its power-down temperature
-    pub fn should_power_down(&self, t: Celsius) -> bool {
-        t.0 >= self.power_down_temperature.0
-    }
-
-    /// Returns whether this part is exceeding its critical temperature
-    pub fn is_critical(&self, t: Celsius) -> bool {
-        t.0 >= self.critical_temperature.0
-    }
+/// Status of a single fan, as reported by `get_fan_status`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FanStatus {
+    pub health: FanHealth,
 
-    /// Returns whether this part is below its critical temperature, with
-    /// a user-configured hysteresis band.
-    pub fn is_sub_critical(&self, t: Celsius, hysteresis: Celsius) -> bool {
-        t.0 < self.critical_temperature.0 - hysteresis.0
-    }
+    /// Most recently commanded duty cycle, if any
+    pub pwm: Option<u8>,
 
-    /// Returns the margin of this part, given a current temperature reading.
-    ///
-    /// Positive margin means that the part is below its max temperature;
-    /// negative means that it's overheating.
-    pub fn margin(&self, t: Celsius) -> Celsius {
-        Celsius(self.target_temperature.0 - t.0)
-    }
+    /// Most recent tach reading, if any
+    pub rpm: Option<u16>,
 }
 
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
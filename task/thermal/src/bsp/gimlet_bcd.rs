@@ -5,8 +5,12 @@
 //! BSP for the Gimlet rev B hardware
 
 use crate::{
-    control::{Device, FanControl, InputChannel, PidConfig, TemperatureSensor},
+    control::{
+        Device, FanControl, FanModel, InputChannel, PidConfig,
+        TemperatureSensor, Zone,
+    },
     i2c_config::{devices, sensors},
+    Fan,
 };
 use core::convert::TryInto;
 pub use drv_gimlet_seq_api::SeqError;
@@ -14,7 +18,11 @@ use drv_gimlet_seq_api::{PowerState, Sequencer};
 use drv_i2c_devices::max31790::Max31790;
 use task_sensor_api::SensorId;
 use task_thermal_api::ThermalProperties;
-use userlib::{task_slot, units::Celsius, TaskId};
+use userlib::{
+    task_slot,
+    units::{Celsius, PWMDuty, Rpm},
+    TaskId,
+};
 
 task_slot!(SEQ, gimlet_seq);
 
@@ -32,8 +40,14 @@ pub const NUM_TEMPERATURE_INPUTS: usize = sensors::NUM_SBTSI_TEMPERATURE_SENSORS
 // Every temperature sensor on Gimlet is owned by this task
 pub const NUM_DYNAMIC_TEMPERATURE_INPUTS: usize = 0;
 
+// ...so there's nothing for the sensors task to tell us about
+pub const SENSOR_NODATA_MASK: u32 = 0;
+
 // We've got 6 fans, driven from a single MAX31790 IC
-const NUM_FANS: usize = drv_i2c_devices::max31790::MAX_FANS as usize;
+pub const NUM_FANS: usize = drv_i2c_devices::max31790::MAX_FANS as usize;
+
+// Every fan blows across every part, so there's a single thermal zone
+pub const NUM_ZONES: usize = 1;
 
 /// This controller is tuned and ready to go
 pub const USE_CONTROLLER: bool = true;
@@ -55,11 +69,14 @@ pub(crate) struct Bsp {
     /// Handle to the sequencer task, to query power state
     seq: Sequencer,
 
+    /// Expected fan behavior, for judging fan health
+    pub fan_model: FanModel,
+
     /// Id of the I2C task, to query MAX5970 status
     i2c_task: TaskId,
 
-    /// Tuning for the PID controller
-    pub pid_config: PidConfig,
+    /// Thermal zones, each with its own PID controller
+    pub zones: &'static [Zone; NUM_ZONES],
 }
 
 bitflags::bitflags! {
@@ -165,13 +182,8 @@ impl Bsp {
             fans,
             fctrl,
 
-            // Based on experimental tuning!
-            pid_config: PidConfig {
-                zero: 35.0,
-                gain_p: 1.75,
-                gain_i: 0.0135,
-                gain_d: 0.4,
-            },
+            zones: &ZONES,
+            fan_model: FAN_MODEL,
 
             inputs: &INPUTS,
             dynamic_inputs: &[],
@@ -182,6 +194,21 @@ impl Bsp {
     }
 }
 
+// TODO: these are guesses, pending measurements on real hardware
+const FAN_MODEL: FanModel = FanModel::new(Rpm(11000), PWMDuty(20), 0.3);
+
+const ZONES: [Zone; NUM_ZONES] = [Zone::new(
+    0..NUM_TEMPERATURE_INPUTS,
+    &[Fan(0), Fan(1), Fan(2), Fan(3), Fan(4), Fan(5)],
+    // Based on experimental tuning!
+    PidConfig {
+        zero: 35.0,
+        gain_p: 1.75,
+        gain_i: 0.0135,
+        gain_d: 0.4,
+    },
+)];
+
 // In general, see RFD 276 Detailed Thermal Loop Design for references.
 // TODO: temperature_slew_deg_per_sec is made up.
 
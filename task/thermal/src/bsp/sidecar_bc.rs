@@ -4,8 +4,12 @@
 
 //! BSP for Sidecar
 
-use crate::control::{
-    Device, FanControl, InputChannel, PidConfig, TemperatureSensor,
+use crate::{
+    control::{
+        Device, FanControl, FanModel, InputChannel, PidConfig,
+        TemperatureSensor, Zone,
+    },
+    Fan,
 };
 use core::convert::TryInto;
 use drv_i2c_devices::max31790::Max31790;
@@ -14,7 +18,11 @@ pub use drv_sidecar_seq_api::SeqError;
 use drv_sidecar_seq_api::{Sequencer, TofinoSeqState, TofinoSequencerPolicy};
 use task_sensor_api::SensorId;
 use task_thermal_api::ThermalProperties;
-use userlib::{task_slot, units::Celsius, TaskId};
+use userlib::{
+    task_slot,
+    units::{Celsius, PWMDuty, Rpm},
+    TaskId,
+};
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 use i2c_config::devices;
@@ -37,7 +45,14 @@ pub const NUM_TEMPERATURE_INPUTS: usize =
 pub const NUM_DYNAMIC_TEMPERATURE_INPUTS: usize =
     drv_transceivers_api::NUM_PORTS as usize;
 
-const NUM_FANS: usize = sensors::NUM_MAX31790_SPEED_SENSORS;
+// Notification from the sensors task when a dynamic input stops reporting
+pub const SENSOR_NODATA_MASK: u32 = crate::notifications::SENSOR_NODATA_MASK;
+
+pub const NUM_FANS: usize = sensors::NUM_MAX31790_SPEED_SENSORS;
+
+// The Tofino (and friends) and the front IO transceivers are cooled by
+// different fans, so they're controlled separately.
+pub const NUM_ZONES: usize = 2;
 
 // Run the PID loop on startup
 pub const USE_CONTROLLER: bool = true;
@@ -71,7 +86,11 @@ pub(crate) struct Bsp {
 
     seq: Sequencer,
 
-    pub pid_config: PidConfig,
+    /// Expected fan behavior, for judging fan health
+    pub fan_model: FanModel,
+
+    /// Thermal zones, each with its own PID controller
+    pub zones: &'static [Zone; NUM_ZONES],
 }
 
 impl Bsp {
@@ -162,13 +181,8 @@ impl Bsp {
             fctrl_east,
             fctrl_west,
 
-            // TODO: this is all made up, copied from tuned Gimlet values
-            pid_config: PidConfig {
-                zero: 35.0,
-                gain_p: 1.75,
-                gain_i: 0.0135,
-                gain_d: 0.4,
-            },
+            zones: &ZONES,
+            fan_model: FAN_MODEL,
 
             inputs: &INPUTS,
             dynamic_inputs:
@@ -180,6 +194,39 @@ impl Bsp {
     }
 }
 
+// TODO: this is all made up, copied from tuned Gimlet values
+const PID_CONFIG: PidConfig = PidConfig {
+    zero: 35.0,
+    gain_p: 1.75,
+    gain_i: 0.0135,
+    gain_d: 0.4,
+};
+
+// TODO: more guessing; these should come from the fan datasheet and
+// measurements on real hardware
+const FAN_MODEL: FanModel = FanModel::new(Rpm(16000), PWMDuty(20), 0.3);
+
+// TODO: the split of fans between zones follows their placement, and both
+// zones share the same (untuned) PID gains; both should be revisited with
+// airflow measurements on real hardware.  The inner fans (NNE, SNE, NNW, SNW)
+// sit behind the Tofino and VSC7448; the outer fans pull air past the front
+// IO transceivers.
+const ZONES: [Zone; NUM_ZONES] = [
+    // Tofino and VSC7448
+    Zone::new(
+        0..NUM_TEMPERATURE_INPUTS,
+        &[Fan(0), Fan(1), Fan(6), Fan(7)],
+        PID_CONFIG,
+    ),
+    // Front IO transceivers, which are dynamic inputs
+    Zone::new(
+        NUM_TEMPERATURE_INPUTS
+            ..NUM_TEMPERATURE_INPUTS + NUM_DYNAMIC_TEMPERATURE_INPUTS,
+        &[Fan(2), Fan(3), Fan(4), Fan(5)],
+        PID_CONFIG,
+    ),
+];
+
 //
 // Guessing, big time
 //
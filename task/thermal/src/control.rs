@@ -18,8 +18,15 @@ use drv_i2c_devices::{
 };
 
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
-use task_sensor_api::{Reading, Sensor as SensorApi, SensorError, SensorId};
-use task_thermal_api::{ThermalAutoState, ThermalProperties};
+use task_packrat_api::{ThermalTuning, ThermalZoneTuning, MAX_THERMAL_ZONES};
+use task_sensor_api::{
+    Condition, Reading, Sensor as SensorApi, SensorError, SensorId,
+};
+use task_thermal_api::{
+    FanStatus, ThermalAutoState, ThermalProperties, ThermalZoneState,
+};
+use thermal_control::{fan_pwm, ControlResult, FanMonitor, ThermalController};
+pub use thermal_control::{FanModel, PidConfig, Zone};
 use userlib::{
     sys_get_timer,
     units::{Celsius, PWMDuty, Rpm},
@@ -242,6 +249,18 @@ pub(crate) struct DynamicInputChannel {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Runtime state for a single `Zone`
+struct ZoneControl {
+    /// Control loop, whose PID parameters are pulled from the BSP by default
+    /// but user-modifiable
+    controller: ThermalController<TEMPERATURE_ARRAY_SIZE>,
+
+    /// Most recent duty cycle requested by the control loop
+    pwm: PWMDuty,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 /// The thermal control loop.
 ///
 /// This object uses slices of sensors and fans, which must be owned
@@ -257,26 +276,15 @@ pub(crate) struct ThermalControl<'a> {
     /// Task to which we should post sensor data updates
     sensor_api: SensorApi,
 
-    /// Target temperature margin. This must be >= 0; as it increases, parts
-    /// are kept cooler than their target temperature value.
-    target_margin: Celsius,
-
-    /// Controller state
-    state: ThermalControlState,
-
-    /// How long to wait in the `Overheated` state before powering down
-    overheat_timeout_ms: u64,
+    /// Control loops, one per `Bsp::zones`
+    zones: [ZoneControl; bsp::NUM_ZONES],
 
-    /// Once we're in `Overheated`, how much does the temperature have to drop
-    /// by before we return to `Normal`
-    overheat_hysteresis: Celsius,
+    /// Health of each fan in `Bsp::fans`
+    fans: [FanMonitor; bsp::NUM_FANS],
 
     /// Most recent power mode mask
     power_mode: PowerBitmask,
 
-    /// PID parameters, pulled from the BSP by default but user-modifiable
-    pid_config: PidConfig,
-
     /// Dynamic inputs are fixed in number but configured at runtime.
     ///
     /// `None` values in this list are ignored.
@@ -284,188 +292,47 @@ pub(crate) struct ThermalControl<'a> {
         [Option<DynamicInputChannel>; bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS],
 }
 
-/// Represents the state of a temperature sensor, which either has a valid
-/// reading or is marked as inactive (due to power state or being missing)
-#[derive(Copy, Clone, Debug)]
-enum TemperatureReading {
-    /// Normal reading, timestamped using monotonic system time
-    Valid(TimestampedTemperatureReading),
+// Every zone's tuning must fit in packrat
+static_assertions::const_assert!(bsp::NUM_ZONES <= MAX_THERMAL_ZONES);
 
-    /// This sensor is not used in the current power state
-    Inactive,
-}
-
-/// Represents a temperature reading at the time at which it was taken
-#[derive(Copy, Clone, Debug)]
-struct TimestampedTemperatureReading {
-    time_ms: u64,
-    value: Celsius,
-}
-
-impl TimestampedTemperatureReading {
-    /// Returns the worst-case temperature, given a current time and thermal
-    /// model for this part.
-    ///
-    /// This only matters when samples are dropped or if there is significant
-    /// lag in the sensors system; if we received a reading on this control
-    /// cycle, then time_ms ≈ now_ms, so this is close to v.value (i.e. the most
-    /// recent reading).
-    ///
-    /// Typically, time_ms is earlier (less) than now_ms, so this subtraction is
-    /// safe.  If there's invalid data in the sensors task (i.e. readings
-    /// claiming to be from the future), then this will saturate instead of
-    /// underflowing.
-    fn worst_case(&self, now_ms: u64, model: &ThermalProperties) -> Celsius {
-        Celsius(
-            self.value.0
-                + now_ms.saturating_sub(self.time_ms) as f32 / 1000.0
-                    * model.temperature_slew_deg_per_sec,
-        )
+fn check_pid(cfg: &PidConfig) -> Result<(), ThermalError> {
+    if !cfg.zero.is_finite() {
+        return Err(ThermalError::InvalidParameter);
     }
+    if cfg.gain_p <= 0.0 || cfg.gain_p.is_nan() || cfg.gain_p.is_infinite() {
+        return Err(ThermalError::InvalidParameter);
+    }
+    if cfg.gain_i < 0.0 || cfg.gain_i.is_nan() || cfg.gain_i.is_infinite() {
+        return Err(ThermalError::InvalidParameter);
+    }
+    if cfg.gain_d < 0.0 || cfg.gain_d.is_nan() || cfg.gain_d.is_infinite() {
+        return Err(ThermalError::InvalidParameter);
+    }
+    Ok(())
 }
 
-/// Configuration for a PID controller
-#[derive(Copy, Clone)]
-pub struct PidConfig {
-    pub zero: f32,
-    pub gain_p: f32,
-    pub gain_i: f32,
-    pub gain_d: f32,
-}
-
-/// Represents a PID controller that can only push in one direction (i.e. the
-/// output must always be positive).
-struct OneSidedPidState {
-    /// Previous (time, input) tuple, for derivative term
-    prev_error: Option<f32>,
-
-    /// Accumulated integral term, pre-multiplied by gain
-    integral: f32,
-}
-
-impl OneSidedPidState {
-    /// Attempts to drive the error to zero.
-    ///
-    /// The error and output are expected to have the same signs, i.e. a large
-    /// positive error will produce a large positive output.
-    fn run(&mut self, cfg: &PidConfig, error: f32, output_limit: f32) -> f32 {
-        let p_contribution = cfg.gain_p * error;
-
-        // Pre-multiply accumulated integral by gain, to make clamping easier
-        // (this also means we can change the gain_i without glitches)
-        self.integral += error * cfg.gain_i;
-
-        // Calculate the derivative term if there was a previous error
-        let d_contribution = if let Some(prev_error) = self.prev_error {
-            (error - prev_error) * cfg.gain_d
-        } else {
-            0.0
-        };
-        self.prev_error = Some(error);
-
-        // To prevent integral windup, integral term needs to be clamped to values
-        // can effect the output.
-        let out_pd = cfg.zero + p_contribution + d_contribution;
-        let (integral_min, integral_max) = if out_pd > output_limit {
-            (-out_pd, 0.0)
-        } else if out_pd < 0.0 {
-            (0.0, -out_pd + output_limit)
-        } else {
-            (-out_pd, output_limit - out_pd)
-        };
-        self.integral = self.integral.clamp(integral_min, integral_max);
-
-        // Clamp output values to valid range.
-        let out = out_pd + self.integral;
-        out.clamp(0.0, output_limit)
+fn check_margin(margin: f32) -> Result<(), ThermalError> {
+    if margin < 0.0 || margin.is_nan() || margin.is_infinite() {
+        return Err(ThermalError::InvalidParameter);
     }
+    Ok(())
 }
 
-impl Default for OneSidedPidState {
-    fn default() -> Self {
-        Self {
-            prev_error: None,
-            integral: 0.0,
-        }
+fn pid_from_tuning(t: &ThermalZoneTuning) -> PidConfig {
+    let [zero, gain_p, gain_i, gain_d] = t.pid;
+    PidConfig {
+        zero,
+        gain_p,
+        gain_i,
+        gain_d,
     }
 }
 
-const TEMPERATURE_ARRAY_SIZE: usize =
-    bsp::NUM_TEMPERATURE_INPUTS + bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS;
-
-/// This corresponds to states shown in RFD 276
-///
-/// All of our temperature arrays contain, in order
+/// All of our temperature inputs, in order:
 /// - I2C temperature inputs (read by this task)
 /// - Dynamic temperature inputs (read by another task and passed in)
-///
-/// Note that the canonical temperatures are stored in the `sensors` task; we
-/// copy them into these arrays for local operations.
-enum ThermalControlState {
-    /// Wait for each sensor to report in at least once
-    ///
-    /// (dynamic sensors must report in *if* they are present, i.e. not `None`
-    /// in the `dynamic_inputs` array)
-    Boot {
-        values: [Option<TemperatureReading>; TEMPERATURE_ARRAY_SIZE],
-    },
-
-    /// Normal happy control loop
-    Running {
-        values: [TemperatureReading; TEMPERATURE_ARRAY_SIZE],
-        pid: OneSidedPidState,
-    },
-
-    /// In the overheated state, one or more components has entered their
-    /// critical temperature ranges.  We turn on fans at high power and record
-    /// the time at which we entered this state; at a certain point, we will
-    /// timeout and drop into `Uncontrolled` if components do not recover.
-    Overheated {
-        values: [TemperatureReading; TEMPERATURE_ARRAY_SIZE],
-        start_time: u64,
-    },
-
-    /// The system cannot control the temperature; power down and wait for
-    /// intervention from higher up the stack.
-    Uncontrollable,
-}
-
-enum ControlResult {
-    Pwm(PWMDuty),
-    PowerDown,
-}
-
-impl ThermalControlState {
-    fn write_temperature(&mut self, index: usize, reading: Reading) {
-        let r = TemperatureReading::Valid(TimestampedTemperatureReading {
-            time_ms: reading.timestamp,
-            value: Celsius(reading.value),
-        });
-        match self {
-            ThermalControlState::Boot { values } => {
-                values[index] = Some(r);
-            }
-            ThermalControlState::Running { values, .. }
-            | ThermalControlState::Overheated { values, .. } => {
-                values[index] = r;
-            }
-            ThermalControlState::Uncontrollable => (),
-        }
-    }
-
-    fn write_temperature_inactive(&mut self, index: usize) {
-        match self {
-            ThermalControlState::Boot { values } => {
-                values[index] = Some(TemperatureReading::Inactive)
-            }
-            ThermalControlState::Running { values, .. }
-            | ThermalControlState::Overheated { values, .. } => {
-                values[index] = TemperatureReading::Inactive;
-            }
-            ThermalControlState::Uncontrollable => (),
-        }
-    }
-}
+const TEMPERATURE_ARRAY_SIZE: usize =
+    bsp::NUM_TEMPERATURE_INPUTS + bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS;
 
 impl<'a> ThermalControl<'a> {
     /// Constructs a new `ThermalControl` based on a `struct Bsp`. This
@@ -475,14 +342,11 @@ impl<'a> ThermalControl<'a> {
             bsp,
             i2c_task,
             sensor_api,
-            target_margin: Celsius(0.0f32),
-            state: ThermalControlState::Boot {
-                values: [None; TEMPERATURE_ARRAY_SIZE],
-            },
-            pid_config: bsp.pid_config,
-
-            overheat_hysteresis: Celsius(1.0),
-            overheat_timeout_ms: 60_000,
+            zones: core::array::from_fn(|i| ZoneControl {
+                controller: ThermalController::new(bsp.zones[i].pid_config),
+                pwm: PWMDuty(0),
+            }),
+            fans: [FanMonitor::new(); bsp::NUM_FANS],
 
             power_mode: PowerBitmask::empty(), // no sensors active
 
@@ -497,75 +361,156 @@ impl<'a> ThermalControl<'a> {
         i: f32,
         d: f32,
     ) -> Result<(), ThermalError> {
-        if p <= 0.0 || p.is_nan() || p.is_infinite() {
-            return Err(ThermalError::InvalidParameter);
-        }
-        if i < 0.0 || i.is_nan() || i.is_infinite() {
-            return Err(ThermalError::InvalidParameter);
-        }
-        if d < 0.0 || d.is_nan() || d.is_infinite() {
-            return Err(ThermalError::InvalidParameter);
+        let cfg = PidConfig {
+            zero: z,
+            gain_p: p,
+            gain_i: i,
+            gain_d: d,
+        };
+        check_pid(&cfg)?;
+
+        for zone in &mut self.zones {
+            zone.controller.set_pid_config(cfg);
         }
 
-        // If the incoming integral gain is zero, then it will never be able
-        // to wind down the integral accumulator (which is pre-multiplied),
-        // so clear it here.
-        if let ThermalControlState::Running { pid, .. } = &mut self.state {
-            if i == 0.0 {
-                pid.integral = 0.0;
-            }
+        Ok(())
+    }
+
+    pub fn set_margin(&mut self, margin: f32) -> Result<(), ThermalError> {
+        check_margin(margin)?;
+        for zone in &mut self.zones {
+            zone.controller.set_target_margin(Celsius(margin));
         }
+        Ok(())
+    }
 
-        self.pid_config.zero = z;
-        self.pid_config.gain_p = p;
-        self.pid_config.gain_i = i;
-        self.pid_config.gain_d = d;
+    /// Returns the largest target margin of any zone
+    pub fn get_margin(&mut self) -> f32 {
+        self.zones
+            .iter()
+            .map(|z| z.controller.target_margin().0)
+            .fold(0.0, f32::max)
+    }
 
+    pub fn set_zone_margin(
+        &mut self,
+        zone: usize,
+        margin: f32,
+    ) -> Result<(), ThermalError> {
+        let zone =
+            self.zones.get_mut(zone).ok_or(ThermalError::InvalidIndex)?;
+        check_margin(margin)?;
+        zone.controller.set_target_margin(Celsius(margin));
         Ok(())
     }
 
-    pub fn set_margin(&mut self, margin: f32) -> Result<(), ThermalError> {
-        if margin < 0.0 || margin.is_nan() || margin.is_infinite() {
+    /// Returns the current PID parameters and margin of every zone, for
+    /// caching in packrat.  The watchdog timeout is left as 0 (disabled),
+    /// since it isn't ours to know.
+    pub fn tuning(&self) -> ThermalTuning {
+        let mut tuning = ThermalTuning {
+            zone_count: self.zones.len() as u8,
+            ..Default::default()
+        };
+        for (z, t) in self.zones.iter().zip(tuning.zones.iter_mut()) {
+            let pid = z.controller.pid_config();
+            *t = ThermalZoneTuning {
+                pid: [pid.zero, pid.gain_p, pid.gain_i, pid.gain_d],
+                margin: z.controller.target_margin().0,
+            };
+        }
+        tuning
+    }
+
+    /// Applies PID parameters and margins previously returned by `tuning`.
+    ///
+    /// The tuning is checked in its entirety before any of it is applied, so
+    /// on error, nothing has changed.
+    pub fn apply_tuning(
+        &mut self,
+        tuning: &ThermalTuning,
+    ) -> Result<(), ThermalError> {
+        if tuning.zone_count as usize != self.zones.len() {
             return Err(ThermalError::InvalidParameter);
         }
-        self.target_margin = Celsius(margin);
+        let tuning = &tuning.zones[..self.zones.len()];
+        for t in tuning {
+            check_pid(&pid_from_tuning(t))?;
+            check_margin(t.margin)?;
+        }
+        for (z, t) in self.zones.iter_mut().zip(tuning) {
+            z.controller.set_pid_config(pid_from_tuning(t));
+            z.controller.set_target_margin(Celsius(t.margin));
+        }
         Ok(())
     }
 
-    pub fn get_margin(&mut self) -> f32 {
-        self.target_margin.0
+    /// Resets the PID configuration and margin of every zone to the BSP's
+    /// defaults, without disturbing the control state
+    pub fn restore_defaults(&mut self) {
+        for (zone, z) in self.bsp.zones.iter().zip(self.zones.iter_mut()) {
+            // Reset the PID configuration from the BSP
+            z.controller.set_pid_config(zone.pid_config);
+
+            // Set the target_margin to 0, indicating no overcooling
+            z.controller.set_target_margin(Celsius(0.0f32));
+        }
     }
 
-    /// Resets the control state and the PID configuration
-    pub fn reset(&mut self) {
-        self.reset_state();
+    /// Resets the control state of every zone
+    pub fn reset_state(&mut self) {
+        for i in 0..self.zones.len() {
+            self.reset_zone_state(i);
+        }
+    }
 
-        // Reset the PID configuration from the BSP
-        self.pid_config = self.bsp.pid_config;
+    /// Resets the control state of a single zone
+    fn reset_zone_state(&mut self, zone: usize) {
+        let controller = &mut self.zones[zone].controller;
+        controller.reset_state();
+        ringbuf_entry!(Trace::AutoState(zone as u8, controller.state()));
+    }
 
-        // Set the target_margin to 0, indicating no overcooling
-        self.target_margin = Celsius(0.0f32);
+    /// Records a temperature reading in every zone containing the given input
+    fn write_temperature(&mut self, index: usize, r: Reading) {
+        for (zone, z) in self.bsp.zones.iter().zip(self.zones.iter_mut()) {
+            if zone.inputs.contains(&index) {
+                z.controller.write_temperature(
+                    index,
+                    r.timestamp,
+                    Celsius(r.value),
+                );
+            }
+        }
     }
 
-    /// Resets the control state
-    fn reset_state(&mut self) {
-        self.state = ThermalControlState::Boot {
-            values: [None; TEMPERATURE_ARRAY_SIZE],
-        };
-        ringbuf_entry!(Trace::AutoState(self.get_state()));
+    /// Marks the given input as inactive in every zone containing it
+    fn write_temperature_inactive(&mut self, index: usize) {
+        for (zone, z) in self.bsp.zones.iter().zip(self.zones.iter_mut()) {
+            if zone.inputs.contains(&index) {
+                z.controller.write_temperature_inactive(index);
+            }
+        }
     }
 
     /// Reads all temperature and fan RPM sensors, posting their results
     /// to the sensors task API.
     ///
     /// Records failed sensor reads and failed posts to the sensors task in
-    /// the local ringbuf.
-    pub fn read_sensors(&self) {
+    /// the local ringbuf.  Fan speeds are also checked against their commanded
+    /// duty cycles, with changes in fan health recorded in the ringbuf.
+    pub fn read_sensors(&mut self) {
         // Read fan data and log it to the sensors task
         for (index, sensor_id) in self.bsp.fans.iter().enumerate() {
             let post_result =
                 match self.bsp.fan_control(Fan::from(index)).fan_rpm() {
                     Ok(reading) => {
+                        let monitor = &mut self.fans[index];
+                        if let Some(h) =
+                            monitor.record(&self.bsp.fan_model, reading)
+                        {
+                            ringbuf_entry!(Trace::FanHealth(index as u8, h));
+                        }
                         self.sensor_api.post_now(*sensor_id, reading.0.into())
                     }
                     Err(e) => {
@@ -634,33 +579,6 @@ impl<'a> ThermalControl<'a> {
         // they are, so someone else has to do that.
     }
 
-    /// Returns an iterator over tuples of `(value, thermal model)`
-    ///
-    /// The `values` array must contain `static_inputs.len()` +
-    /// `dynamic_inputs.len()` values, in that order; this function will panic
-    /// otherwise.
-    ///
-    /// In cases where dynamic inputs are not present (i.e. they are `None` in
-    /// the array), the iterator will skip that entire tuple.
-    fn zip_temperatures<'b, T>(
-        values: &'b [T],
-        (static_inputs, dynamic_inputs): (
-            &'b [InputChannel],
-            &'b [Option<DynamicInputChannel>],
-        ),
-    ) -> impl Iterator<Item = (&'b T, ThermalProperties)> {
-        assert_eq!(values.len(), static_inputs.len() + dynamic_inputs.len());
-        values
-            .iter()
-            .zip(
-                static_inputs
-                    .iter()
-                    .map(|i| Some(i.model))
-                    .chain(dynamic_inputs.iter().map(|i| i.map(|i| i.model))),
-            )
-            .filter_map(|(v, model)| model.map(|t| (v, t)))
-    }
-
     /// An extremely simple thermal control loop.
     ///
     /// Returns an error if the control loop failed to read critical sensors;
@@ -682,26 +600,26 @@ impl<'a> ThermalControl<'a> {
         // Load sensor readings from the `sensors` API.
         //
         // If the most recent reading is an error, then leave the previous value
-        // in `self.state`.  When we're in the `Boot` state, this will leave the
-        // value as `None`; when we're `Running`, it will maintain the previous
-        // state, estimating a new temperature with the thermal model.
+        // in the controller.  When we're in the `Boot` state, this will leave
+        // the value as `None`; when we're `Running`, it will maintain the
+        // previous state, estimating a new temperature with the thermal model.
         for (i, s) in self.bsp.inputs.iter().enumerate() {
             if self.power_mode.intersects(s.power_mode_mask) {
                 let sensor_id = s.sensor.sensor_id;
                 let r = self.sensor_api.get_reading(sensor_id);
                 match r {
                     Ok(r) => {
-                        self.state.write_temperature(i, r);
+                        self.write_temperature(i, r);
                     }
                     Err(SensorError::NotPresent) if s.removable => {
                         // Ignore errors if the sensor is removable and the
                         // error indicates that it's not present.
-                        self.state.write_temperature_inactive(i);
+                        self.write_temperature_inactive(i);
                     }
                     Err(_) => (),
                 }
             } else {
-                self.state.write_temperature_inactive(i);
+                self.write_temperature_inactive(i);
             }
         }
 
@@ -714,184 +632,93 @@ impl<'a> ThermalControl<'a> {
             match self.dynamic_inputs[i] {
                 Some(..) => {
                     if let Ok(r) = self.sensor_api.get_reading(*sensor_id) {
-                        self.state.write_temperature(index, r);
+                        self.write_temperature(index, r);
                     }
                 }
-                None => self.state.write_temperature_inactive(index),
+                None => self.write_temperature_inactive(index),
             }
         }
 
-        // A bit awkward, but we have to borrow these explicitly to work around
-        // the lifetime checker, which won't let us call a &self function when
-        // self.state is mutably borrowed.
-        let inputs = (self.bsp.inputs, self.dynamic_inputs.as_slice());
-
-        let control_result = match &mut self.state {
-            ThermalControlState::Boot { values } => {
-                let mut all_some = true;
-                let mut any_power_down = false;
-                let mut worst_margin = f32::MAX;
-                for (v, model) in Self::zip_temperatures(values, inputs) {
-                    match v {
-                        Some(TemperatureReading::Valid(v)) => {
-                            let temperature = v.worst_case(now_ms, &model);
-                            any_power_down |=
-                                model.should_power_down(temperature);
-                            worst_margin =
-                                worst_margin.min(model.margin(temperature).0);
-                        }
-                        Some(TemperatureReading::Inactive) => {
-                            // Inactive sensors are ignored, but do not gate us
-                            // from transitioning to `Running`
-                        }
-
-                        None => all_some = false,
-                    }
+        // Run each zone's control loop, looking up the thermal model for each
+        // of its inputs; dynamic inputs without a model are skipped entirely.
+        let (inputs, dynamic_inputs) = (self.bsp.inputs, &self.dynamic_inputs);
+        let mut power_down = false;
+        for (i, (zone, z)) in
+            self.bsp.zones.iter().zip(self.zones.iter_mut()).enumerate()
+        {
+            let prev_state = z.controller.state();
+            let result = z.controller.run(now_ms, |input| {
+                if !zone.inputs.contains(&input) {
+                    return None;
                 }
-
-                if any_power_down {
-                    self.state = ThermalControlState::Uncontrollable;
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::PowerDown
-                } else if all_some {
-                    // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
-                    let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
-                    self.state = ThermalControlState::Running {
-                        values: values.map(Option::unwrap),
-                        pid,
-                    };
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
-                } else {
-                    ControlResult::Pwm(PWMDuty(100))
+                match input.checked_sub(inputs.len()) {
+                    None => Some(inputs[input].model),
+                    Some(d) => dynamic_inputs[d].map(|d| d.model),
                 }
+            });
+            let state = z.controller.state();
+            if state != prev_state {
+                ringbuf_entry!(Trace::AutoState(i as u8, state));
             }
-            ThermalControlState::Running { values, pid } => {
-                let mut any_power_down = false;
-                let mut any_critical = false;
-                let mut worst_margin = f32::MAX;
-
-                // Remember, positive margin means that all parts are happily
-                // below their max temperature; negative means someone is
-                // overheating.  We want to pick the _smallest_ margin, since
-                // that's the part which is most overheated.
-                for (v, model) in Self::zip_temperatures(values, inputs) {
-                    if let TemperatureReading::Valid(v) = v {
-                        let temperature = v.worst_case(now_ms, &model);
-                        any_power_down |= model.should_power_down(temperature);
-                        any_critical |= model.is_critical(temperature);
-
-                        worst_margin =
-                            worst_margin.min(model.margin(temperature).0);
-                    }
-                }
 
-                if any_power_down {
-                    self.state = ThermalControlState::Uncontrollable;
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::PowerDown
-                } else if any_critical {
-                    self.state = ThermalControlState::Overheated {
-                        values: *values,
-                        start_time: now_ms,
-                    };
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::Pwm(PWMDuty(100))
-                } else {
-                    // We adjust the worst component margin by our target
-                    // margin, which must be > 0.  This effectively tells the
-                    // control loop to overcool the system.
-                    //
-                    // `PidControl::run` expects the sign of the input and
-                    // output to match, so we negate things here: if the worst
-                    // margin is negative (i.e. the system is overheating), then
-                    // the input to `run` is positive, because we want a
-                    // positive fan speed.
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
+            match result {
+                ControlResult::Pwm(target_pwm) => {
+                    ringbuf_entry!(Trace::ControlPwm(i as u8, target_pwm.0));
+                    z.pwm = target_pwm;
                 }
-            }
-            ThermalControlState::Overheated { values, start_time } => {
-                let mut all_subcritical = true;
-                let mut any_power_down = false;
-                let mut worst_margin = f32::MAX;
-
-                for (v, model) in Self::zip_temperatures(values, inputs) {
-                    if let TemperatureReading::Valid(v) = v {
-                        let temperature = v.worst_case(now_ms, &model);
-                        all_subcritical &= model.is_sub_critical(
-                            temperature,
-                            self.overheat_hysteresis,
-                        );
-                        any_power_down |= model.should_power_down(temperature);
-                        worst_margin =
-                            worst_margin.min(model.margin(temperature).0);
-                    }
-                }
-
-                if any_power_down {
-                    self.state = ThermalControlState::Uncontrollable;
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::PowerDown
-                } else if all_subcritical {
-                    // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
-                    let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                        100.0,
-                    );
-                    self.state = ThermalControlState::Running {
-                        values: *values,
-                        pid,
-                    };
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::Pwm(PWMDuty(pwm as u8))
-                } else if now_ms > *start_time + self.overheat_timeout_ms {
-                    // If blasting the fans hasn't cooled us down in this amount
-                    // of time, then something is terribly wrong - abort!
-                    self.state = ThermalControlState::Uncontrollable;
-                    ringbuf_entry!(Trace::AutoState(self.get_state()));
-
-                    ControlResult::PowerDown
-                } else {
-                    ControlResult::Pwm(PWMDuty(100))
+                ControlResult::PowerDown => {
+                    z.pwm = PWMDuty(0);
+                    power_down = true;
                 }
             }
-            ThermalControlState::Uncontrollable => ControlResult::PowerDown,
-        };
+        }
 
-        match control_result {
-            ControlResult::Pwm(target_pwm) => {
-                // Send the new RPM to all of our fans
-                ringbuf_entry!(Trace::ControlPwm(target_pwm.0));
-                self.set_pwm(target_pwm)?;
+        // If any zone is uncontrollable, then the whole system powers down
+        if power_down {
+            if let Err(e) = self.bsp.power_down() {
+                ringbuf_entry!(Trace::PowerDownFailed(e));
             }
-            ControlResult::PowerDown => {
-                if let Err(e) = self.bsp.power_down() {
-                    ringbuf_entry!(Trace::PowerDownFailed(e));
-                }
-                self.set_pwm(PWMDuty(0))?;
+            self.set_pwm(PWMDuty(0))?;
+        } else {
+            self.set_zone_pwm()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends each fan the highest PWM duty cycle requested by the zones that
+    /// it cools.
+    ///
+    /// Returns the last error if one occurred, but does not short circuit
+    /// (i.e. attempts to set *all* fan duty cycles, even if one fails)
+    pub fn set_zone_pwm(&mut self) -> Result<(), ThermalError> {
+        let mut zone_pwm = [PWMDuty(0); bsp::NUM_ZONES];
+        for (pwm, (zone, z)) in zone_pwm
+            .iter_mut()
+            .zip(self.bsp.zones.iter().zip(self.zones.iter()))
+        {
+            *pwm = zone.compensated_pwm(z.pwm, &self.fans);
+        }
+
+        let mut last_err = Ok(());
+        for index in 0..self.bsp.fans.len() {
+            let fan = Fan::from(index);
+            let pwm = fan_pwm(fan, self.bsp.zones, &zone_pwm);
+            if let Err(e) = self.write_fan_pwm(fan, pwm) {
+                last_err = Err(e);
             }
         }
+        last_err.map_err(|_| ThermalError::DeviceError)
+    }
 
+    /// Sets the PWM for a single fan, recording it for health monitoring
+    fn write_fan_pwm(
+        &mut self,
+        fan: Fan,
+        pwm: PWMDuty,
+    ) -> Result<(), ResponseCode> {
+        self.bsp.fan_control(fan).set_pwm(pwm)?;
+        self.fans[fan.0 as usize].set_pwm(pwm);
         Ok(())
     }
 
@@ -899,14 +726,13 @@ impl<'a> ThermalControl<'a> {
     ///
     /// Returns the last error if one occurred, but does not short circuit
     /// (i.e. attempts to set *all* fan duty cycles, even if one fails)
-    pub fn set_pwm(&self, pwm: PWMDuty) -> Result<(), ThermalError> {
+    pub fn set_pwm(&mut self, pwm: PWMDuty) -> Result<(), ThermalError> {
         if pwm.0 > 100 {
             return Err(ThermalError::InvalidPWM);
         }
         let mut last_err = Ok(());
-        for (index, _sensor_id) in self.bsp.fans.iter().enumerate() {
-            if let Err(e) = self.bsp.fan_control(Fan::from(index)).set_pwm(pwm)
-            {
+        for index in 0..self.bsp.fans.len() {
+            if let Err(e) = self.write_fan_pwm(Fan::from(index), pwm) {
                 last_err = Err(e);
             }
         }
@@ -915,11 +741,20 @@ impl<'a> ThermalControl<'a> {
 
     /// Sets the PWM for a single fan
     pub fn set_fan_pwm(
-        &self,
+        &mut self,
         fan: Fan,
         pwm: PWMDuty,
     ) -> Result<(), ResponseCode> {
-        self.bsp.fan_control(fan).set_pwm(pwm)
+        self.write_fan_pwm(fan, pwm)
+    }
+
+    pub fn get_fan_status(&self, fan: Fan) -> FanStatus {
+        let m = &self.fans[fan.0 as usize];
+        FanStatus {
+            health: m.health(),
+            pwm: m.pwm().map(|p| p.0),
+            rpm: m.rpm().map(|r| r.0),
+        }
     }
 
     pub fn fan(&self, index: u8) -> Option<Fan> {
@@ -944,17 +779,80 @@ impl<'a> ThermalControl<'a> {
         result
     }
 
+    /// Returns the most severe state of any zone
     pub fn get_state(&self) -> ThermalAutoState {
-        match self.state {
-            ThermalControlState::Boot { .. } => ThermalAutoState::Boot,
-            ThermalControlState::Running { .. } => ThermalAutoState::Running,
-            ThermalControlState::Overheated { .. } => {
-                ThermalAutoState::Overheated
+        let states = || self.zones.iter().map(|z| z.controller.state());
+        [
+            ThermalAutoState::Uncontrollable,
+            ThermalAutoState::Overheated,
+            ThermalAutoState::Boot,
+        ]
+        .into_iter()
+        .find(|s| states().any(|z| z == *s))
+        .unwrap_or(ThermalAutoState::Running)
+    }
+
+    pub fn get_zone_count(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn get_zone_state(
+        &self,
+        zone: usize,
+    ) -> Result<ThermalZoneState, ThermalError> {
+        let z = self.zones.get(zone).ok_or(ThermalError::InvalidIndex)?;
+        Ok(ThermalZoneState {
+            auto_state: z.controller.state(),
+            pwm: z.pwm.0,
+            margin: z.controller.target_margin().0,
+        })
+    }
+
+    /// Subscribes to be notified when any of our dynamic inputs, which are
+    /// posted by other tasks on their own schedule, stops reporting.
+    pub fn subscribe_dynamic_inputs(&self, notification_bit: u8) {
+        for &id in self.bsp.dynamic_inputs {
+            if let Err(e) = self.sensor_api.subscribe(
+                id,
+                notification_bit,
+                Condition::NoData,
+            ) {
+                ringbuf_entry!(Trace::SubscribeFailed(id, e));
+            }
+        }
+    }
+
+    /// Re-reads our active dynamic inputs after being told that one has lost
+    /// its reading.  Each zone containing an input without a reading goes
+    /// back to `Boot`, running its fans at 100% until every one of its inputs
+    /// has reported in again.
+    ///
+    /// Returns true if any zone was reset, in which case the caller should
+    /// apply the new PWM duty cycles with `set_zone_pwm`.
+    pub fn check_dynamic_inputs(&mut self) -> bool {
+        let mut reset = false;
+
+        for i in 0..self.bsp.dynamic_inputs.len() {
+            if self.dynamic_inputs[i].is_none() {
+                continue;
             }
-            ThermalControlState::Uncontrollable => {
-                ThermalAutoState::Uncontrollable
+
+            let id = self.bsp.dynamic_inputs[i];
+            if let Err(e) = self.sensor_api.get_reading(id) {
+                ringbuf_entry!(Trace::DynamicInputLost(i as u8, e));
+
+                let input = i + self.bsp.inputs.len();
+                for zone in 0..self.zones.len() {
+                    if self.bsp.zones[zone].inputs.contains(&input) {
+                        self.reset_zone_state(zone);
+                        self.zones[zone].pwm = PWMDuty(100);
+                        reset = true;
+                    }
+                }
             }
         }
+
+        reset
     }
 
     pub fn update_dynamic_input(
@@ -965,12 +863,17 @@ impl<'a> ThermalControl<'a> {
         if index >= bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS {
             return Err(ThermalError::InvalidIndex);
         }
-        // If we're adding a new dynamic input, then reset the state to `Boot`,
-        // ensuring that we'll wait for that channel to provide us with at least
-        // one valid reading before resuming the PID loop.
+        // If we're adding a new dynamic input, then reset the state of its
+        // zone to `Boot`, ensuring that we'll wait for that channel to provide
+        // us with at least one valid reading before resuming the PID loop.
         if self.dynamic_inputs[index].is_none() {
             self.dynamic_inputs[index] = Some(DynamicInputChannel { model });
-            self.reset_state();
+            let input = index + self.bsp.inputs.len();
+            for zone in 0..self.zones.len() {
+                if self.bsp.zones[zone].inputs.contains(&input) {
+                    self.reset_zone_state(zone);
+                }
+            }
         }
         Ok(())
     }
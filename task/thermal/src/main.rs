@@ -36,41 +36,42 @@ use drv_i2c_api::ResponseCode;
 use drv_i2c_devices::max31790::I2cWatchdog;
 use idol_runtime::{NotificationHandler, RequestError};
 use ringbuf::*;
+use task_packrat_api::{CacheGetError, Packrat, ThermalTuning};
 use task_sensor_api::{Sensor as SensorApi, SensorError, SensorId};
 use task_thermal_api::{
-    ThermalAutoState, ThermalError, ThermalMode, ThermalProperties,
+    FanHealth, FanStatus, ThermalAutoState, ThermalError, ThermalMode,
+    ThermalProperties, ThermalZoneState,
 };
+use thermal_control::Fan;
 use userlib::units::PWMDuty;
 use userlib::*;
 
-// We define our own Fan type, as we may have more fans than any single
-// controller supports.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct Fan(u8);
-
-impl From<usize> for Fan {
-    fn from(index: usize) -> Self {
-        Fan(index as u8)
-    }
-}
-
 task_slot!(I2C, i2c_driver);
 task_slot!(SENSOR, sensor);
+task_slot!(PACKRAT, packrat);
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Trace {
     None,
     Start,
     ThermalMode(ThermalMode),
-    AutoState(ThermalAutoState),
+    AutoState(u8, ThermalAutoState),
     FanReadFailed(SensorId, ResponseCode),
+    FanHealth(u8, FanHealth),
     MiscReadFailed(SensorId, SensorReadError),
     SensorReadFailed(SensorId, SensorReadError),
     PostFailed(SensorId, SensorError),
-    ControlPwm(u8),
+    ControlPwm(u8, u8),
     PowerModeChanged(PowerBitmask),
     PowerDownFailed(SeqError),
     ControlError(ThermalError),
+    TuningLoaded,
+    TuningRejected(ThermalError),
+    TuningSaved,
+    DefaultsRestored,
+    WatchdogFailed,
+    SubscribeFailed(SensorId, SensorError),
+    DynamicInputLost(u8, SensorError),
 }
 ringbuf!(Trace, 32, Trace::None);
 
@@ -81,10 +82,26 @@ struct ServerImpl<'a> {
     control: ThermalControl<'a>,
     deadline: u64,
     runtime: u64,
+
+    /// Cache for our tuning, so that it survives a restart of this task
+    packrat: Packrat,
+
+    /// Most recently configured fan controller watchdog
+    watchdog: I2cWatchdog,
 }
 
 const TIMER_INTERVAL: u64 = 1000;
 
+//
+// We enable the fan watchdog by default, but with its longest timeout of 30
+// seconds.  This is longer than it takes to flash on Gimlet -- and right on
+// the edge of how long it takes to dump.  On some platforms and/or under some
+// conditions, "humility dump" might be able to induce the watchdog to kick,
+// which may induce a flight-or-fight reaction for whomever is near the fans
+// when they blast off...
+//
+const DEFAULT_WATCHDOG: I2cWatchdog = I2cWatchdog::ThirtySeconds;
+
 impl<'a> ServerImpl<'a> {
     /// Configures the control loop to run in manual mode, loading the given
     /// PWM value immediately to all fans.
@@ -102,12 +119,14 @@ impl<'a> ServerImpl<'a> {
     /// Configures the control loop to run in automatic mode.
     ///
     /// The fans will not change speed until the next controller update tick.
+    /// The control state starts afresh, but the tuning (which was loaded at
+    /// startup, and may since have been changed) is kept.
     ///
     /// Returns an error if the given PWM value is invalid.
     fn set_mode_auto(&mut self) -> Result<(), ThermalError> {
         if self.mode != ThermalMode::Auto {
             self.set_mode(ThermalMode::Auto);
-            self.control.reset();
+            self.control.reset_state();
             Ok(())
         } else {
             Err(ThermalError::AlreadyInAutoMode)
@@ -119,10 +138,79 @@ impl<'a> ServerImpl<'a> {
         ringbuf_entry!(Trace::ThermalMode(m));
     }
 
-    fn set_watchdog(&self, wd: I2cWatchdog) -> Result<(), ThermalError> {
+    fn set_watchdog(&mut self, wd: I2cWatchdog) -> Result<(), ThermalError> {
         self.control
             .set_watchdog(wd)
-            .map_err(|_| ThermalError::DeviceError)
+            .map_err(|_| ThermalError::DeviceError)?;
+        self.watchdog = wd;
+        Ok(())
+    }
+
+    /// Caches our current tuning in packrat
+    fn save_tuning(&self) {
+        let tuning = ThermalTuning {
+            watchdog_s: watchdog_to_secs(self.watchdog),
+            ..self.control.tuning()
+        };
+        self.packrat.set_thermal_tuning(tuning);
+        ringbuf_entry!(Trace::TuningSaved);
+    }
+
+    /// Loads tuning that we cached in packrat before restarting, if any.
+    ///
+    /// Tuning which fails validation is discarded in its entirety, leaving
+    /// the BSP defaults in place.
+    fn load_tuning(&mut self) {
+        let tuning = match self.packrat.get_thermal_tuning() {
+            Ok(t) => t,
+            Err(CacheGetError::ValueNotSet) => return,
+        };
+        let wd = match watchdog_from_secs(tuning.watchdog_s) {
+            Some(wd) => wd,
+            None => {
+                ringbuf_entry!(Trace::TuningRejected(
+                    ThermalError::InvalidWatchdogTime
+                ));
+                return;
+            }
+        };
+        if let Err(e) = self.control.apply_tuning(&tuning) {
+            ringbuf_entry!(Trace::TuningRejected(e));
+            return;
+        }
+        if self.set_watchdog(wd).is_err() {
+            ringbuf_entry!(Trace::WatchdogFailed);
+        }
+        ringbuf_entry!(Trace::TuningLoaded);
+    }
+
+    /// Discards any cached tuning, reverting to the BSP defaults
+    fn restore_defaults(&mut self) -> Result<(), ThermalError> {
+        self.packrat.clear_thermal_tuning();
+        self.control.restore_defaults();
+        ringbuf_entry!(Trace::DefaultsRestored);
+        self.set_watchdog(DEFAULT_WATCHDOG)
+    }
+}
+
+/// Converts a watchdog timeout into the form cached in packrat
+fn watchdog_to_secs(wd: I2cWatchdog) -> u8 {
+    match wd {
+        I2cWatchdog::Disabled => 0,
+        I2cWatchdog::FiveSeconds => 5,
+        I2cWatchdog::TenSeconds => 10,
+        I2cWatchdog::ThirtySeconds => 30,
+    }
+}
+
+/// Converts a watchdog timeout cached in packrat back into its enum
+fn watchdog_from_secs(secs: u8) -> Option<I2cWatchdog> {
+    match secs {
+        0 => Some(I2cWatchdog::Disabled),
+        5 => Some(I2cWatchdog::FiveSeconds),
+        10 => Some(I2cWatchdog::TenSeconds),
+        30 => Some(I2cWatchdog::ThirtySeconds),
+        _ => None,
     }
 }
 
@@ -165,6 +253,18 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
         }
     }
 
+    fn get_fan_status(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<FanStatus, RequestError<ThermalError>> {
+        if let Some(fan) = self.control.fan(index) {
+            Ok(self.control.get_fan_status(fan))
+        } else {
+            Err(ThermalError::InvalidFan.into())
+        }
+    }
+
     fn set_mode_manual(
         &mut self,
         _: &RecvMessage,
@@ -187,8 +287,9 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
         &mut self,
         _: &RecvMessage,
     ) -> Result<(), RequestError<ThermalError>> {
-        ServerImpl::set_watchdog(self, I2cWatchdog::Disabled)
-            .map_err(Into::into)
+        ServerImpl::set_watchdog(self, I2cWatchdog::Disabled)?;
+        self.save_tuning();
+        Ok(())
     }
 
     fn enable_watchdog(
@@ -202,7 +303,9 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
             30 => I2cWatchdog::ThirtySeconds,
             _ => return Err(ThermalError::InvalidWatchdogTime.into()),
         };
-        ServerImpl::set_watchdog(self, wd).map_err(Into::into)
+        ServerImpl::set_watchdog(self, wd)?;
+        self.save_tuning();
+        Ok(())
     }
 
     fn set_pid(
@@ -217,6 +320,7 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
             return Err(ThermalError::NotInAutoMode.into());
         }
         self.control.set_pid(z, p, i, d)?;
+        self.save_tuning();
         Ok(())
     }
 
@@ -229,6 +333,7 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
             return Err(ThermalError::NotInAutoMode.into());
         }
         self.control.set_margin(margin)?;
+        self.save_tuning();
         Ok(())
     }
 
@@ -242,6 +347,40 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
         Ok(self.control.get_margin())
     }
 
+    fn get_zone_count(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u8, RequestError<ThermalError>> {
+        Ok(self.control.get_zone_count() as u8)
+    }
+
+    fn get_zone_state(
+        &mut self,
+        _: &RecvMessage,
+        zone: u8,
+    ) -> Result<ThermalZoneState, RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control
+            .get_zone_state(zone as usize)
+            .map_err(RequestError::from)
+    }
+
+    fn set_zone_margin(
+        &mut self,
+        _: &RecvMessage,
+        zone: u8,
+        margin: f32,
+    ) -> Result<(), RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control.set_zone_margin(zone as usize, margin)?;
+        self.save_tuning();
+        Ok(())
+    }
+
     fn update_dynamic_input(
         &mut self,
         _: &RecvMessage,
@@ -269,6 +408,13 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
             .map_err(RequestError::from)
     }
 
+    fn restore_defaults(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<ThermalError>> {
+        ServerImpl::restore_defaults(self).map_err(Into::into)
+    }
+
     fn get_runtime(
         &mut self,
         _: &RecvMessage,
@@ -279,10 +425,22 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
 
 impl<'a> NotificationHandler for ServerImpl<'a> {
     fn current_notification_mask(&self) -> u32 {
-        notifications::TIMER_MASK
+        notifications::TIMER_MASK | bsp::SENSOR_NODATA_MASK
     }
 
-    fn handle_notification(&mut self, _bits: u32) {
+    fn handle_notification(&mut self, bits: u32) {
+        // A dynamic input has stopped reporting, so fall back to full speed
+        // in its zones rather than waiting for the next tick.  (In manual
+        // mode, the zones are reset anyway when auto mode resumes.)
+        if bits & bsp::SENSOR_NODATA_MASK != 0
+            && self.control.check_dynamic_inputs()
+            && self.mode == ThermalMode::Auto
+        {
+            if let Err(e) = self.control.set_zone_pwm() {
+                ringbuf_entry!(Trace::ControlError(e));
+            }
+        }
+
         let now = sys_get_timer().now;
         if now >= self.deadline {
             // We *always* read sensor data, which does not touch the control
@@ -326,6 +484,11 @@ fn main() -> ! {
 
     let bsp = Bsp::new(i2c_task);
     let control = ThermalControl::new(&bsp, i2c_task, sensor_api);
+    if bsp::SENSOR_NODATA_MASK != 0 {
+        control.subscribe_dynamic_inputs(
+            bsp::SENSOR_NODATA_MASK.trailing_zeros() as u8,
+        );
+    }
 
     // This will put our timer in the past, and should immediately kick us.
     let deadline = sys_get_timer().now;
@@ -336,23 +499,21 @@ fn main() -> ! {
         control,
         deadline,
         runtime: 0,
+        packrat: Packrat::from(PACKRAT.get_task_id()),
+        watchdog: DEFAULT_WATCHDOG,
     };
+
+    // Enable the watchdog, then pick up any tuning (including a watchdog
+    // timeout) cached in packrat before we restarted, whatever mode we're
+    // about to enter.
+    server.set_watchdog(DEFAULT_WATCHDOG).unwrap();
+    server.load_tuning();
     if bsp::USE_CONTROLLER {
         server.set_mode_auto().unwrap();
     } else {
         server.set_mode_manual(PWMDuty(0)).unwrap();
     }
 
-    //
-    // We enable the fan watchdog, but with its longest timeout of 30 seconds.
-    // This is longer than it takes to flash on Gimlet -- and right on the edge
-    // of how long it takes to dump.  On some platforms and/or under some
-    // conditions, "humility dump" might be able to induce the watchdog to kick,
-    // which may induce a flight-or-fight reaction for whomever is near the
-    // fans when they blast off...
-    //
-    server.set_watchdog(I2cWatchdog::ThirtySeconds).unwrap();
-
     let mut buffer = [0; idl::INCOMING_SIZE];
     loop {
         idol_runtime::dispatch_n(&mut buffer, &mut server);
@@ -363,7 +524,8 @@ fn main() -> ! {
 
 mod idl {
     use super::{
-        ThermalAutoState, ThermalError, ThermalMode, ThermalProperties,
+        FanStatus, ThermalAutoState, ThermalError, ThermalMode,
+        ThermalProperties, ThermalZoneState,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
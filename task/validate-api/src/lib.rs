@@ -13,6 +13,7 @@ use serde::{Deserialize, Serialize};
 use userlib::*;
 use zerocopy::AsBytes;
 
+pub use drv_i2c_api::Controller;
 pub use drv_i2c_api::Mux;
 pub use drv_i2c_api::Segment;
 pub use task_sensor_api::SensorId;
@@ -55,6 +56,64 @@ pub struct MuxSegment {
     pub segment: Segment,
 }
 
+///
+/// A set of 7-bit I2C addresses
+///
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    SerializedSize,
+    Serialize,
+    Deserialize,
+)]
+pub struct AddressSet([u32; 4]);
+
+impl AddressSet {
+    pub fn insert(&mut self, address: u8) {
+        let address = address & 0x7f;
+        self.0[address as usize / 32] |= 1 << (address % 32);
+    }
+
+    pub fn contains(&self, address: u8) -> bool {
+        address < 0x80
+            && self.0[address as usize / 32] & (1 << (address % 32)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..0x80).filter(|&address| self.contains(address))
+    }
+}
+
+///
+/// The result of scanning a single bus (that is, a port, or a segment of a
+/// mux on a port) for devices, relative to the devices that the application
+/// expects to find there.
+///
+#[derive(Copy, Clone, Debug, SerializedSize, Serialize, Deserialize)]
+pub struct I2cScan {
+    pub controller: Controller,
+    pub port: u8,
+    pub segment: Option<MuxSegment>,
+    /// Addresses that acknowledged a probe
+    pub present: AddressSet,
+    /// Expected (and not removable) devices that did not
+    pub missing: AddressSet,
+    /// Addresses that acknowledged, but at which no device is expected
+    pub unexpected: AddressSet,
+    /// Expected devices that acknowledged but failed validation
+    pub misidentified: AddressSet,
+    /// Whether the bus wasn't probed because doing so isn't safe
+    pub skipped: bool,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
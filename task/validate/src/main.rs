@@ -9,7 +9,7 @@
 
 use idol_runtime::RequestError;
 use ringbuf::*;
-use task_validate_api::{MuxSegment, ValidateError, ValidateOk};
+use task_validate_api::{I2cScan, MuxSegment, ValidateError, ValidateOk};
 use userlib::*;
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
@@ -20,6 +20,8 @@ struct ServerImpl;
 enum Trace {
     Validate(usize),
     ValidateFailure(drv_i2c_api::ResponseCode),
+    Scan(usize),
+    ScanFailure(u8, drv_i2c_api::ResponseCode),
     None,
 }
 
@@ -79,6 +81,93 @@ impl idl::InOrderValidateImpl for ServerImpl {
             }
         }
     }
+
+    fn scan_i2c(
+        &mut self,
+        _: &RecvMessage,
+        bus: u32,
+    ) -> Result<I2cScan, RequestError<ValidateError>> {
+        use drv_i2c_api::{I2cDevice, ReservedAddress, ResponseCode};
+        use i2c_config::validation::I2cValidation;
+        use num_traits::FromPrimitive;
+
+        let index = bus as usize;
+        let bus = i2c_config::scan::BUSES
+            .get(index)
+            .ok_or(ValidateError::InvalidDevice)?;
+
+        ringbuf_entry!(Trace::Scan(index));
+
+        let mut scan = I2cScan {
+            controller: bus.controller,
+            port: bus.port.0,
+            segment: bus
+                .segment
+                .map(|(mux, segment)| MuxSegment { mux, segment }),
+            present: Default::default(),
+            missing: Default::default(),
+            unexpected: Default::default(),
+            misidentified: Default::default(),
+            skipped: bus.skip,
+        };
+
+        if bus.skip {
+            return Ok(scan);
+        }
+
+        let task = I2C.get_task_id();
+
+        //
+        // We probe with a single-byte read, which is harmless for all but
+        // the most pathological devices; any failure other than a NACK
+        // indicates a problem with the bus itself, and ends the scan.
+        //
+        for address in 0..0x80 {
+            if ReservedAddress::from_u8(address).is_some()
+                || bus.shadowed.contains(&address)
+            {
+                continue;
+            }
+
+            let device = I2cDevice::new(
+                task,
+                bus.controller,
+                bus.port,
+                bus.segment,
+                address,
+            );
+
+            match device.read::<u8>() {
+                Ok(_) => scan.present.insert(address),
+                Err(ResponseCode::NoDevice) => {}
+                Err(code) => {
+                    ringbuf_entry!(Trace::ScanFailure(address, code));
+                    let err: ValidateError = code.into();
+                    return Err(err.into());
+                }
+            }
+        }
+
+        for &(address, index, removable) in bus.expected {
+            if !scan.present.contains(address) {
+                if !removable {
+                    scan.missing.insert(address);
+                }
+            } else if let Ok(I2cValidation::Bad) =
+                i2c_config::validation::validate(task, index)
+            {
+                scan.misidentified.insert(address);
+            }
+        }
+
+        for address in scan.present.iter() {
+            if !bus.expected.iter().any(|&(a, _, _)| a == address) {
+                scan.unexpected.insert(address);
+            }
+        }
+
+        Ok(scan)
+    }
 }
 
 #[export_name = "main"]
@@ -92,7 +181,7 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{MuxSegment, ValidateError, ValidateOk};
+    use super::{I2cScan, MuxSegment, ValidateError, ValidateOk};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }